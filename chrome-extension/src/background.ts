@@ -1,6 +1,8 @@
 // background.ts - Context menu + Native Messaging relay to tmux
 
 const NATIVE_HOST = "pigeon";
+// Request/response shapes this extension expects from pigeon-host
+const PROTOCOL_VERSION = 2;
 let port: chrome.runtime.Port | null = null;
 
 function getPort(): chrome.runtime.Port {
@@ -19,20 +21,49 @@ function getPort(): chrome.runtime.Port {
   return port;
 }
 
+// Large responses arrive as continuation frames that must be reassembled
+interface ResponseFrame {
+  id: number;
+  part: number;
+  total: number;
+  data: string;
+}
+
+function isFrame(message: unknown): message is ResponseFrame {
+  const m = message as Partial<ResponseFrame> | null;
+  return (
+    typeof m?.id === "number" &&
+    typeof m.part === "number" &&
+    typeof m.total === "number" &&
+    typeof m.data === "string"
+  );
+}
+
 function relayToNativeHost(
   payload: unknown,
   sendResponse: (response: unknown) => void,
 ): void {
   try {
     const p = getPort();
+    const parts: string[] = [];
+    let received = 0;
 
     const listener = (response: unknown) => {
+      if (isFrame(response)) {
+        if (parts[response.part] === undefined) received++;
+        parts[response.part] = response.data;
+        if (received < response.total) return;
+        response = JSON.parse(parts.join(""));
+      }
       p.onMessage.removeListener(listener);
       sendResponse(response);
     };
     p.onMessage.addListener(listener);
 
-    p.postMessage(payload);
+    p.postMessage({
+      ...(payload as object),
+      protocol_version: PROTOCOL_VERSION,
+    });
   } catch (e) {
     sendResponse({
       ok: false,
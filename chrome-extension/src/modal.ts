@@ -6,10 +6,45 @@ import type {
   ListSessionsResponse,
   SelectionContext,
   SendResponse,
+  TmuxSession,
 } from "./types";
 
 const MODAL_ID = "pigeon-modal";
 
+// Selections larger than this are streamed to the host in chunks
+const CHUNK_SIZE = 512 * 1024;
+
+function sendToServer<T>(payload: unknown): Promise<T> {
+  return chrome.runtime.sendMessage({ action: "sendToServer", payload });
+}
+
+// Send via begin-send / append-chunk / commit-send so that large selections
+// stay under Chrome's per-message limit
+async function sendChunked(
+  payload: { code: string } & Record<string, unknown>,
+): Promise<SendResponse> {
+  const uploadId = crypto.randomUUID();
+  const { code, ...rest } = payload;
+  const begin: SendResponse = await sendToServer({
+    ...rest,
+    action: "begin-send",
+    upload_id: uploadId,
+    code: code.slice(0, CHUNK_SIZE),
+  });
+  if (!begin?.ok) return begin;
+
+  for (let offset = CHUNK_SIZE; offset < code.length; offset += CHUNK_SIZE) {
+    const append: SendResponse = await sendToServer({
+      action: "append-chunk",
+      upload_id: uploadId,
+      data: code.slice(offset, offset + CHUNK_SIZE),
+    });
+    if (!append?.ok) return append;
+  }
+
+  return sendToServer({ action: "commit-send", upload_id: uploadId });
+}
+
 function createModal(): HTMLDivElement {
   const existing = document.getElementById(MODAL_ID);
   if (existing) existing.remove();
@@ -121,7 +156,7 @@ export async function showModal(
   ) as HTMLElement;
 
   // Fetch sessions
-  let sessions: string[] = [];
+  let sessions: TmuxSession[] = [];
   try {
     const response = await fetchSessions();
     if (!response.ok) {
@@ -132,7 +167,10 @@ export async function showModal(
       sendButton.disabled = true;
       sendButton.style.opacity = "0.5";
     } else {
-      sessions = response.sessions || [];
+      // Most recently active first
+      sessions = (response.sessions || []).sort(
+        (a, b) => b.last_activity - a.last_activity,
+      );
       loadingElement.style.display = "none";
       contentElement.style.display = "block";
 
@@ -142,20 +180,20 @@ export async function showModal(
         sendButton.disabled = true;
         sendButton.style.opacity = "0.5";
       } else {
-        for (const name of sessions) {
+        for (const session of sessions) {
           const option = document.createElement("option");
-          option.value = name;
-          option.textContent = name;
+          option.value = session.name;
+          option.textContent = `${session.name} (${session.current_command})`;
           selectElement.appendChild(option);
         }
         // Pre-select session matching the repo name by prefix
         const repoName = context.pullRequest?.repo;
         if (repoName) {
           const match = sessions.find((session) =>
-            session.startsWith(repoName),
+            session.name.startsWith(repoName),
           );
           if (match) {
-            selectElement.value = match;
+            selectElement.value = match.name;
           }
         }
       }
@@ -193,6 +231,9 @@ export async function showModal(
     const { debugMode } = await chrome.storage.local.get("debugMode");
     const payload = {
       action: "send",
+      repo: context.pullRequest
+        ? `${context.pullRequest.owner}/${context.pullRequest.repo}`
+        : undefined,
       file: context.file,
       start_line:
         context.startLine && context.endLine
@@ -206,16 +247,27 @@ export async function showModal(
       code: context.code,
       question: questionElement.value || "",
       tmux_target: target,
-      debug_html: debugMode ? buildDebugInfo(startElement) : undefined,
+      url: context.url,
     };
 
     try {
-      const sendResponse: SendResponse = await chrome.runtime.sendMessage({
-        action: "sendToServer",
-        payload,
-      });
+      if (debugMode) {
+        await sendToServer({
+          action: "report-debug",
+          html: buildDebugInfo(startElement),
+          context: { file: context.file, url: context.url },
+        });
+      }
+      const sendResponse: SendResponse =
+        payload.code.length > CHUNK_SIZE
+          ? await sendChunked(payload)
+          : await sendToServer(payload);
       closeModal();
-      if (sendResponse?.ok) {
+      if (sendResponse?.ok && sendResponse.stale) {
+        showNotification(
+          "Sent to tmux session (your local checkout differs from this PR)",
+        );
+      } else if (sendResponse?.ok) {
         showNotification("Sent to tmux session");
       } else {
         showNotification(sendResponse?.error || "Failed to send", true);
@@ -32,13 +32,218 @@ export interface DebugStrategy {
   [key: string]: unknown;
 }
 
+export interface ProbeEnvironmentResponse {
+  ok: boolean;
+  tmux: { path: string; source: string };
+  version?: string;
+  server_running: boolean;
+  socket_path?: string;
+  features: {
+    display_popup: boolean;
+    bracketed_paste: boolean;
+  };
+  error?: string;
+}
+
+export interface TmuxSession {
+  name: string;
+  attached: boolean;
+  windows: number;
+  /** Unix time of the session's last activity */
+  last_activity: number;
+  /** Program running in the active pane, such as "claude" or "zsh" */
+  current_command: string;
+}
+
 export interface ListSessionsResponse {
   ok: boolean;
-  sessions?: string[];
+  sessions?: TmuxSession[];
+  error?: string;
+}
+
+export interface TargetAlias {
+  target: string;
+  pane_id?: string;
+  session?: string;
+  command?: string;
+  path?: string;
+}
+
+export interface AliasesResponse {
+  ok: boolean;
+  aliases?: Record<string, TargetAlias>;
+  code?: string;
+  error?: string;
+}
+
+export interface TmuxPane {
+  id: string;
+  target: string;
+  current_command: string;
+  current_path: string;
+}
+
+export interface ListPanesResponse {
+  ok: boolean;
+  panes?: TmuxPane[];
+  error?: string;
+}
+
+export interface LifecycleResponse {
+  ok: boolean;
+  session?: string;
+  panes?: TmuxPane[];
+  code?: string;
+  error?: string;
+}
+
+export interface UndoResponse {
+  ok: boolean;
+  /** Staged send that was cleared */
+  stage_id?: string;
+  /** Pane whose input line was cleared */
+  tmux_target?: string;
+  code?: string;
   error?: string;
 }
 
+export interface TrimmedSection {
+  section: "code" | "context_before" | "context_after" | "question";
+  bytes: number;
+  kept_bytes: number;
+}
+
 export interface SendResponse {
   ok: boolean;
   error?: string;
+  stale?: boolean;
+  stale_lines?: number[];
+  trimmed?: TrimmedSection[];
+  strategy?: "send-keys" | "paste-buffer";
+  duration_ms?: number;
+  timings?: {
+    format_ms: number;
+    tmux_spawn_ms: number;
+    tmux_exec_ms: number;
+  };
+  send_id?: string;
+  fallback?: "clipboard" | "notes" | "queue";
+  hint?: string;
+  /** Target used instead of a tmux_target whose session is gone */
+  resolved_target?: string;
+  /** Per-target outcomes of a send to several tmux_targets */
+  results?: TargetResult[];
+  /** Pass to commit-send or abort-send after a stage-send */
+  stage_id?: string;
+  /** Sends to the same target this one waited for */
+  queue_position?: number;
+}
+
+export interface TargetResult {
+  target: string;
+  ok: boolean;
+  code?: string;
+  error?: string;
+}
+
+export interface JobResponse {
+  ok: boolean;
+  job_id: string;
+  /** Sends ahead of this one to the same target when it was accepted */
+  queue_position?: number;
+}
+
+export type JobState = "scheduled" | "running" | "succeeded" | "failed" | "cancelled";
+
+export interface JobStatusResponse {
+  ok: boolean;
+  action?: string;
+  state?: JobState;
+  /** When a scheduled job starts, in milliseconds since the Unix epoch */
+  due_at?: number;
+  /** The response the action would have returned synchronously */
+  result?: unknown;
+  error?: string;
+}
+
+/** Result of a watch-target job */
+export interface WatchTargetResponse {
+  ok: boolean;
+  /** Lines printed since the watch started */
+  new_lines?: number;
+  new_bytes?: number;
+  waited_ms?: number;
+  code?: string;
+  error?: string;
+}
+
+export interface TranscriptResponse {
+  ok: boolean;
+  /** Where the transcript was written */
+  path?: string;
+  /** The transcript itself, when no path was given */
+  markdown?: string;
+  code?: string;
+  error?: string;
+}
+
+export interface JobEntry {
+  job_id: string;
+  action: string;
+  state: JobState;
+  due_at?: number;
+  result?: unknown;
+}
+
+export interface JobListResponse {
+  ok: boolean;
+  jobs: JobEntry[];
+}
+
+export interface MetricsCounter {
+  name: string;
+  labels?: Record<string, string>;
+  value: number;
+}
+
+export interface MetricsHistogram {
+  name: string;
+  buckets: { le: number | null; count: number }[];
+  sum: number;
+  count: number;
+}
+
+export interface MetricsResponse {
+  ok: boolean;
+  metrics?: { counters: MetricsCounter[]; histograms: MetricsHistogram[] };
+  text?: string;
+}
+
+export interface SchemaResponse {
+  ok: boolean;
+  /** JSON Schema of the protocol, as in protocol.schema.json */
+  schema: Record<string, unknown>;
+}
+
+export interface GetReplyResponse {
+  ok: boolean;
+  reply?: string;
+  code?: string;
+  error?: string;
+}
+
+export interface ReportDebugResponse {
+  ok: boolean;
+  path?: string;
+  error?: string;
+}
+
+export interface CheckUpdateResponse {
+  ok: boolean;
+  current: string;
+  latest?: string;
+  update_available?: boolean;
+  installed?: string;
+  code?: string;
+  error?: string;
 }
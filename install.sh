@@ -65,13 +65,18 @@ done
 echo ""
 echo "Done! Please restart Chrome."
 
-# Create default config file (only if it doesn't exist)
-CONFIG_DIR="$HOME/.config/pigeon"
-CONFIG_FILE="$CONFIG_DIR/config"
+# Create an empty config file (only if it doesn't exist), where the host reads it
+if [ -n "${XDG_CONFIG_HOME:-}" ]; then
+    CONFIG_DIR="$XDG_CONFIG_HOME/pigeon"
+elif [ "$(uname)" = "Darwin" ]; then
+    CONFIG_DIR="$HOME/Library/Application Support/pigeon"
+else
+    CONFIG_DIR="$HOME/.config/pigeon"
+fi
+CONFIG_FILE="$CONFIG_DIR/config.json"
 if [ ! -f "$CONFIG_FILE" ]; then
     mkdir -p "$CONFIG_DIR"
-    echo "# tmux session name (default: claude)" > "$CONFIG_FILE"
-    echo "# tmux_target=claude" >> "$CONFIG_FILE"
+    echo "{}" > "$CONFIG_FILE"
     echo "Created config: $CONFIG_FILE"
 fi
 
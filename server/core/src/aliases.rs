@@ -0,0 +1,478 @@
+//! Target aliases: names such as `backend-agent` for tmux targets, kept in
+//! `aliases.json` in the data directory so every host process shares them.
+//! A request may give an alias anywhere it gives a target.
+//!
+//! An alias remembers the id of the pane it was added for (`%12`), so it
+//! keeps pointing at that pane when windows are moved or renumbered.
+//! `default_target` is bookmarked the same way the first time it is used.
+//! When the pane is gone, a pane in the same session with the same program
+//! and working directory takes its place, and failing that the session.
+
+use crate::lock::FileLock;
+use crate::paths;
+use crate::tmux::{Pane, Tmux};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Longest alias name accepted
+pub const MAX_NAME_LEN: usize = 64;
+
+/// What an alias stands for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Alias {
+    /// tmux target as given when the alias was added, such as `work:2.1`
+    pub target: String,
+    /// Id of the pane `target` named when bookmarked, such as `%12`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pane_id: Option<String>,
+    /// Session, program and working directory of that pane, for finding
+    /// its replacement once it is gone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Alias {
+    /// `target` as it stands, without a pane to follow
+    pub fn positional(target: &str) -> Self {
+        Self {
+            target: target.to_string(),
+            pane_id: None,
+            session: None,
+            command: None,
+            path: None,
+        }
+    }
+
+    /// `target` bookmarked as the pane it names now. When tmux cannot say
+    /// which pane that is, the alias stays positional.
+    pub fn bookmark(target: &str, tmux: &Tmux) -> Self {
+        match tmux.pane(target) {
+            Ok(pane) => Self::at(target, &pane),
+            Err(e) => {
+                eprintln!("pigeon: not following a pane for {target}: {e}");
+                Self::positional(target)
+            }
+        }
+    }
+
+    fn at(target: &str, pane: &Pane) -> Self {
+        Self {
+            target: target.to_string(),
+            pane_id: Some(pane.id.clone()),
+            session: Some(pane.session().to_string()),
+            command: Some(pane.current_command.clone()),
+            path: Some(pane.current_path.clone()),
+        }
+    }
+
+    /// The bookmarked pane among `panes`, or the pane that replaced it: one
+    /// in the same session running the same program in the same directory
+    fn locate<'a>(&self, panes: &'a [Pane]) -> Option<&'a Pane> {
+        let id = self.pane_id.as_deref()?;
+        panes.iter().find(|pane| pane.id == id).or_else(|| {
+            panes.iter().find(|pane| {
+                Some(pane.session()) == self.session.as_deref()
+                    && Some(pane.current_command.as_str()) == self.command.as_deref()
+                    && Some(pane.current_path.as_str()) == self.path.as_deref()
+            })
+        })
+    }
+
+    /// Where to send now, and the alias updated to follow a replacement pane.
+    /// Without the pane or a replacement, the bookmarked session is left to
+    /// `session_matching`; without a bookmark, `target` is used as given.
+    fn resolve(&self, tmux: &Tmux) -> (String, Option<Alias>) {
+        if self.pane_id.is_none() {
+            return (self.target.clone(), None);
+        }
+        let panes = match tmux.list_panes() {
+            Ok(panes) => panes,
+            Err(e) => {
+                eprintln!("pigeon: cannot list panes for {}: {e}", self.target);
+                return (self.target.clone(), None);
+            }
+        };
+        match self.locate(&panes) {
+            Some(pane) if Some(&pane.id) == self.pane_id.as_ref() => (pane.target.clone(), None),
+            Some(pane) => {
+                eprintln!(
+                    "pigeon: pane {} is gone; following {} in {}",
+                    self.pane_id.as_deref().unwrap_or_default(),
+                    pane.id,
+                    pane.target
+                );
+                (pane.target.clone(), Some(Self::at(&self.target, pane)))
+            }
+            None => (
+                self.session.clone().unwrap_or_else(|| self.target.clone()),
+                None,
+            ),
+        }
+    }
+}
+
+/// Aliases by name, stored as one JSON file rewritten atomically on change
+pub struct Aliases {
+    path: PathBuf,
+}
+
+impl Aliases {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Default location: `aliases.json` in the platform data directory
+    pub fn default_location() -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("aliases.json")))
+    }
+
+    fn lock(&self) -> io::Result<FileLock> {
+        FileLock::acquire(&self.path.with_extension("json.lock"))
+    }
+
+    pub fn list(&self) -> io::Result<BTreeMap<String, Alias>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store(&self, aliases: &BTreeMap<String, Alias>) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self
+            .path
+            .with_extension(format!("json.tmp.{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_string_pretty(aliases)? + "\n")?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Point `name` at `alias`, replacing what it pointed at before
+    pub fn add(&self, name: &str, alias: Alias) -> Result<BTreeMap<String, Alias>, String> {
+        validate_name(name)?;
+        let target = &alias.target;
+        if target.trim().is_empty() {
+            return Err("An alias needs a tmux target".to_string());
+        }
+        if is_name(target) && target != name && self.lookup(target).is_some() {
+            return Err(format!("{target} is itself an alias; give its target"));
+        }
+        let _lock = self.lock().map_err(|e| e.to_string())?;
+        let mut aliases = self.list().map_err(|e| e.to_string())?;
+        aliases.insert(name.to_string(), alias);
+        self.store(&aliases).map_err(|e| e.to_string())?;
+        Ok(aliases)
+    }
+
+    /// Replace `name` with `alias` if it still exists, as when its pane was
+    /// replaced. Failures only mean the pane is searched for again next time.
+    fn refresh(&self, name: &str, alias: Alias) {
+        let result = self.lock().and_then(|_lock| {
+            let mut aliases = self.list()?;
+            match aliases.get_mut(name) {
+                Some(stored) => *stored = alias,
+                None => return Ok(()),
+            }
+            self.store(&aliases)
+        });
+        if let Err(e) = result {
+            eprintln!("pigeon: cannot update alias {name}: {e}");
+        }
+    }
+
+    /// Forget `name`; `false` when there was no such alias
+    pub fn remove(&self, name: &str) -> io::Result<bool> {
+        let _lock = self.lock()?;
+        let mut aliases = self.list()?;
+        if aliases.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.store(&aliases)?;
+        Ok(true)
+    }
+
+    /// The target `name` stands for. Strings that cannot be alias names,
+    /// such as `work:2.1`, are not looked up at all.
+    pub fn lookup(&self, name: &str) -> Option<Alias> {
+        if !is_name(name) {
+            return None;
+        }
+        match self.list() {
+            Ok(mut aliases) => aliases.remove(name),
+            Err(e) => {
+                eprintln!("pigeon: cannot read {}: {e}", self.path.display());
+                None
+            }
+        }
+    }
+}
+
+/// The pane `default_target` named when first used, kept in
+/// `default-target.json` next to `aliases.json`
+pub struct DefaultPin {
+    path: PathBuf,
+}
+
+impl DefaultPin {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn default_location() -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("default-target.json")))
+    }
+
+    fn load(&self) -> Option<Alias> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        serde_json::from_str(&content)
+            .map_err(|e| eprintln!("pigeon: ignoring {}: {e}", self.path.display()))
+            .ok()
+    }
+
+    fn store(&self, pin: &Alias) {
+        let result = (|| {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let tmp = self
+                .path
+                .with_extension(format!("json.tmp.{}", std::process::id()));
+            fs::write(&tmp, serde_json::to_string_pretty(pin)? + "\n")?;
+            fs::rename(&tmp, &self.path)
+        })();
+        if let Err(e) = result {
+            eprintln!("pigeon: cannot write {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Where `given`, or `default_target` when `given` is empty, points now:
+/// an alias's pane, or the pane `default_target` was first used with.
+/// `None` means the target is used as it is. Panes are only followed when
+/// `tmux` is given.
+pub fn resolve(given: &str, default_target: Option<&str>, tmux: Option<&Tmux>) -> Option<String> {
+    resolve_in(
+        &Aliases::default_location()?,
+        &DefaultPin::default_location()?,
+        given,
+        default_target,
+        tmux,
+    )
+}
+
+fn resolve_in(
+    aliases: &Aliases,
+    pin: &DefaultPin,
+    given: &str,
+    default_target: Option<&str>,
+    tmux: Option<&Tmux>,
+) -> Option<String> {
+    let name = match given {
+        "" => default_target?,
+        given => given,
+    };
+    if let Some(alias) = aliases.lookup(name) {
+        let Some(tmux) = tmux else {
+            return Some(alias.target);
+        };
+        let (target, moved) = alias.resolve(tmux);
+        if let Some(moved) = moved {
+            aliases.refresh(name, moved);
+        }
+        return Some(target);
+    }
+    let tmux = tmux.filter(|_| given.is_empty())?;
+    let target = match pin.load().filter(|pinned| pinned.target == name) {
+        Some(pinned) => {
+            let (target, moved) = pinned.resolve(tmux);
+            if let Some(moved) = moved {
+                pin.store(&moved);
+            }
+            target
+        }
+        None => {
+            let pinned = Alias::bookmark(name, tmux);
+            if pinned.pane_id.is_some() {
+                pin.store(&pinned);
+            }
+            return None;
+        }
+    };
+    Some(target).filter(|target| target != name)
+}
+
+/// Letters, digits, `-` and `_`, so a name never reads as tmux target syntax
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if is_name(name) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Alias names are 1 to {MAX_NAME_LEN} letters, digits, '-' or '_': {name:?}"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::exec::fake::Fake;
+    use std::rc::Rc;
+
+    const PANES: &str = "%12\twork:2.1\tclaude\t/src/api\n%13\twork:3.0\tzsh\t/src\n";
+
+    fn fake_tmux() -> (Tmux, Rc<Fake>) {
+        let fake = Fake::new();
+        let tmux = Tmux::new(&Config::default()).with_exec(fake.clone());
+        (tmux, fake)
+    }
+
+    fn temp_aliases(name: &str) -> (PathBuf, Aliases) {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-aliases-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let aliases = Aliases::new(dir.join("aliases.json"));
+        (dir, aliases)
+    }
+
+    #[test]
+    fn test_add_lookup_remove() {
+        let (dir, aliases) = temp_aliases("round-trip");
+        assert_eq!(aliases.lookup("backend-agent"), None);
+        aliases
+            .add("backend-agent", Alias::positional("work:2.1"))
+            .unwrap();
+        let listed = aliases.add("docs", Alias::positional("%7")).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(aliases.lookup("backend-agent").unwrap().target, "work:2.1");
+        aliases
+            .add("backend-agent", Alias::positional("work:3"))
+            .unwrap();
+        assert_eq!(aliases.lookup("backend-agent").unwrap().target, "work:3");
+        assert!(aliases.remove("docs").unwrap());
+        assert!(!aliases.remove("docs").unwrap());
+        assert_eq!(aliases.list().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_names_cannot_look_like_targets() {
+        let (dir, aliases) = temp_aliases("names");
+        assert!(aliases.add("work:2", Alias::positional("dev")).is_err());
+        assert!(aliases.add("", Alias::positional("dev")).is_err());
+        assert!(aliases.add("agent", Alias::positional(" ")).is_err());
+        assert!(!dir.exists());
+        assert_eq!(aliases.lookup("work:2.1"), None);
+    }
+
+    #[test]
+    fn test_alias_of_an_alias_is_refused() {
+        let (dir, aliases) = temp_aliases("chain");
+        aliases.add("agent", Alias::positional("work:1")).unwrap();
+        let err = aliases
+            .add("other", Alias::positional("agent"))
+            .unwrap_err();
+        assert!(err.contains("itself an alias"), "{err}");
+        // A plain session name that is not an alias is a target like any other
+        aliases.add("other", Alias::positional("dev")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bookmark_follows_the_pane() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("%12\n");
+        fake.succeed(PANES);
+        let alias = Alias::bookmark("work:2.1", &tmux);
+        assert_eq!(alias.pane_id.as_deref(), Some("%12"));
+        assert_eq!(alias.session.as_deref(), Some("work"));
+        // The window was moved: the pane id still finds it
+        fake.succeed("%13\twork:2.0\tzsh\t/src\n%12\twork:5.1\tclaude\t/src/api\n");
+        assert_eq!(alias.resolve(&tmux), ("work:5.1".to_string(), None));
+    }
+
+    #[test]
+    fn test_replacement_pane_then_session() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("%12\n");
+        fake.succeed(PANES);
+        let alias = Alias::bookmark("work:2.1", &tmux);
+        // The agent was restarted in a new pane in the same directory
+        fake.succeed("%13\twork:3.0\tzsh\t/src\n%20\twork:4.0\tclaude\t/src/api\n");
+        let (target, moved) = alias.resolve(&tmux);
+        assert_eq!(target, "work:4.0");
+        assert_eq!(moved.unwrap().pane_id.as_deref(), Some("%20"));
+        // Nothing like it left: the session, for session_matching to handle
+        fake.succeed("%13\twork:3.0\tzsh\t/src\n");
+        assert_eq!(alias.resolve(&tmux), ("work".to_string(), None));
+    }
+
+    #[test]
+    fn test_positional_without_tmux() {
+        let (tmux, fake) = fake_tmux();
+        fake.fail("no server running");
+        let alias = Alias::bookmark("work:2.1", &tmux);
+        assert_eq!(alias, Alias::positional("work:2.1"));
+        assert_eq!(alias.resolve(&tmux), ("work:2.1".to_string(), None));
+        assert_eq!(fake.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_follows_aliases_and_the_default_target() {
+        let (dir, aliases) = temp_aliases("resolve");
+        let pin = DefaultPin::new(dir.join("default-target.json"));
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("%12\n");
+        fake.succeed(PANES);
+        aliases
+            .add("agent", Alias::bookmark("work:2.1", &tmux))
+            .unwrap();
+        let moved = "%12\twork:0.1\tclaude\t/src/api\n";
+        fake.succeed(moved);
+        assert_eq!(
+            resolve_in(&aliases, &pin, "agent", None, Some(&tmux)).as_deref(),
+            Some("work:0.1")
+        );
+        assert_eq!(
+            resolve_in(&aliases, &pin, "agent", None, None).as_deref(),
+            Some("work:2.1")
+        );
+        // Targets given explicitly are used as they are
+        assert_eq!(
+            resolve_in(&aliases, &pin, "work:3.0", None, Some(&tmux)),
+            None
+        );
+        // default_target is pinned on first use and followed afterwards
+        fake.succeed("%13\n");
+        fake.succeed(PANES);
+        assert_eq!(
+            resolve_in(&aliases, &pin, "", Some("work:3.0"), Some(&tmux)),
+            None
+        );
+        fake.succeed("%13\twork:1.0\tzsh\t/src\n");
+        assert_eq!(
+            resolve_in(&aliases, &pin, "", Some("work:3.0"), Some(&tmux)).as_deref(),
+            Some("work:1.0")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+use crate::lock::FileLock;
+use crate::retention::Policy;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory of timestamped files named `<prefix><millis><extension>`,
+/// pruned oldest-first on every save to stay within count and size caps.
+pub struct ArtifactStore {
+    dir: PathBuf,
+    prefix: &'static str,
+    extension: &'static str,
+    max_artifacts: usize,
+    max_total_bytes: u64,
+    min_interval: Duration,
+}
+
+impl ArtifactStore {
+    pub fn new(
+        dir: PathBuf,
+        prefix: &'static str,
+        extension: &'static str,
+        max_artifacts: usize,
+        max_total_bytes: u64,
+    ) -> Self {
+        Self {
+            dir,
+            prefix,
+            extension,
+            max_artifacts,
+            max_total_bytes,
+            min_interval: Duration::ZERO,
+        }
+    }
+
+    /// Refuse saves (with `ErrorKind::WouldBlock`) until `interval` has
+    /// passed since the newest artifact was written
+    pub fn with_min_interval(self, interval: Duration) -> Self {
+        Self {
+            min_interval: interval,
+            ..self
+        }
+    }
+
+    /// Write `content` to a new timestamped file and prune old artifacts.
+    /// Returns the path of the saved file.
+    pub fn save(&self, content: impl AsRef<[u8]>) -> io::Result<PathBuf> {
+        // Serialize naming and pruning with other hosts sharing the store. The
+        // lock sits beside the directory so it is never counted as an artifact.
+        let _lock = FileLock::acquire(&self.dir.with_extension("lock"))?;
+        fs::create_dir_all(&self.dir)?;
+        self.check_interval()?;
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let path = self.unique_path(millis);
+        fs::write(&path, content)?;
+
+        self.prune(&path)?;
+        Ok(path)
+    }
+
+    fn check_interval(&self) -> io::Result<()> {
+        if self.min_interval.is_zero() {
+            return Ok(());
+        }
+        let newest = self.artifacts()?.into_iter().map(|a| a.modified).max();
+        let Some(age) = newest.map(|m| m.elapsed().unwrap_or_default()) else {
+            return Ok(());
+        };
+        if age < self.min_interval {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!(
+                    "the last one was saved {}s ago (at most one per {}s)",
+                    age.as_secs(),
+                    self.min_interval.as_secs()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Avoid clobbering an artifact written within the same millisecond
+    fn unique_path(&self, millis: u128) -> PathBuf {
+        let (prefix, extension) = (self.prefix, self.extension);
+        let base = self.dir.join(format!("{prefix}{millis}{extension}"));
+        if !base.exists() {
+            return base;
+        }
+        (1..)
+            .map(|n| self.dir.join(format!("{prefix}{millis}-{n}{extension}")))
+            .find(|p| !p.exists())
+            .expect("unbounded range always yields a free path")
+    }
+
+    /// Remove the oldest artifacts until both the count and size caps are met.
+    /// The artifact just written (`keep`) is never removed.
+    fn prune(&self, keep: &Path) -> io::Result<()> {
+        self.remove_oldest(Some(keep), |count, total, _| {
+            count > self.max_artifacts || total > self.max_total_bytes
+        })?;
+        Ok(())
+    }
+
+    /// Remove artifacts older than `policy` allows, then the oldest ones
+    /// until the rest fit its size limit. The number removed.
+    pub fn sweep(&self, policy: &Policy, now: SystemTime) -> io::Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+        let _lock = FileLock::acquire(&self.dir.with_extension("lock"))?;
+        let cutoff = policy.cutoff(now);
+        self.remove_oldest(None, |_, total, artifact| {
+            cutoff.is_some_and(|cutoff| artifact.modified < cutoff) || policy.exceeded_by(total)
+        })
+    }
+
+    /// Remove artifacts oldest first, except `keep`, for as long as
+    /// `excess(count, total_bytes, oldest_left)` holds
+    fn remove_oldest(
+        &self,
+        keep: Option<&Path>,
+        excess: impl Fn(usize, u64, &Artifact) -> bool,
+    ) -> io::Result<usize> {
+        let mut artifacts = self.artifacts()?;
+        // Oldest first
+        artifacts.sort_by(|a, b| a.modified.cmp(&b.modified).then(a.path.cmp(&b.path)));
+
+        let mut count = artifacts.len();
+        let mut total: u64 = artifacts.iter().map(|a| a.size).sum();
+        let mut removed = 0;
+
+        for artifact in &artifacts {
+            if !excess(count, total, artifact) {
+                break;
+            }
+            if Some(artifact.path.as_path()) == keep {
+                continue;
+            }
+            fs::remove_file(&artifact.path)?;
+            count -= 1;
+            total -= artifact.size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn artifacts(&self) -> io::Result<Vec<Artifact>> {
+        let mut artifacts = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !name.starts_with(self.prefix) || !name.ends_with(self.extension) {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            if !meta.is_file() {
+                continue;
+            }
+            artifacts.push(Artifact {
+                path: entry.path(),
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(UNIX_EPOCH),
+            });
+        }
+        Ok(artifacts)
+    }
+}
+
+struct Artifact {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "pigeon-artifacts-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn store(dir: PathBuf) -> ArtifactStore {
+        ArtifactStore::new(dir, "debug-", ".json", 20, 10 * 1024 * 1024)
+    }
+
+    fn count_files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn test_save_creates_directory() {
+        let dir = temp_dir("create");
+        let store = store(dir.join("nested"));
+        let path = store.save("<html></html>").unwrap();
+        assert!(path.starts_with(dir.join("nested")));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<html></html>");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_does_not_overwrite() {
+        let dir = temp_dir("unique");
+        let store = store(dir.clone());
+        let a = store.save("a").unwrap();
+        let b = store.save("b").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(count_files(&dir), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_by_count() {
+        let dir = temp_dir("count");
+        let store = ArtifactStore {
+            max_artifacts: 3,
+            ..store(dir.clone())
+        };
+        let mut last = PathBuf::new();
+        for i in 0..6 {
+            last = store.save(format!("artifact {i}")).unwrap();
+        }
+        assert_eq!(count_files(&dir), 3);
+        assert!(last.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_by_size_keeps_latest() {
+        let dir = temp_dir("size");
+        let store = ArtifactStore {
+            max_total_bytes: 10,
+            ..store(dir.clone())
+        };
+        store.save("0123456789").unwrap();
+        let latest = store.save("0123456789abcdef").unwrap();
+        assert_eq!(count_files(&dir), 1);
+        assert!(latest.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sweep_removes_old_and_oversized() {
+        let dir = temp_dir("sweep");
+        let store = store(dir.clone());
+        let old = store.save("old").unwrap();
+        let middle = store.save("0123456789").unwrap();
+        let newest = store.save("abc").unwrap();
+        let ago = |secs| SystemTime::now() - Duration::from_secs(secs);
+        let set_modified = |path: &Path, time| {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        set_modified(&old, ago(10 * 86_400));
+        set_modified(&middle, ago(86_400));
+
+        let by_age = Policy {
+            max_age_days: 7,
+            max_bytes: 0,
+        };
+        assert_eq!(store.sweep(&by_age, SystemTime::now()).unwrap(), 1);
+        assert!(!old.exists());
+        let by_size = Policy {
+            max_age_days: 0,
+            max_bytes: 5,
+        };
+        assert_eq!(store.sweep(&by_size, SystemTime::now()).unwrap(), 1);
+        assert!(!middle.exists() && newest.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_min_interval_refuses_rapid_saves() {
+        let dir = temp_dir("interval");
+        let store = store(dir.clone()).with_min_interval(Duration::from_secs(60));
+        store.save("a").unwrap();
+        let err = store.save("b").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(count_files(&dir), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_ignores_unrelated_files() {
+        let dir = temp_dir("unrelated");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), "keep me").unwrap();
+        let store = ArtifactStore {
+            max_artifacts: 1,
+            ..store(dir.clone())
+        };
+        store.save("a").unwrap();
+        store.save("b").unwrap();
+        assert!(dir.join("notes.txt").exists());
+        assert_eq!(count_files(&dir), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
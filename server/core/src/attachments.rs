@@ -0,0 +1,117 @@
+use crate::artifacts::ArtifactStore;
+use crate::base64;
+use crate::paths;
+use std::path::PathBuf;
+
+/// Keep at most this many images in the attachments directory
+const MAX_ATTACHMENTS: usize = 50;
+
+/// Cap on the combined size of all images in the attachments directory
+const MAX_TOTAL_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Largest single image accepted
+pub const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Shown in previews, where nothing is saved yet
+pub const PREVIEW_PATH: &str = "<attachment path>";
+
+/// Attachment failure with a machine-readable code for the extension
+#[derive(Debug, PartialEq, Eq)]
+pub struct AttachmentError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl AttachmentError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Store for images attached by the extension, such as screenshots of a
+/// rendered diff or CI failure. Oldest images are pruned on every save.
+pub fn store(dir: PathBuf) -> ArtifactStore {
+    ArtifactStore::new(dir, "attachment-", ".png", MAX_ATTACHMENTS, MAX_TOTAL_BYTES)
+}
+
+/// Default location: `attachments` in the platform cache directory
+pub fn default_store() -> Option<ArtifactStore> {
+    Some(store(paths::cache_dir()?.join("attachments")))
+}
+
+/// Decode a base64 PNG, enforcing the size cap and file signature
+pub fn decode_png(encoded: &str) -> Result<Vec<u8>, AttachmentError> {
+    // Reject oversized input before spending memory on decoding it
+    if encoded.len() / 4 * 3 > MAX_IMAGE_BYTES + 3 {
+        return Err(too_large());
+    }
+    let bytes = base64::decode(encoded)
+        .map_err(|e| AttachmentError::new("INVALID_BASE64", format!("Invalid image_b64: {e}")))?;
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(too_large());
+    }
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return Err(AttachmentError::new(
+            "INVALID_ATTACHMENT",
+            "Attachment is not a PNG image",
+        ));
+    }
+    Ok(bytes)
+}
+
+fn too_large() -> AttachmentError {
+    AttachmentError::new(
+        "ATTACHMENT_TOO_LARGE",
+        format!("Attachment exceeds {} MiB", MAX_IMAGE_BYTES / (1024 * 1024)),
+    )
+}
+
+/// Decode and save a base64 PNG in the default store
+pub fn save_png(encoded: &str) -> Result<PathBuf, AttachmentError> {
+    let bytes = decode_png(encoded)?;
+    let store = default_store()
+        .ok_or_else(|| AttachmentError::new("SAVE_FAILED", "Cannot locate cache directory"))?;
+    store
+        .save(bytes)
+        .map_err(|e| AttachmentError::new("SAVE_FAILED", format!("Failed to save attachment: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Base64 of a PNG signature followed by a few bytes of payload
+    const TINY_PNG: &str = "iVBORw0KGgoAAAAN";
+
+    #[test]
+    fn test_decode_png() {
+        let bytes = decode_png(TINY_PNG).unwrap();
+        assert!(bytes.starts_with(PNG_SIGNATURE));
+    }
+
+    #[test]
+    fn test_rejects_non_png() {
+        // "GIF89a"
+        let err = decode_png("R0lGODlh").unwrap_err();
+        assert_eq!(err.code, "INVALID_ATTACHMENT");
+    }
+
+    #[test]
+    fn test_rejects_bad_base64() {
+        assert_eq!(decode_png("iVBO*").unwrap_err().code, "INVALID_BASE64");
+    }
+
+    #[test]
+    fn test_rejects_oversized_image() {
+        let encoded = "A".repeat((MAX_IMAGE_BYTES + 3) / 3 * 4 + 4);
+        assert_eq!(
+            decode_png(&encoded).unwrap_err().code,
+            "ATTACHMENT_TOO_LARGE"
+        );
+    }
+}
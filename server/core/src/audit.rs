@@ -0,0 +1,313 @@
+use crate::lock::FileLock;
+use crate::paths;
+use crate::retention::Policy;
+use crate::sha256;
+use crate::time;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Audit log settings. Off by default; meant for environments where every
+/// message that left the browser must be reviewable afterwards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSettings {
+    pub enabled: bool,
+    /// Rotate once the current file grows beyond this size
+    pub max_bytes: u64,
+    /// Rotated files to keep in addition to the current one
+    pub max_files: u32,
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// One line of the audit log. The question is stored only as a hash so the
+/// log records what was sent where without retaining its content.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub ts: String,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub file: String,
+    pub question_sha256: String,
+    pub code_bytes: usize,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        target: &str,
+        repo: Option<&str>,
+        file: &str,
+        question: &str,
+        code: &str,
+        result: Result<(), &str>,
+    ) -> Self {
+        Self {
+            ts: time::rfc3339(time::unix_now()),
+            target: target.to_string(),
+            repo: repo.map(String::from),
+            file: file.to_string(),
+            question_sha256: sha256::hex_digest(question.as_bytes()),
+            code_bytes: code.len(),
+            ok: result.is_ok(),
+            error: result.err().map(String::from),
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Append-only JSONL file with size-based rotation:
+/// `audit.jsonl` → `audit.1.jsonl` → … → `audit.<max_files>.jsonl`
+pub struct AuditLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+}
+
+impl AuditLog {
+    pub fn new(dir: PathBuf, settings: &AuditSettings) -> Self {
+        Self {
+            dir,
+            max_bytes: settings.max_bytes,
+            max_files: settings.max_files,
+        }
+    }
+
+    /// Default location: `audit` in the platform data directory
+    pub fn default_location(settings: &AuditSettings) -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("audit"), settings))
+    }
+
+    fn file(&self, index: u32) -> PathBuf {
+        match index {
+            0 => self.dir.join("audit.jsonl"),
+            n => self.dir.join(format!("audit.{n}.jsonl")),
+        }
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+        // Another host rotating mid-append would lose or misplace the line
+        let _lock = FileLock::acquire(&self.dir.join("audit.lock"))?;
+        self.rotate_if_needed()?;
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        // A single write_all on an O_APPEND file keeps lines intact
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file(0))?
+            .write_all(line.as_bytes())
+    }
+
+    /// All entries, oldest first. Lines that do not parse (for example one
+    /// cut short by a crash) are skipped rather than failing the whole read.
+    pub fn entries(&self) -> io::Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        for index in (0..=self.max_files).rev() {
+            let content = match fs::read_to_string(self.file(index)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            entries.extend(content.lines().filter_map(|l| serde_json::from_str(l).ok()));
+        }
+        Ok(entries)
+    }
+
+    /// Remove files whose last line is older than `policy` allows, then the
+    /// oldest rotated files until the log fits its size limit. The current
+    /// file is only removed for its age. The number of files removed.
+    pub fn sweep(&self, policy: &Policy, now: SystemTime) -> io::Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+        let _lock = FileLock::acquire(&self.dir.join("audit.lock"))?;
+        // Oldest first
+        let mut files = Vec::new();
+        for index in (0..=self.max_files).rev() {
+            match fs::metadata(self.file(index)) {
+                Ok(meta) => files.push((index, meta.len(), meta.modified()?)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let cutoff = policy.cutoff(now).unwrap_or(UNIX_EPOCH);
+        let mut total: u64 = files.iter().map(|&(_, size, _)| size).sum();
+        let mut removed = 0;
+        for (index, size, modified) in files {
+            let too_big = index > 0 && policy.exceeded_by(total);
+            if modified >= cutoff && !too_big {
+                break;
+            }
+            fs::remove_file(self.file(index))?;
+            total -= size;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn rotate_if_needed(&self) -> io::Result<()> {
+        let size = match fs::metadata(self.file(0)) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if size < self.max_bytes {
+            return Ok(());
+        }
+        if self.max_files == 0 {
+            return fs::remove_file(self.file(0));
+        }
+        let _ = fs::remove_file(self.file(self.max_files));
+        for index in (0..self.max_files).rev() {
+            let from = self.file(index);
+            if from.exists() {
+                fs::rename(&from, self.file(index + 1))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-audit-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn entry(file: &str) -> AuditEntry {
+        AuditEntry::new("dev", Some("acme/api"), file, "Why?", "code", Ok(()))
+    }
+
+    #[test]
+    fn test_entry_hashes_question() {
+        let e = AuditEntry::new("dev", None, "a.rs", "abc", "xyz", Err("boom"));
+        assert_eq!(
+            e.question_sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(e.code_bytes, 3);
+        assert!(!e.ok);
+        assert_eq!(e.error.as_deref(), Some("boom"));
+        let json = serde_json::to_string(&e).unwrap();
+        assert!(!json.contains("abc"));
+    }
+
+    #[test]
+    fn test_append_writes_jsonl() {
+        let dir = temp_dir("append");
+        let log = AuditLog::new(dir.clone(), &AuditSettings::default());
+        log.append(&entry("a.rs")).unwrap();
+        log.append(&entry("b.rs")).unwrap();
+        let content = fs::read_to_string(dir.join("audit.jsonl")).unwrap();
+        let files: Vec<String> = content
+            .lines()
+            .map(|l| serde_json::from_str::<AuditEntry>(l).unwrap().file)
+            .collect();
+        assert_eq!(files, ["a.rs", "b.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() {
+        let dir = temp_dir("rotate");
+        let settings = AuditSettings {
+            enabled: true,
+            max_bytes: 1,
+            max_files: 2,
+        };
+        let log = AuditLog::new(dir.clone(), &settings);
+        for name in ["1.rs", "2.rs", "3.rs", "4.rs"] {
+            log.append(&entry(name)).unwrap();
+        }
+        let read = |name: &str| {
+            let content = fs::read_to_string(dir.join(name)).unwrap();
+            serde_json::from_str::<AuditEntry>(content.trim())
+                .unwrap()
+                .file
+        };
+        assert_eq!(read("audit.jsonl"), "4.rs");
+        assert_eq!(read("audit.1.jsonl"), "3.rs");
+        assert_eq!(read("audit.2.jsonl"), "2.rs");
+        assert!(!dir.join("audit.3.jsonl").exists());
+
+        let files: Vec<String> = log.entries().unwrap().into_iter().map(|e| e.file).collect();
+        assert_eq!(files, ["2.rs", "3.rs", "4.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sweep_removes_old_and_excess_files() {
+        let dir = temp_dir("sweep");
+        let settings = AuditSettings {
+            enabled: true,
+            max_bytes: 1,
+            max_files: 3,
+        };
+        let log = AuditLog::new(dir.clone(), &settings);
+        for name in ["1.rs", "2.rs", "3.rs", "4.rs"] {
+            log.append(&entry(name)).unwrap();
+        }
+        let old = SystemTime::now() - std::time::Duration::from_secs(40 * 86_400);
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.join("audit.3.jsonl"))
+            .unwrap();
+        file.set_modified(old).unwrap();
+
+        let by_age = Policy {
+            max_age_days: 30,
+            max_bytes: 0,
+        };
+        assert_eq!(log.sweep(&by_age, SystemTime::now()).unwrap(), 1);
+        // The current file stays, however small the limit
+        let by_size = Policy {
+            max_age_days: 0,
+            max_bytes: 1,
+        };
+        assert_eq!(log.sweep(&by_size, SystemTime::now()).unwrap(), 2);
+        let files: Vec<String> = log.entries().unwrap().into_iter().map(|e| e.file).collect();
+        assert_eq!(files, ["4.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_entries_skip_malformed_lines() {
+        let dir = temp_dir("malformed");
+        let log = AuditLog::new(dir.clone(), &AuditSettings::default());
+        log.append(&entry("a.rs")).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join("audit.jsonl"))
+            .unwrap();
+        file.write_all(b"{\"ts\": \"trunc").unwrap();
+        assert_eq!(log.entries().unwrap().len(), 1);
+        assert!(AuditLog::new(dir.join("none"), &AuditSettings::default())
+            .entries()
+            .unwrap()
+            .is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
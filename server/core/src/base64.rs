@@ -0,0 +1,79 @@
+//! Standard base64 (RFC 4648 §4) decoding for binary-safe request fields.
+
+fn value(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decode `input`. Padding is optional, but characters outside the
+/// alphabet (including whitespace) are rejected.
+pub fn decode(input: &str) -> Result<Vec<u8>, String> {
+    let data = input.trim_end_matches('=');
+    if input.len() - data.len() > 2 {
+        return Err("too much padding".to_string());
+    }
+    if data.len() % 4 == 1 {
+        return Err("invalid length".to_string());
+    }
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for (n, chunk) in data.as_bytes().chunks(4).enumerate() {
+        let mut acc = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let v = value(c).ok_or_else(|| {
+                format!("invalid character {:?} at offset {}", c as char, n * 4 + i)
+            })?;
+            acc |= v << (18 - 6 * i);
+        }
+        let bytes = acc.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc4648_vectors() {
+        let cases = [
+            ("", ""),
+            ("Zg==", "f"),
+            ("Zm8=", "fo"),
+            ("Zm9v", "foo"),
+            ("Zm9vYg==", "foob"),
+            ("Zm9vYmE=", "fooba"),
+            ("Zm9vYmFy", "foobar"),
+        ];
+        for (encoded, decoded) in cases {
+            assert_eq!(decode(encoded).unwrap(), decoded.as_bytes(), "{encoded}");
+        }
+    }
+
+    #[test]
+    fn test_padding_is_optional() {
+        assert_eq!(decode("Zm8").unwrap(), b"fo");
+    }
+
+    #[test]
+    fn test_binary_content() {
+        assert_eq!(decode("AP8A/w==").unwrap(), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_input() {
+        assert_eq!(
+            decode("Zm9v!A").unwrap_err(),
+            "invalid character '!' at offset 4"
+        );
+        assert!(decode("Zm9vY").is_err());
+        assert!(decode("Zg===").is_err());
+        assert!(decode("Zm 9v").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! One size limit shared by the parts of a message. When the code, the
+//! surrounding context and the question together exceed it, each is cut
+//! back in proportion to its priority; a part smaller than its share is kept
+//! whole and leaves the rest of its share to the others.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Highest priority accepted, so shares cannot overflow
+pub const MAX_PRIORITY: u32 = 100;
+
+/// A part of the message the budget is shared between
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Section {
+    Code,
+    ContextBefore,
+    ContextAfter,
+    Question,
+}
+
+impl Section {
+    pub const ALL: [Section; 4] = [
+        Section::Code,
+        Section::ContextBefore,
+        Section::ContextAfter,
+        Section::Question,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetSettings {
+    /// Bytes the sections of one message may take together; 0 for no limit
+    pub max_bytes: usize,
+    /// Share of the budget each section gets relative to the others.
+    /// Sections left out get priority 1.
+    pub priorities: BTreeMap<Section, u32>,
+}
+
+impl Default for BudgetSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: 0,
+            priorities: BTreeMap::from([
+                (Section::Code, 4),
+                (Section::ContextBefore, 1),
+                (Section::ContextAfter, 1),
+                (Section::Question, 2),
+            ]),
+        }
+    }
+}
+
+impl BudgetSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .priorities
+            .values()
+            .any(|&p| p == 0 || p > MAX_PRIORITY)
+        {
+            return Err(format!(
+                "budget.priorities must be between 1 and {MAX_PRIORITY}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn priority(&self, section: Section) -> usize {
+        self.priorities.get(&section).copied().unwrap_or(1) as usize
+    }
+
+    /// Bytes each of `sizes` may keep, in the same order
+    pub fn allocate(&self, sizes: &[(Section, usize)]) -> Vec<usize> {
+        let total: usize = sizes.iter().map(|&(_, size)| size).sum();
+        if self.max_bytes == 0 || total <= self.max_bytes {
+            return sizes.iter().map(|&(_, size)| size).collect();
+        }
+        let mut kept = vec![0; sizes.len()];
+        let mut left = self.max_bytes;
+        let mut open: Vec<usize> = (0..sizes.len()).filter(|&i| sizes[i].1 > 0).collect();
+        while !open.is_empty() {
+            let weights: usize = open.iter().map(|&i| self.priority(sizes[i].0)).sum();
+            let share = |i: usize| left * self.priority(sizes[i].0) / weights;
+            let (fitting, over): (Vec<usize>, Vec<usize>) =
+                open.iter().partition(|&&i| sizes[i].1 <= share(i));
+            if fitting.is_empty() {
+                for &i in &over {
+                    kept[i] = share(i);
+                }
+                break;
+            }
+            for &i in &fitting {
+                kept[i] = sizes[i].1;
+            }
+            left -= fitting.iter().map(|&i| sizes[i].1).sum::<usize>();
+            open = over;
+        }
+        kept
+    }
+}
+
+/// A section that did not fit whole
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Trimmed {
+    pub section: Section,
+    /// Size before trimming, in UTF-8 bytes
+    pub bytes: usize,
+    /// Size of what was kept
+    pub kept_bytes: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(max_bytes: usize) -> BudgetSettings {
+        BudgetSettings {
+            max_bytes,
+            ..BudgetSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_within_budget_keeps_everything() {
+        let sizes = [(Section::Code, 500), (Section::Question, 100)];
+        assert_eq!(settings(0).allocate(&sizes), [500, 100]);
+        assert_eq!(settings(600).allocate(&sizes), [500, 100]);
+    }
+
+    #[test]
+    fn test_shares_follow_priorities() {
+        let sizes = [
+            (Section::Code, 5000),
+            (Section::ContextBefore, 5000),
+            (Section::ContextAfter, 5000),
+            (Section::Question, 5000),
+        ];
+        assert_eq!(settings(800).allocate(&sizes), [400, 100, 100, 200]);
+    }
+
+    #[test]
+    fn test_small_sections_leave_their_share_to_others() {
+        let sizes = [
+            (Section::Code, 5000),
+            (Section::ContextBefore, 0),
+            (Section::ContextAfter, 50),
+            (Section::Question, 30),
+        ];
+        let kept = settings(1000).allocate(&sizes);
+        assert_eq!(kept, [920, 0, 50, 30]);
+        assert!(kept.iter().sum::<usize>() <= 1000);
+    }
+
+    #[test]
+    fn test_validate_priorities() {
+        assert!(BudgetSettings::default().validate().is_ok());
+        let mut zero = BudgetSettings::default();
+        zero.priorities.insert(Section::Code, 0);
+        assert!(zero.validate().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Rules that look at the question of a send and pick a named template and
+//! tags for it, so a workflow can live in the config rather than in the
+//! extension: questions mentioning "test" can get the `write-test` template
+//! and the `testing` tag. What the send sets itself wins over the rules.
+
+use crate::glob;
+use crate::protocol::SendRequest;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rule {
+    /// Words or phrases, any of which in the question (ignoring case) makes
+    /// the rule match
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Glob matched against the whole question, ignoring case, such as
+    /// `why*fail*`; the rule also matches when this does
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Named template from `templates`, used unless the send picks one or an
+    /// earlier rule did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Added to the send's tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Rule {
+    fn matches(&self, question: &str) -> bool {
+        let question = question.to_lowercase();
+        self.keywords
+            .iter()
+            .any(|keyword| question.contains(&keyword.to_lowercase()))
+            || self
+                .pattern
+                .as_ref()
+                .is_some_and(|pattern| glob::matches(&pattern.to_lowercase(), question.trim()))
+    }
+}
+
+pub fn validate(rules: &[Rule], templates: &BTreeMap<String, String>) -> Result<(), String> {
+    for (i, rule) in rules.iter().enumerate() {
+        let what = format!("classifiers[{i}]");
+        if rule.keywords.iter().any(|k| k.trim().is_empty()) {
+            return Err(format!("{what}: keywords must not be empty"));
+        }
+        if rule.keywords.is_empty() && rule.pattern.is_none() {
+            return Err(format!("{what} needs keywords or a pattern"));
+        }
+        if rule.template.is_none() && rule.tags.is_empty() {
+            return Err(format!("{what} needs a template or tags to apply"));
+        }
+        if let Some(name) = &rule.template {
+            if !templates.contains_key(name) {
+                return Err(format!("{what}: no template named {name} in templates"));
+            }
+        }
+        if rule.tags.iter().any(|t| t.trim().is_empty()) {
+            return Err(format!("{what}: tags must not be empty"));
+        }
+    }
+    Ok(())
+}
+
+/// `req` with what the matching `rules` apply, or `None` when they change
+/// nothing
+pub fn apply(rules: &[Rule], req: &SendRequest) -> Option<SendRequest> {
+    let mut classified = req.clone();
+    for rule in rules.iter().filter(|rule| rule.matches(&req.question)) {
+        if classified.template.is_none() {
+            classified.template = rule.template.clone();
+        }
+        for tag in &rule.tags {
+            if !classified.tags.contains(tag) {
+                classified.tags.push(tag.clone());
+            }
+        }
+    }
+    let changed = classified.template != req.template || classified.tags != req.tags;
+    changed.then_some(classified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<Rule> {
+        serde_json::from_str(
+            r#"[
+                {"keywords": ["test"], "template": "write-test", "tags": ["testing"]},
+                {"pattern": "why*fail*", "template": "debug", "tags": ["debugging"]},
+                {"keywords": ["flaky"], "tags": ["testing", "ci"]}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn request(question: &str) -> SendRequest {
+        serde_json::from_value(serde_json::json!({"file": "a.rs", "question": question})).unwrap()
+    }
+
+    #[test]
+    fn test_first_template_and_every_tag() {
+        let req = apply(&rules(), &request("Why does this flaky Test fail?")).unwrap();
+        assert_eq!(req.template.as_deref(), Some("write-test"));
+        assert_eq!(req.tags, ["testing", "debugging", "ci"]);
+    }
+
+    #[test]
+    fn test_send_choices_win() {
+        let mut req = request("Add a test for this");
+        req.template = Some("review".to_string());
+        req.tags = vec!["testing".to_string()];
+        assert!(apply(&rules(), &req).is_none());
+        assert!(apply(&rules(), &request("What does this do?")).is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let templates = BTreeMap::from([
+            ("write-test".to_string(), "{code}".to_string()),
+            ("debug".to_string(), "{code}".to_string()),
+        ]);
+        assert!(validate(&rules(), &templates).is_ok());
+        assert!(validate(&rules(), &BTreeMap::new()).is_err());
+        let unmatched = Rule {
+            tags: vec!["x".to_string()],
+            ..Rule::default()
+        };
+        assert!(validate(&[unmatched], &templates).is_err());
+        let no_effect = Rule {
+            keywords: vec!["x".to_string()],
+            ..Rule::default()
+        };
+        assert!(validate(&[no_effect], &templates).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Copy a message to the system clipboard, for when it cannot be typed into
+//! tmux and the user has to paste it by hand.
+
+use crate::exec::Exec;
+
+/// Clipboard tools for `os`, tried in order until one works
+fn tools(os: &str) -> &'static [(&'static str, &'static [&'static str])] {
+    match os {
+        "macos" => &[("pbcopy", &[])],
+        "windows" => &[("clip.exe", &[])],
+        _ => &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ],
+    }
+}
+
+/// Put `text` on the clipboard. Returns the tool that took it. The tools'
+/// output is not read: `wl-copy` and `xclip` stay behind to own the clipboard
+/// with whatever they were given, which would hold a pipe open until another
+/// program takes the clipboard.
+pub fn copy(exec: &dyn Exec, os: &str, text: &str) -> Result<&'static str, String> {
+    let mut errors = Vec::new();
+    for &(program, args) in tools(os) {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        match exec.run_discarding_output(program, &args, Some(text.as_bytes())) {
+            Ok(output) if output.success => return Ok(program),
+            Ok(output) if output.stderr.trim().is_empty() => {
+                errors.push(format!("{program} failed"))
+            }
+            Ok(output) => errors.push(format!("{program}: {}", output.stderr.trim())),
+            Err(e) => errors.push(format!("{program}: {e}")),
+        }
+    }
+    Err(format!("No clipboard tool worked ({})", errors.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+
+    #[test]
+    fn test_copy_feeds_text_to_first_tool() {
+        let fake = Fake::new();
+        assert_eq!(copy(&*fake, "macos", "hello").unwrap(), "pbcopy");
+        assert_eq!(fake.calls(), [vec!["pbcopy"]]);
+        assert_eq!(fake.inputs(), [Some(b"hello".to_vec())]);
+        assert_eq!(fake.captured(), [false]);
+    }
+
+    #[test]
+    fn test_copy_tries_next_tool() {
+        let fake = Fake::new();
+        fake.missing();
+        fake.fail("Can't open display");
+        assert_eq!(copy(&*fake, "linux", "hello").unwrap(), "xsel");
+        assert_eq!(fake.calls().len(), 3);
+    }
+
+    #[test]
+    fn test_copy_reports_every_failure() {
+        let fake = Fake::new();
+        fake.missing();
+        let err = copy(&*fake, "windows", "hello").unwrap_err();
+        assert!(err.contains("clip.exe"), "{err}");
+    }
+}
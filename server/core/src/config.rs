@@ -0,0 +1,1085 @@
+use crate::audit;
+use crate::budget;
+use crate::classify;
+use crate::context;
+use crate::debug;
+use crate::emacs;
+use crate::format;
+use crate::fuzzy;
+use crate::git;
+use crate::glob;
+use crate::history;
+use crate::lanes;
+use crate::lock::FileLock;
+use crate::notes;
+use crate::notify;
+use crate::paths;
+use crate::pipe;
+use crate::probes;
+use crate::protocol;
+use crate::reply;
+use crate::retention;
+use crate::strings;
+use crate::template;
+use crate::tmux;
+use crate::update;
+use crate::vscode;
+use crate::whitespace;
+use crate::wrap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Upper bound accepted for `max_code_bytes`
+const MAX_CODE_BYTES_LIMIT: usize = 1024 * 1024;
+
+/// Largest message Chrome sends to a native host, and the upper bound for
+/// `max_message_bytes`
+pub const MAX_MESSAGE_BYTES_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Where formatted messages are delivered
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    Tmux,
+    /// Hold messages for agents that pull them through `pigeon-host --mcp`
+    Queue,
+    /// Append messages to the markdown file in `notes.path`
+    Notes,
+    /// Write messages to `pipe.path` or the stdin of `pipe.command`
+    Pipe,
+    /// Open the file and show the message in Emacs via emacsclient
+    Emacs,
+    /// Open the file in VS Code and hand the message over via `vscode.handoff`
+    Vscode,
+}
+
+/// Where a message goes when tmux itself is unavailable
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fallback {
+    /// Copy the message to the system clipboard to paste by hand
+    Clipboard,
+    /// Append the message to the markdown file in `notes.path`
+    Notes,
+    /// Hold the message for agents that pull it over MCP
+    Queue,
+}
+
+/// Server settings stored in `config.json` under `paths::config_dir()`.
+/// Every field is optional in the file; missing ones take their defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Target used when a request leaves `tmux_target` empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_target: Option<String>,
+    /// Glob patterns of tmux targets the host may send to; empty allows any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_targets: Vec<String>,
+    /// Extensions (IDs or `chrome-extension://` origins) the host answers;
+    /// empty allows any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_origins: Vec<String>,
+    /// Actions the host refuses, for a restricted mode on shared machines
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub disabled_actions: Vec<String>,
+    /// Allow `kill-session` and `respawn-agent`, which end programs running
+    /// in tmux
+    pub lifecycle_actions: bool,
+    /// Sends that may wait behind the one being delivered to a target
+    /// before more are refused with `TARGET_BUSY`; 0 for no limit
+    pub max_queued_per_target: usize,
+    /// Code longer than this (in UTF-8 bytes) is truncated at a line end
+    pub max_code_bytes: usize,
+    /// Size limit shared by code, context and question, trimming each by
+    /// priority when they do not fit together
+    pub budget: budget::BudgetSettings,
+    /// Incoming messages longer than this are skipped with an error
+    pub max_message_bytes: usize,
+    pub backend: Backend,
+    /// Tried in order when the tmux backend cannot reach tmux at all
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<Fallback>,
+    /// Lines of surrounding context to include from the local checkout
+    pub expand_context: u32,
+    /// Mention in the message when the local checkout differs from the selection
+    pub note_stale: bool,
+    /// Message template; see `format::TEMPLATE_VARS` for placeholders
+    pub template: String,
+    /// Language of fixed phrases such as "deleted lines" (e.g. `ja`)
+    pub locale: String,
+    /// End messages with a "Source: <url>" line linking back to the page
+    pub source_footer: bool,
+    /// Environment facts to stamp after the message, such as the branch of
+    /// the mapped checkout
+    pub stamp: probes::StampSettings,
+    /// Prefix code lines with their source line numbers
+    pub number_lines: bool,
+    /// Tab expansion, trailing whitespace and indentation clean-up for code
+    pub whitespace: whitespace::WhitespaceSettings,
+    /// Soft-wrap code lines to the width of the target pane
+    pub wrap: wrap::WrapSettings,
+    /// Templates for specific targets, keyed by target name or glob pattern
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_templates: BTreeMap<String, String>,
+    /// Named templates a send can pick with `template`, overriding both of
+    /// the above
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, String>,
+    /// Rules that pick a named template and tags from the question
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classifiers: Vec<classify::Rule>,
+    /// Keys pressed after pasting, keyed by target name or glob pattern;
+    /// other targets get a single Enter
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub submit_keys: BTreeMap<String, Vec<tmux::SubmitKey>>,
+    /// Keys that empty the input line of a target, for `abort-send` and
+    /// `undo-last`, matched like `submit_keys`; other targets get C-u
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub clear_keys: BTreeMap<String, Vec<tmux::SubmitKey>>,
+    /// Local checkouts keyed by "owner/name" (or just "name"), so messages
+    /// can reference absolute paths the agent can open
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub repos: BTreeMap<String, String>,
+    /// Check out the PR's commit in the mapped clone before sending
+    pub checkout_before_send: bool,
+    pub checkout_mode: git::CheckoutMode,
+    /// Paste multi-line messages with `paste-buffer -p`, so programs that
+    /// enable bracketed paste receive them as one input
+    pub bracketed_paste: bool,
+    /// Flash a tmux status-line message in the target after pasting
+    pub display_message: bool,
+    /// Shorten consecutive sends about the same selection to the same
+    /// target, even without a `conversation_id`
+    pub thread_follow_ups: bool,
+    /// Wrap tmux sends in markers so `get-reply` can find the answer
+    pub reply_markers: bool,
+    /// Clean-up of the answers `get-reply` returns
+    pub reply: reply::ReplySettings,
+    /// Ask for y/n in a tmux popup over the target before pasting
+    pub confirm_before_send: bool,
+    /// Desktop notifications per event type
+    pub notifications: notify::Notifications,
+    /// Append-only record of every send in the data directory
+    pub audit: audit::AuditSettings,
+    /// Recent questions, kept for autocomplete in the extension
+    pub history: history::HistorySettings,
+    /// Directory `export-transcript` writes into; `transcripts/` in the data
+    /// directory when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcripts_dir: Option<String>,
+    /// Age and size limits of the history, audit log, debug reports and
+    /// attachments, enforced on startup and periodically by the daemon
+    pub retention: retention::RetentionSettings,
+    /// Markdown inbox used by the `notes` backend (or mirrored to)
+    pub notes: notes::NotesSettings,
+    /// Named pipe or command used by the `pipe` backend
+    pub pipe: pipe::PipeSettings,
+    /// emacsclient and buffers used by the `emacs` backend
+    pub emacs: emacs::EmacsSettings,
+    /// Launcher and handoff used by the `vscode` backend
+    pub vscode: vscode::VscodeSettings,
+    /// Size and rate limits for debug reports in the cache directory
+    pub debug: debug::DebugSettings,
+    /// Resolve a target whose session no longer exists to the closest name
+    pub session_matching: fuzzy::MatchSettings,
+    /// Without a target or `default_target`, send to the pane working in
+    /// the checkout of the request's `repo`
+    pub infer_target: bool,
+    /// Relay messages to a `pigeon-host --daemon` instead of handling them in
+    /// the process Chrome starts (Unix only)
+    pub daemon: bool,
+    /// Where `check-update` looks for releases, and whether it may install
+    /// them
+    pub update: update::UpdateSettings,
+    /// Named sets of settings laid over the rest of the config for requests
+    /// that select them with `profile`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, serde_json::Map<String, serde_json::Value>>,
+    /// Explicit tmux binary; `PIGEON_TMUX` takes precedence over this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmux_path: Option<String>,
+    /// Windows only: WSL distribution running tmux (default distribution if unset)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wsl_distro: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_target: None,
+            allowed_targets: Vec::new(),
+            allowed_origins: Vec::new(),
+            disabled_actions: Vec::new(),
+            lifecycle_actions: false,
+            max_queued_per_target: lanes::DEFAULT_MAX_QUEUED,
+            max_code_bytes: format::DEFAULT_MAX_CODE_BYTES,
+            budget: budget::BudgetSettings::default(),
+            max_message_bytes: MAX_MESSAGE_BYTES_LIMIT,
+            backend: Backend::default(),
+            fallbacks: Vec::new(),
+            expand_context: 0,
+            note_stale: false,
+            template: format::DEFAULT_TEMPLATE.to_string(),
+            locale: strings::DEFAULT_LOCALE.to_string(),
+            source_footer: false,
+            stamp: probes::StampSettings::default(),
+            number_lines: false,
+            whitespace: whitespace::WhitespaceSettings::default(),
+            wrap: wrap::WrapSettings::default(),
+            target_templates: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            classifiers: Vec::new(),
+            submit_keys: BTreeMap::new(),
+            clear_keys: BTreeMap::new(),
+            repos: BTreeMap::new(),
+            checkout_before_send: false,
+            checkout_mode: git::CheckoutMode::default(),
+            bracketed_paste: false,
+            display_message: false,
+            confirm_before_send: false,
+            reply_markers: false,
+            reply: reply::ReplySettings::default(),
+            thread_follow_ups: false,
+            notifications: notify::Notifications::default(),
+            audit: audit::AuditSettings::default(),
+            history: history::HistorySettings::default(),
+            transcripts_dir: None,
+            retention: retention::RetentionSettings::default(),
+            notes: notes::NotesSettings::default(),
+            pipe: pipe::PipeSettings::default(),
+            emacs: emacs::EmacsSettings::default(),
+            vscode: vscode::VscodeSettings::default(),
+            debug: debug::DebugSettings::default(),
+            session_matching: fuzzy::MatchSettings::default(),
+            infer_target: true,
+            daemon: false,
+            update: update::UpdateSettings::default(),
+            profiles: BTreeMap::new(),
+            tmux_path: None,
+            wsl_distro: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(target) = &self.default_target {
+            if target.trim().is_empty() {
+                return Err("default_target must not be empty".to_string());
+            }
+        }
+        if self.allowed_targets.iter().any(|p| p.trim().is_empty()) {
+            return Err("allowed_targets patterns must not be empty".to_string());
+        }
+        if self
+            .allowed_origins
+            .iter()
+            .any(|o| o.is_empty() || o.contains(char::is_whitespace))
+        {
+            return Err("allowed_origins entries must be non-empty and without spaces".to_string());
+        }
+        for action in &self.disabled_actions {
+            if !protocol::ACTIONS.contains(&action.as_str()) {
+                return Err(format!("disabled_actions: unknown action {action}"));
+            }
+            // The extension needs it to find out the host is there at all
+            if action == "handshake" {
+                return Err("disabled_actions: handshake cannot be disabled".to_string());
+            }
+        }
+        if self.expand_context > context::MAX_EXPAND_LINES {
+            return Err(format!(
+                "expand_context must be at most {}",
+                context::MAX_EXPAND_LINES
+            ));
+        }
+        for (repo, dir) in &self.repos {
+            if repo.trim().is_empty() || dir.trim().is_empty() {
+                return Err("repos entries must have a non-empty name and directory".to_string());
+            }
+        }
+        if let Some(path) = &self.tmux_path {
+            if path.trim().is_empty() {
+                return Err("tmux_path must not be empty".to_string());
+            }
+        }
+        if let Some(dir) = &self.transcripts_dir {
+            if !Path::new(dir).is_absolute() {
+                return Err("transcripts_dir must be an absolute path".to_string());
+            }
+        }
+        if let Some(distro) = &self.wsl_distro {
+            if distro.trim().is_empty() {
+                return Err("wsl_distro must not be empty".to_string());
+            }
+        }
+        if self.history.max_entries == 0 {
+            return Err("history.max_entries must be greater than 0".to_string());
+        }
+        if self.debug.max_total_bytes == 0 {
+            return Err("debug.max_total_bytes must be greater than 0".to_string());
+        }
+        if self.audit.max_bytes == 0 {
+            return Err("audit.max_bytes must be greater than 0".to_string());
+        }
+        self.retention.validate()?;
+        strings::validate_locale(&self.locale)?;
+        self.notes.validate()?;
+        if self.backend == Backend::Notes && self.notes.path.is_none() {
+            return Err("The notes backend requires notes.path".to_string());
+        }
+        self.pipe.validate()?;
+        self.emacs.validate()?;
+        self.vscode.validate()?;
+        self.budget.validate()?;
+        self.reply.validate()?;
+        self.whitespace.validate()?;
+        self.wrap.validate()?;
+        self.update.validate()?;
+        for (name, overlay) in &self.profiles {
+            if name.trim().is_empty() {
+                return Err("profiles names must not be empty".to_string());
+            }
+            if overlay.contains_key("profiles") {
+                return Err(format!("Profile {name} cannot define profiles"));
+            }
+            self.profile(name)?;
+        }
+        if self.backend == Backend::Pipe && !self.pipe.is_configured() {
+            return Err("The pipe backend requires pipe.path or pipe.command".to_string());
+        }
+        if self.fallbacks.contains(&Fallback::Notes) && self.notes.path.is_none() {
+            return Err("The notes fallback requires notes.path".to_string());
+        }
+        if self.max_code_bytes == 0 || self.max_code_bytes > MAX_CODE_BYTES_LIMIT {
+            return Err(format!(
+                "max_code_bytes must be between 1 and {MAX_CODE_BYTES_LIMIT}"
+            ));
+        }
+        if !(1024..=MAX_MESSAGE_BYTES_LIMIT).contains(&self.max_message_bytes) {
+            return Err(format!(
+                "max_message_bytes must be between 1024 and {MAX_MESSAGE_BYTES_LIMIT}"
+            ));
+        }
+        for (pattern, template) in &self.target_templates {
+            if pattern.trim().is_empty() {
+                return Err("target_templates keys must not be empty".to_string());
+            }
+            template::validate(template, format::TEMPLATE_VARS)
+                .map_err(|e| format!("Invalid template for {pattern}: {e}"))?;
+        }
+        for (name, template) in &self.templates {
+            if name.trim().is_empty() {
+                return Err("templates names must not be empty".to_string());
+            }
+            template::validate(template, format::TEMPLATE_VARS)
+                .map_err(|e| format!("Invalid template {name}: {e}"))?;
+        }
+        classify::validate(&self.classifiers, &self.templates)?;
+        validate_keys("submit_keys", &self.submit_keys)?;
+        validate_keys("clear_keys", &self.clear_keys)?;
+        template::validate(&self.template, format::TEMPLATE_VARS)
+            .map_err(|e| format!("Invalid template: {e}"))
+    }
+
+    pub fn action_enabled(&self, action: &str) -> bool {
+        !self.disabled_actions.iter().any(|a| a == action)
+    }
+
+    /// Whether `allowed_origins` permits the extension Chrome started the
+    /// host for. `None` is a host started some other way, which only an
+    /// empty list allows.
+    pub fn origin_allowed(&self, origin: Option<&str>) -> bool {
+        if self.allowed_origins.is_empty() {
+            return true;
+        }
+        origin.is_some_and(|origin| {
+            self.allowed_origins.iter().any(|allowed| {
+                origin == allowed || origin == format!("chrome-extension://{allowed}/")
+            })
+        })
+    }
+
+    /// Whether `allowed_targets` permits typing into `target`
+    pub fn target_allowed(&self, target: &str) -> bool {
+        self.allowed_targets.is_empty()
+            || self
+                .allowed_targets
+                .iter()
+                .any(|p| glob::matches(p, target))
+    }
+
+    /// Options for a message to `target`. A `name`d entry from `templates`
+    /// wins, then an exact `target_templates` entry, then the longest
+    /// matching pattern, then `template`.
+    pub fn format_options(
+        &self,
+        target: Option<&str>,
+        name: Option<&str>,
+    ) -> format::FormatOptions<'_> {
+        let template = name
+            .and_then(|name| self.templates.get(name))
+            .or_else(|| target.and_then(|target| for_target(&self.target_templates, target)));
+        format::FormatOptions {
+            max_code_bytes: self.max_code_bytes,
+            template: template.unwrap_or(&self.template),
+            locale: &self.locale,
+            source_footer: self.source_footer,
+            number_lines: self.number_lines,
+            whitespace: self.whitespace.clone(),
+            wrap_width: None,
+            wrap_marker: &self.wrap.marker,
+            budget: self.budget.clone(),
+        }
+    }
+
+    /// Keys that submit a paste into `target`, matched like `target_templates`
+    pub fn submit_keys(&self, target: &str) -> Vec<tmux::SubmitKey> {
+        for_target(&self.submit_keys, target)
+            .cloned()
+            .unwrap_or_else(tmux::SubmitKey::default_sequence)
+    }
+
+    /// Keys that empty the input line of `target`, matched like `submit_keys`
+    pub fn clear_keys(&self, target: &str) -> Vec<tmux::SubmitKey> {
+        for_target(&self.clear_keys, target)
+            .cloned()
+            .unwrap_or_else(tmux::SubmitKey::clear_sequence)
+    }
+
+    /// This config with the settings of profile `name` laid over it. The
+    /// result has no profiles of its own.
+    pub fn profile(&self, name: &str) -> Result<Config, String> {
+        let overlay = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| format!("No profile named {name} in config"))?;
+        let base = Config {
+            profiles: BTreeMap::new(),
+            ..self.clone()
+        };
+        base.merged(&serde_json::Value::Object(overlay.clone()))
+            .map_err(|e| format!("Profile {name}: {e}"))
+    }
+
+    /// Apply a partial update. Keys set to `null` revert to their defaults.
+    pub fn merged(&self, changes: &serde_json::Value) -> Result<Config, String> {
+        let changes = changes
+            .as_object()
+            .ok_or_else(|| "config changes must be a JSON object".to_string())?;
+        let mut current = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let fields = current
+            .as_object_mut()
+            .expect("Config serializes to an object");
+        for (key, value) in changes {
+            if value.is_null() {
+                fields.remove(key);
+            } else {
+                fields.insert(key.clone(), value.clone());
+            }
+        }
+        let config: Config =
+            serde_json::from_value(current).map_err(|e| format!("Invalid config: {e}"))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Rejects `submit_keys`-style entries without a target or keys, or with bad keys
+fn validate_keys(
+    name: &str,
+    entries: &BTreeMap<String, Vec<tmux::SubmitKey>>,
+) -> Result<(), String> {
+    for (pattern, keys) in entries {
+        if pattern.trim().is_empty() || keys.is_empty() {
+            return Err(format!(
+                "{name} entries must have a target and at least one key"
+            ));
+        }
+        for (key, delay_ms) in keys.iter().map(tmux::SubmitKey::parts) {
+            if key.trim().is_empty() {
+                return Err(format!("{name} for {pattern}: key names must not be empty"));
+            }
+            if delay_ms > tmux::MAX_SUBMIT_DELAY_MS {
+                return Err(format!(
+                    "{name} for {pattern}: delay_ms must be at most {}",
+                    tmux::MAX_SUBMIT_DELAY_MS
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The entry for `target` in a map keyed by target name or glob pattern: an
+/// exact key wins, otherwise the longest matching pattern
+fn for_target<'a, T>(entries: &'a BTreeMap<String, T>, target: &str) -> Option<&'a T> {
+    entries.get(target).or_else(|| {
+        entries
+            .iter()
+            .filter(|(pattern, _)| glob::matches(pattern, target))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, entry)| entry)
+    })
+}
+
+pub struct ConfigFile {
+    path: PathBuf,
+}
+
+impl ConfigFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Default location: `config.json` in the platform config directory
+    pub fn default_location() -> Option<Self> {
+        Some(Self::new(paths::config_dir()?.join("config.json")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Exclusive lock for read-modify-write updates shared with other hosts
+    pub fn lock(&self) -> Result<FileLock, String> {
+        FileLock::acquire(&self.path.with_extension("json.lock"))
+            .map_err(|e| format!("Failed to lock {}: {e}", self.path.display()))
+    }
+
+    /// Read the config; a missing file yields the defaults
+    pub fn load(&self) -> Result<Config, String> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Failed to read {}: {e}", self.path.display())),
+        };
+        let config: Config = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid config in {}: {e}", self.path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Modification time, or `None` if the file does not exist
+    pub fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Write via a temporary file and rename so readers never see a partial file
+    pub fn save(&self, config: &Config) -> Result<(), String> {
+        let write = || -> io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let json = serde_json::to_string_pretty(config)?;
+            let tmp = self
+                .path
+                .with_extension(format!("json.tmp.{}", std::process::id()));
+            fs::write(&tmp, json + "\n")?;
+            fs::rename(&tmp, &self.path)
+        };
+        write().map_err(|e| format!("Failed to write {}: {e}", self.path.display()))
+    }
+}
+
+/// The active config plus the file it came from. The file is re-read
+/// whenever its modification time changes, so edits take effect without
+/// restarting the host. Each applied change bumps `generation`.
+pub struct LiveConfig {
+    file: Option<ConfigFile>,
+    config: Config,
+    modified: Option<SystemTime>,
+    generation: u64,
+}
+
+/// Keys `set-config` may not change: they limit what the extension can do,
+/// name programs the host runs or files it writes, keep the audit log, or
+/// ask before a send is typed, so only the file sets them
+pub const PROTECTED_KEYS: &[&str] = &[
+    "allowed_targets",
+    "allowed_origins",
+    "disabled_actions",
+    "lifecycle_actions",
+    "update",
+    "tmux_path",
+    "pipe",
+    "emacs",
+    "vscode",
+    "backend",
+    "profiles",
+    "max_message_bytes",
+    "transcripts_dir",
+    "notes",
+    "fallbacks",
+    "audit",
+    "retention",
+    "confirm_before_send",
+];
+
+/// Why `set-config` did not apply a change
+#[derive(Debug, PartialEq, Eq)]
+pub struct SetError {
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+impl From<String> for SetError {
+    fn from(message: String) -> Self {
+        Self {
+            code: None,
+            message,
+        }
+    }
+}
+
+/// The first of `PROTECTED_KEYS` whose value differs between `before` and
+/// `after`
+fn protected_change(before: &Config, after: &Config) -> Option<&'static str> {
+    let before = serde_json::to_value(before).ok()?;
+    let after = serde_json::to_value(after).ok()?;
+    PROTECTED_KEYS
+        .iter()
+        .copied()
+        .find(|key| before.get(key) != after.get(key))
+}
+
+impl LiveConfig {
+    pub fn new(file: Option<ConfigFile>) -> Self {
+        let mut live = Self {
+            file,
+            config: Config::default(),
+            modified: None,
+            generation: 0,
+        };
+        live.reload();
+        live
+    }
+
+    pub fn get(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|f| f.path())
+    }
+
+    /// Re-read the file if it changed since the last load or save.
+    /// Cheap enough (one stat) to call before every request.
+    pub fn reload_if_changed(&mut self) {
+        let modified = self.file.as_ref().and_then(|f| f.modified());
+        if modified != self.modified {
+            self.reload();
+        }
+    }
+
+    /// A broken config file should not take the host down; keep the current
+    /// config and let get-config/set-config surface and repair it.
+    fn reload(&mut self) {
+        let Some(file) = &self.file else { return };
+        self.modified = file.modified();
+        match file.load() {
+            Ok(config) => self.apply(config),
+            Err(e) => eprintln!("pigeon: {e}; keeping current config"),
+        }
+    }
+
+    /// Validate and persist a partial update. The change is applied to the
+    /// file's current contents, so updates made by another host in the
+    /// meantime are kept; a broken file is replaced using the active config.
+    /// Changes to `PROTECTED_KEYS` are refused.
+    pub fn set(&mut self, changes: &serde_json::Value) -> Result<(), SetError> {
+        let file = self
+            .file
+            .as_ref()
+            .ok_or_else(|| "Cannot locate config directory".to_string())?;
+        let _lock = file.lock()?;
+        let current = file.load().unwrap_or_else(|_| self.config.clone());
+        let config = current.merged(changes)?;
+        if let Some(key) = protected_change(&current, &config) {
+            return Err(SetError {
+                code: Some("CONFIG_KEY_PROTECTED"),
+                message: format!(
+                    "{key} can only be changed by editing {}",
+                    file.path().display()
+                ),
+            });
+        }
+        file.save(&config)?;
+        self.modified = file.modified();
+        self.apply(config);
+        Ok(())
+    }
+
+    fn apply(&mut self, config: Config) {
+        if config != self.config || self.generation == 0 {
+            self.config = config;
+            self.generation += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_file(name: &str) -> ConfigFile {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-config-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ConfigFile::new(dir.join("config.json"))
+    }
+
+    #[test]
+    fn test_missing_file_yields_defaults() {
+        let file = temp_file("missing");
+        assert_eq!(file.load().unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_partial_file_fills_defaults() {
+        let file = temp_file("partial");
+        fs::create_dir_all(file.path().parent().unwrap()).unwrap();
+        fs::write(file.path(), r#"{"default_target": "dev"}"#).unwrap();
+        let config = file.load().unwrap();
+        assert_eq!(config.default_target.as_deref(), Some("dev"));
+        assert_eq!(config.max_code_bytes, format::DEFAULT_MAX_CODE_BYTES);
+        fs::remove_dir_all(file.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_save_then_load_roundtrip() {
+        let file = temp_file("roundtrip");
+        let config = Config {
+            default_target: Some("work:1".to_string()),
+            max_code_bytes: 500,
+            ..Config::default()
+        };
+        file.save(&config).unwrap();
+        assert_eq!(file.load().unwrap(), config);
+        fs::remove_dir_all(file.path().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_merged_applies_changes_and_resets_nulls() {
+        let config = Config {
+            default_target: Some("old".to_string()),
+            ..Config::default()
+        };
+        let merged = config
+            .merged(&json!({"default_target": null, "max_code_bytes": 10}))
+            .unwrap();
+        assert_eq!(merged.default_target, None);
+        assert_eq!(merged.max_code_bytes, 10);
+    }
+
+    #[test]
+    fn test_merged_rejects_invalid_values() {
+        let config = Config::default();
+        assert!(config.merged(&json!({"max_code_bytes": 0})).is_err());
+        assert!(config
+            .merged(&json!({"backend": "carrier-pigeon"}))
+            .is_err());
+        assert!(config.merged(&json!({"template": "{{oops}}"})).is_err());
+        assert!(config
+            .merged(&json!({"templates": {"short": "{{oops}}"}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"templates": {" ": "{{code}}"}}))
+            .is_err());
+        assert!(config.merged(&json!({"backend": "notes"})).is_err());
+        assert!(config.merged(&json!({"backend": "pipe"})).is_err());
+        assert!(config.merged(&json!({"emacs": {"buffer": ""}})).is_err());
+        assert!(config
+            .merged(&json!({"whitespace": {"tab_width": 64}}))
+            .is_err());
+        assert!(config.merged(&json!({"wrap": {"marker": "\t"}})).is_err());
+        assert!(config
+            .merged(&json!({"budget": {"priorities": {"code": 0}}}))
+            .is_err());
+        assert!(config.merged(&json!({"stamp": {"git": true}})).is_err());
+        assert!(config.merged(&json!({"reply": {"chrome": [" "]}})).is_err());
+        assert!(config
+            .merged(&json!({"transcripts_dir": "reviews"}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"update": {"repo": "pigeon"}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"budget": {"priorities": {"diff": 1}}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"profiles": {"work": {"max_code_bytes": 0}}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"profiles": {"work": {"profiles": {}}}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"vscode": {"handoff": "email"}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"pipe": {"path": "/tmp/agent.fifo", "command": ["cat"]}}))
+            .is_err());
+        assert!(config.merged(&json!({"fallbacks": ["notes"]})).is_err());
+        assert!(config.merged(&json!({"submit_keys": {"dev": []}})).is_err());
+        assert!(config
+            .merged(&json!({"clear_keys": {"dev": [""]}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"submit_keys": {"dev": [{"key": "Enter", "delay_ms": 60000}]}}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"fallbacks": ["carrier-pigeon"]}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"disabled_actions": ["send-keys"]}))
+            .is_err());
+        assert!(config
+            .merged(&json!({"disabled_actions": ["handshake"]}))
+            .is_err());
+        assert!(config.merged(&json!({"unknown_key": 1})).is_err());
+        assert!(config.merged(&json!(["not", "an", "object"])).is_err());
+    }
+
+    #[test]
+    fn test_target_allowed() {
+        assert!(Config::default().target_allowed("anything"));
+        let config = Config {
+            allowed_targets: vec!["agents:*".to_string(), "dev".to_string()],
+            ..Config::default()
+        };
+        assert!(config.target_allowed("agents:claude.1"));
+        assert!(config.target_allowed("dev"));
+        assert!(!config.target_allowed("dev:shell"));
+        assert!(!config.target_allowed("other"));
+    }
+
+    #[test]
+    fn test_origin_allowed() {
+        assert!(Config::default().origin_allowed(None));
+        let config = Config {
+            allowed_origins: vec![
+                "abcdefghijklmnopabcdefghijklmnop".to_string(),
+                "chrome-extension://ponmlkjihgfedcbaponmlkjihgfedcba/".to_string(),
+            ],
+            ..Config::default()
+        };
+        assert!(config.origin_allowed(Some("chrome-extension://abcdefghijklmnopabcdefghijklmnop/")));
+        assert!(config.origin_allowed(Some("chrome-extension://ponmlkjihgfedcbaponmlkjihgfedcba/")));
+        assert!(
+            !config.origin_allowed(Some("chrome-extension://aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa/"))
+        );
+        assert!(!config.origin_allowed(Some("abcdefghijklmnop")));
+        assert!(!config.origin_allowed(None));
+    }
+
+    #[test]
+    fn test_submit_keys_per_target() {
+        let config = Config::default()
+            .merged(
+                &json!({"submit_keys": {"codex:*": ["Enter", {"key": "Enter", "delay_ms": 50}]}}),
+            )
+            .unwrap();
+        assert_eq!(config.submit_keys("codex:1").len(), 2);
+        assert_eq!(
+            config.submit_keys("claude"),
+            tmux::SubmitKey::default_sequence()
+        );
+    }
+
+    #[test]
+    fn test_format_options_picks_target_template() {
+        let config = Config {
+            target_templates: BTreeMap::from([
+                ("*".to_string(), "any".to_string()),
+                ("claude-*".to_string(), "claude".to_string()),
+                ("claude-review".to_string(), "review".to_string()),
+            ]),
+            ..Config::default()
+        };
+        assert_eq!(
+            config.format_options(Some("claude-review"), None).template,
+            "review"
+        );
+        assert_eq!(
+            config.format_options(Some("claude-2"), None).template,
+            "claude"
+        );
+        assert_eq!(config.format_options(Some("codex"), None).template, "any");
+        assert_eq!(
+            config.format_options(None, None).template,
+            format::DEFAULT_TEMPLATE
+        );
+    }
+
+    #[test]
+    fn test_format_options_picks_named_template() {
+        let config = Config {
+            target_templates: BTreeMap::from([("claude-*".to_string(), "claude".to_string())]),
+            templates: BTreeMap::from([("short".to_string(), "{{question}}".to_string())]),
+            ..Config::default()
+        };
+        let options = config.format_options(Some("claude-2"), Some("short"));
+        assert_eq!(options.template, "{{question}}");
+        let options = config.format_options(Some("claude-2"), Some("missing"));
+        assert_eq!(options.template, "claude");
+    }
+
+    #[test]
+    fn test_profile_overrides_settings() {
+        let config: Config = serde_json::from_value(json!({
+            "default_target": "dev",
+            "max_code_bytes": 500,
+            "profiles": {
+                "work": {"default_target": "work:agent", "backend": "queue"},
+                "personal": {"default_target": null}
+            }
+        }))
+        .unwrap();
+        config.validate().unwrap();
+        let work = config.profile("work").unwrap();
+        assert_eq!(work.default_target.as_deref(), Some("work:agent"));
+        assert_eq!(work.backend, Backend::Queue);
+        assert_eq!(work.max_code_bytes, 500);
+        assert!(work.profiles.is_empty());
+        assert_eq!(config.profile("personal").unwrap().default_target, None);
+        let err = config.profile("home").unwrap_err();
+        assert!(err.contains("No profile named home"), "{err}");
+    }
+
+    #[test]
+    fn test_live_config_picks_up_file_changes() {
+        let file = temp_file("live");
+        let path = file.path().to_path_buf();
+        file.save(&Config::default()).unwrap();
+        let mut live = LiveConfig::new(Some(file));
+        let initial = live.generation();
+
+        let edited = ConfigFile::new(path.clone());
+        edited
+            .save(&Config {
+                default_target: Some("edited".to_string()),
+                ..Config::default()
+            })
+            .unwrap();
+        // Make the change visible even on filesystems with coarse mtimes
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        live.reload_if_changed();
+        assert_eq!(live.get().default_target.as_deref(), Some("edited"));
+        assert_eq!(live.generation(), initial + 1);
+
+        live.reload_if_changed();
+        assert_eq!(live.generation(), initial + 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_live_config_keeps_last_good_config_on_error() {
+        let file = temp_file("broken");
+        let path = file.path().to_path_buf();
+        file.save(&Config {
+            max_code_bytes: 42,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut live = LiveConfig::new(Some(file));
+        let generation = live.generation();
+
+        fs::write(&path, "{ not json").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        live.reload_if_changed();
+        assert_eq!(live.get().max_code_bytes, 42);
+        assert_eq!(live.generation(), generation);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_live_config_set_bumps_generation() {
+        let file = temp_file("set");
+        let path = file.path().to_path_buf();
+        let mut live = LiveConfig::new(Some(file));
+        let generation = live.generation();
+        live.set(&json!({"default_target": "dev"})).unwrap();
+        assert_eq!(live.generation(), generation + 1);
+        assert!(live.set(&json!({"max_code_bytes": 0})).is_err());
+        assert_eq!(live.generation(), generation + 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_live_config_set_keeps_changes_from_other_hosts() {
+        let file = temp_file("two-hosts");
+        let path = file.path().to_path_buf();
+        let mut first = LiveConfig::new(Some(file));
+        let mut second = LiveConfig::new(Some(ConfigFile::new(path.clone())));
+        first.set(&json!({"default_target": "dev"})).unwrap();
+        second.set(&json!({"max_code_bytes": 10})).unwrap();
+        let saved = ConfigFile::new(path.clone()).load().unwrap();
+        assert_eq!(saved.default_target.as_deref(), Some("dev"));
+        assert_eq!(saved.max_code_bytes, 10);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_live_config_set_refuses_protected_keys() {
+        let file = temp_file("protected");
+        let path = file.path().to_path_buf();
+        let mut live = LiveConfig::new(Some(file));
+        for change in [
+            json!({"allowed_targets": ["agents:*"]}),
+            json!({"allowed_origins": ["abcdefghijklmnopabcdefghijklmnop"]}),
+            json!({"disabled_actions": ["checkout"]}),
+            json!({"lifecycle_actions": true}),
+            json!({"update": {"allow_install": true}}),
+            json!({"update": {"repo": "someone/else"}}),
+            json!({"tmux_path": "/tmp/evil"}),
+            json!({"pipe": {"command": ["sh", "-c", "true"]}}),
+            json!({"emacs": {"emacsclient": "/tmp/evil"}}),
+            json!({"vscode": {"code": "/tmp/evil"}}),
+            json!({"backend": "queue"}),
+            json!({"profiles": {"work": {"lifecycle_actions": true}}}),
+            json!({"max_message_bytes": 2048}),
+            json!({"transcripts_dir": "/home"}),
+            json!({"notes": {"path": "/home/u/.bashrc", "mirror": true}}),
+            json!({"fallbacks": ["clipboard"]}),
+            json!({"audit": {"enabled": true}}),
+            json!({"retention": {"audit": {"max_age_days": 1}}}),
+            json!({"confirm_before_send": true}),
+        ] {
+            let err = live.set(&change).unwrap_err();
+            assert_eq!(err.code, Some("CONFIG_KEY_PROTECTED"), "{change}");
+        }
+        assert_eq!(live.get(), &Config::default());
+        assert!(!path.exists());
+        // Unchanged values pass, so a config read with get-config can be sent back
+        live.set(&json!({"backend": "tmux", "allowed_targets": null, "default_target": "dev"}))
+            .unwrap();
+        assert_eq!(live.get().default_target.as_deref(), Some("dev"));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
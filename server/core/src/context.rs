@@ -0,0 +1,91 @@
+/// Refuse to expand by more than this many lines on either side
+pub const MAX_EXPAND_LINES: u32 = 200;
+
+/// Consecutive source lines starting at `first_line` (1-based)
+#[derive(Debug, PartialEq, Eq)]
+pub struct Block {
+    pub first_line: u64,
+    pub last_line: u64,
+    pub text: String,
+}
+
+/// Lines just outside the selection, read from the local checkout
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Surrounding {
+    pub before: Option<Block>,
+    pub after: Option<Block>,
+}
+
+/// Up to `n` lines of `content` before `start` and after `end`
+pub fn surrounding(content: &str, start: u64, end: u64, n: u32) -> Surrounding {
+    let n = u64::from(n.min(MAX_EXPAND_LINES));
+    if n == 0 || start == 0 {
+        return Surrounding::default();
+    }
+    let (start, end) = (start.min(end), start.max(end));
+    let lines: Vec<&str> = content.lines().collect();
+    let total = lines.len() as u64;
+
+    let block = |first: u64, last: u64| {
+        (first <= last && first >= 1 && last <= total).then(|| Block {
+            first_line: first,
+            last_line: last,
+            text: lines[(first - 1) as usize..last as usize].join("\n"),
+        })
+    };
+
+    Surrounding {
+        before: block(start.saturating_sub(n).max(1), start - 1),
+        after: block(end + 1, (end + n).min(total)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+
+    #[test]
+    fn test_surrounding_lines() {
+        let s = surrounding(FILE, 3, 4, 2);
+        assert_eq!(
+            s.before,
+            Some(Block {
+                first_line: 1,
+                last_line: 2,
+                text: "one\ntwo".to_string()
+            })
+        );
+        assert_eq!(
+            s.after,
+            Some(Block {
+                first_line: 5,
+                last_line: 6,
+                text: "five\nsix".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_clamped_at_file_edges() {
+        let s = surrounding(FILE, 1, 7, 3);
+        assert_eq!(s, Surrounding::default());
+
+        let s = surrounding(FILE, 2, 6, 5);
+        assert_eq!(s.before.unwrap().text, "one");
+        assert_eq!(s.after.unwrap().text, "seven");
+    }
+
+    #[test]
+    fn test_selection_beyond_file() {
+        let s = surrounding(FILE, 40, 42, 2);
+        assert_eq!(s.before, None);
+        assert_eq!(s.after, None);
+    }
+
+    #[test]
+    fn test_zero_expansion() {
+        assert_eq!(surrounding(FILE, 3, 3, 0), Surrounding::default());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::artifacts::ArtifactStore;
+use crate::gzip;
+use crate::paths;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Keep at most this many reports in the debug directory
+const MAX_REPORTS: usize = 20;
+
+/// Limits on stored debug reports; a page that keeps failing to parse
+/// would otherwise write a report on every send
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugSettings {
+    /// Cap on the combined (compressed) size of the debug directory
+    pub max_total_bytes: u64,
+    /// Reports arriving sooner than this after the last one are dropped
+    pub min_interval_secs: u64,
+}
+
+impl Default for DebugSettings {
+    fn default() -> Self {
+        Self {
+            max_total_bytes: 10 * 1024 * 1024,
+            min_interval_secs: 10,
+        }
+    }
+}
+
+/// Store for debug reports sent by the extension when file path extraction
+/// fails. Each report gets its own timestamped, gzipped file; old ones are
+/// pruned on every save.
+pub fn store(dir: PathBuf, settings: &DebugSettings) -> ArtifactStore {
+    ArtifactStore::new(
+        dir,
+        "debug-",
+        ".json.gz",
+        MAX_REPORTS,
+        settings.max_total_bytes,
+    )
+    .with_min_interval(Duration::from_secs(settings.min_interval_secs))
+}
+
+/// Default location: `debug` in the platform cache directory
+pub fn default_store(settings: &DebugSettings) -> Option<ArtifactStore> {
+    Some(store(paths::cache_dir()?.join("debug"), settings))
+}
+
+/// Report saved for an extraction failure, with host environment attached
+#[derive(Serialize)]
+pub struct DebugReport {
+    pub version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<serde_json::Value>,
+    pub html: String,
+}
+
+impl DebugReport {
+    pub fn new(html: String, context: Option<serde_json::Value>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            context,
+            html,
+        }
+    }
+}
+
+/// Save a report in the default store and return where it was written
+pub fn save_report(
+    html: String,
+    context: Option<serde_json::Value>,
+    settings: &DebugSettings,
+) -> Result<String, String> {
+    let store =
+        default_store(settings).ok_or_else(|| "Cannot locate cache directory".to_string())?;
+    save_to(&store, DebugReport::new(html, context))
+}
+
+fn save_to(store: &ArtifactStore, report: DebugReport) -> Result<String, String> {
+    let content = serde_json::to_string_pretty(&report)
+        .map_err(|e| format!("Failed to serialize debug report: {e}"))?;
+    store
+        .save(gzip::compress(content.as_bytes()))
+        .map(|path| path.display().to_string())
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => format!("Debug report skipped: {e}"),
+            _ => format!("Failed to save debug report: {e}"),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_includes_environment() {
+        let report = DebugReport::new("<p></p>".to_string(), None);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["os"], std::env::consts::OS);
+        assert_eq!(json["html"], "<p></p>");
+        assert!(json.get("context").is_none());
+    }
+
+    #[test]
+    fn test_reports_are_compressed_and_rate_limited() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-debug-test-save-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = store(dir.clone(), &DebugSettings::default());
+        let html = "<div class=\"file\"></div>".repeat(1000);
+        let path = save_to(&store, DebugReport::new(html.clone(), None)).unwrap();
+        assert!(path.ends_with(".json.gz"));
+        let saved = std::fs::read(&path).unwrap();
+        assert_eq!(saved[..2], [0x1f, 0x8b]);
+        assert!(saved.len() < html.len() / 10);
+
+        let err = save_to(&store, DebugReport::new(html, None)).unwrap_err();
+        assert!(err.starts_with("Debug report skipped"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
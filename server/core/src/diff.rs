@@ -0,0 +1,96 @@
+/// Result of comparing a browser selection against the local file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Comparison {
+    /// 1-based local line numbers whose content differs from the selection
+    pub differing_lines: Vec<u64>,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.differing_lines.is_empty()
+    }
+}
+
+/// Compare `snippet` line by line with `local` starting at `start_line`.
+///
+/// Selections rarely begin or end exactly at a line boundary, so the first
+/// snippet line only has to be a suffix of its local line and the last one a
+/// prefix. Leading/trailing whitespace is ignored because the diff view may
+/// drop indentation or trailing spaces when copying.
+pub fn compare(snippet: &str, local: &str, start_line: u64) -> Comparison {
+    let snippet: Vec<&str> = snippet.lines().collect();
+    let local: Vec<&str> = local.lines().collect();
+    let last = snippet.len().saturating_sub(1);
+
+    let differing_lines = snippet
+        .iter()
+        .enumerate()
+        .filter_map(|(i, want)| {
+            let line_no = start_line + i as u64;
+            let want = want.trim();
+            let Some(have) = local.get((line_no as usize).wrapping_sub(1)) else {
+                return Some(line_no);
+            };
+            let have = have.trim();
+            let same = match i {
+                _ if want.is_empty() => have.is_empty() || i == 0 || i == last,
+                0 if last == 0 => have.contains(want),
+                0 => have.ends_with(want),
+                _ if i == last => have.starts_with(want),
+                _ => have == want,
+            };
+            (!same).then_some(line_no)
+        })
+        .collect();
+
+    Comparison { differing_lines }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n";
+
+    #[test]
+    fn test_exact_match() {
+        let c = compare("    let x = 1;\n    println!(\"{x}\");", LOCAL, 2);
+        assert!(c.matches());
+    }
+
+    #[test]
+    fn test_partial_first_and_last_lines() {
+        let c = compare("x = 1;\n    println!(\"{x}\");\n}", LOCAL, 2);
+        assert!(c.matches());
+        let c = compare("fn main() {\n    let x", LOCAL, 1);
+        assert!(c.matches());
+    }
+
+    #[test]
+    fn test_single_line_fragment() {
+        assert!(compare("let x", LOCAL, 2).matches());
+        assert!(!compare("let y", LOCAL, 2).matches());
+    }
+
+    #[test]
+    fn test_detects_changed_lines() {
+        let c = compare(
+            "fn main() {\n    let x = 2;\n    println!(\"{x}\");",
+            LOCAL,
+            1,
+        );
+        assert_eq!(c.differing_lines, vec![2]);
+    }
+
+    #[test]
+    fn test_lines_past_end_of_file_differ() {
+        let c = compare("}\nextra\nmore", LOCAL, 4);
+        assert_eq!(c.differing_lines, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_whitespace_differences_ignored() {
+        let c = compare("let x = 1;  \nprintln!(\"{x}\");", LOCAL, 2);
+        assert!(c.matches());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::exec::Exec;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Settings for the `emacs` backend, which drives a running Emacs server
+/// through `emacsclient --eval`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmacsSettings {
+    /// emacsclient binary
+    pub emacsclient: String,
+    /// Server socket name or path (`emacsclient -s`); Emacs' default if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>,
+    /// Buffer the messages are appended to
+    pub buffer: String,
+    /// Send messages to this vterm buffer, submitting them, instead of
+    /// appending to `buffer`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vterm_buffer: Option<String>,
+}
+
+impl Default for EmacsSettings {
+    fn default() -> Self {
+        Self {
+            emacsclient: "emacsclient".to_string(),
+            socket: None,
+            buffer: "*pigeon*".to_string(),
+            vterm_buffer: None,
+        }
+    }
+}
+
+impl EmacsSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.emacsclient.trim().is_empty() {
+            return Err("emacs.emacsclient must not be empty".to_string());
+        }
+        if self.buffer.is_empty() || self.vterm_buffer.as_deref() == Some("") {
+            return Err("emacs buffer names must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Open `file` at its line, when the selection maps to a local file, and
+/// put `message` in the pigeon (or vterm) buffer
+pub fn deliver(
+    settings: &EmacsSettings,
+    exec: &dyn Exec,
+    message: &str,
+    file: Option<(&Path, Option<u64>)>,
+) -> Result<(), String> {
+    let mut args = Vec::new();
+    if let Some(socket) = &settings.socket {
+        args.push(format!("--socket-name={socket}"));
+    }
+    args.extend(["--eval".to_string(), expression(settings, message, file)]);
+    let program = &settings.emacsclient;
+    let output = exec
+        .run(program, &args)
+        .map_err(|e| format!("Failed to run {program}: {e}"))?;
+    if !output.success {
+        return Err(format!("{program} failed: {}", output.stderr.trim()));
+    }
+    Ok(())
+}
+
+fn expression(
+    settings: &EmacsSettings,
+    message: &str,
+    file: Option<(&Path, Option<u64>)>,
+) -> String {
+    let mut forms = Vec::new();
+    if let Some((path, line)) = file {
+        forms.push(format!(
+            "(find-file {})",
+            elisp_string(&path.display().to_string())
+        ));
+        if let Some(line) = line {
+            forms.push(format!(
+                "(goto-char (point-min)) (forward-line {})",
+                line.saturating_sub(1)
+            ));
+        }
+    }
+    let message = elisp_string(message);
+    match &settings.vterm_buffer {
+        Some(vterm) => forms.push(format!(
+            "(with-current-buffer {} (vterm-send-string {message}) (vterm-send-return))",
+            elisp_string(vterm)
+        )),
+        None => {
+            let buffer = elisp_string(&settings.buffer);
+            forms.push(format!(
+                "(with-current-buffer (get-buffer-create {buffer}) \
+                 (goto-char (point-max)) (insert {message} \"\\n\\n\"))"
+            ));
+            forms.push(format!("(display-buffer {buffer})"));
+        }
+    }
+    // Return t so emacsclient prints nothing interesting
+    format!("(progn {} t)", forms.join(" "))
+}
+
+/// `text` as an Emacs Lisp string literal
+fn elisp_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+
+    #[test]
+    fn test_elisp_string() {
+        assert_eq!(elisp_string(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+        assert_eq!(elisp_string("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_expression_opens_file_and_appends() {
+        let expr = expression(
+            &EmacsSettings::default(),
+            "Why?",
+            Some((Path::new("/src/api/lib.rs"), Some(42))),
+        );
+        assert_eq!(
+            expr,
+            "(progn (find-file \"/src/api/lib.rs\") (goto-char (point-min)) (forward-line 41) \
+             (with-current-buffer (get-buffer-create \"*pigeon*\") (goto-char (point-max)) \
+             (insert \"Why?\" \"\\n\\n\")) (display-buffer \"*pigeon*\") t)"
+        );
+    }
+
+    #[test]
+    fn test_expression_sends_to_vterm() {
+        let settings = EmacsSettings {
+            vterm_buffer: Some("*vterm*".to_string()),
+            ..EmacsSettings::default()
+        };
+        assert_eq!(
+            expression(&settings, "Why?", None),
+            "(progn (with-current-buffer \"*vterm*\" (vterm-send-string \"Why?\") \
+             (vterm-send-return)) t)"
+        );
+    }
+
+    #[test]
+    fn test_deliver_passes_socket() {
+        let settings = EmacsSettings {
+            socket: Some("work".to_string()),
+            ..EmacsSettings::default()
+        };
+        let fake = Fake::new();
+        deliver(&settings, &*fake, "Why?", None).unwrap();
+        let call = &fake.calls()[0];
+        assert_eq!(call[..3], ["emacsclient", "--socket-name=work", "--eval"]);
+
+        fake.fail("can't find socket");
+        let err = deliver(&settings, &*fake, "Why?", None).unwrap_err();
+        assert_eq!(err, "emacsclient failed: can't find socket");
+    }
+}
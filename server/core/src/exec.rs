@@ -0,0 +1,456 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a cancellable child is checked for exit or cancellation
+const CANCEL_POLL: Duration = Duration::from_millis(10);
+
+thread_local! {
+    static CANCEL: RefCell<Option<Cancel>> = const { RefCell::new(None) };
+}
+
+/// Cancellation flag shared between a background job and whoever cancels
+/// it. While `Cancel::scope` runs, `System` kills the running child once the
+/// flag is set and refuses to start new ones.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Run `f` with every process it spawns on this thread tied to `self`
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let previous = CANCEL.with(|c| c.replace(Some(self.clone())));
+        let result = f();
+        CANCEL.with(|c| *c.borrow_mut() = previous);
+        result
+    }
+
+    fn current() -> Option<Cancel> {
+        CANCEL.with(|c| c.borrow().clone())
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Cancelled")
+}
+
+/// Captured result of a finished process
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Output {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    /// Time taken to start the process
+    pub spawn_time: Duration,
+    /// Time from start until the process exited and its output was read
+    pub exec_time: Duration,
+}
+
+/// Runs external programs. Code that shells out takes an `Exec` so tests can
+/// script the results instead of needing tmux (or git) installed.
+pub trait Exec {
+    /// Run `program`, feeding `input` to its stdin when given
+    fn run_with_input(
+        &self,
+        program: &str,
+        args: &[String],
+        input: Option<&[u8]>,
+    ) -> io::Result<Output>;
+
+    fn run(&self, program: &str, args: &[String]) -> io::Result<Output> {
+        self.run_with_input(program, args, None)
+    }
+
+    /// Like `run_with_input`, but waiting only for `program` to exit, with
+    /// its output discarded. For tools like `wl-copy` that leave a process
+    /// behind holding whatever stdout and stderr they were given.
+    fn run_discarding_output(
+        &self,
+        program: &str,
+        args: &[String],
+        input: Option<&[u8]>,
+    ) -> io::Result<Output> {
+        self.run_with_input(program, args, input)
+    }
+}
+
+/// Spawns real processes
+pub struct System;
+
+impl Exec for System {
+    fn run_with_input(
+        &self,
+        program: &str,
+        args: &[String],
+        input: Option<&[u8]>,
+    ) -> io::Result<Output> {
+        run_system(program, args, input, true)
+    }
+
+    fn run_discarding_output(
+        &self,
+        program: &str,
+        args: &[String],
+        input: Option<&[u8]>,
+    ) -> io::Result<Output> {
+        run_system(program, args, input, false)
+    }
+}
+
+/// Run `program`, reading its output only when `capture` is set
+fn run_system(
+    program: &str,
+    args: &[String],
+    input: Option<&[u8]>,
+    capture: bool,
+) -> io::Result<Output> {
+    let cancel = Cancel::current();
+    if cancel.as_ref().is_some_and(Cancel::is_cancelled) {
+        return Err(cancelled());
+    }
+    // stdout is the native messaging channel, so children must never
+    // inherit it
+    let output = || {
+        if capture {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        }
+    };
+    let started = Instant::now();
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(output())
+        .stderr(output())
+        .spawn()?;
+    let spawned = Instant::now();
+    // Fed from its own thread, so a child that writes while it reads
+    // cannot fill its output pipe and block both sides
+    let feeder = match (input, child.stdin.take()) {
+        (Some(input), Some(stdin)) => Some(feed(stdin, input.to_vec())),
+        _ => None,
+    };
+    let (status, stdout, stderr) = match cancel {
+        Some(cancel) => wait_cancellable(child, &cancel)?,
+        None => {
+            let output = child.wait_with_output()?;
+            (output.status, output.stdout, output.stderr)
+        }
+    };
+    match feeder.map(|f| f.join()) {
+        // A child may exit without reading all of its input; its status
+        // says how that went
+        Some(Ok(Err(e))) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+        _ => {}
+    }
+    Ok(Output {
+        success: status.success(),
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        spawn_time: spawned - started,
+        exec_time: spawned.elapsed(),
+    })
+}
+
+/// Write `input` to `stdin` on its own thread. Dropping stdin afterwards
+/// signals end of input.
+fn feed(mut stdin: ChildStdin, input: Vec<u8>) -> thread::JoinHandle<io::Result<()>> {
+    thread::spawn(move || stdin.write_all(&input))
+}
+
+/// Wait for `child` like `wait_with_output`, killing it if `cancel` fires.
+/// Pipes are drained on their own threads so a chatty child cannot block.
+fn wait_cancellable(
+    mut child: Child,
+    cancel: &Cancel,
+) -> io::Result<(ExitStatus, Vec<u8>, Vec<u8>)> {
+    fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    }
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(cancelled());
+        }
+        thread::sleep(CANCEL_POLL);
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    Ok((status, stdout, stderr))
+}
+
+impl<T: Exec + ?Sized> Exec for Rc<T> {
+    fn run_with_input(
+        &self,
+        program: &str,
+        args: &[String],
+        input: Option<&[u8]>,
+    ) -> io::Result<Output> {
+        (**self).run_with_input(program, args, input)
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Replays scripted results in order and records every invocation.
+    /// Once the script runs out, calls succeed with empty output.
+    #[derive(Default)]
+    pub struct Fake {
+        script: RefCell<VecDeque<io::Result<Output>>>,
+        calls: RefCell<Vec<Vec<String>>>,
+        inputs: RefCell<Vec<Option<Vec<u8>>>>,
+        captured: RefCell<Vec<bool>>,
+    }
+
+    impl Fake {
+        pub fn new() -> Rc<Self> {
+            Rc::new(Self::default())
+        }
+
+        pub fn succeed(&self, stdout: &str) {
+            self.script.borrow_mut().push_back(Ok(Output {
+                success: true,
+                stdout: stdout.to_string(),
+                ..Output::default()
+            }));
+        }
+
+        pub fn fail(&self, stderr: &str) {
+            self.script.borrow_mut().push_back(Ok(Output {
+                success: false,
+                stderr: stderr.to_string(),
+                ..Output::default()
+            }));
+        }
+
+        pub fn missing(&self) {
+            self.script
+                .borrow_mut()
+                .push_back(Err(io::Error::from(io::ErrorKind::NotFound)));
+        }
+
+        /// Program followed by its arguments, one entry per call
+        pub fn calls(&self) -> Vec<Vec<String>> {
+            self.calls.borrow().clone()
+        }
+
+        /// Stdin passed to each call, parallel to `calls`
+        pub fn inputs(&self) -> Vec<Option<Vec<u8>>> {
+            self.inputs.borrow().clone()
+        }
+
+        /// Whether each call's output was read, parallel to `calls`
+        pub fn captured(&self) -> Vec<bool> {
+            self.captured.borrow().clone()
+        }
+    }
+
+    impl Exec for Fake {
+        fn run_with_input(
+            &self,
+            program: &str,
+            args: &[String],
+            input: Option<&[u8]>,
+        ) -> io::Result<Output> {
+            self.inputs.borrow_mut().push(input.map(<[u8]>::to_vec));
+            self.captured.borrow_mut().push(true);
+            let mut call = vec![program.to_string()];
+            call.extend(args.iter().cloned());
+            self.calls.borrow_mut().push(call);
+            self.script.borrow_mut().pop_front().unwrap_or_else(|| {
+                Ok(Output {
+                    success: true,
+                    ..Output::default()
+                })
+            })
+        }
+
+        fn run_discarding_output(
+            &self,
+            program: &str,
+            args: &[String],
+            input: Option<&[u8]>,
+        ) -> io::Result<Output> {
+            let result = self.run_with_input(program, args, input);
+            *self.captured.borrow_mut().last_mut().unwrap() = false;
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::Fake;
+    use super::*;
+
+    #[test]
+    fn test_system_captures_output() {
+        let output = System
+            .run(
+                "sh",
+                &[
+                    "-c".to_string(),
+                    "echo out; echo err >&2; exit 3".to_string(),
+                ],
+            )
+            .unwrap();
+        assert!(!output.success);
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+    }
+
+    #[test]
+    fn test_system_feeds_stdin() {
+        let output = System
+            .run_with_input("cat", &[], Some(b"piped text"))
+            .unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, "piped text");
+    }
+
+    #[test]
+    fn test_system_feeds_stdin_while_reading_output() {
+        // More than a pipe buffer each way: writing all input before reading
+        // any output would block both sides
+        let input = vec![b'x'; 1 << 20];
+        let script = "head -c 1048576 /dev/zero; cat >/dev/null".to_string();
+        let output = System
+            .run_with_input("sh", &["-c".to_string(), script.clone()], Some(&input))
+            .unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout.len(), 1 << 20);
+        let output = Cancel::new()
+            .scope(|| System.run_with_input("sh", &["-c".to_string(), script], Some(&input)))
+            .unwrap();
+        assert_eq!(output.stdout.len(), 1 << 20);
+    }
+
+    #[test]
+    fn test_system_child_may_ignore_stdin() {
+        let input = vec![b'x'; 1 << 20];
+        let output = System
+            .run_with_input(
+                "sh",
+                &["-c".to_string(), "exit 2".to_string()],
+                Some(&input),
+            )
+            .unwrap();
+        assert!(!output.success);
+    }
+
+    #[test]
+    fn test_cancel_kills_child_not_reading_stdin() {
+        let cancel = Cancel::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        let input = vec![b'x'; 1 << 20];
+        let err = cancel
+            .scope(|| System.run_with_input("sleep", &["5".to_string()], Some(&input)))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_cancel_kills_running_child() {
+        let cancel = Cancel::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        let err = cancel
+            .scope(|| System.run("sleep", &["5".to_string()]))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // Nothing new starts once cancelled
+        let err = cancel.scope(|| System.run("true", &[])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(System.run("true", &[]).unwrap().success);
+    }
+
+    #[test]
+    fn test_cancellable_child_output() {
+        let output = Cancel::new()
+            .scope(|| System.run_with_input("cat", &[], Some(b"piped text")))
+            .unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, "piped text");
+    }
+
+    #[test]
+    fn test_discarding_output_does_not_wait_for_leftover_process() {
+        // Like wl-copy and xclip: the process left behind keeps the pipes
+        // it was given open
+        let args = ["-c".to_string(), "cat >/dev/null; sleep 5 &".to_string()];
+        let started = Instant::now();
+        let output = System
+            .run_discarding_output("sh", &args, Some(b"copied"))
+            .unwrap();
+        assert!(output.success);
+        let output = Cancel::new()
+            .scope(|| System.run_discarding_output("sh", &args, Some(b"copied")))
+            .unwrap();
+        assert!(output.success);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_system_missing_program() {
+        assert!(System.run("pigeon-no-such-program", &[]).is_err());
+    }
+
+    #[test]
+    fn test_fake_replays_script() {
+        let fake = Fake::new();
+        fake.fail("boom");
+        let first = fake.run("tmux", &["a".to_string()]).unwrap();
+        assert!(!first.success);
+        assert!(fake.run("tmux", &[]).unwrap().success);
+        assert_eq!(fake.calls(), [vec!["tmux", "a"], vec!["tmux"]]);
+    }
+}
@@ -0,0 +1,961 @@
+use crate::budget::{BudgetSettings, Section, Trimmed};
+use crate::context::{Block, Surrounding};
+use crate::strings::{self, Text};
+use crate::template;
+use crate::whitespace::{self, WhitespaceSettings};
+use crate::wrap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Code longer than this (in bytes) is truncated unless configured otherwise
+pub const DEFAULT_MAX_CODE_BYTES: usize = 2000;
+
+/// Reproduces the built-in message layout: location, fenced code, question.
+/// The context placeholders expand to nothing unless context was requested.
+pub const DEFAULT_TEMPLATE: &str =
+    "{{location}}\n{{context_before}}```\n{{code}}\n```\n{{context_after}}{{question}}{{attachment}}";
+
+/// Placeholders available to message templates
+pub const TEMPLATE_VARS: &[&str] = &[
+    "location",
+    "file",
+    "path",
+    "lines",
+    "side",
+    "code",
+    "question",
+    "context_before",
+    "context_after",
+    "attachment",
+    "review",
+    "url",
+    "branch",
+    "commit_sha",
+    "source_kind",
+    "permalink",
+];
+
+/// Kind of page a selection was made on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// A review's diff, whose lines belong to an old or a new side
+    #[default]
+    Diff,
+    /// A file at some revision, such as GitHub's blob view
+    Blob,
+    /// A file of a gist
+    Gist,
+    /// Any other page, such as documentation; the selection is text to
+    /// quote rather than code
+    Generic,
+}
+
+impl SourceKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceKind::Diff => "diff",
+            SourceKind::Blob => "blob",
+            SourceKind::Gist => "gist",
+            SourceKind::Generic => "generic",
+        }
+    }
+}
+
+/// The part of a send request that ends up in the message
+pub struct Selection<'a> {
+    pub file: &'a str,
+    /// Absolute path in a local checkout, when the repository is mapped
+    pub local_path: Option<&'a str>,
+    pub start_line: Option<u64>,
+    pub end_line: Option<u64>,
+    pub side: Option<&'a str>,
+    /// How the review host refers to the PR/MR/change, e.g. "MR !12"
+    pub review: Option<&'a str>,
+    pub code: &'a str,
+    pub question: &'a str,
+    /// Lines around the selection read from the local checkout
+    pub surrounding: Option<&'a Surrounding>,
+    /// The local checkout differs from the selection at these lines
+    pub stale: bool,
+    /// Saved image the agent should look at alongside the question
+    pub attachment: Option<&'a str>,
+    /// The agent was just sent this same selection; refer back to it
+    /// instead of repeating the code
+    pub follow_up: bool,
+    /// Page the selection was made on
+    pub url: Option<&'a str>,
+    /// Head branch and commit of the PR
+    pub branch: Option<&'a str>,
+    pub commit_sha: Option<&'a str>,
+    pub kind: SourceKind,
+    /// `url` pinned to `commit_sha`, for blob and gist selections
+    pub permalink: Option<&'a str>,
+    /// Environment facts line stamped after everything else
+    pub environment: Option<&'a str>,
+}
+
+pub struct FormatOptions<'a> {
+    pub max_code_bytes: usize,
+    pub template: &'a str,
+    /// Language of the fixed phrases, with fallback (see `strings`)
+    pub locale: &'a str,
+    /// End the message with a "Source: <url>" line when the URL is known
+    pub source_footer: bool,
+    /// Prefix code lines with their line numbers in the source file
+    pub number_lines: bool,
+    /// Clean-up applied to the selected code before it is truncated
+    pub whitespace: WhitespaceSettings,
+    /// Soft-wrap code lines wider than this many columns
+    pub wrap_width: Option<usize>,
+    /// Put after the indentation of wrapped rows
+    pub wrap_marker: &'a str,
+    /// Size limit shared by code, context and question
+    pub budget: BudgetSettings,
+}
+
+impl Default for FormatOptions<'_> {
+    fn default() -> Self {
+        Self {
+            max_code_bytes: DEFAULT_MAX_CODE_BYTES,
+            template: DEFAULT_TEMPLATE,
+            locale: strings::DEFAULT_LOCALE,
+            source_footer: false,
+            number_lines: false,
+            whitespace: WhitespaceSettings::default(),
+            wrap_width: None,
+            wrap_marker: wrap::DEFAULT_MARKER,
+            budget: BudgetSettings::default(),
+        }
+    }
+}
+
+/// A formatted message and the sections cut to make it fit
+pub struct Rendered {
+    pub message: String,
+    pub trimmed: Vec<Trimmed>,
+}
+
+pub fn render(selection: &Selection, options: &FormatOptions) -> String {
+    render_trimmed(selection, options).message
+}
+
+/// `render`, also reporting what `max_code_bytes` and the budget cut
+pub fn render_trimmed(selection: &Selection, options: &FormatOptions) -> Rendered {
+    let text = |key| strings::text(options.locale, key);
+
+    // File location
+    let lines = match (selection.start_line, selection.end_line) {
+        (Some(s), Some(e)) if s != e => format!("{s}-{e}"),
+        (Some(s), _) => s.to_string(),
+        _ => String::new(),
+    };
+    let path = selection.local_path.unwrap_or(selection.file);
+    let mut location = path.to_string();
+    if !lines.is_empty() {
+        location.push(':');
+        location.push_str(&lines);
+    }
+    if let Some(review) = selection.review {
+        location.push_str(&format!(" ({review})"));
+    }
+    if selection.side == Some("old") {
+        location.push(' ');
+        location.push_str(text(Text::DeletedLines));
+    }
+    if selection.stale {
+        location.push(' ');
+        location.push_str(text(Text::LocalDiffers));
+    }
+
+    let normalized = whitespace::normalize(selection.code, &options.whitespace);
+    let kept = truncate(&normalized, options.max_code_bytes);
+
+    // Question
+    let asked = if !selection.question.is_empty() {
+        selection.question
+    } else {
+        text(Text::DefaultQuestion)
+    };
+
+    let surrounding = selection.surrounding;
+    let before = surrounding.and_then(|s| s.before.as_ref());
+    let after = surrounding.and_then(|s| s.after.as_ref());
+    let block_len = |block: Option<&Block>| block.map_or(0, |b| b.text.len());
+    let sizes = [
+        (
+            Section::Code,
+            if selection.follow_up { 0 } else { kept.len() },
+        ),
+        (Section::ContextBefore, block_len(before)),
+        (Section::ContextAfter, block_len(after)),
+        (Section::Question, asked.len()),
+    ];
+    let allowed = options.budget.allocate(&sizes);
+    let kept = truncate(kept, allowed[0]);
+    let before = before.and_then(|b| keep_last_lines(b, allowed[1]));
+    let after = after.and_then(|b| keep_first_lines(b, allowed[2]));
+    let question_kept = truncate(asked, allowed[3]);
+    let mut trimmed = Vec::new();
+    if kept.len() < normalized.len() && !selection.follow_up {
+        trimmed.push(Trimmed {
+            section: Section::Code,
+            bytes: normalized.len(),
+            kept_bytes: kept.len(),
+        });
+    }
+    for (i, kept_bytes) in [
+        (1, block_len(before.as_ref())),
+        (2, block_len(after.as_ref())),
+        (3, question_kept.len()),
+    ] {
+        let (section, bytes) = sizes[i];
+        if kept_bytes < bytes {
+            trimmed.push(Trimmed {
+                section,
+                bytes,
+                kept_bytes,
+            });
+        }
+    }
+    // Numbered before the marker goes on, so the marker is never numbered
+    let wrap = options.wrap_width.map(|width| (width, options.wrap_marker));
+    let mut code = match selection.start_line {
+        Some(first) if options.number_lines => number_lines(kept, first, wrap),
+        _ if wrap.is_some() => kept
+            .split('\n')
+            .flat_map(|line| rows(line, wrap))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => kept.to_string(),
+    };
+    if kept.len() < normalized.len() {
+        if normalized[kept.len()..].starts_with(['\n', '\r']) {
+            code.push('\n');
+        }
+        code.push_str(text(Text::Truncated));
+    }
+
+    let question = if question_kept.len() < asked.len() {
+        format!("{question_kept}\n{}", text(Text::Truncated))
+    } else {
+        asked.to_string()
+    };
+    let question = question.as_str();
+
+    let before = before
+        .map(|b| context_block(text(Text::ContextBefore), &b, options.number_lines))
+        .unwrap_or_default();
+    let after = after
+        .map(|b| context_block(text(Text::ContextAfter), &b, options.number_lines))
+        .unwrap_or_default();
+
+    let attachment = selection
+        .attachment
+        .map(|path| {
+            let line = template::render(text(Text::AttachedImage), &[("path", path)]);
+            format!("\n\n{line}")
+        })
+        .unwrap_or_default();
+
+    // A permalink is how the agent finds a blob or gist selection again,
+    // so it is not left to `source_footer`; generic pages lead with the URL
+    let footer = match (selection.kind, selection.permalink, selection.url) {
+        (SourceKind::Generic, ..) => String::new(),
+        (_, Some(link), _) => {
+            let line = template::render(text(Text::Permalink), &[("url", link)]);
+            format!("\n\n{line}")
+        }
+        (_, None, Some(url)) if options.source_footer => {
+            let line = template::render(text(Text::Source), &[("url", url)]);
+            format!("\n\n{line}")
+        }
+        _ => String::new(),
+    };
+
+    let stamp = selection
+        .environment
+        .map(|line| format!("\n\n{line}"))
+        .unwrap_or_default();
+    let footer = footer + &stamp;
+
+    if selection.follow_up {
+        let header = template::render(text(Text::FollowUp), &[("location", &location)]);
+        return Rendered {
+            message: format!("{header}\n{question}{attachment}{footer}"),
+            trimmed,
+        };
+    }
+
+    if selection.kind == SourceKind::Generic {
+        let source = selection.url.unwrap_or(selection.file);
+        let header = template::render(text(Text::QuotedFrom), &[("source", source)]);
+        let mut quoted = quote(kept);
+        if kept.len() < normalized.len() {
+            quoted.push('\n');
+            quoted.push_str(&quote(text(Text::Truncated)));
+        }
+        return Rendered {
+            message: format!("{header}\n{quoted}\n\n{question}{attachment}{footer}"),
+            trimmed,
+        };
+    }
+
+    let message = template::render(
+        options.template,
+        &[
+            ("location", &location),
+            ("file", selection.file),
+            ("path", path),
+            ("lines", &lines),
+            ("side", selection.side.unwrap_or("new")),
+            ("code", &code),
+            ("question", question),
+            ("context_before", &before),
+            ("context_after", &after),
+            ("attachment", &attachment),
+            ("review", selection.review.unwrap_or_default()),
+            ("url", selection.url.unwrap_or_default()),
+            ("branch", selection.branch.unwrap_or_default()),
+            ("commit_sha", selection.commit_sha.unwrap_or_default()),
+            ("source_kind", selection.kind.as_str()),
+            ("permalink", selection.permalink.unwrap_or_default()),
+        ],
+    );
+    Rendered {
+        message: message + &footer,
+        trimmed,
+    }
+}
+
+/// `text` as a Markdown block quote
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| match line {
+            "" => ">".to_string(),
+            line => format!("> {line}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The last lines of `block` within `max_bytes`, those nearest the selection
+fn keep_last_lines(block: &Block, max_bytes: usize) -> Option<Block> {
+    let lines: Vec<&str> = block.text.split('\n').collect();
+    let mut bytes = 0;
+    let count = lines
+        .iter()
+        .rev()
+        .take_while(|line| {
+            bytes += line.len() + usize::from(bytes > 0);
+            bytes <= max_bytes
+        })
+        .count();
+    (count > 0).then(|| Block {
+        first_line: block.last_line + 1 - count as u64,
+        last_line: block.last_line,
+        text: lines[lines.len() - count..].join("\n"),
+    })
+}
+
+/// The first lines of `block` within `max_bytes`, those nearest the selection
+fn keep_first_lines(block: &Block, max_bytes: usize) -> Option<Block> {
+    let lines: Vec<&str> = block.text.split('\n').collect();
+    let mut bytes = 0;
+    let count = lines
+        .iter()
+        .take_while(|line| {
+            bytes += line.len() + usize::from(bytes > 0);
+            bytes <= max_bytes
+        })
+        .count();
+    (count > 0).then(|| Block {
+        first_line: block.first_line,
+        last_line: block.first_line + count as u64 - 1,
+        text: lines[..count].join("\n"),
+    })
+}
+
+/// Fenced block labeled with its line range so it is not mistaken for the selection
+fn context_block(label: &str, block: &Block, numbered: bool) -> String {
+    let lines = format!("{}-{}", block.first_line, block.last_line);
+    let label = template::render(label, &[("lines", &lines)]);
+    let text = if numbered {
+        number_lines(&block.text, block.first_line, None)
+    } else {
+        block.text.clone()
+    };
+    format!("{label}\n```\n{text}\n```\n")
+}
+
+/// Prefix each line with its number, counting from `first`, right-aligned
+/// so the code stays aligned
+fn number_lines(code: &str, first: u64, wrap: Option<(usize, &str)>) -> String {
+    let count = code.split('\n').count() as u64;
+    let width = (first + count - 1).to_string().len();
+    // Rows wrapped off a line go under it with an empty gutter
+    let wrap = wrap.map(|(columns, marker)| (columns.saturating_sub(width + 3), marker));
+    code.split('\n')
+        .zip(first..)
+        .flat_map(|(line, n)| {
+            rows(line, wrap)
+                .into_iter()
+                .enumerate()
+                .map(move |(i, row)| match i {
+                    0 => format!("{n:>width$} | {row}"),
+                    _ => format!("{:>width$} | {row}", ""),
+                })
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `line` wrapped as `wrap` asks, or whole
+fn rows(line: &str, wrap: Option<(usize, &str)>) -> Vec<String> {
+    match wrap {
+        Some((width, marker)) => wrap::wrap_line(line, width, marker),
+        None => vec![line.to_string()],
+    }
+}
+
+/// The longest prefix of `code` within `max_bytes` UTF-8 bytes. It ends
+/// with the last whole line that fits; only a first line longer than the
+/// limit is cut inside, and then between grapheme clusters, so emoji
+/// sequences and combining marks stay intact.
+fn truncate(code: &str, max_bytes: usize) -> &str {
+    if code.len() <= max_bytes {
+        return code;
+    }
+    // A newline right at the limit still ends a whole line. Searching bytes
+    // is safe: 0x0A never occurs inside a multibyte character.
+    if let Some(end) = code.as_bytes()[..=max_bytes]
+        .iter()
+        .rposition(|&b| b == b'\n')
+    {
+        let line = &code[..end];
+        return line.strip_suffix('\r').unwrap_or(line);
+    }
+    let end = code
+        .grapheme_indices(true)
+        .map(|(i, g)| i + g.len())
+        .take_while(|&end| end <= max_bytes)
+        .last()
+        .unwrap_or(0);
+    &code[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection<'a>(code: &'a str, question: &'a str) -> Selection<'a> {
+        Selection {
+            file: "src/lib.rs",
+            local_path: None,
+            start_line: Some(3),
+            end_line: Some(4),
+            side: None,
+            review: None,
+            code,
+            question,
+            surrounding: None,
+            stale: false,
+            attachment: None,
+            follow_up: false,
+            url: None,
+            branch: None,
+            commit_sha: None,
+            kind: SourceKind::Diff,
+            permalink: None,
+            environment: None,
+        }
+    }
+
+    fn format_message(
+        file: &str,
+        start_line: Option<u64>,
+        end_line: Option<u64>,
+        side: Option<&str>,
+        code: &str,
+        question: &str,
+    ) -> String {
+        let selection = Selection {
+            file,
+            local_path: None,
+            start_line,
+            end_line,
+            side,
+            review: None,
+            code,
+            question,
+            surrounding: None,
+            stale: false,
+            attachment: None,
+            follow_up: false,
+            url: None,
+            branch: None,
+            commit_sha: None,
+            kind: SourceKind::Diff,
+            permalink: None,
+            environment: None,
+        };
+        render(&selection, &FormatOptions::default())
+    }
+
+    #[test]
+    fn test_format_message_basic() {
+        let msg = format_message(
+            "src/main.rs",
+            Some(10),
+            Some(20),
+            None,
+            "fn main() {}",
+            "Explain",
+        );
+        assert!(msg.contains("src/main.rs:10-20"));
+        assert!(msg.contains("fn main() {}"));
+        assert!(msg.contains("Explain"));
+    }
+
+    #[test]
+    fn test_format_message_deleted_lines() {
+        let msg = format_message("old.rs", Some(5), None, Some("old"), "deleted code", "Why?");
+        assert!(msg.contains("old.rs:5 (deleted lines)"));
+    }
+
+    #[test]
+    fn test_format_message_empty_question() {
+        let msg = format_message("file.rs", None, None, None, "code", "");
+        assert!(msg.contains("Explain this code"));
+    }
+
+    #[test]
+    fn test_default_template_layout() {
+        let msg = render(&selection("let x = 1;", "Why?"), &FormatOptions::default());
+        assert_eq!(msg, "src/lib.rs:3-4\n```\nlet x = 1;\n```\nWhy?");
+    }
+
+    #[test]
+    fn test_custom_template() {
+        let options = FormatOptions {
+            template: "Q: {{question}}\n{{file}} L{{lines}}\n{{code}}",
+            ..FormatOptions::default()
+        };
+        let msg = render(&selection("a()", "What?"), &options);
+        assert_eq!(msg, "Q: What?\nsrc/lib.rs L3-4\na()");
+    }
+
+    #[test]
+    fn test_local_path_replaces_file_in_location() {
+        let selection = Selection {
+            local_path: Some("/home/me/src/app/src/lib.rs"),
+            ..selection("x", "Why?")
+        };
+        let options = FormatOptions {
+            template: "{{location}} | {{file}} | {{path}}",
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            render(&selection, &options),
+            "/home/me/src/app/src/lib.rs:3-4 | src/lib.rs | /home/me/src/app/src/lib.rs"
+        );
+    }
+
+    #[test]
+    fn test_surrounding_context_is_marked() {
+        let surrounding = Surrounding {
+            before: Some(Block {
+                first_line: 1,
+                last_line: 2,
+                text: "use std::io;\n".to_string(),
+            }),
+            after: Some(Block {
+                first_line: 5,
+                last_line: 5,
+                text: "}".to_string(),
+            }),
+        };
+        let selection = Selection {
+            surrounding: Some(&surrounding),
+            ..selection("let x = 1;", "Why?")
+        };
+        let msg = render(&selection, &FormatOptions::default());
+        assert_eq!(
+            msg,
+            "src/lib.rs:3-4\n\
+             Context before (lines 1-2, not selected):\n```\nuse std::io;\n\n```\n\
+             ```\nlet x = 1;\n```\n\
+             Context after (lines 5-5, not selected):\n```\n}\n```\n\
+             Why?"
+        );
+    }
+
+    #[test]
+    fn test_stale_note_in_location() {
+        let selection = Selection {
+            stale: true,
+            ..selection("x", "Why?")
+        };
+        let msg = render(&selection, &FormatOptions::default());
+        assert!(msg.starts_with("src/lib.rs:3-4 (local checkout differs from the PR)\n"));
+    }
+
+    #[test]
+    fn test_follow_up_skips_code_and_template() {
+        let selection = Selection {
+            follow_up: true,
+            ..selection("let x = 1;", "And this?")
+        };
+        let options = FormatOptions {
+            template: "{{code}}",
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            render(&selection, &options),
+            "Follow-up on src/lib.rs:3-4 (same code as above):\nAnd this?"
+        );
+    }
+
+    #[test]
+    fn test_localized_phrases() {
+        let selection = Selection {
+            side: Some("old"),
+            ..selection("abcdef", "")
+        };
+        let options = FormatOptions {
+            max_code_bytes: 3,
+            locale: "ja-JP",
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            render(&selection, &options),
+            "src/lib.rs:3-4 (削除された行)\n```\nabc...(省略)\n```\nこのコードを説明してください"
+        );
+    }
+
+    #[test]
+    fn test_pr_metadata_variables_and_footer() {
+        let linked = Selection {
+            url: Some("https://github.com/acme/api/pull/7/files"),
+            branch: Some("fix-parser"),
+            commit_sha: Some("abc123"),
+            ..selection("x", "Why?")
+        };
+        let options = FormatOptions {
+            template: "{{branch}}@{{commit_sha}}: {{question}}",
+            source_footer: true,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            render(&linked, &options),
+            "fix-parser@abc123: Why?\n\nSource: https://github.com/acme/api/pull/7/files"
+        );
+        // No URL, no footer
+        let msg = render(&selection("x", "Why?"), &options);
+        assert_eq!(msg, "@: Why?");
+    }
+
+    #[test]
+    fn test_blob_selection_links_its_permalink() {
+        let blob = Selection {
+            kind: SourceKind::Blob,
+            url: Some("https://github.com/acme/api/blob/main/src/lib.rs"),
+            permalink: Some("https://github.com/acme/api/blob/0123abc/src/lib.rs"),
+            ..selection("x", "Why?")
+        };
+        assert_eq!(
+            render(&blob, &FormatOptions::default()),
+            "src/lib.rs:3-4\n```\nx\n```\nWhy?\n\n\
+             Permalink: https://github.com/acme/api/blob/0123abc/src/lib.rs"
+        );
+    }
+
+    #[test]
+    fn test_generic_selection_is_quoted() {
+        let generic = Selection {
+            kind: SourceKind::Generic,
+            url: Some("https://docs.rs/serde"),
+            ..selection("Serde is a framework.\n\nIt is fast.", "Summarize")
+        };
+        let options = FormatOptions {
+            source_footer: true,
+            number_lines: true,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            render(&generic, &options),
+            "From https://docs.rs/serde:\n> Serde is a framework.\n>\n> It is fast.\n\nSummarize"
+        );
+        let options = FormatOptions {
+            max_code_bytes: 21,
+            ..FormatOptions::default()
+        };
+        assert!(render(&generic, &options).contains("> Serde is a framework.\n> ...(truncated)\n"));
+    }
+
+    #[test]
+    fn test_environment_stamp_comes_last() {
+        let stamped = Selection {
+            url: Some("https://github.com/acme/api/pull/7/files"),
+            environment: Some("Environment: linux, 2026-10-15"),
+            ..selection("x", "Why?")
+        };
+        let options = FormatOptions {
+            template: "{{question}}",
+            source_footer: true,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            render(&stamped, &options),
+            "Why?\n\nSource: https://github.com/acme/api/pull/7/files\n\nEnvironment: linux, 2026-10-15"
+        );
+        let follow_up = Selection {
+            follow_up: true,
+            ..stamped
+        };
+        assert!(render(&follow_up, &options).ends_with("\n\nEnvironment: linux, 2026-10-15"));
+    }
+
+    #[test]
+    fn test_review_in_location() {
+        let selection = Selection {
+            review: Some("MR !12"),
+            side: Some("old"),
+            ..selection("x", "Why?")
+        };
+        let msg = render(&selection, &FormatOptions::default());
+        assert!(msg.starts_with("src/lib.rs:3-4 (MR !12) (deleted lines)\n"));
+    }
+
+    #[test]
+    fn test_attachment_path_follows_question() {
+        let selection = Selection {
+            attachment: Some("/cache/pigeon/attachments/attachment-1.png"),
+            ..selection("x", "Why?")
+        };
+        let msg = render(&selection, &FormatOptions::default());
+        assert!(msg.ends_with("Why?\n\nAttached image: /cache/pigeon/attachments/attachment-1.png"));
+    }
+
+    #[test]
+    fn test_number_lines() {
+        let options = FormatOptions {
+            number_lines: true,
+            ..FormatOptions::default()
+        };
+        let sel = Selection {
+            start_line: Some(9),
+            end_line: Some(10),
+            ..selection("let a = 1;\nlet b = 2;", "Why?")
+        };
+        let msg = render(&sel, &options);
+        assert!(
+            msg.contains("```\n 9 | let a = 1;\n10 | let b = 2;\n```"),
+            "{msg}"
+        );
+
+        // Without a known start line there is nothing to number from
+        let sel = Selection {
+            start_line: None,
+            ..selection("let a = 1;", "Why?")
+        };
+        assert!(render(&sel, &options).contains("```\nlet a = 1;\n```"));
+    }
+
+    #[test]
+    fn test_number_lines_after_truncation() {
+        let options = FormatOptions {
+            number_lines: true,
+            max_code_bytes: 6,
+            ..FormatOptions::default()
+        };
+        let msg = render(&selection("abc\ndefgh\nij", ""), &options);
+        assert!(msg.contains("```\n3 | abc\n...(truncated)\n```"), "{msg}");
+    }
+
+    #[test]
+    fn test_number_lines_in_context_blocks() {
+        let surrounding = Surrounding {
+            before: Some(Block {
+                first_line: 1,
+                last_line: 2,
+                text: "use x;\nuse y;".to_string(),
+            }),
+            after: None,
+        };
+        let options = FormatOptions {
+            number_lines: true,
+            ..FormatOptions::default()
+        };
+        let sel = Selection {
+            surrounding: Some(&surrounding),
+            ..selection("x();", "Why?")
+        };
+        let msg = render(&sel, &options);
+        assert!(msg.contains("```\n1 | use x;\n2 | use y;\n```"), "{msg}");
+        assert!(msg.contains("```\n3 | x();\n```"), "{msg}");
+    }
+
+    #[test]
+    fn test_wrap_to_width() {
+        let options = FormatOptions {
+            wrap_width: Some(24),
+            wrap_marker: "> ",
+            ..FormatOptions::default()
+        };
+        let code = "short\n  let total = first + second + third;";
+        let msg = render(&selection(code, "Why?"), &options);
+        assert!(
+            msg.contains("```\nshort\n  let total = first + \n  > second + third;\n```"),
+            "{msg}"
+        );
+
+        // Continuation rows get an empty gutter, counted in the width
+        let options = FormatOptions {
+            number_lines: true,
+            ..options
+        };
+        let sel = Selection {
+            start_line: Some(9),
+            end_line: Some(10),
+            ..selection(code, "Why?")
+        };
+        let msg = render(&sel, &options);
+        assert!(
+            msg.contains("```\n 9 | short\n10 |   let total = \n   |   > first + second \n   |   > + third;\n```"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn test_whitespace_normalized_before_truncation() {
+        let options = FormatOptions {
+            max_code_bytes: 10,
+            whitespace: WhitespaceSettings {
+                dedent: true,
+                ..WhitespaceSettings::default()
+            },
+            ..FormatOptions::default()
+        };
+        let msg = render(&selection("        a();\n        b();", ""), &options);
+        assert!(msg.contains("```\na();\nb();\n```"), "{msg}");
+    }
+
+    #[test]
+    fn test_configurable_truncation() {
+        let options = FormatOptions {
+            max_code_bytes: 4,
+            ..FormatOptions::default()
+        };
+        let msg = render(&selection("abcdefgh", ""), &options);
+        assert!(msg.contains("abcd...(truncated)"));
+    }
+
+    #[test]
+    fn test_budget_trims_every_section() {
+        let numbered = |first: u64, last: u64| Block {
+            first_line: first,
+            last_line: last,
+            text: (first..=last)
+                .map(|n| format!("line {n:02}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        let surrounding = Surrounding {
+            before: Some(numbered(1, 20)),
+            after: Some(numbered(41, 60)),
+        };
+        let code = "let x = 1;\n".repeat(20);
+        let question = "Why? ".repeat(40);
+        let selection = Selection {
+            surrounding: Some(&surrounding),
+            ..selection(&code, &question)
+        };
+        let options = FormatOptions {
+            budget: BudgetSettings {
+                max_bytes: 400,
+                ..BudgetSettings::default()
+            },
+            ..FormatOptions::default()
+        };
+        let rendered = render_trimmed(&selection, &options);
+        let sections: Vec<Section> = rendered.trimmed.iter().map(|t| t.section).collect();
+        assert_eq!(sections, Section::ALL);
+        let kept: usize = rendered.trimmed.iter().map(|t| t.kept_bytes).sum();
+        assert!(kept <= 400, "{kept}");
+        // Context nearest the selection is what stays
+        let msg = &rendered.message;
+        assert!(msg.contains("(lines 15-20, not selected)"), "{msg}");
+        assert!(msg.contains("(lines 41-46, not selected)"), "{msg}");
+        assert!(msg.contains("line 20\n```"), "{msg}");
+        assert!(msg.ends_with("Why? \n...(truncated)"), "{msg}");
+        // Without a budget only max_code_bytes applies
+        let rendered = render_trimmed(&selection, &FormatOptions::default());
+        assert!(rendered.trimmed.is_empty());
+    }
+
+    #[test]
+    fn test_truncate_keeps_whole_lines() {
+        let code = "fn a() {}\nfn b() {}\nfn c() {}";
+        assert_eq!(truncate(code, 100), code);
+        assert_eq!(truncate(code, 15), "fn a() {}");
+        // The newline ending the second line is the 20th byte
+        assert_eq!(truncate(code, 19), "fn a() {}\nfn b() {}");
+        assert_eq!(truncate(code, 4), "fn a");
+
+        let msg = render(
+            &selection(code, ""),
+            &FormatOptions {
+                max_code_bytes: 15,
+                ..FormatOptions::default()
+            },
+        );
+        assert!(msg.contains("```\nfn a() {}\n...(truncated)\n```"), "{msg}");
+    }
+
+    #[test]
+    fn test_truncate_between_graphemes() {
+        // Family emoji: four people joined by zero-width joiners, 25 bytes
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+        assert_eq!(truncate(&format!("{family}{family}"), 30), family);
+        assert_eq!(truncate(family, 24), "");
+        // e followed by a combining acute accent
+        assert_eq!(truncate("cafe\u{301}s", 5), "caf");
+        assert_eq!(truncate("日本語", 7), "日本");
+    }
+
+    #[test]
+    fn test_truncate_properties() {
+        let samples = [
+            "let 名前 = \"日本語のテキスト\";\n// 🎉🎉 done\nx();",
+            "🇯🇵🇫🇷\n👍🏽 ok\n\nnaïve cafe\u{301}",
+            "한국어\r\n中文注释\n\u{1F469}\u{200D}\u{1F4BB} wrote this",
+        ];
+        for code in samples {
+            let boundaries: Vec<usize> = code
+                .grapheme_indices(true)
+                .map(|(i, _)| i)
+                .chain([code.len()])
+                .collect();
+            let first_line = code.find('\n').unwrap_or(code.len());
+            for max_bytes in 0..=code.len() + 1 {
+                let kept = truncate(code, max_bytes);
+                assert!(code.starts_with(kept));
+                assert!(kept.len() <= max_bytes, "{kept:?} over {max_bytes}");
+                assert!(
+                    boundaries.contains(&kept.len()),
+                    "{kept:?} splits a grapheme"
+                );
+                if kept.len() < code.len() {
+                    if max_bytes >= first_line {
+                        let rest = &code[kept.len()..];
+                        assert!(rest.starts_with(['\n', '\r']), "{kept:?}");
+                    } else {
+                        assert!(kept.len() <= first_line);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Approximate matching of names the user typed (or the extension
+//! remembered) against the ones that exist now.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How far a tmux session name may be off and still be resolved
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchSettings {
+    pub enabled: bool,
+    /// Most single-character edits allowed; 0 only ignores case
+    pub max_distance: usize,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_distance: 2,
+        }
+    }
+}
+
+/// Levenshtein distance between `a` and `b`, ignoring case
+pub fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate nearest to `wanted` within `max_distance`. A tie means the
+/// name is ambiguous, so nothing is returned.
+pub fn closest<'a>(
+    wanted: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    max_distance: usize,
+) -> Option<&'a str> {
+    let mut best: Option<(usize, &str)> = None;
+    let mut tied = false;
+    for candidate in candidates {
+        let d = distance(wanted, candidate);
+        if d > max_distance {
+            continue;
+        }
+        match best {
+            Some((best_d, _)) if d > best_d => {}
+            Some((best_d, _)) if d == best_d => tied = true,
+            _ => {
+                best = Some((d, candidate));
+                tied = false;
+            }
+        }
+    }
+    best.filter(|_| !tied).map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("dev", "DEV"), 0);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("api", ""), 3);
+        assert_eq!(distance("claude-old", "claude"), 4);
+    }
+
+    #[test]
+    fn test_closest() {
+        let sessions = ["claude", "codex", "scratch"];
+        assert_eq!(closest("Claude", sessions, 0), Some("claude"));
+        assert_eq!(closest("clade", sessions, 2), Some("claude"));
+        assert_eq!(closest("clade", sessions, 0), None);
+        assert_eq!(closest("zzz", sessions, 2), None);
+    }
+
+    #[test]
+    fn test_closest_refuses_ties() {
+        assert_eq!(closest("dev", ["dev1", "dev2"], 2), None);
+        assert_eq!(closest("dev", ["dev1", "dev22"], 2), Some("dev1"));
+    }
+}
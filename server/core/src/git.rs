@@ -0,0 +1,329 @@
+use crate::exec::{self, Exec};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How the PR's commit is made available locally
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckoutMode {
+    /// Detach HEAD of the mapped clone at the commit
+    #[default]
+    Checkout,
+    /// Leave the clone alone and add a detached worktree for the commit
+    Worktree,
+}
+
+/// Git failure with a machine-readable code for the extension
+#[derive(Debug, PartialEq, Eq)]
+pub struct GitError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl GitError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Make `sha` available in the clone at `repo_dir` and return the directory
+/// whose files match it: the clone itself, or the worktree under `worktrees`.
+pub fn checkout(
+    repo_dir: &Path,
+    sha: &str,
+    mode: CheckoutMode,
+    worktrees: &Path,
+) -> Result<PathBuf, GitError> {
+    validate_sha(sha)?;
+
+    if !has_commit(repo_dir, sha)? {
+        // Fetching by SHA works on GitHub even for commits only reachable from PR refs
+        run(repo_dir, &["fetch", "--quiet", "origin", sha])
+            .map_err(|e| GitError::new("FETCH_FAILED", e.message))?;
+    }
+
+    match mode {
+        CheckoutMode::Checkout => {
+            if is_dirty(repo_dir)? {
+                return Err(GitError::new(
+                    "DIRTY_WORKTREE",
+                    format!(
+                        "{} has uncommitted changes; refusing to check out {sha}",
+                        repo_dir.display()
+                    ),
+                ));
+            }
+            run(repo_dir, &["checkout", "--quiet", "--detach", sha])?;
+            Ok(repo_dir.to_path_buf())
+        }
+        CheckoutMode::Worktree => {
+            let dir = worktrees.join(&sha[..sha.len().min(12)]);
+            if dir.exists() {
+                run(&dir, &["checkout", "--quiet", "--detach", sha])?;
+            } else {
+                let dir_arg = dir.display().to_string();
+                run(
+                    repo_dir,
+                    &["worktree", "add", "--quiet", "--detach", &dir_arg, sha],
+                )?;
+            }
+            Ok(dir)
+        }
+    }
+}
+
+/// A working tree of a clone and the branch checked out in it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Worktree {
+    pub path: PathBuf,
+    /// `None` when HEAD is detached
+    pub branch: Option<String>,
+}
+
+/// The clone at `repo_dir` and its linked worktrees, main one first
+pub fn worktrees(repo_dir: &Path) -> Result<Vec<Worktree>, GitError> {
+    let stdout = run(repo_dir, &["worktree", "list", "--porcelain"])?;
+    Ok(parse_worktrees(&stdout))
+}
+
+/// Records of `git worktree list --porcelain`, separated by blank lines
+fn parse_worktrees(stdout: &str) -> Vec<Worktree> {
+    let mut worktrees: Vec<Worktree> = Vec::new();
+    for line in stdout.lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            worktrees.push(Worktree {
+                path: PathBuf::from(path),
+                branch: None,
+            });
+        } else if let (Some(branch), Some(last)) =
+            (line.strip_prefix("branch "), worktrees.last_mut())
+        {
+            let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+            last.branch = Some(branch.to_string());
+        }
+    }
+    worktrees
+}
+
+/// Only plain hex object names are accepted, which also rules out
+/// option injection through the `sha` argument.
+fn validate_sha(sha: &str) -> Result<(), GitError> {
+    let valid = (7..=64).contains(&sha.len()) && sha.chars().all(|c| c.is_ascii_hexdigit());
+    if valid {
+        Ok(())
+    } else {
+        Err(GitError::new(
+            "INVALID_COMMIT",
+            format!("Not a commit SHA: {sha:?}"),
+        ))
+    }
+}
+
+fn has_commit(repo_dir: &Path, sha: &str) -> Result<bool, GitError> {
+    let output = git(repo_dir, &["cat-file", "-e", &format!("{sha}^{{commit}}")])?;
+    Ok(output.success)
+}
+
+/// Branch checked out in `repo_dir`; `None` when HEAD is detached
+pub fn current_branch(repo_dir: &Path) -> Result<Option<String>, GitError> {
+    let stdout = run(repo_dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    Ok(match stdout.trim() {
+        "HEAD" => None,
+        branch => Some(branch.to_string()),
+    })
+}
+
+/// Whether tracked files in `repo_dir` have uncommitted changes
+pub fn is_dirty(repo_dir: &Path) -> Result<bool, GitError> {
+    let stdout = run(repo_dir, &["status", "--porcelain", "--untracked-files=no"])?;
+    Ok(!stdout.trim().is_empty())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<exec::Output, GitError> {
+    let mut argv = vec!["-C".to_string(), dir.display().to_string()];
+    argv.extend(args.iter().map(|a| a.to_string()));
+    exec::System
+        .run("git", &argv)
+        .map_err(|e| GitError::new("GIT_FAILED", format!("Failed to run git: {e}")))
+}
+
+/// Run git and return stdout, turning a non-zero exit into an error
+fn run(dir: &Path, args: &[&str]) -> Result<String, GitError> {
+    let output = git(dir, args)?;
+    if !output.success {
+        return Err(GitError::new(
+            "GIT_FAILED",
+            format!("git {} failed: {}", args[0], output.stderr.trim()),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-git-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn git_ok(dir: &Path, args: &[&str]) -> String {
+        run(dir, args).unwrap().trim().to_string()
+    }
+
+    /// Repository with two commits; returns (dir, first_sha, second_sha)
+    fn fixture(name: &str) -> (PathBuf, String, String) {
+        let dir = temp_dir(name);
+        let repo = dir.join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        git_ok(&repo, &["init", "--quiet"]);
+        git_ok(&repo, &["config", "user.email", "test@example.com"]);
+        git_ok(&repo, &["config", "user.name", "test"]);
+        fs::write(repo.join("a.txt"), "one\n").unwrap();
+        git_ok(&repo, &["add", "a.txt"]);
+        git_ok(&repo, &["commit", "--quiet", "-m", "one"]);
+        let first = git_ok(&repo, &["rev-parse", "HEAD"]);
+        fs::write(repo.join("a.txt"), "two\n").unwrap();
+        git_ok(&repo, &["commit", "--quiet", "-am", "two"]);
+        let second = git_ok(&repo, &["rev-parse", "HEAD"]);
+        (dir, first, second)
+    }
+
+    #[test]
+    fn test_validate_sha() {
+        assert!(validate_sha("0123abc").is_ok());
+        assert_eq!(
+            validate_sha("--upload-pack=x").unwrap_err().code,
+            "INVALID_COMMIT"
+        );
+        assert_eq!(validate_sha("abc").unwrap_err().code, "INVALID_COMMIT");
+    }
+
+    #[test]
+    fn test_checkout_detaches_at_commit() {
+        let (dir, first, _) = fixture("checkout");
+        let repo = dir.join("repo");
+        let out = checkout(&repo, &first, CheckoutMode::Checkout, &dir.join("wt")).unwrap();
+        assert_eq!(out, repo);
+        assert_eq!(fs::read_to_string(repo.join("a.txt")).unwrap(), "one\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkout_refuses_dirty_tree() {
+        let (dir, first, _) = fixture("dirty");
+        let repo = dir.join("repo");
+        fs::write(repo.join("a.txt"), "local edit\n").unwrap();
+        let err = checkout(&repo, &first, CheckoutMode::Checkout, &dir.join("wt")).unwrap_err();
+        assert_eq!(err.code, "DIRTY_WORKTREE");
+        assert_eq!(
+            fs::read_to_string(repo.join("a.txt")).unwrap(),
+            "local edit\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_worktree_leaves_clone_untouched() {
+        let (dir, first, _) = fixture("worktree");
+        let repo = dir.join("repo");
+        fs::write(repo.join("a.txt"), "local edit\n").unwrap();
+        let out = checkout(&repo, &first, CheckoutMode::Worktree, &dir.join("wt")).unwrap();
+        assert_eq!(fs::read_to_string(out.join("a.txt")).unwrap(), "one\n");
+        assert_eq!(
+            fs::read_to_string(repo.join("a.txt")).unwrap(),
+            "local edit\n"
+        );
+
+        // Reusing the worktree for the same commit succeeds
+        checkout(&repo, &first, CheckoutMode::Worktree, &dir.join("wt")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_worktrees() {
+        let stdout = "worktree /src/api\nHEAD 0123abc\nbranch refs/heads/main\n\n\
+                      worktree /src/api-wt/0123abc\nHEAD 0123abc\ndetached\n\n";
+        assert_eq!(
+            parse_worktrees(stdout),
+            [
+                Worktree {
+                    path: PathBuf::from("/src/api"),
+                    branch: Some("main".to_string()),
+                },
+                Worktree {
+                    path: PathBuf::from("/src/api-wt/0123abc"),
+                    branch: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_worktrees_lists_branches() {
+        let (dir, _, _) = fixture("list");
+        let repo = dir.join("repo");
+        let linked = dir.join("feature");
+        git_ok(
+            &repo,
+            &[
+                "worktree",
+                "add",
+                "--quiet",
+                "-b",
+                "feature/x",
+                &linked.display().to_string(),
+            ],
+        );
+        let branches: Vec<Option<String>> = worktrees(&repo)
+            .unwrap()
+            .into_iter()
+            .map(|w| w.branch)
+            .collect();
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[1].as_deref(), Some("feature/x"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_current_branch_and_dirty_state() {
+        let (dir, first, _) = fixture("branch");
+        let repo = dir.join("repo");
+        git_ok(&repo, &["checkout", "--quiet", "-b", "fix-parser"]);
+        assert_eq!(
+            current_branch(&repo).unwrap().as_deref(),
+            Some("fix-parser")
+        );
+        assert!(!is_dirty(&repo).unwrap());
+        fs::write(repo.join("a.txt"), "three\n").unwrap();
+        assert!(is_dirty(&repo).unwrap());
+        git_ok(&repo, &["checkout", "--quiet", "--", "a.txt"]);
+        git_ok(&repo, &["checkout", "--quiet", "--detach", &first]);
+        assert_eq!(current_branch(&repo).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_commit_without_remote_fails_fetch() {
+        let (dir, _, _) = fixture("missing");
+        let repo = dir.join("repo");
+        let err = checkout(
+            &repo,
+            "deadbeefdeadbeefdeadbeefdeadbeefdeadbeef",
+            CheckoutMode::Checkout,
+            &dir.join("wt"),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, "FETCH_FAILED");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+//! Minimal glob matching for config patterns: `*` matches any run of
+//! characters (including none) and `?` matches exactly one.
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Pattern index just after the last `*`, and the text index it resumes from
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` absorb one more character and retry
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal() {
+        assert!(matches("dev", "dev"));
+        assert!(!matches("dev", "dev:1"));
+        assert!(!matches("dev", "de"));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("dev:*", "dev:1.0"));
+        assert!(matches("dev:*", "dev:"));
+        assert!(matches("*", ""));
+        assert!(matches("agent-?", "agent-1"));
+        assert!(!matches("agent-?", "agent-12"));
+        assert!(matches("*:claude*", "work:claude-2.1"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+    }
+}
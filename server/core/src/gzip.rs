@@ -0,0 +1,289 @@
+//! gzip (RFC 1952) compression for stored artifacts: LZ77 matching encoded
+//! as a single fixed-Huffman DEFLATE block (RFC 1951). Well short of zlib's
+//! ratio, but HTML still shrinks several times over.
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Candidates checked per position; bounds the time spent on repetitive input
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+const NONE: usize = usize::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compress `data` into a complete gzip member
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate, no flags, no mtime, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// CRC-32 as used by gzip (IEEE, reflected)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // BFINAL, then BTYPE 01 (fixed Huffman)
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut matcher = Matcher::new(data);
+    let mut i = 0;
+    while i < data.len() {
+        match matcher.longest(i) {
+            Some((length, distance)) => {
+                write_length(&mut bits, length);
+                write_distance(&mut bits, distance);
+                for j in i..i + length {
+                    matcher.insert(j);
+                }
+                i += length;
+            }
+            None => {
+                write_symbol(&mut bits, u16::from(data[i]));
+                matcher.insert(i);
+                i += 1;
+            }
+        }
+    }
+    write_symbol(&mut bits, 256);
+    bits.finish()
+}
+
+/// Hash chains over the last `WINDOW` bytes, keyed by the next three bytes
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; WINDOW],
+        }
+    }
+
+    fn hash(&self, i: usize) -> Option<usize> {
+        let bytes = self.data.get(i..i + MIN_MATCH)?;
+        let v = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        Some((v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize)
+    }
+
+    fn insert(&mut self, i: usize) {
+        if let Some(h) = self.hash(i) {
+            self.prev[i % WINDOW] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    /// Longest earlier match for the bytes at `i`, as (length, distance)
+    fn longest(&self, i: usize) -> Option<(usize, usize)> {
+        let max = MAX_MATCH.min(self.data.len() - i);
+        let mut candidate = self.head[self.hash(i)?];
+        let mut best = (0, 0);
+        for _ in 0..MAX_CHAIN {
+            if candidate == NONE || i - candidate > WINDOW {
+                break;
+            }
+            let length = self.data[candidate..]
+                .iter()
+                .zip(&self.data[i..i + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, i - candidate);
+                if length == max {
+                    break;
+                }
+            }
+            let next = self.prev[candidate % WINDOW];
+            // The slot may have been reused by a newer position
+            if next == NONE || next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        (best.0 >= MIN_MATCH).then_some(best)
+    }
+}
+
+/// Bits packed least significant first, as DEFLATE stores them
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.acc |= value << self.len;
+        self.len += bits;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Huffman codes are defined most significant bit first
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// Literal/length symbol in the fixed code (RFC 1951, 3.2.6)
+fn write_symbol(bits: &mut BitWriter, symbol: u16) {
+    let symbol = u32::from(symbol);
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_length(bits: &mut BitWriter, length: usize) {
+    let code = LENGTH_BASE.partition_point(|&base| usize::from(base) <= length) - 1;
+    write_symbol(bits, 257 + code as u16);
+    let extra = length - usize::from(LENGTH_BASE[code]);
+    bits.write(extra as u32, u32::from(LENGTH_EXTRA[code]));
+}
+
+fn write_distance(bits: &mut BitWriter, distance: usize) {
+    let code = DIST_BASE.partition_point(|&base| usize::from(base) <= distance) - 1;
+    bits.write_code(code as u32, 5);
+    let extra = distance - usize::from(DIST_BASE[code]);
+    bits.write(extra as u32, u32::from(DIST_EXTRA[code]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just enough inflate to read back what `compress` writes
+    fn decompress(gz: &[u8]) -> Vec<u8> {
+        assert_eq!(gz[..4], [0x1f, 0x8b, 8, 0]);
+        let body = &gz[10..gz.len() - 8];
+        let mut pos = 0;
+        let mut bit = |n: u32| {
+            let mut value = 0;
+            for i in 0..n {
+                let b = (body[pos / 8] >> (pos % 8)) & 1;
+                value |= u32::from(b) << i;
+                pos += 1;
+            }
+            value
+        };
+        assert_eq!(bit(3), 0b011);
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let mut code = 0;
+            for _ in 0..7 {
+                code = code << 1 | bit(1);
+            }
+            let symbol = if code <= 23 {
+                code + 256
+            } else {
+                code = code << 1 | bit(1);
+                match code {
+                    0x30..=0xbf => code - 0x30,
+                    0xc0..=0xc7 => code - 0xc0 + 280,
+                    _ => (code << 1 | bit(1)) - 0x190 + 144,
+                }
+            };
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let i = symbol as usize - 257;
+                    let length =
+                        usize::from(LENGTH_BASE[i]) + bit(u32::from(LENGTH_EXTRA[i])) as usize;
+                    let mut d = 0;
+                    for _ in 0..5 {
+                        d = d << 1 | bit(1);
+                    }
+                    let d = d as usize;
+                    let distance =
+                        usize::from(DIST_BASE[d]) + bit(u32::from(DIST_EXTRA[d])) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+        let trailer = &gz[gz.len() - 8..];
+        assert_eq!(trailer[..4], crc32(&out).to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let html: String = (0..2000)
+            .map(|i| {
+                format!(
+                    "<tr><td class=\"line\">{i}</td><td>let x = {};</td></tr>\n",
+                    i % 7
+                )
+            })
+            .collect();
+        for data in [&b""[..], b"a", b"aaaaaaaaaaaaaaaaaaaa", html.as_bytes()] {
+            assert_eq!(decompress(&compress(data)), data);
+        }
+        assert!(compress(html.as_bytes()).len() < html.len() / 4);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let data: Vec<u8> = (0..70_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        assert_eq!(decompress(&compress(&data)), data);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::config::Config;
+use crate::lock::FileLock;
+use crate::paths;
+use crate::retention::Policy;
+use crate::time;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Largest `limit` accepted by `recent_questions`
+pub const MAX_RECENT_LIMIT: usize = 100;
+
+/// `limit` used when a request leaves it out
+pub const DEFAULT_RECENT_LIMIT: usize = 20;
+
+/// History settings. Unlike the audit log, history keeps question text, so
+/// it is off by default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistorySettings {
+    pub enabled: bool,
+    /// Oldest entries are dropped beyond this many
+    pub max_entries: usize,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 500,
+        }
+    }
+}
+
+/// One delivered send
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub ts: String,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub file: String,
+    pub question: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Marker id of the send, when `reply_markers` was enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_id: Option<String>,
+    /// Typed without submitting, then cleared from the input line
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub aborted: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(target: &str, repo: Option<&str>, file: &str, question: &str) -> Self {
+        Self {
+            ts: time::rfc3339(time::unix_now()),
+            target: target.to_string(),
+            repo: repo.map(String::from),
+            file: file.to_string(),
+            question: question.to_string(),
+            tags: Vec::new(),
+            send_id: None,
+            aborted: false,
+        }
+    }
+}
+
+/// Narrows `recent_questions` to one repository, file and/or tag
+#[derive(Default)]
+pub struct Filter<'a> {
+    pub repo: Option<&'a str>,
+    pub file: Option<&'a str>,
+    pub tag: Option<&'a str>,
+}
+
+impl Filter<'_> {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.repo
+            .is_none_or(|repo| entry.repo.as_deref() == Some(repo))
+            && self.file.is_none_or(|file| entry.file == file)
+            && self
+                .tag
+                .is_none_or(|tag| entry.tags.iter().any(|t| t == tag))
+    }
+}
+
+/// Recent sends, oldest first, in one JSON file capped at `max_entries`
+pub struct History {
+    path: PathBuf,
+    max_entries: usize,
+}
+
+impl History {
+    pub fn new(path: PathBuf, settings: &HistorySettings) -> Self {
+        Self {
+            path,
+            max_entries: settings.max_entries,
+        }
+    }
+
+    /// Default location: `history.json` in the platform data directory
+    pub fn default_location(settings: &HistorySettings) -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("history.json"), settings))
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<HistoryEntry>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn append(&self, entry: HistoryEntry) -> io::Result<()> {
+        let _lock = FileLock::acquire(&self.path.with_extension("json.lock"))?;
+        let mut entries = self.entries()?;
+        entries.push(entry);
+        let excess = entries.len().saturating_sub(self.max_entries);
+        entries.drain(..excess);
+        self.store(&entries)
+    }
+
+    fn store(&self, entries: &[HistoryEntry]) -> io::Result<()> {
+        let tmp = self
+            .path
+            .with_extension(format!("json.tmp.{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_string_pretty(entries)? + "\n")?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Drop entries older than `policy` allows, then the oldest ones until
+    /// the file fits its size limit. Entries whose time cannot be read are
+    /// kept. The number dropped.
+    pub fn sweep(&self, policy: &Policy, now: SystemTime) -> io::Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let _lock = FileLock::acquire(&self.path.with_extension("json.lock"))?;
+        let mut entries = self.entries()?;
+        let before = entries.len();
+        if let Some(cutoff) = policy.cutoff(now) {
+            let cutoff = cutoff
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            entries.retain(|e| time::parse_rfc3339(&e.ts).is_none_or(|ts| ts >= cutoff));
+        }
+        // Each entry as indented in the array, with its separator, and the
+        // brackets around them all
+        let sizes: Vec<u64> = entries
+            .iter()
+            .map(|e| serde_json::to_string_pretty(std::slice::from_ref(e)).map_or(0, |s| s.len()))
+            .map(|len| (len as u64).saturating_sub(2))
+            .collect();
+        let mut total: u64 = 3 + sizes.iter().sum::<u64>();
+        let excess = sizes
+            .iter()
+            .take_while(|&&size| {
+                let over = policy.exceeded_by(total);
+                total -= size;
+                over
+            })
+            .count();
+        entries.drain(..excess);
+        let dropped = before - entries.len();
+        if dropped > 0 {
+            self.store(&entries)?;
+        }
+        Ok(dropped)
+    }
+
+    /// Mark the latest send to `target` aborted; false when there is none
+    pub fn mark_aborted(&self, target: &str) -> io::Result<bool> {
+        let _lock = FileLock::acquire(&self.path.with_extension("json.lock"))?;
+        let mut entries = self.entries()?;
+        let Some(entry) = entries.iter_mut().rev().find(|e| e.target == target) else {
+            return Ok(false);
+        };
+        entry.aborted = true;
+        self.store(&entries)?;
+        Ok(true)
+    }
+
+    /// Distinct questions, most recent first. Questions differing only in
+    /// surrounding whitespace count as the same.
+    pub fn recent_questions(&self, filter: &Filter, limit: usize) -> io::Result<Vec<String>> {
+        let mut seen = HashSet::new();
+        Ok(self
+            .entries()?
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .map(|entry| entry.question.trim())
+            .filter(|question| !question.is_empty() && seen.insert(*question))
+            .take(limit.min(MAX_RECENT_LIMIT))
+            .map(String::from)
+            .collect())
+    }
+}
+
+/// Sends to `target` that reached it, oldest first, from the history
+/// selected by `config`
+pub fn for_target(config: &Config, target: &str) -> Result<Vec<HistoryEntry>, String> {
+    if !config.history.enabled {
+        return Err("Transcripts need the history; set history.enabled in config".to_string());
+    }
+    let history = History::default_location(&config.history)
+        .ok_or_else(|| "Cannot locate data directory".to_string())?;
+    let entries = history
+        .entries()
+        .map_err(|e| format!("Failed to read history: {e}"))?;
+    Ok(entries
+        .into_iter()
+        .filter(|e| e.target == target && !e.aborted)
+        .collect())
+}
+
+/// Mark the latest send to `target` aborted in the history selected by
+/// `config`, if it is kept. Like recording, a failure is reported on stderr only.
+pub fn mark_aborted(config: &Config, target: &str) {
+    if !config.history.enabled {
+        return;
+    }
+    let Some(history) = History::default_location(&config.history) else {
+        return eprintln!("pigeon: no data directory for the history");
+    };
+    if let Err(e) = history.mark_aborted(target) {
+        eprintln!("pigeon: failed to update history: {e}");
+    }
+}
+
+/// Recent distinct questions from the history selected by `config`
+pub fn recent_questions(
+    config: &Config,
+    filter: &Filter,
+    limit: Option<usize>,
+) -> Result<Vec<String>, String> {
+    if !config.history.enabled {
+        return Err("Recent questions need the history; set history.enabled in config".to_string());
+    }
+    let history = History::default_location(&config.history)
+        .ok_or_else(|| "Cannot locate data directory".to_string())?;
+    history
+        .recent_questions(filter, limit.unwrap_or(DEFAULT_RECENT_LIMIT))
+        .map_err(|e| format!("Failed to read history: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_history(name: &str, max_entries: usize) -> (PathBuf, History) {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-history-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let settings = HistorySettings {
+            enabled: true,
+            max_entries,
+        };
+        let history = History::new(dir.join("history.json"), &settings);
+        (dir, history)
+    }
+
+    #[test]
+    fn test_append_caps_entries() {
+        let (dir, history) = temp_history("cap", 2);
+        for file in ["a.rs", "b.rs", "c.rs"] {
+            history
+                .append(HistoryEntry::new("dev", None, file, "Why?"))
+                .unwrap();
+        }
+        let files: Vec<String> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["b.rs", "c.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mark_aborted_marks_latest_for_target() {
+        let (dir, history) = temp_history("aborted", 10);
+        for (target, file) in [("dev", "a.rs"), ("dev", "b.rs"), ("ops", "c.rs")] {
+            history
+                .append(HistoryEntry::new(target, None, file, "Why?"))
+                .unwrap();
+        }
+        assert!(history.mark_aborted("dev").unwrap());
+        assert!(!history.mark_aborted("docs").unwrap());
+        let aborted: Vec<bool> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.aborted)
+            .collect();
+        assert_eq!(aborted, [false, true, false]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sweep_drops_old_entries() {
+        let (dir, history) = temp_history("sweep", 10);
+        let now = 1_792_051_200;
+        for (ts, file) in [
+            ("2026-06-01T00:00:00Z", "old.rs"),
+            // Before the cutoff in UTC, though not by its local clock
+            ("2026-10-08T05:00:00+09:00", "offset.rs"),
+            ("not a time", "unknown.rs"),
+            ("2026-10-14T23:00:00Z", "new.rs"),
+        ] {
+            let entry = HistoryEntry {
+                ts: ts.to_string(),
+                ..HistoryEntry::new("dev", None, file, "Why?")
+            };
+            history.append(entry).unwrap();
+        }
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(now);
+        let policy = Policy {
+            max_age_days: 7,
+            max_bytes: 0,
+        };
+        assert_eq!(history.sweep(&policy, now).unwrap(), 2);
+        let files: Vec<String> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["unknown.rs", "new.rs"]);
+
+        let policy = Policy {
+            max_age_days: 0,
+            max_bytes: fs::metadata(&history.path).unwrap().len() - 1,
+        };
+        assert_eq!(history.sweep(&policy, now).unwrap(), 1);
+        let files: Vec<String> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.file)
+            .collect();
+        assert_eq!(files, ["new.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recent_questions_are_distinct_and_newest_first() {
+        let (dir, history) = temp_history("recent", 10);
+        for (repo, question) in [
+            ("acme/api", "Is this thread-safe?"),
+            ("acme/api", "Write a unit test for this"),
+            ("acme/web", "Why?"),
+            ("acme/api", "Is this thread-safe? "),
+            ("acme/api", ""),
+        ] {
+            history
+                .append(HistoryEntry::new("dev", Some(repo), "a.rs", question))
+                .unwrap();
+        }
+        assert_eq!(
+            history.recent_questions(&Filter::default(), 10).unwrap(),
+            ["Is this thread-safe?", "Why?", "Write a unit test for this"]
+        );
+        let api = Filter {
+            repo: Some("acme/api"),
+            ..Filter::default()
+        };
+        assert_eq!(
+            history.recent_questions(&api, 1).unwrap(),
+            ["Is this thread-safe?"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter_by_tag() {
+        let (dir, history) = temp_history("tags", 10);
+        for (question, tags) in [("Any races?", vec!["perf", "security"]), ("Typo?", vec![])] {
+            history
+                .append(HistoryEntry {
+                    tags: tags.into_iter().map(String::from).collect(),
+                    ..HistoryEntry::new("dev", None, "a.rs", question)
+                })
+                .unwrap();
+        }
+        let security = Filter {
+            tag: Some("security"),
+            ..Filter::default()
+        };
+        assert_eq!(
+            history.recent_questions(&security, 10).unwrap(),
+            ["Any races?"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Target inference: a send without a target, and without `default_target`,
+//! goes to the tmux pane working inside the local checkout of the request's
+//! `repo`, preferring a worktree on the PR's `branch`. One agent per project
+//! then needs no targets configured at all.
+
+use crate::config::Config;
+use crate::git::{self, Worktree};
+use crate::repos;
+use crate::tmux::{Pane, Tmux};
+use std::path::Path;
+
+/// The pane to send to for `repo` and `branch`, or why there is none
+pub fn infer_target(
+    config: &Config,
+    repo: &str,
+    branch: Option<&str>,
+    tmux: &Tmux,
+) -> Result<String, String> {
+    let dir = repos::checkout_dir(config, repo)
+        .ok_or_else(|| format!("{repo} is not mapped in repos"))?;
+    let worktrees = git::worktrees(dir).map_err(|e| e.message)?;
+    let panes = tmux.list_panes()?;
+    let pane = pick_pane(&worktrees, branch, &panes, |path| tmux.host_path(path))
+        .ok_or_else(|| format!("no tmux pane is working in a checkout of {repo}"))?;
+    eprintln!(
+        "pigeon: inferred target {} from {repo} in {}",
+        pane.id, pane.current_path
+    );
+    Ok(pane.id.clone())
+}
+
+/// The first pane inside a worktree on `branch`, or else inside any worktree.
+/// A pane belongs to the innermost worktree containing it, so one in a
+/// worktree nested under the main checkout is not taken for the main one.
+fn pick_pane<'a>(
+    worktrees: &[Worktree],
+    branch: Option<&str>,
+    panes: &'a [Pane],
+    host_path: impl Fn(&Path) -> String,
+) -> Option<&'a Pane> {
+    let roots: Vec<String> = worktrees.iter().map(|w| host_path(&w.path)).collect();
+    let owner = |pane: &Pane| {
+        (0..worktrees.len())
+            .filter(|&i| is_inside(&pane.current_path, &roots[i]))
+            .max_by_key(|&i| roots[i].len())
+    };
+    let owned: Vec<(&Pane, &Worktree)> = panes
+        .iter()
+        .filter_map(|pane| owner(pane).map(|i| (pane, &worktrees[i])))
+        .collect();
+    owned
+        .iter()
+        .find(|(_, w)| branch.is_some() && w.branch.as_deref() == branch)
+        .or_else(|| owned.first())
+        .map(|(pane, _)| *pane)
+}
+
+/// Panes working in `dir` or below it, such as a checkout, those nearest
+/// to `dir` first since an agent is usually started at the top
+pub fn panes_in(dir: &Path, tmux: &Tmux) -> Result<Vec<Pane>, String> {
+    if !dir.is_absolute() {
+        return Err(format!("{} is not an absolute path", dir.display()));
+    }
+    let root = tmux.host_path(dir);
+    let mut panes: Vec<Pane> = tmux
+        .list_panes()?
+        .into_iter()
+        .filter(|pane| is_inside(&pane.current_path, &root))
+        .collect();
+    panes.sort_by_key(|pane| pane.current_path.len());
+    Ok(panes)
+}
+
+/// Whether `path` is `root` or below it
+fn is_inside(path: &str, root: &str) -> bool {
+    let root = root.trim_end_matches('/');
+    path.strip_prefix(root)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+    use std::path::PathBuf;
+
+    fn worktree(path: &str, branch: Option<&str>) -> Worktree {
+        Worktree {
+            path: PathBuf::from(path),
+            branch: branch.map(String::from),
+        }
+    }
+
+    fn pane(id: &str, path: &str) -> Pane {
+        Pane {
+            id: id.to_string(),
+            target: format!("work:{}.0", &id[1..]),
+            current_command: "claude".to_string(),
+            current_path: path.to_string(),
+        }
+    }
+
+    fn pick<'a>(
+        worktrees: &[Worktree],
+        branch: Option<&str>,
+        panes: &'a [Pane],
+    ) -> Option<&'a str> {
+        pick_pane(worktrees, branch, panes, |p| p.display().to_string()).map(|p| p.id.as_str())
+    }
+
+    #[test]
+    fn test_pane_inside_checkout() {
+        let worktrees = [worktree("/src/api", Some("main"))];
+        let panes = [
+            pane("%0", "/home/me"),
+            pane("%1", "/src/api-old"),
+            pane("%2", "/src/api/server"),
+        ];
+        assert_eq!(pick(&worktrees, None, &panes), Some("%2"));
+        assert_eq!(pick(&worktrees, None, &panes[..2]), None);
+    }
+
+    #[test]
+    fn test_branch_worktree_preferred() {
+        let worktrees = [
+            worktree("/src/api", Some("main")),
+            worktree("/src/api/.worktrees/fix", Some("fix-login")),
+        ];
+        let panes = [
+            pane("%1", "/src/api"),
+            pane("%2", "/src/api/.worktrees/fix/src"),
+        ];
+        assert_eq!(pick(&worktrees, Some("fix-login"), &panes), Some("%2"));
+        assert_eq!(pick(&worktrees, Some("main"), &panes), Some("%1"));
+        // No worktree on the branch: any pane in the project will do
+        assert_eq!(pick(&worktrees, Some("other"), &panes), Some("%1"));
+    }
+
+    #[test]
+    fn test_panes_in_directory() {
+        let fake = Fake::new();
+        fake.succeed(
+            "%0\twork:0.0\tzsh\t/src/api-old\n\
+             %2\twork:2.1\tvim\t/src/api/server/src\n\
+             %1\twork:1.0\tclaude\t/src/api\n",
+        );
+        let tmux = Tmux::new(&Config::default()).with_exec(fake.clone());
+        let panes = panes_in(Path::new("/src/api/"), &tmux).unwrap();
+        let targets: Vec<&str> = panes.iter().map(|p| p.target.as_str()).collect();
+        assert_eq!(targets, ["work:1.0", "work:2.1"]);
+        assert!(panes_in(Path::new("src/api"), &tmux).is_err());
+    }
+
+    #[test]
+    fn test_nested_worktree_is_not_the_main_checkout() {
+        let worktrees = [
+            worktree("/src/api", Some("main")),
+            worktree("/src/api/.worktrees/fix", Some("fix-login")),
+        ];
+        let panes = [
+            pane("%2", "/src/api/.worktrees/fix"),
+            pane("%1", "/src/api/"),
+        ];
+        assert_eq!(pick(&worktrees, Some("main"), &panes), Some("%1"));
+    }
+}
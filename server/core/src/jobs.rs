@@ -0,0 +1,654 @@
+//! Background jobs: slow actions sent with `"async": true` are acknowledged
+//! at once with a job id, run on their own thread, and polled through
+//! `job-status`. Scheduled jobs wait for their due time on a shared timer
+//! thread first. Jobs live as long as the host process; a daemon shutting
+//! down sets its scheduled jobs aside in `SavedJobs` for the next one.
+
+use crate::exec::Cancel;
+use crate::lock::FileLock;
+use crate::paths;
+use crate::reply;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Finished jobs kept for polling; the oldest are forgotten beyond this
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Furthest ahead a job may be scheduled
+pub const MAX_SCHEDULE_AHEAD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+type Work = Box<dyn FnOnce() -> serde_json::Value + Send>;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobState {
+    /// Waiting for its `due_at` time
+    Scheduled,
+    Running,
+    Succeeded,
+    Failed,
+    /// Stopped by `cancel-job`; whatever the job was doing is discarded
+    Cancelled,
+}
+
+/// When a job asked to run `after_ms` from now, or `at_ms` since the Unix
+/// epoch, should start; `None` when it asked for neither
+pub fn due_time(after_ms: Option<u64>, at_ms: Option<u64>) -> Result<Option<SystemTime>, String> {
+    let now = SystemTime::now();
+    let due = match (after_ms, at_ms) {
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => {
+            return Err("Set either deliver_after_ms or deliver_at, not both".to_string())
+        }
+        (Some(ms), None) => now + Duration::from_millis(ms),
+        // A time already past means now
+        (None, Some(ms)) => (UNIX_EPOCH + Duration::from_millis(ms)).max(now),
+    };
+    if due > now + MAX_SCHEDULE_AHEAD {
+        return Err(format!(
+            "Deliveries can be scheduled at most {} days ahead",
+            MAX_SCHEDULE_AHEAD.as_secs() / 86_400
+        ));
+    }
+    Ok(Some(due))
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobState::Scheduled | JobState::Running)
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct JobStatus {
+    /// Action that started the job
+    pub action: &'static str,
+    pub state: JobState,
+    /// When a scheduled job starts, in milliseconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<u64>,
+    /// The response the action would have returned synchronously
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+struct Job {
+    /// Start order, for forgetting the oldest finished jobs first
+    seq: u64,
+    status: JobStatus,
+    cancel: Cancel,
+    /// The work of a scheduled job, taken when it becomes due
+    pending: Option<Work>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_seq: u64,
+    jobs: HashMap<String, Job>,
+}
+
+/// Due times of scheduled jobs, soonest first
+#[derive(Default)]
+struct Queue {
+    due: BinaryHeap<Reverse<(SystemTime, String)>>,
+    /// The timer thread runs only while something is waiting
+    running: bool,
+}
+
+#[derive(Default)]
+struct Timer {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+impl Timer {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Jobs started by this host, shared with the threads running them
+#[derive(Clone, Default)]
+pub struct Jobs {
+    registry: Arc<Mutex<Registry>>,
+    timer: Arc<Timer>,
+    /// Signalled whenever a job stops running
+    stopped: Arc<Condvar>,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `work` on a new thread and return the job id. `work` produces
+    /// the action's response; the job fails when its `ok` is not true.
+    /// Processes `work` spawns are killed if the job is cancelled.
+    pub fn spawn(
+        &self,
+        action: &'static str,
+        work: impl FnOnce() -> serde_json::Value + Send + 'static,
+    ) -> String {
+        let cancel = Cancel::new();
+        let id = self.register(action, None, cancel.clone(), None);
+        self.run(&id, action, cancel, Box::new(work));
+        id
+    }
+
+    /// Like `spawn`, but `work` starts at `due` rather than now. Until then
+    /// the job is `scheduled` and cancelling it means it never runs.
+    pub fn schedule(
+        &self,
+        action: &'static str,
+        due: SystemTime,
+        work: impl FnOnce() -> serde_json::Value + Send + 'static,
+    ) -> String {
+        let due_at = due
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let id = self.register(action, Some(due_at), Cancel::new(), Some(Box::new(work)));
+        let mut queue = self.timer.lock();
+        queue.due.push(Reverse((due, id.clone())));
+        if queue.running {
+            self.timer.wake.notify_one();
+        } else {
+            queue.running = true;
+            let jobs = self.clone();
+            thread::spawn(move || jobs.wait_for_due());
+        }
+        id
+    }
+
+    fn register(
+        &self,
+        action: &'static str,
+        due_at: Option<u64>,
+        cancel: Cancel,
+        pending: Option<Work>,
+    ) -> String {
+        let mut registry = self.lock();
+        let id = std::iter::repeat_with(reply::new_id)
+            .find(|id| !registry.jobs.contains_key(id))
+            .expect("unbounded iterator always yields a free id");
+        let seq = registry.next_seq;
+        registry.next_seq += 1;
+        let state = if pending.is_some() {
+            JobState::Scheduled
+        } else {
+            JobState::Running
+        };
+        registry.jobs.insert(
+            id.clone(),
+            Job {
+                seq,
+                status: JobStatus {
+                    action,
+                    state,
+                    due_at,
+                    result: None,
+                },
+                cancel,
+                pending,
+            },
+        );
+        id
+    }
+
+    fn run(&self, id: &str, action: &'static str, cancel: Cancel, work: Work) {
+        let jobs = self.clone();
+        let job_id = id.to_string();
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| cancel.scope(work)))
+                .unwrap_or_else(
+                |_| serde_json::json!({"ok": false, "error": format!("The {action} job panicked")}),
+            );
+            jobs.finish(&job_id, result);
+        });
+    }
+
+    /// The timer thread: start each scheduled job when it becomes due, and
+    /// exit once none are left waiting
+    fn wait_for_due(&self) {
+        let mut queue = self.timer.lock();
+        loop {
+            let Some(Reverse((due, _))) = queue.due.peek() else {
+                queue.running = false;
+                return;
+            };
+            match due.duration_since(SystemTime::now()) {
+                Ok(wait) if !wait.is_zero() => {
+                    queue = self
+                        .timer
+                        .wake
+                        .wait_timeout(queue, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+                _ => {
+                    let Some(Reverse((_, id))) = queue.due.pop() else {
+                        continue;
+                    };
+                    drop(queue);
+                    self.start_due(&id);
+                    queue = self.timer.lock();
+                }
+            }
+        }
+    }
+
+    /// Start the scheduled job `id`, unless it was cancelled meanwhile
+    fn start_due(&self, id: &str) {
+        let started = {
+            let mut registry = self.lock();
+            registry
+                .jobs
+                .get_mut(id)
+                .filter(|job| job.status.state == JobState::Scheduled)
+                .and_then(|job| {
+                    job.status.state = JobState::Running;
+                    let work = job.pending.take()?;
+                    Some((job.status.action, job.cancel.clone(), work))
+                })
+        };
+        if let Some((action, cancel, work)) = started {
+            self.run(id, action, cancel, work);
+        }
+    }
+
+    fn finish(&self, id: &str, result: serde_json::Value) {
+        let mut registry = self.lock();
+        if let Some(job) = registry
+            .jobs
+            .get_mut(id)
+            .filter(|job| job.status.state == JobState::Running)
+        {
+            job.status.state = if result["ok"] == true {
+                JobState::Succeeded
+            } else {
+                JobState::Failed
+            };
+            job.status.result = Some(result);
+        }
+        self.stopped.notify_all();
+        let mut finished: Vec<(u64, String)> = registry
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.status.state.is_finished())
+            .map(|(id, job)| (job.seq, id.clone()))
+            .collect();
+        finished.sort();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_JOBS);
+        for (_, id) in &finished[..excess] {
+            registry.jobs.remove(id);
+        }
+    }
+
+    pub fn status(&self, id: &str) -> Option<JobStatus> {
+        self.lock().jobs.get(id).map(|job| job.status.clone())
+    }
+
+    /// Every job still known, oldest first, optionally only those in `state`
+    pub fn list(&self, state: Option<JobState>) -> Vec<(String, JobStatus)> {
+        let registry = self.lock();
+        let mut jobs: Vec<(u64, String, JobStatus)> = registry
+            .jobs
+            .iter()
+            .filter(|(_, job)| state.is_none_or(|s| job.status.state == s))
+            .map(|(id, job)| (job.seq, id.clone(), job.status.clone()))
+            .collect();
+        jobs.sort_by_key(|(seq, ..)| *seq);
+        jobs.into_iter()
+            .map(|(_, id, status)| (id, status))
+            .collect()
+    }
+
+    /// Stop a running job: its child processes are killed, nothing new is
+    /// spawned, and its eventual result is dropped. A scheduled job is
+    /// dropped before it starts.
+    pub fn cancel(&self, id: &str) -> Result<JobStatus, String> {
+        let mut registry = self.lock();
+        let job = registry
+            .jobs
+            .get_mut(id)
+            .ok_or_else(|| format!("Unknown job: {id}"))?;
+        if job.status.state.is_finished() {
+            return Err(format!("Job {id} already finished"));
+        }
+        job.cancel.cancel();
+        job.pending = None;
+        job.status.state = JobState::Cancelled;
+        self.stopped.notify_all();
+        Ok(job.status.clone())
+    }
+
+    /// Withdraw every job still waiting for its due time, so none of them
+    /// starts here, with the time each was due in milliseconds since the
+    /// Unix epoch. They are left `cancelled`.
+    pub fn take_scheduled(&self) -> Vec<(String, u64)> {
+        let mut registry = self.lock();
+        let mut taken: Vec<(u64, String, u64)> = registry
+            .jobs
+            .iter_mut()
+            .filter(|(_, job)| job.status.state == JobState::Scheduled)
+            .map(|(id, job)| {
+                job.pending = None;
+                job.status.state = JobState::Cancelled;
+                (job.seq, id.clone(), job.status.due_at.unwrap_or(0))
+            })
+            .collect();
+        taken.sort();
+        taken
+            .into_iter()
+            .map(|(_, id, due_at)| (id, due_at))
+            .collect()
+    }
+
+    /// Wait up to `timeout` for running jobs to finish. How many are still
+    /// running.
+    pub fn drain(&self, timeout: Duration) -> usize {
+        let running = |registry: &Registry| {
+            registry
+                .jobs
+                .values()
+                .filter(|job| job.status.state == JobState::Running)
+                .count()
+        };
+        let (registry, _) = self
+            .stopped
+            .wait_timeout_while(self.lock(), timeout, |registry| running(registry) > 0)
+            .unwrap_or_else(|e| e.into_inner());
+        running(&registry)
+    }
+}
+
+/// A scheduled job set aside by a host shutting down
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SavedJob {
+    pub action: String,
+    /// Milliseconds since the Unix epoch
+    pub due_at: u64,
+    /// The request that scheduled it
+    pub request: serde_json::Value,
+}
+
+/// Scheduled jobs waiting on disk for the next host to schedule them again,
+/// in one JSON file
+pub struct SavedJobs {
+    path: PathBuf,
+}
+
+impl SavedJobs {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Default location: `scheduled-jobs.json` in the platform data directory
+    pub fn default_location() -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("scheduled-jobs.json")))
+    }
+
+    fn lock(&self) -> io::Result<FileLock> {
+        FileLock::acquire(&self.path.with_extension("json.lock"))
+    }
+
+    fn load(&self) -> io::Result<Vec<SavedJob>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Add `jobs` to those already waiting
+    pub fn put(&self, jobs: Vec<SavedJob>) -> io::Result<()> {
+        if jobs.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        let mut saved = self.load()?;
+        saved.extend(jobs);
+        let tmp = self
+            .path
+            .with_extension(format!("json.tmp.{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_string_pretty(&saved)? + "\n")?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Every job waiting, which is then no longer on disk
+    pub fn take(&self) -> io::Result<Vec<SavedJob>> {
+        let _lock = self.lock()?;
+        let saved = self.load()?;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(saved),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn wait(jobs: &Jobs, id: &str) -> JobStatus {
+        for _ in 0..500 {
+            let status = jobs.status(id).unwrap();
+            if status.state.is_finished() {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        panic!("job {id} did not finish");
+    }
+
+    #[test]
+    fn test_job_reports_running_then_result() {
+        let jobs = Jobs::new();
+        let (release, gate) = mpsc::channel::<()>();
+        let id = jobs.spawn("send", move || {
+            gate.recv().unwrap();
+            json!({"ok": true, "strategy": "send-keys"})
+        });
+        assert_eq!(jobs.status(&id).unwrap().state, JobState::Running);
+        release.send(()).unwrap();
+        let status = wait(&jobs, &id);
+        assert_eq!(status.state, JobState::Succeeded);
+        assert_eq!(status.action, "send");
+        assert_eq!(status.result.unwrap()["strategy"], "send-keys");
+    }
+
+    #[test]
+    fn test_failed_and_panicking_jobs() {
+        let jobs = Jobs::new();
+        let failed = jobs.spawn("checkout", || json!({"ok": false, "error": "dirty"}));
+        assert_eq!(wait(&jobs, &failed).state, JobState::Failed);
+        let panicked = jobs.spawn("checkout", || panic!("boom"));
+        let status = wait(&jobs, &panicked);
+        assert_eq!(status.state, JobState::Failed);
+        assert!(status.result.unwrap()["error"]
+            .as_str()
+            .unwrap()
+            .contains("panicked"));
+        assert!(jobs.status("unknown").is_none());
+    }
+
+    #[test]
+    fn test_cancel_running_job() {
+        let jobs = Jobs::new();
+        let (release, gate) = mpsc::channel::<()>();
+        let id = jobs.spawn("send", move || {
+            gate.recv().unwrap();
+            json!({"ok": true})
+        });
+        let status = jobs.cancel(&id).unwrap();
+        assert_eq!(status.state, JobState::Cancelled);
+        release.send(()).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        // The late result does not resurrect the job
+        let status = jobs.status(&id).unwrap();
+        assert_eq!(status.state, JobState::Cancelled);
+        assert!(status.result.is_none());
+
+        let err = jobs.cancel(&id).unwrap_err();
+        assert!(err.contains("already finished"), "{err}");
+        assert!(jobs.cancel("unknown").unwrap_err().contains("Unknown job"));
+    }
+
+    #[test]
+    fn test_cancel_kills_job_process() {
+        use crate::exec::{Exec, System};
+        let jobs = Jobs::new();
+        let (done, finished) = mpsc::channel();
+        let id = jobs.spawn("checkout", move || {
+            let run = System.run("sleep", &["5".to_string()]);
+            done.send(run.map(|_| ()).map_err(|e| e.kind())).unwrap();
+            json!({"ok": true})
+        });
+        thread::sleep(Duration::from_millis(50));
+        jobs.cancel(&id).unwrap();
+        let run = finished.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(run, Err(std::io::ErrorKind::Interrupted));
+    }
+
+    #[test]
+    fn test_due_time() {
+        assert_eq!(due_time(None, None), Ok(None));
+        let due = due_time(Some(1000), None).unwrap().unwrap();
+        assert!(due > SystemTime::now() + Duration::from_millis(900));
+        // Past times are due at once
+        assert!(due_time(None, Some(0)).unwrap().unwrap() <= SystemTime::now());
+        assert!(due_time(Some(1), Some(1)).unwrap_err().contains("not both"));
+        let too_late = MAX_SCHEDULE_AHEAD.as_millis() as u64 + 60_000;
+        assert!(due_time(Some(too_late), None).is_err());
+    }
+
+    #[test]
+    fn test_scheduled_job_waits_until_due() {
+        let jobs = Jobs::new();
+        let due = SystemTime::now() + Duration::from_millis(100);
+        let later = jobs.schedule(
+            "send",
+            due + Duration::from_millis(50),
+            || json!({"ok": true}),
+        );
+        let id = jobs.schedule("send", due, || json!({"ok": true, "n": 1}));
+        let status = jobs.status(&id).unwrap();
+        assert_eq!(status.state, JobState::Scheduled);
+        assert_eq!(
+            status.due_at,
+            Some(due.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64)
+        );
+        let scheduled = jobs.list(Some(JobState::Scheduled));
+        assert_eq!(
+            scheduled.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            [&later, &id]
+        );
+
+        let status = wait(&jobs, &id);
+        assert!(SystemTime::now() >= due);
+        assert_eq!(status.state, JobState::Succeeded);
+        assert_eq!(status.result.unwrap()["n"], 1);
+        assert_eq!(wait(&jobs, &later).state, JobState::Succeeded);
+        assert!(jobs.list(Some(JobState::Scheduled)).is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_scheduled_job_never_runs() {
+        let jobs = Jobs::new();
+        let (ran, check) = mpsc::channel();
+        let due = SystemTime::now() + Duration::from_millis(30);
+        let id = jobs.schedule("send", due, move || {
+            ran.send(()).unwrap();
+            json!({"ok": true})
+        });
+        assert_eq!(jobs.cancel(&id).unwrap().state, JobState::Cancelled);
+        assert!(check.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(jobs.status(&id).unwrap().state, JobState::Cancelled);
+
+        // The timer thread exited with nothing left and starts again
+        let id = jobs.schedule("send", SystemTime::now(), || json!({"ok": true}));
+        assert_eq!(wait(&jobs, &id).state, JobState::Succeeded);
+    }
+
+    #[test]
+    fn test_take_scheduled_withdraws_waiting_jobs() {
+        let jobs = Jobs::new();
+        let (ran, check) = mpsc::channel();
+        let due = SystemTime::now() + Duration::from_millis(30);
+        let id = jobs.schedule("send", due, move || {
+            ran.send(()).unwrap();
+            json!({"ok": true})
+        });
+        let due_at = due.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        assert_eq!(jobs.take_scheduled(), [(id.clone(), due_at)]);
+        assert!(jobs.take_scheduled().is_empty());
+        assert!(check.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(jobs.status(&id).unwrap().state, JobState::Cancelled);
+    }
+
+    #[test]
+    fn test_drain_waits_for_running_jobs() {
+        let jobs = Jobs::new();
+        let (release, hold) = mpsc::channel::<()>();
+        let id = jobs.spawn("send", move || {
+            hold.recv().unwrap();
+            json!({"ok": true})
+        });
+        assert_eq!(jobs.drain(Duration::from_millis(20)), 1);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release.send(()).unwrap();
+        });
+        assert_eq!(jobs.drain(Duration::from_secs(5)), 0);
+        assert_eq!(jobs.status(&id).unwrap().state, JobState::Succeeded);
+    }
+
+    #[test]
+    fn test_saved_jobs_are_taken_once() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-jobs-test-saved-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let saved = SavedJobs::new(dir.join("scheduled-jobs.json"));
+        let job = |due_at| SavedJob {
+            action: "send".to_string(),
+            due_at,
+            request: json!({"file": "a.rs"}),
+        };
+        saved.put(vec![job(1)]).unwrap();
+        saved.put(vec![job(2)]).unwrap();
+        assert_eq!(saved.take().unwrap(), [job(1), job(2)]);
+        assert!(saved.take().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oldest_finished_jobs_are_forgotten() {
+        let jobs = Jobs::new();
+        let ids: Vec<String> = (0..=MAX_FINISHED_JOBS)
+            .map(|_| {
+                let id = jobs.spawn("send", || json!({"ok": true}));
+                wait(&jobs, &id);
+                id
+            })
+            .collect();
+        assert!(jobs.status(&ids[0]).is_none());
+        assert!(jobs.status(&ids[MAX_FINISHED_JOBS]).is_some());
+    }
+}
@@ -0,0 +1,285 @@
+//! Sends to the same target take turns. Each send takes a place in its
+//! target's line when it arrives and is delivered once every send ahead of
+//! it has finished, so the text and Enter keys of two messages never
+//! interleave in one pane; sends to other targets go ahead in parallel.
+//! Lines are per process, so a lock file per target keeps the hosts of
+//! other browser profiles out while a send is being typed.
+
+use crate::lock::FileLock;
+use crate::sha256;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Sends allowed to wait behind the one being delivered to a target
+pub const DEFAULT_MAX_QUEUED: usize = 16;
+
+#[derive(Default)]
+struct Lane {
+    /// Place the next arrival gets
+    next: u64,
+    /// Place whose turn it is
+    serving: u64,
+    /// Places given up before their turn came, skipped when it does
+    abandoned: BTreeSet<u64>,
+}
+
+struct Shared {
+    lanes: Mutex<HashMap<String, Lane>>,
+    turn: Condvar,
+    /// Where the per-target lock files go; `None` locks in-process only
+    lock_dir: Option<PathBuf>,
+}
+
+/// The lines of every target, cheap to clone and share between threads
+#[derive(Clone)]
+pub struct Lanes {
+    shared: Arc<Shared>,
+}
+
+/// Too many sends already waiting for a target
+#[derive(Debug, PartialEq, Eq)]
+pub struct Busy {
+    pub target: String,
+    pub queued: usize,
+}
+
+impl Busy {
+    pub fn message(&self) -> String {
+        format!(
+            "{} sends are already waiting for {}; try again once they are delivered",
+            self.queued, self.target
+        )
+    }
+}
+
+impl Lanes {
+    pub fn new(lock_dir: Option<PathBuf>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                lanes: Mutex::new(HashMap::new()),
+                turn: Condvar::new(),
+                lock_dir,
+            }),
+        }
+    }
+
+    /// Default location for lock files: `lanes/` in the platform cache directory
+    pub fn default_location() -> Self {
+        Self::new(crate::paths::cache_dir().map(|dir| dir.join("lanes")))
+    }
+
+    /// Take a place in the line of every one of `targets` at once, refusing
+    /// when `max_queued` (0 for no limit) sends already wait for any of them
+    pub fn enter(&self, targets: &[String], max_queued: usize) -> Result<Ticket, Busy> {
+        let targets: BTreeSet<&String> = targets.iter().collect();
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        for &target in &targets {
+            let queued = lanes.get(target).map_or(0, Lane::waiting);
+            if max_queued > 0 && queued > max_queued {
+                return Err(Busy {
+                    target: target.clone(),
+                    queued: queued - 1,
+                });
+            }
+        }
+        let mut position = 0;
+        let places = targets
+            .into_iter()
+            .map(|target| {
+                let lane = lanes.entry(target.clone()).or_default();
+                position = position.max(lane.waiting());
+                lane.next += 1;
+                (target.clone(), lane.next - 1)
+            })
+            .collect();
+        Ok(Ticket {
+            shared: self.shared.clone(),
+            places,
+            position,
+        })
+    }
+}
+
+impl Lane {
+    /// Sends ahead of the next arrival, the one being delivered included
+    fn waiting(&self) -> usize {
+        (self.next - self.serving) as usize - self.abandoned.len()
+    }
+
+    /// Give up `place`, passing the turn on when it was this place's
+    fn leave(&mut self, place: u64) {
+        if place != self.serving {
+            self.abandoned.insert(place);
+            return;
+        }
+        self.serving += 1;
+        while self.abandoned.remove(&self.serving) {
+            self.serving += 1;
+        }
+    }
+}
+
+/// A place in line; dropping it without waiting gives the place up
+pub struct Ticket {
+    shared: Arc<Shared>,
+    /// Target and place in its line, ordered by target
+    places: Vec<(String, u64)>,
+    /// Sends that were ahead when the ticket was taken
+    pub position: usize,
+}
+
+impl Ticket {
+    /// Block until it is this ticket's turn at every target
+    pub fn wait(self) -> Turn {
+        let lanes = self.shared.lanes.lock().unwrap();
+        let served = |lanes: &HashMap<String, Lane>| {
+            self.places
+                .iter()
+                .all(|(target, place)| lanes[target].serving == *place)
+        };
+        let lanes = self
+            .shared
+            .turn
+            .wait_while(lanes, |lanes| !served(lanes))
+            .unwrap();
+        drop(lanes);
+        // Taken in target order, so two hosts cannot hold one each
+        let locks = self
+            .places
+            .iter()
+            .filter_map(|(target, _)| {
+                let dir = self.shared.lock_dir.as_ref()?;
+                let name = &sha256::hex_digest(target.as_bytes())[..16];
+                FileLock::acquire(&dir.join(format!("{name}.lock")))
+                    .map_err(|e| eprintln!("pigeon: cannot lock {target}: {e}"))
+                    .ok()
+            })
+            .collect();
+        Turn {
+            _locks: locks,
+            _ticket: self,
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        for (target, place) in &self.places {
+            let lane = lanes.get_mut(target).expect("lane of a live ticket");
+            lane.leave(*place);
+            if lane.serving == lane.next {
+                lanes.remove(target);
+            }
+        }
+        self.shared.turn.notify_all();
+    }
+}
+
+/// The turn to deliver; the next send in line goes when it is dropped
+pub struct Turn {
+    // Released before the ticket passes the turn on
+    _locks: Vec<FileLock>,
+    _ticket: Ticket,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn targets(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_same_target_is_served_in_order() {
+        let lanes = Lanes::new(None);
+        let first = lanes.enter(&targets(&["work:1"]), 0).unwrap();
+        let second = lanes.enter(&targets(&["work:1"]), 0).unwrap();
+        assert_eq!((first.position, second.position), (0, 1));
+
+        let turn = first.wait();
+        let (done, delivered) = mpsc::channel();
+        let waiter = thread::spawn(move || {
+            let _turn = second.wait();
+            done.send(()).unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(delivered.try_recv().is_err());
+        drop(turn);
+        delivered.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+        assert!(lanes.shared.lanes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_other_targets_do_not_wait() {
+        let lanes = Lanes::new(None);
+        let _busy = lanes.enter(&targets(&["work:1"]), 0).unwrap().wait();
+        let other = lanes.enter(&targets(&["work:2"]), 0).unwrap();
+        assert_eq!(other.position, 0);
+        let _turn = other.wait();
+    }
+
+    #[test]
+    fn test_abandoned_place_is_skipped() {
+        let lanes = Lanes::new(None);
+        let first = lanes.enter(&targets(&["work:1"]), 0).unwrap().wait();
+        let second = lanes.enter(&targets(&["work:1"]), 0).unwrap();
+        let third = lanes.enter(&targets(&["work:1"]), 0).unwrap();
+        assert_eq!(third.position, 2);
+        drop(second);
+        assert_eq!(lanes.enter(&targets(&["work:1"]), 0).unwrap().position, 2);
+        drop(first);
+        let _turn = third.wait();
+    }
+
+    #[test]
+    fn test_several_targets_wait_for_each() {
+        let lanes = Lanes::new(None);
+        let _one = lanes.enter(&targets(&["work:1"]), 0).unwrap();
+        let both = lanes.enter(&targets(&["work:2", "work:1"]), 0).unwrap();
+        assert_eq!(both.position, 1);
+        assert_eq!(lanes.enter(&targets(&["work:2"]), 0).unwrap().position, 1);
+    }
+
+    #[test]
+    fn test_full_line_is_refused() {
+        let lanes = Lanes::new(None);
+        let _delivering = lanes.enter(&targets(&["work:1"]), 1).unwrap();
+        let _queued = lanes.enter(&targets(&["work:1"]), 1).unwrap();
+        let busy = lanes.enter(&targets(&["work:1"]), 1).err().unwrap();
+        assert_eq!(
+            busy,
+            Busy {
+                target: "work:1".to_string(),
+                queued: 1
+            }
+        );
+        assert!(lanes.enter(&targets(&["work:2"]), 1).is_ok());
+    }
+
+    #[test]
+    fn test_turn_holds_lock_file() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-lanes-test-lock-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let lanes = Lanes::new(Some(dir.clone()));
+        let turn = lanes.enter(&targets(&["work:1"]), 0).unwrap().wait();
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let other = std::fs::File::options().write(true).open(&path).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(turn);
+        other.try_lock().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
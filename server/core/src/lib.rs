@@ -0,0 +1,67 @@
+//! Core of pigeon: everything needed to turn a code selection and a question
+//! into a message in a terminal session, independent of how the request
+//! arrives.
+//!
+//! - [`protocol`] defines the request and response types spoken with the
+//!   browser extension.
+//! - [`send`] runs the full pipeline; [`send::compose`] formats a message
+//!   without side effects.
+//! - [`config`] loads, validates and hot-reloads the user's settings.
+//! - [`tmux`] is the delivery backend, driven through [`exec::Exec`] so it
+//!   can be tested without a tmux server.
+//!
+//! The `pigeon-host` binary wraps this crate in Chrome's native messaging
+//! transport; other front-ends can link it the same way.
+
+pub mod aliases;
+pub mod artifacts;
+pub mod attachments;
+pub mod audit;
+pub mod base64;
+pub mod budget;
+pub mod classify;
+pub mod clipboard;
+pub mod config;
+pub mod context;
+pub mod debug;
+pub mod diff;
+pub mod emacs;
+pub mod exec;
+pub mod format;
+pub mod fuzzy;
+pub mod git;
+pub mod glob;
+pub mod gzip;
+pub mod history;
+pub mod infer;
+pub mod jobs;
+pub mod lanes;
+pub mod lock;
+pub mod metrics;
+pub mod notes;
+pub mod notify;
+pub mod paths;
+pub mod pipe;
+pub mod probes;
+pub mod protocol;
+pub mod provider;
+pub mod queue;
+pub mod reply;
+pub mod repos;
+pub mod retention;
+pub mod send;
+pub mod sha256;
+pub mod stage;
+pub mod stats;
+pub mod strings;
+pub mod template;
+pub mod thread;
+pub mod time;
+pub mod tmux;
+pub mod transcript;
+pub mod update;
+pub mod upload;
+pub mod vscode;
+pub mod watch;
+pub mod whitespace;
+pub mod wrap;
@@ -0,0 +1,52 @@
+//! Advisory file locks shared by every host process. Chrome, Chromium and
+//! each browser profile start their own `pigeon-host`, so read-modify-write
+//! updates to shared files (config, queue, audit log, artifact stores) take
+//! an exclusive lock first.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Exclusive lock on a lock file, released when dropped. The lock file
+/// itself is left in place; only the OS-level lock matters.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Block until the lock on `path` is held, creating the file if needed
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        file.lock()?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::TryLockError;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-lock-test-excl-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("state.lock");
+
+        let held = FileLock::acquire(&path).unwrap();
+        let other = File::options().write(true).open(&path).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+
+        drop(held);
+        other.try_lock().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Operational metrics: counters and latency histograms kept in memory for
+//! as long as the host runs, which in daemon mode spans every browser
+//! profile. Read through the `metrics` action, as JSON or Prometheus text.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// Upper bounds of the latency histogram buckets, in milliseconds
+pub const BUCKETS_MS: [u64; 11] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Prefix of every metric name in the Prometheus output
+const PROMETHEUS_PREFIX: &str = "pigeon_";
+
+type Labels = BTreeMap<String, String>;
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of `BUCKETS_MS`, then those above the last
+    counts: [u64; BUCKETS_MS.len() + 1],
+    sum: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<(&'static str, Labels), u64>,
+    histograms: BTreeMap<&'static str, Histogram>,
+}
+
+/// Metrics registry shared by everything handling requests
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Counter {
+    pub name: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: Labels,
+    pub value: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Bucket {
+    /// Upper bound in milliseconds; `None` for the bucket holding everything
+    pub le: Option<u64>,
+    /// Observations at or below `le`, as Prometheus counts them
+    pub count: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct HistogramSnapshot {
+    pub name: &'static str,
+    pub buckets: Vec<Bucket>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+/// Values of every metric at one moment
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Snapshot {
+    pub counters: Vec<Counter>,
+    pub histograms: Vec<HistogramSnapshot>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add one to the counter `name` with `labels`
+    pub fn increment(&self, name: &'static str, labels: &[(&str, &str)]) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        *self.lock().counters.entry((name, labels)).or_default() += 1;
+    }
+
+    /// Record `ms` in the histogram `name`
+    pub fn observe(&self, name: &'static str, ms: u64) {
+        let mut registry = self.lock();
+        let histogram = registry.histograms.entry(name).or_default();
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&le| ms <= le)
+            .unwrap_or(BUCKETS_MS.len());
+        histogram.counts[bucket] += 1;
+        histogram.sum += ms;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let registry = self.lock();
+        let counters = registry
+            .counters
+            .iter()
+            .map(|((name, labels), &value)| Counter {
+                name,
+                labels: labels.clone(),
+                value,
+            })
+            .collect();
+        let histograms = registry
+            .histograms
+            .iter()
+            .map(|(name, histogram)| {
+                let mut cumulative = 0;
+                let buckets = histogram
+                    .counts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &count)| {
+                        cumulative += count;
+                        Bucket {
+                            le: BUCKETS_MS.get(i).copied(),
+                            count: cumulative,
+                        }
+                    })
+                    .collect();
+                HistogramSnapshot {
+                    name,
+                    buckets,
+                    sum: histogram.sum,
+                    count: cumulative,
+                }
+            })
+            .collect();
+        Snapshot {
+            counters,
+            histograms,
+        }
+    }
+}
+
+impl Snapshot {
+    /// The Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        let mut typed = None;
+        for counter in &self.counters {
+            if typed != Some(counter.name) {
+                let _ = writeln!(text, "# TYPE {PROMETHEUS_PREFIX}{} counter", counter.name);
+                typed = Some(counter.name);
+            }
+            let _ = writeln!(
+                text,
+                "{PROMETHEUS_PREFIX}{}{} {}",
+                counter.name,
+                label_set(&counter.labels),
+                counter.value
+            );
+        }
+        for histogram in &self.histograms {
+            let name = format!("{PROMETHEUS_PREFIX}{}", histogram.name);
+            let _ = writeln!(text, "# TYPE {name} histogram");
+            for bucket in &histogram.buckets {
+                let le = bucket.le.map_or("+Inf".to_string(), |le| le.to_string());
+                let _ = writeln!(text, "{name}_bucket{{le=\"{le}\"}} {}", bucket.count);
+            }
+            let _ = writeln!(text, "{name}_sum {}", histogram.sum);
+            let _ = writeln!(text, "{name}_count {}", histogram.count);
+        }
+        text
+    }
+}
+
+/// `{key="value",...}`, or nothing without labels
+fn label_set(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_by_label() {
+        let metrics = Metrics::new();
+        metrics.increment("sends_total", &[]);
+        metrics.increment("sends_total", &[]);
+        metrics.increment("send_failures_total", &[("code", "TMUX_UNAVAILABLE")]);
+        let counters = metrics.snapshot().counters;
+        assert_eq!(counters.len(), 2);
+        assert_eq!(counters[1].name, "sends_total");
+        assert_eq!(counters[1].value, 2);
+        assert_eq!(counters[0].labels["code"], "TMUX_UNAVAILABLE");
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        for ms in [3, 5, 40, 20_000] {
+            metrics.observe("tmux_exec_ms", ms);
+        }
+        let histogram = &metrics.snapshot().histograms[0];
+        assert_eq!((histogram.sum, histogram.count), (20_048, 4));
+        assert_eq!(
+            histogram.buckets[0],
+            Bucket {
+                le: Some(5),
+                count: 2
+            }
+        );
+        assert_eq!(
+            histogram.buckets[3],
+            Bucket {
+                le: Some(50),
+                count: 3
+            }
+        );
+        assert_eq!(
+            histogram.buckets.last(),
+            Some(&Bucket { le: None, count: 4 })
+        );
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let metrics = Metrics::new();
+        metrics.increment("send_failures_total", &[("code", "say \"hi\"")]);
+        metrics.observe("send_duration_ms", 7);
+        let text = metrics.snapshot().prometheus();
+        assert!(
+            text.starts_with(
+                "# TYPE pigeon_send_failures_total counter\n\
+             pigeon_send_failures_total{code=\"say \\\"hi\\\"\"} 1\n\
+             # TYPE pigeon_send_duration_ms histogram\n\
+             pigeon_send_duration_ms_bucket{le=\"5\"} 0\n\
+             pigeon_send_duration_ms_bucket{le=\"10\"} 1\n"
+            ),
+            "{text}"
+        );
+        assert!(
+            text.ends_with(
+                "pigeon_send_duration_ms_bucket{le=\"+Inf\"} 1\n\
+             pigeon_send_duration_ms_sum 7\n\
+             pigeon_send_duration_ms_count 1\n"
+            ),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::time;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Markdown inbox settings, for questions to triage later instead of
+/// interrupting a running agent
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotesSettings {
+    /// Absolute path of the markdown file; required by the `notes` backend
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Also append messages delivered through other backends
+    pub mirror: bool,
+}
+
+impl NotesSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.path {
+            Some(path) if !std::path::Path::new(path).is_absolute() => {
+                Err("notes.path must be an absolute path".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A markdown file that messages are appended to, one section each
+pub struct NotesFile {
+    path: PathBuf,
+}
+
+impl NotesFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The configured file, if any
+    pub fn from_settings(settings: &NotesSettings) -> Option<Self> {
+        settings.path.as_ref().map(|p| Self::new(PathBuf::from(p)))
+    }
+
+    pub fn append(&self, file: &str, url: Option<&str>, message: &str) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let section = render(&time::rfc3339(time::unix_now()), file, url, message);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(section.as_bytes())
+    }
+}
+
+/// One inbox entry: a heading with the time and file, the source page, and
+/// the message as it would have been pasted
+fn render(ts: &str, file: &str, url: Option<&str>, message: &str) -> String {
+    let mut section = format!("\n## {ts} {file}\n\n");
+    if let Some(url) = url {
+        section.push_str(&format!("Source: <{url}>\n\n"));
+    }
+    section.push_str(message.trim_end());
+    section.push('\n');
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(
+                "2025-01-02T03:04:05Z",
+                "src/a.rs",
+                Some("https://github.com/acme/api/pull/1/files"),
+                "Why?\n"
+            ),
+            "\n## 2025-01-02T03:04:05Z src/a.rs\n\nSource: <https://github.com/acme/api/pull/1/files>\n\nWhy?\n"
+        );
+        assert_eq!(
+            render("2025-01-02T03:04:05Z", "a.rs", None, "Why?"),
+            "\n## 2025-01-02T03:04:05Z a.rs\n\nWhy?\n"
+        );
+    }
+
+    #[test]
+    fn test_append_creates_and_extends_file() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-notes-test-append-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let notes = NotesFile::new(dir.join("inbox/pigeon.md"));
+        notes.append("a.rs", None, "one").unwrap();
+        notes.append("b.rs", None, "two").unwrap();
+        let content = fs::read_to_string(dir.join("inbox/pigeon.md")).unwrap();
+        assert_eq!(content.matches("\n## ").count(), 2);
+        assert!(content.find("one").unwrap() < content.find("two").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_requires_absolute_path() {
+        let relative = NotesSettings {
+            path: Some("inbox.md".to_string()),
+            mirror: false,
+        };
+        assert!(relative.validate().is_err());
+        assert!(NotesSettings::default().validate().is_ok());
+    }
+}
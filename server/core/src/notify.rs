@@ -0,0 +1,126 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+
+/// Which events pop a desktop notification. All are off by default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    pub send_failed: bool,
+    pub send_succeeded: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    SendFailed,
+    SendSucceeded,
+}
+
+impl Notifications {
+    pub fn enabled(&self, event: Event) -> bool {
+        match event {
+            Event::SendFailed => self.send_failed,
+            Event::SendSucceeded => self.send_succeeded,
+        }
+    }
+}
+
+/// Show a desktop notification if `event` is enabled. Notifications are a
+/// convenience, so failures are only logged and the call never blocks on
+/// the notifier.
+pub fn notify(settings: &Notifications, event: Event, title: &str, body: &str) {
+    if !settings.enabled(event) {
+        return;
+    }
+    let Some(mut cmd) = command(std::env::consts::OS, title, body) else {
+        return;
+    };
+    let spawned = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawned {
+        // Reap the notifier in the background so it does not linger as a zombie
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("pigeon: failed to show notification: {e}"),
+    }
+}
+
+fn command(os: &str, title: &str, body: &str) -> Option<Command> {
+    match os {
+        "macos" => {
+            // Pass text as arguments so it never needs AppleScript escaping
+            let mut cmd = Command::new("osascript");
+            cmd.args([
+                "-e",
+                "on run argv",
+                "-e",
+                "display notification (item 2 of argv) with title (item 1 of argv)",
+                "-e",
+                "end run",
+                title,
+                body,
+            ]);
+            Some(cmd)
+        }
+        "linux" | "freebsd" | "openbsd" | "netbsd" => {
+            let mut cmd = Command::new("notify-send");
+            cmd.args(["--app-name=pigeon", title, body]);
+            Some(cmd)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_macos_passes_text_as_arguments() {
+        let cmd = command("macos", "pigeon", r#"say "hi""#).unwrap();
+        let argv = argv(&cmd);
+        assert_eq!(argv[0], "osascript");
+        assert_eq!(argv[argv.len() - 2..], ["pigeon", r#"say "hi""#]);
+    }
+
+    #[test]
+    fn test_linux_uses_notify_send() {
+        let cmd = command("linux", "pigeon", "Delivery failed").unwrap();
+        assert_eq!(
+            argv(&cmd),
+            [
+                "notify-send",
+                "--app-name=pigeon",
+                "pigeon",
+                "Delivery failed"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_platform() {
+        assert!(command("windows", "t", "b").is_none());
+    }
+
+    #[test]
+    fn test_events_are_configurable() {
+        let settings = Notifications {
+            send_failed: true,
+            send_succeeded: false,
+        };
+        assert!(settings.enabled(Event::SendFailed));
+        assert!(!settings.enabled(Event::SendSucceeded));
+        assert!(!Notifications::default().enabled(Event::SendFailed));
+    }
+}
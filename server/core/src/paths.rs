@@ -0,0 +1,191 @@
+use std::path::PathBuf;
+
+const APP: &str = "pigeon";
+
+/// Kinds of per-user directories pigeon stores files in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dir {
+    /// Settings the user may edit (config.json)
+    Config,
+    /// Disposable files that can be regenerated or pruned (debug reports)
+    Cache,
+    /// Records that should survive cache cleanup (audit log)
+    Data,
+}
+
+/// `$XDG_CONFIG_HOME/pigeon`, `~/Library/Application Support/pigeon`, or `%APPDATA%\pigeon`
+pub fn config_dir() -> Option<PathBuf> {
+    resolve(Dir::Config, std::env::consts::OS, |k| std::env::var(k).ok())
+}
+
+/// `$XDG_CACHE_HOME/pigeon`, `~/Library/Caches/pigeon`, or `%LOCALAPPDATA%\pigeon\cache`
+pub fn cache_dir() -> Option<PathBuf> {
+    resolve(Dir::Cache, std::env::consts::OS, |k| std::env::var(k).ok())
+}
+
+/// `$XDG_DATA_HOME/pigeon`, `~/Library/Application Support/pigeon`, or `%LOCALAPPDATA%\pigeon\data`
+pub fn data_dir() -> Option<PathBuf> {
+    resolve(Dir::Data, std::env::consts::OS, |k| std::env::var(k).ok())
+}
+
+/// XDG variables win on every Unix so users can relocate everything
+/// consistently; otherwise fall back to the platform convention.
+fn resolve(dir: Dir, os: &str, env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    // Relative XDG paths are invalid per the spec and must be ignored
+    let var = |key: &str| {
+        env(key)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute() || os == "windows")
+    };
+
+    if os == "windows" {
+        return match dir {
+            Dir::Config => var("APPDATA").map(|p| p.join(APP)),
+            Dir::Cache => var("LOCALAPPDATA").map(|p| p.join(APP).join("cache")),
+            Dir::Data => var("LOCALAPPDATA").map(|p| p.join(APP).join("data")),
+        };
+    }
+
+    let xdg = match dir {
+        Dir::Config => "XDG_CONFIG_HOME",
+        Dir::Cache => "XDG_CACHE_HOME",
+        Dir::Data => "XDG_DATA_HOME",
+    };
+    if let Some(base) = var(xdg) {
+        return Some(base.join(APP));
+    }
+
+    let home = var("HOME")?;
+    let base = match (os, dir) {
+        ("macos", Dir::Config | Dir::Data) => home.join("Library/Application Support"),
+        ("macos", Dir::Cache) => home.join("Library/Caches"),
+        (_, Dir::Config) => home.join(".config"),
+        (_, Dir::Cache) => home.join(".cache"),
+        (_, Dir::Data) => home.join(".local/share"),
+    };
+    Some(base.join(APP))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn test_linux_defaults() {
+        let vars = [("HOME", "/home/me")];
+        assert_eq!(
+            resolve(Dir::Config, "linux", env(&vars)),
+            Some(PathBuf::from("/home/me/.config/pigeon"))
+        );
+        assert_eq!(
+            resolve(Dir::Cache, "linux", env(&vars)),
+            Some(PathBuf::from("/home/me/.cache/pigeon"))
+        );
+    }
+
+    #[test]
+    fn test_xdg_overrides() {
+        let vars = [
+            ("HOME", "/home/me"),
+            ("XDG_CONFIG_HOME", "/cfg"),
+            ("XDG_CACHE_HOME", "/tmp/cache"),
+        ];
+        assert_eq!(
+            resolve(Dir::Config, "linux", env(&vars)),
+            Some(PathBuf::from("/cfg/pigeon"))
+        );
+        assert_eq!(
+            resolve(Dir::Cache, "macos", env(&vars)),
+            Some(PathBuf::from("/tmp/cache/pigeon"))
+        );
+    }
+
+    #[test]
+    fn test_relative_or_empty_xdg_is_ignored() {
+        let vars = [
+            ("HOME", "/home/me"),
+            ("XDG_CONFIG_HOME", "relative/dir"),
+            ("XDG_CACHE_HOME", ""),
+        ];
+        assert_eq!(
+            resolve(Dir::Config, "linux", env(&vars)),
+            Some(PathBuf::from("/home/me/.config/pigeon"))
+        );
+        assert_eq!(
+            resolve(Dir::Cache, "linux", env(&vars)),
+            Some(PathBuf::from("/home/me/.cache/pigeon"))
+        );
+    }
+
+    #[test]
+    fn test_macos_defaults() {
+        let vars = [("HOME", "/Users/me")];
+        assert_eq!(
+            resolve(Dir::Config, "macos", env(&vars)),
+            Some(PathBuf::from(
+                "/Users/me/Library/Application Support/pigeon"
+            ))
+        );
+        assert_eq!(
+            resolve(Dir::Cache, "macos", env(&vars)),
+            Some(PathBuf::from("/Users/me/Library/Caches/pigeon"))
+        );
+    }
+
+    #[test]
+    fn test_windows_appdata() {
+        let vars = [
+            ("APPDATA", r"C:\Users\me\AppData\Roaming"),
+            ("LOCALAPPDATA", r"C:\Users\me\AppData\Local"),
+        ];
+        assert_eq!(
+            resolve(Dir::Config, "windows", env(&vars)),
+            Some(PathBuf::from(r"C:\Users\me\AppData\Roaming").join("pigeon"))
+        );
+        assert_eq!(
+            resolve(Dir::Cache, "windows", env(&vars)),
+            Some(
+                PathBuf::from(r"C:\Users\me\AppData\Local")
+                    .join("pigeon")
+                    .join("cache")
+            )
+        );
+    }
+
+    #[test]
+    fn test_data_dir() {
+        let vars = [("HOME", "/home/me")];
+        assert_eq!(
+            resolve(Dir::Data, "linux", env(&vars)),
+            Some(PathBuf::from("/home/me/.local/share/pigeon"))
+        );
+        let vars = [("HOME", "/home/me"), ("XDG_DATA_HOME", "/data")];
+        assert_eq!(
+            resolve(Dir::Data, "linux", env(&vars)),
+            Some(PathBuf::from("/data/pigeon"))
+        );
+        let vars = [("LOCALAPPDATA", r"C:\Users\me\AppData\Local")];
+        assert_eq!(
+            resolve(Dir::Data, "windows", env(&vars)),
+            Some(
+                PathBuf::from(r"C:\Users\me\AppData\Local")
+                    .join("pigeon")
+                    .join("data")
+            )
+        );
+    }
+
+    #[test]
+    fn test_missing_home() {
+        assert_eq!(resolve(Dir::Config, "linux", env(&[])), None);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::exec::Exec;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Settings for the `pipe` backend, which hands messages to a program
+/// outside tmux: typically a CLI agent in Windows Terminal listening on a
+/// named pipe, or a PowerShell script that forwards its stdin
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipeSettings {
+    /// Named pipe (`\\.\pipe\agent`) or FIFO opened for each message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Program and arguments run for each message, with it on stdin
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+}
+
+impl PipeSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path.as_deref().is_some_and(|p| p.trim().is_empty()) {
+            return Err("pipe.path must not be empty".to_string());
+        }
+        if self.command.first().is_some_and(|p| p.trim().is_empty()) {
+            return Err("pipe.command must start with a program".to_string());
+        }
+        if self.path.is_some() && !self.command.is_empty() {
+            return Err("Set either pipe.path or pipe.command, not both".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.path.is_some() || !self.command.is_empty()
+    }
+}
+
+/// Write `message` to the configured pipe, or run the configured command
+/// with it on stdin. Each message is one connection, closed afterwards.
+pub fn deliver(settings: &PipeSettings, exec: &dyn Exec, message: &str) -> Result<(), String> {
+    if let Some(path) = &settings.path {
+        // No create: the listening program owns the pipe
+        let mut pipe = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("Cannot open pipe {path}: {e}"))?;
+        return pipe
+            .write_all(message.as_bytes())
+            .and_then(|()| pipe.flush())
+            .map_err(|e| format!("Failed to write to pipe {path}: {e}"));
+    }
+    let Some((program, args)) = settings.command.split_first() else {
+        return Err("The pipe backend requires pipe.path or pipe.command".to_string());
+    };
+    let output = exec
+        .run_with_input(program, args, Some(message.as_bytes()))
+        .map_err(|e| format!("Failed to run {program}: {e}"))?;
+    if !output.success {
+        return Err(format!("{program} failed: {}", output.stderr.trim()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+    use std::fs;
+
+    #[test]
+    fn test_validate() {
+        assert!(PipeSettings::default().validate().is_ok());
+        let both = PipeSettings {
+            path: Some(r"\\.\pipe\agent".to_string()),
+            command: vec!["powershell.exe".to_string()],
+        };
+        assert!(both.validate().is_err());
+        let blank = PipeSettings {
+            command: vec![" ".to_string()],
+            ..PipeSettings::default()
+        };
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn test_deliver_writes_to_existing_path() {
+        let path =
+            std::env::temp_dir().join(format!("pigeon-pipe-test-write-{}", std::process::id()));
+        let settings = PipeSettings {
+            path: Some(path.display().to_string()),
+            ..PipeSettings::default()
+        };
+        let err = deliver(&settings, &*Fake::new(), "hello").unwrap_err();
+        assert!(err.starts_with("Cannot open pipe"), "{err}");
+
+        fs::write(&path, "").unwrap();
+        deliver(&settings, &*Fake::new(), "hello").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_deliver_feeds_command_stdin() {
+        let settings = PipeSettings {
+            command: vec![
+                "powershell.exe".to_string(),
+                "-File".to_string(),
+                "forward.ps1".to_string(),
+            ],
+            ..PipeSettings::default()
+        };
+        let fake = Fake::new();
+        deliver(&settings, &*fake, "hello").unwrap();
+        assert_eq!(
+            fake.calls(),
+            [vec!["powershell.exe", "-File", "forward.ps1"]]
+        );
+        assert_eq!(fake.inputs(), [Some(b"hello".to_vec())]);
+
+        fake.fail("no agent");
+        let err = deliver(&settings, &*fake, "hello").unwrap_err();
+        assert_eq!(err, "powershell.exe failed: no agent");
+    }
+}
@@ -0,0 +1,129 @@
+//! Facts about the local environment gathered at send time and stamped onto
+//! the message as a footer when `stamp` asks for them: the branch of the
+//! mapped checkout and whether it has uncommitted changes, the OS, and the
+//! date. A probe that fails is left out rather than failing the send.
+
+use crate::git;
+use crate::strings::{self, Text};
+use crate::template;
+use crate::time;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Which facts to stamp; all off by default
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct StampSettings {
+    /// Branch checked out in the mapped checkout
+    pub branch: bool,
+    /// Whether the mapped checkout has uncommitted changes
+    pub dirty: bool,
+    /// Operating system the host runs on
+    pub os: bool,
+    /// Today's date (UTC)
+    pub date: bool,
+}
+
+impl StampSettings {
+    pub fn any(&self) -> bool {
+        self.branch || self.dirty || self.os || self.date
+    }
+}
+
+/// Facts as gathered; `None` for those not asked for or not found out
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Environment {
+    pub branch: Option<String>,
+    pub dirty: Option<bool>,
+    pub os: Option<&'static str>,
+    /// Such as `2026-10-15`
+    pub date: Option<String>,
+}
+
+/// The facts `settings` asks for. Checkout facts need `checkout`, the
+/// mapped clone (or the worktree the PR's commit was checked out in).
+pub fn gather(settings: &StampSettings, checkout: Option<&Path>, now: u64) -> Environment {
+    let wanted = |wanted: bool| checkout.filter(|_| wanted);
+    Environment {
+        branch: probe(wanted(settings.branch), "branch", git::current_branch).flatten(),
+        dirty: probe(wanted(settings.dirty), "dirty state", git::is_dirty),
+        os: settings.os.then_some(std::env::consts::OS),
+        date: settings.date.then(|| time::rfc3339(now)[..10].to_string()),
+    }
+}
+
+fn probe<T>(
+    checkout: Option<&Path>,
+    name: &str,
+    run: impl Fn(&Path) -> Result<T, git::GitError>,
+) -> Option<T> {
+    run(checkout?)
+        .map_err(|e| eprintln!("pigeon: cannot stamp {name}: {}", e.message))
+        .ok()
+}
+
+impl Environment {
+    /// The footer line in `locale`, or `None` with nothing to say
+    pub fn describe(&self, locale: &str) -> Option<String> {
+        let text = |key| strings::text(locale, key);
+        let mut facts = Vec::new();
+        if let Some(branch) = &self.branch {
+            facts.push(template::render(text(Text::Branch), &[("branch", branch)]));
+        }
+        match self.dirty {
+            Some(true) => facts.push(text(Text::Uncommitted).to_string()),
+            Some(false) => facts.push(text(Text::Clean).to_string()),
+            None => {}
+        }
+        facts.extend(self.os.map(str::to_string));
+        facts.extend(self.date.clone());
+        if facts.is_empty() {
+            return None;
+        }
+        Some(template::render(
+            text(Text::Environment),
+            &[("facts", &facts.join(", "))],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let environment = Environment {
+            branch: Some("fix-parser".to_string()),
+            dirty: Some(true),
+            os: Some("linux"),
+            date: Some("2026-10-15".to_string()),
+        };
+        assert_eq!(
+            environment.describe("en").as_deref(),
+            Some("Environment: branch fix-parser, uncommitted changes, linux, 2026-10-15")
+        );
+        assert_eq!(Environment::default().describe("en"), None);
+    }
+
+    #[test]
+    fn test_gather_only_what_is_asked() {
+        let settings = StampSettings {
+            os: true,
+            date: true,
+            ..StampSettings::default()
+        };
+        let environment = gather(&settings, None, 1_792_051_200);
+        assert_eq!(environment.os, Some(std::env::consts::OS));
+        assert_eq!(environment.date.as_deref(), Some("2026-10-15"));
+        assert_eq!(environment.branch, None);
+        // Checkout facts without a checkout are left out
+        let settings = StampSettings {
+            branch: true,
+            dirty: true,
+            ..StampSettings::default()
+        };
+        assert_eq!(gather(&settings, None, 0), Environment::default());
+    }
+}
@@ -0,0 +1,1374 @@
+//! Messages exchanged with the browser extension. Requests are tagged by
+//! `action`; every response carries `ok`.
+
+use crate::{aliases, budget, config, format, git, jobs, metrics, provider, stats, tmux, watch};
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct SendRequest {
+    /// "owner/name" of the repository, used to find a local checkout
+    pub repo: Option<String>,
+    pub file: String,
+    pub start_line: Option<u64>,
+    pub end_line: Option<u64>,
+    /// "old" for deleted lines, "new" (or absent) for current/added lines.
+    /// The provider's own names (e.g. Gerrit's PARENT/REVISION) also work.
+    /// Only diffs have sides.
+    pub side: Option<String>,
+    /// Kind of page the selection was made on: a diff unless given
+    #[serde(default)]
+    pub source_kind: format::SourceKind,
+    /// Review host the selection comes from
+    #[serde(default)]
+    pub provider: provider::Provider,
+    /// PR, MR or change number, mentioned in the message when given
+    pub review: Option<String>,
+    /// Gerrit patchset the selection belongs to
+    pub patchset: Option<u32>,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub question: String,
+    /// Base64 alternatives to `code`/`question` for content that does not
+    /// survive a JSON string intact; they take precedence when present
+    pub code_b64: Option<String>,
+    pub question_b64: Option<String>,
+    /// Base64 PNG (e.g. a screenshot) saved locally and referenced in the message
+    pub image_b64: Option<String>,
+    #[serde(default)]
+    pub tmux_target: String,
+    /// Send to each of these targets instead of `tmux_target`, reporting
+    /// each outcome in `results`
+    #[serde(default)]
+    pub tmux_targets: Vec<String>,
+    /// Lines of local context around the selection (overrides config)
+    pub expand_context: Option<u32>,
+    /// Head commit of the PR, checked out locally when enabled in config
+    pub commit_sha: Option<String>,
+    /// Head branch of the PR, available to templates
+    pub branch: Option<String>,
+    /// Name of an entry in the `templates` config to format this send with
+    pub template: Option<String>,
+    /// Number the code lines (overrides config)
+    pub number_lines: Option<bool>,
+    /// Page the selection was made on, recorded by the notes backend
+    pub url: Option<String>,
+    /// Labels such as "security" or "perf", kept in the history and audit log
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Groups follow-up questions: a send repeating the previous selection
+    /// in the same conversation refers back to it instead of repeating it
+    pub conversation_id: Option<String>,
+    /// Answer at once with a `job_id` and deliver in the background
+    #[serde(default, rename = "async")]
+    pub asynchronous: bool,
+    /// Answer at once with a `job_id` and deliver this many milliseconds
+    /// from now
+    pub deliver_after_ms: Option<u64>,
+    /// Like `deliver_after_ms`, at this time in milliseconds since the Unix
+    /// epoch
+    pub deliver_at: Option<u64>,
+    /// Type the message without submitting it; set for `stage-send`
+    #[serde(skip)]
+    pub stage: bool,
+}
+
+/// Protocol version this host speaks. Version 2 lists sessions as objects
+/// with metadata instead of bare names.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest version still answered. Requests without `protocol_version` come
+/// from extensions that predate it and are treated as version 1.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+fn legacy_protocol_version() -> u32 {
+    MIN_PROTOCOL_VERSION
+}
+
+/// A request as it arrives: the action plus the protocol version its sender
+/// speaks and the config profile it selects
+#[derive(Deserialize, JsonSchema)]
+pub struct Envelope {
+    #[serde(default = "legacy_protocol_version")]
+    pub protocol_version: u32,
+    /// Entry in the config's `profiles` to handle the request with
+    pub profile: Option<String>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+impl Envelope {
+    /// Version to answer in. `handshake` is how a client finds out what the
+    /// host supports, so it is answered whatever the client asks for.
+    pub fn negotiate(&self) -> Result<u32, String> {
+        let supported = MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION;
+        match &self.request {
+            Request::Handshake => Ok(self.protocol_version.clamp(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)),
+            _ if supported.contains(&self.protocol_version) => Ok(self.protocol_version),
+            _ => Err(format!(
+                "Unsupported protocol_version {}; this host speaks {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}",
+                self.protocol_version
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "action")]
+pub enum Request {
+    #[serde(rename = "handshake")]
+    Handshake,
+    #[serde(rename = "send")]
+    Send(SendRequest),
+    /// Format a send without delivering it or checking anything out
+    #[serde(rename = "preview")]
+    Preview(SendRequest),
+    /// Start streaming a selection too large for one message.
+    /// `code` holds the first part; the rest follows via `append-chunk`.
+    #[serde(rename = "begin-send")]
+    BeginSend {
+        upload_id: String,
+        #[serde(flatten)]
+        request: SendRequest,
+    },
+    #[serde(rename = "append-chunk")]
+    AppendChunk { upload_id: String, data: String },
+    /// Type the message into the pane without submitting it, for a later
+    /// `commit-send` or `abort-send` with the returned `stage_id`
+    #[serde(rename = "stage-send")]
+    StageSend(SendRequest),
+    /// Deliver a streamed selection once all chunks have arrived, or submit
+    /// a staged one
+    #[serde(rename = "commit-send")]
+    CommitSend {
+        upload_id: Option<String>,
+        stage_id: Option<String>,
+    },
+    /// Clear a staged message from the pane's input line
+    #[serde(rename = "abort-send")]
+    AbortSend { stage_id: String },
+    /// Clear the message staged last, or staged last into `tmux_target`,
+    /// before it is submitted
+    #[serde(rename = "undo-last")]
+    UndoLast {
+        #[serde(default)]
+        tmux_target: String,
+    },
+    #[serde(rename = "list-sessions")]
+    ListSessions,
+    /// Name `tmux_target` so requests can give `name` instead
+    #[serde(rename = "add-alias")]
+    AddAlias { name: String, tmux_target: String },
+    #[serde(rename = "remove-alias")]
+    RemoveAlias { name: String },
+    #[serde(rename = "list-aliases")]
+    ListAliases,
+    /// Every tmux pane with its target and working directory
+    #[serde(rename = "list-panes")]
+    ListPanes,
+    /// Panes working in `path` or below it
+    #[serde(rename = "find-target-by-path")]
+    FindTargetByPath { path: String },
+    /// End the tmux session containing the target, with every pane in it;
+    /// needs `lifecycle_actions`
+    #[serde(rename = "kill-session")]
+    KillSession {
+        /// Falls back to `default_target` when empty
+        #[serde(default)]
+        tmux_target: String,
+    },
+    /// Restart the program in the target's pane (`tmux respawn-pane -k`),
+    /// keeping the pane; needs `lifecycle_actions`
+    #[serde(rename = "respawn-agent")]
+    RespawnAgent {
+        /// Falls back to `default_target` when empty
+        #[serde(default)]
+        tmux_target: String,
+        /// Shell command to run instead of the one the pane started with
+        command: Option<String>,
+    },
+    /// Diagnostics: resolved tmux binary and environment
+    #[serde(rename = "doctor")]
+    Doctor,
+    /// tmux version, server state and supported features
+    #[serde(rename = "probe-environment")]
+    ProbeEnvironment,
+    /// Preview where `file` maps to in the local checkout of `repo`
+    #[serde(rename = "resolve-path")]
+    ResolvePath { repo: String, file: String },
+    /// Make `commit_sha` available in the mapped clone of `repo`
+    #[serde(rename = "checkout")]
+    Checkout {
+        repo: String,
+        commit_sha: String,
+        /// Defaults to `checkout_mode` from config
+        mode: Option<git::CheckoutMode>,
+        /// Answer at once with a `job_id` and check out in the background
+        #[serde(default, rename = "async")]
+        asynchronous: bool,
+    },
+    /// Compare the host's version with the latest release, and with
+    /// `install` replace the binary when a newer one is out
+    #[serde(rename = "check-update")]
+    CheckUpdate {
+        /// Needs `update.allow_install` in config
+        #[serde(default)]
+        install: bool,
+        /// Answer at once with a `job_id` and check in the background
+        #[serde(default, rename = "async")]
+        asynchronous: bool,
+    },
+    /// Progress or result of a job started with `"async": true`
+    #[serde(rename = "job-status")]
+    JobStatus { job_id: String },
+    /// Stop a running job, killing any process it started
+    #[serde(rename = "cancel-job")]
+    CancelJob { job_id: String },
+    /// Jobs this host knows about, optionally only those in one state
+    #[serde(rename = "list-jobs")]
+    ListJobs { state: Option<jobs::JobState> },
+    #[serde(rename = "get-config")]
+    GetConfig,
+    /// Validate and persist a partial config update; `null` resets a key
+    #[serde(rename = "set-config")]
+    SetConfig { config: serde_json::Value },
+    /// Usage counts computed from the audit log
+    #[serde(rename = "stats")]
+    Stats {
+        /// Count only sends with this tag
+        tag: Option<String>,
+    },
+    /// Distinct questions sent recently, newest first, for autocomplete
+    #[serde(rename = "list-recent-questions")]
+    ListRecentQuestions {
+        repo: Option<String>,
+        file: Option<String>,
+        tag: Option<String>,
+        limit: Option<usize>,
+    },
+    /// The agent's answer to an earlier send, read from the pane
+    #[serde(rename = "get-reply")]
+    GetReply {
+        /// Falls back to `default_target` when empty
+        #[serde(default)]
+        tmux_target: String,
+        send_id: String,
+    },
+    /// Start a job that finishes once the pane has produced output and then
+    /// stayed unchanged for `idle_secs`
+    #[serde(rename = "watch-target")]
+    WatchTarget {
+        /// Falls back to `default_target` when empty
+        #[serde(default)]
+        tmux_target: String,
+        idle_secs: Option<u64>,
+        timeout_secs: Option<u64>,
+    },
+    /// Markdown transcript of the sends to a target and the agent's replies
+    #[serde(rename = "export-transcript")]
+    ExportTranscript {
+        /// Falls back to `default_target` when empty
+        #[serde(default)]
+        tmux_target: String,
+        /// New file to write, an absolute path inside `transcripts_dir`;
+        /// without it the transcript is returned inline
+        path: Option<String>,
+    },
+    /// Counters and latency histograms since the host started
+    #[serde(rename = "metrics")]
+    Metrics {
+        #[serde(default)]
+        format: MetricsFormat,
+    },
+    /// JSON Schema of every request and response
+    #[serde(rename = "schema")]
+    Schema,
+    /// Names of the config's profiles
+    #[serde(rename = "list-profiles")]
+    ListProfiles,
+    /// Extraction failure report from the extension; needs no tmux target
+    #[serde(rename = "report-debug")]
+    ReportDebug {
+        html: String,
+        /// Free-form details about the page/selection that failed
+        context: Option<serde_json::Value>,
+    },
+}
+
+/// Wire names of every action, as accepted in `action`
+pub const ACTIONS: &[&str] = &[
+    "handshake",
+    "send",
+    "preview",
+    "begin-send",
+    "append-chunk",
+    "stage-send",
+    "commit-send",
+    "abort-send",
+    "undo-last",
+    "list-sessions",
+    "add-alias",
+    "remove-alias",
+    "list-aliases",
+    "list-panes",
+    "find-target-by-path",
+    "kill-session",
+    "respawn-agent",
+    "doctor",
+    "probe-environment",
+    "resolve-path",
+    "checkout",
+    "check-update",
+    "job-status",
+    "cancel-job",
+    "list-jobs",
+    "get-config",
+    "set-config",
+    "stats",
+    "list-recent-questions",
+    "get-reply",
+    "watch-target",
+    "export-transcript",
+    "list-profiles",
+    "schema",
+    "metrics",
+    "report-debug",
+];
+
+impl Request {
+    /// The `action` this request arrived as
+    pub fn action(&self) -> &'static str {
+        match self {
+            Request::Handshake => "handshake",
+            Request::Send(_) => "send",
+            Request::Preview(_) => "preview",
+            Request::BeginSend { .. } => "begin-send",
+            Request::AppendChunk { .. } => "append-chunk",
+            Request::StageSend(_) => "stage-send",
+            Request::CommitSend { .. } => "commit-send",
+            Request::AbortSend { .. } => "abort-send",
+            Request::UndoLast { .. } => "undo-last",
+            Request::ListSessions => "list-sessions",
+            Request::AddAlias { .. } => "add-alias",
+            Request::RemoveAlias { .. } => "remove-alias",
+            Request::ListAliases => "list-aliases",
+            Request::ListPanes => "list-panes",
+            Request::FindTargetByPath { .. } => "find-target-by-path",
+            Request::KillSession { .. } => "kill-session",
+            Request::RespawnAgent { .. } => "respawn-agent",
+            Request::Doctor => "doctor",
+            Request::ProbeEnvironment => "probe-environment",
+            Request::ResolvePath { .. } => "resolve-path",
+            Request::Checkout { .. } => "checkout",
+            Request::CheckUpdate { .. } => "check-update",
+            Request::JobStatus { .. } => "job-status",
+            Request::CancelJob { .. } => "cancel-job",
+            Request::ListJobs { .. } => "list-jobs",
+            Request::GetConfig => "get-config",
+            Request::SetConfig { .. } => "set-config",
+            Request::Stats { .. } => "stats",
+            Request::ListRecentQuestions { .. } => "list-recent-questions",
+            Request::GetReply { .. } => "get-reply",
+            Request::WatchTarget { .. } => "watch-target",
+            Request::ExportTranscript { .. } => "export-transcript",
+            Request::ListProfiles => "list-profiles",
+            Request::Schema => "schema",
+            Request::Metrics { .. } => "metrics",
+            Request::ReportDebug { .. } => "report-debug",
+        }
+    }
+}
+
+#[derive(Serialize, Default, JsonSchema)]
+pub struct SendResponse {
+    pub ok: bool,
+    /// Machine-readable error code, when the failure has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the local checkout differs from the selection (only when mapped)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_lines: Option<Vec<u64>>,
+    /// Sections cut to fit `max_code_bytes` or the `budget`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trimmed: Vec<budget::Trimmed>,
+    /// How the message was typed into the pane
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<tmux::Strategy>,
+    /// Total time spent handling a delivered send
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+    /// Pass to `get-reply` to fetch the answer (with `reply_markers` enabled)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_id: Option<String>,
+    /// What took the message because tmux was unavailable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<config::Fallback>,
+    /// How to get tmux working again, alongside `fallback`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Target actually used when `tmux_target` named a missing session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_target: Option<String>,
+    /// One entry per target of a `tmux_targets` send; `ok` is true only if
+    /// every one succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<TargetResult>>,
+    /// Pass to `commit-send` or `abort-send` to finish a `stage-send`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage_id: Option<String>,
+    /// Sends to the same target it waited for, when there were any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+}
+
+/// Outcome of a send to one of several targets
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct TargetResult {
+    pub target: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Breakdown of where a send spent its time
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Timings {
+    pub format_ms: u64,
+    /// Starting the tmux processes
+    pub tmux_spawn_ms: u64,
+    /// Waiting for tmux to finish once started
+    pub tmux_exec_ms: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct RecentQuestionsResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub questions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct GetReplyResponse {
+    pub ok: bool,
+    /// Text the agent printed after the prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a `watch-target` job
+#[derive(Serialize, JsonSchema)]
+pub struct WatchTargetResponse {
+    pub ok: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub idle: Option<watch::Idle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TranscriptResponse {
+    pub ok: bool,
+    /// Where the transcript was written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The transcript itself, when no path was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How the `metrics` action returns the metrics
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetricsFormat {
+    /// Structured, in `metrics`
+    #[default]
+    Json,
+    /// Prometheus text exposition format, in `text`
+    Prometheus,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct MetricsResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<metrics::Snapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SchemaResponse {
+    pub ok: bool,
+    pub schema: serde_json::Value,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ProfilesResponse {
+    pub ok: bool,
+    pub profiles: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PreviewResponse {
+    pub ok: bool,
+    /// Exactly the text a send would paste
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_lines: Option<Vec<u64>>,
+    /// Sections cut to fit `max_code_bytes` or the `budget`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trimmed: Vec<budget::Trimmed>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ReportDebugResponse {
+    pub ok: bool,
+    /// Where the report was saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HandshakeResponse {
+    pub ok: bool,
+    pub version: &'static str,
+    /// Lets the extension tell whether a config change has taken effect
+    pub config_generation: u64,
+    /// Version used for the rest of the session: the lower of the client's
+    /// and `PROTOCOL_VERSION`
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DoctorResponse {
+    pub ok: bool,
+    pub version: &'static str,
+    pub os: &'static str,
+    pub tmux: tmux::Discovery,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_path: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ProbeEnvironmentResponse {
+    pub ok: bool,
+    #[serde(flatten)]
+    pub environment: tmux::Environment,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ResolvePathResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Whether the file exists in the local checkout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exists: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct CheckoutResponse {
+    pub ok: bool,
+    /// Directory whose files now match the commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct CheckUpdateResponse {
+    pub ok: bool,
+    /// Version of the running host
+    pub current: &'static str,
+    /// Version of the latest release
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_available: Option<bool>,
+    /// Binary replaced by the latest release, which runs the next time the
+    /// host starts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Acknowledges an action sent with `"async": true`
+#[derive(Serialize, JsonSchema)]
+pub struct JobResponse {
+    pub ok: bool,
+    pub job_id: String,
+    /// Sends ahead of this one to the same target when it was accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct JobStatusResponse {
+    pub ok: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub status: Option<jobs::JobStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct JobEntry {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub status: jobs::JobStatus,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct JobListResponse {
+    pub ok: bool,
+    pub jobs: Vec<JobEntry>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ConfigResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<config::Config>,
+    /// Increments every time a changed config takes effect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    /// Location of the config file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StatsResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<stats::Stats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ListSessionsResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<SessionList>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AliasesResponse {
+    pub ok: bool,
+    /// Every alias, after the change for `add-alias` and `remove-alias`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<BTreeMap<String, aliases::Alias>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ListPanesResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panes: Option<Vec<tmux::Pane>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of `kill-session` and `respawn-agent`
+#[derive(Serialize, JsonSchema)]
+pub struct LifecycleResponse {
+    pub ok: bool,
+    /// Session the target is in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Panes that were killed, or the pane that was respawned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panes: Option<Vec<tmux::Pane>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct UndoResponse {
+    pub ok: bool,
+    /// Staged send that was cleared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage_id: Option<String>,
+    /// Pane whose input line was cleared
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmux_target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Sessions in the shape the client's protocol version expects
+#[derive(Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SessionList {
+    /// Version 1: session names only
+    Names(Vec<String>),
+    Detailed(Vec<tmux::Session>),
+}
+
+impl SessionList {
+    pub fn for_version(version: u32, sessions: Vec<tmux::Session>) -> Self {
+        match version {
+            1 => Self::Names(sessions.into_iter().map(|s| s.name).collect()),
+            _ => Self::Detailed(sessions),
+        }
+    }
+}
+
+/// JSON Schema of the protocol: `request` describes what the host accepts
+/// and `responses` what it answers, by type name, sharing `$defs`
+pub fn schema() -> serde_json::Value {
+    let settings = SchemaSettings::draft2020_12();
+    let mut requests = settings.clone().for_deserialize().into_generator();
+    let request = requests.subschema_for::<Envelope>();
+    let mut responses = settings.for_serialize().into_generator();
+    let mut schemas = serde_json::Map::new();
+    macro_rules! responses {
+        ($($name:ident),* $(,)?) => {
+            $(schemas.insert(stringify!($name).to_string(), responses.subschema_for::<$name>().into());)*
+        };
+    }
+    responses!(
+        SendResponse,
+        RecentQuestionsResponse,
+        GetReplyResponse,
+        WatchTargetResponse,
+        TranscriptResponse,
+        MetricsResponse,
+        ProfilesResponse,
+        PreviewResponse,
+        ReportDebugResponse,
+        HandshakeResponse,
+        DoctorResponse,
+        ProbeEnvironmentResponse,
+        ResolvePathResponse,
+        CheckoutResponse,
+        CheckUpdateResponse,
+        JobResponse,
+        JobStatusResponse,
+        JobListResponse,
+        ConfigResponse,
+        StatsResponse,
+        ListSessionsResponse,
+        ListPanesResponse,
+        LifecycleResponse,
+        UndoResponse,
+        AliasesResponse,
+        SchemaResponse,
+    );
+    let mut defs = requests.take_definitions(true);
+    for (name, def) in responses.take_definitions(true) {
+        let shared = defs.entry(name.clone()).or_insert_with(|| def.clone());
+        assert_eq!(
+            *shared, def,
+            "{name} differs between requests and responses"
+        );
+    }
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "pigeon native messaging protocol",
+        "protocol_version": PROTOCOL_VERSION,
+        "request": request,
+        "responses": schemas,
+        "$defs": defs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_published_schema_is_current() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../chrome-extension/src/protocol.schema.json");
+        let current = serde_json::to_string_pretty(&schema()).unwrap() + "\n";
+        if std::env::var_os("PIGEON_UPDATE_SCHEMA").is_some() {
+            std::fs::write(&path, &current).unwrap();
+        }
+        assert!(
+            std::fs::read_to_string(&path).is_ok_and(|published| published == current),
+            "{} is out of date; rerun the tests with PIGEON_UPDATE_SCHEMA=1",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn test_schema_covers_every_action() {
+        let schema = schema();
+        let text = schema["$defs"]["Envelope"].to_string();
+        for action in ACTIONS {
+            assert!(text.contains(&format!("\"{action}\"")), "{action}");
+        }
+        assert!(schema["responses"]["SendResponse"]["$ref"].is_string());
+    }
+
+    #[test]
+    fn test_action_names_match_wire_names() {
+        for json in [
+            r#"{"action": "handshake"}"#,
+            r#"{"action": "append-chunk", "upload_id": "u", "data": ""}"#,
+            r#"{"action": "stage-send", "file": "a.rs"}"#,
+            r#"{"action": "abort-send", "stage_id": "s"}"#,
+            r#"{"action": "undo-last"}"#,
+            r#"{"action": "list-sessions"}"#,
+            r#"{"action": "add-alias", "name": "agent", "tmux_target": "work:2.1"}"#,
+            r#"{"action": "remove-alias", "name": "agent"}"#,
+            r#"{"action": "list-aliases"}"#,
+            r#"{"action": "list-panes"}"#,
+            r#"{"action": "find-target-by-path", "path": "/src/api"}"#,
+            r#"{"action": "kill-session", "tmux_target": "work"}"#,
+            r#"{"action": "respawn-agent", "command": "claude --continue"}"#,
+            r#"{"action": "probe-environment"}"#,
+            r#"{"action": "check-update", "install": true}"#,
+            r#"{"action": "job-status", "job_id": "j"}"#,
+            r#"{"action": "cancel-job", "job_id": "j"}"#,
+            r#"{"action": "list-jobs", "state": "scheduled"}"#,
+            r#"{"action": "set-config", "config": {}}"#,
+            r#"{"action": "watch-target", "idle_secs": 3}"#,
+            r#"{"action": "export-transcript", "path": "/tmp/t.md"}"#,
+            r#"{"action": "list-profiles"}"#,
+            r#"{"action": "schema"}"#,
+            r#"{"action": "metrics", "format": "prometheus"}"#,
+            r#"{"action": "report-debug", "html": ""}"#,
+        ] {
+            let req: Request = serde_json::from_str(json).unwrap();
+            assert!(json.contains(&format!(r#""{}""#, req.action())), "{json}");
+            assert!(ACTIONS.contains(&req.action()));
+        }
+    }
+
+    #[test]
+    fn test_deserialize_send_request() {
+        let json = r#"{
+            "action": "send",
+            "file": "src/main.rs",
+            "start_line": 10,
+            "end_line": 20,
+            "side": "new",
+            "code": "fn main() {}",
+            "question": "What does this do?",
+            "tmux_target": "my-session"
+        }"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Send(SendRequest {
+                file,
+                tmux_target,
+                question,
+                ..
+            }) => {
+                assert_eq!(file, "src/main.rs");
+                assert_eq!(tmux_target, "my-session");
+                assert_eq!(question, "What does this do?");
+            }
+            _ => panic!("Expected Send variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_stats_request() {
+        let req: Request = serde_json::from_str(r#"{"action": "stats"}"#).unwrap();
+        assert!(matches!(req, Request::Stats { tag: None }));
+        let req: Request = serde_json::from_str(r#"{"action": "stats", "tag": "perf"}"#).unwrap();
+        assert!(matches!(req, Request::Stats { tag: Some(t) } if t == "perf"));
+    }
+
+    #[test]
+    fn test_deserialize_list_sessions_request() {
+        let json = r#"{"action": "list-sessions"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(req, Request::ListSessions));
+    }
+
+    #[test]
+    fn test_deserialize_send_with_optional_fields() {
+        let json = r#"{
+            "action": "send",
+            "file": "lib.rs",
+            "code": "let x = 1;",
+            "question": "",
+            "tmux_target": "dev"
+        }"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Send(SendRequest {
+                start_line,
+                end_line,
+                side,
+                ..
+            }) => {
+                assert!(start_line.is_none());
+                assert!(end_line.is_none());
+                assert!(side.is_none());
+            }
+            _ => panic!("Expected Send variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_report_debug_request() {
+        let json = r#"{
+            "action": "report-debug",
+            "html": "<div></div>",
+            "context": {"url": "https://github.com/o/r/pull/1/files"}
+        }"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::ReportDebug { html, context } => {
+                assert_eq!(html, "<div></div>");
+                assert_eq!(
+                    context.unwrap()["url"],
+                    "https://github.com/o/r/pull/1/files"
+                );
+            }
+            _ => panic!("Expected ReportDebug variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_report_debug_without_context() {
+        let json = r#"{"action": "report-debug", "html": "x"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(req, Request::ReportDebug { context: None, .. }));
+    }
+
+    #[test]
+    fn test_serialize_report_debug_response_ok() {
+        let resp = ReportDebugResponse {
+            ok: true,
+            path: Some("/tmp/debug-1.json".to_string()),
+            error: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, r#"{"ok":true,"path":"/tmp/debug-1.json"}"#);
+    }
+
+    #[test]
+    fn test_deserialize_begin_send_request() {
+        let json = r#"{
+            "action": "begin-send",
+            "upload_id": "u1",
+            "file": "big.rs",
+            "code": "",
+            "question": "Review",
+            "tmux_target": "dev"
+        }"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::BeginSend { upload_id, request } => {
+                assert_eq!(upload_id, "u1");
+                assert_eq!(request.file, "big.rs");
+                assert_eq!(request.tmux_target, "dev");
+            }
+            _ => panic!("Expected BeginSend variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_append_and_commit_requests() {
+        let json = r#"{"action": "append-chunk", "upload_id": "u1", "data": "fn a() {}"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(req, Request::AppendChunk { ref data, .. } if data == "fn a() {}"));
+
+        let json = r#"{"action": "commit-send", "upload_id": "u1"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            req,
+            Request::CommitSend { upload_id: Some(ref id), stage_id: None } if id == "u1"
+        ));
+
+        let json = r#"{"action": "commit-send", "stage_id": "s1"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            req,
+            Request::CommitSend { upload_id: None, stage_id: Some(ref id) } if id == "s1"
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_config_requests() {
+        let req: Request = serde_json::from_str(r#"{"action": "get-config"}"#).unwrap();
+        assert!(matches!(req, Request::GetConfig));
+
+        let json = r#"{"action": "set-config", "config": {"default_target": "dev"}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::SetConfig { config } => assert_eq!(config["default_target"], "dev"),
+            _ => panic!("Expected SetConfig variant"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_config_response_error() {
+        let resp = ConfigResponse {
+            ok: false,
+            config: None,
+            generation: None,
+            path: None,
+            code: None,
+            error: Some("Invalid template".to_string()),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, r#"{"ok":false,"error":"Invalid template"}"#);
+    }
+
+    #[test]
+    fn test_serialize_handshake_response() {
+        let req: Request = serde_json::from_str(r#"{"action": "handshake"}"#).unwrap();
+        assert!(matches!(req, Request::Handshake));
+
+        let resp = HandshakeResponse {
+            ok: true,
+            version: "1.2.3",
+            config_generation: 4,
+            protocol_version: 2,
+            min_protocol_version: 1,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ok":true,"version":"1.2.3","config_generation":4,"protocol_version":2,"min_protocol_version":1}"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_doctor_response() {
+        let req: Request = serde_json::from_str(r#"{"action": "doctor"}"#).unwrap();
+        assert!(matches!(req, Request::Doctor));
+
+        let resp = DoctorResponse {
+            ok: true,
+            version: "0.1.0",
+            os: "linux",
+            tmux: tmux::Discovery {
+                path: "/usr/bin/tmux".to_string(),
+                source: tmux::Source::KnownLocation,
+            },
+            config_path: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""tmux":{"path":"/usr/bin/tmux","source":"known-location"}"#));
+    }
+
+    #[test]
+    fn test_deserialize_resolve_path_request() {
+        let json = r#"{"action": "resolve-path", "repo": "acme/api", "file": "src/a.rs"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::ResolvePath { repo, file } => {
+                assert_eq!(repo, "acme/api");
+                assert_eq!(file, "src/a.rs");
+            }
+            _ => panic!("Expected ResolvePath variant"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_checkout_request() {
+        let json = r#"{
+            "action": "checkout",
+            "repo": "acme/api",
+            "commit_sha": "0123abcd",
+            "mode": "worktree",
+            "async": true
+        }"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Checkout {
+                repo,
+                commit_sha,
+                mode,
+                asynchronous,
+            } => {
+                assert_eq!(repo, "acme/api");
+                assert_eq!(commit_sha, "0123abcd");
+                assert_eq!(mode, Some(git::CheckoutMode::Worktree));
+                assert!(asynchronous);
+            }
+            _ => panic!("Expected Checkout variant"),
+        }
+    }
+
+    #[test]
+    fn test_serialize_job_status_response() {
+        let resp = JobStatusResponse {
+            ok: true,
+            status: Some(jobs::JobStatus {
+                action: "send",
+                state: jobs::JobState::Running,
+                due_at: None,
+                result: None,
+            }),
+            error: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, r#"{"ok":true,"action":"send","state":"running"}"#);
+    }
+
+    #[test]
+    fn test_serialize_job_list_response() {
+        let resp = JobListResponse {
+            ok: true,
+            jobs: vec![JobEntry {
+                job_id: "j1".to_string(),
+                status: jobs::JobStatus {
+                    action: "send",
+                    state: jobs::JobState::Scheduled,
+                    due_at: Some(1_700_000_000_000),
+                    result: None,
+                },
+            }],
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ok":true,"jobs":[{"job_id":"j1","action":"send","state":"scheduled","due_at":1700000000000}]}"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_checkout_response_error() {
+        let resp = CheckoutResponse {
+            ok: false,
+            path: None,
+            code: Some("DIRTY_WORKTREE"),
+            error: Some("uncommitted changes".to_string()),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ok":false,"code":"DIRTY_WORKTREE","error":"uncommitted changes"}"#
+        );
+    }
+
+    #[test]
+    fn test_deserialize_unknown_action_fails() {
+        let json = r#"{"action": "unknown"}"#;
+        let result: Result<Request, _> = serde_json::from_str(json);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_serialize_send_response_ok() {
+        let resp = SendResponse {
+            ok: true,
+            ..SendResponse::default()
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, r#"{"ok":true}"#);
+    }
+
+    #[test]
+    fn test_serialize_send_response_timings() {
+        let resp = SendResponse {
+            ok: true,
+            duration_ms: Some(42),
+            timings: Some(Timings {
+                format_ms: 1,
+                tmux_spawn_ms: 3,
+                tmux_exec_ms: 30,
+            }),
+            ..SendResponse::default()
+        };
+        let json: serde_json::Value = serde_json::to_value(&resp).unwrap();
+        assert_eq!(json["duration_ms"], 42);
+        assert_eq!(json["timings"]["tmux_exec_ms"], 30);
+    }
+
+    #[test]
+    fn test_serialize_send_response_results() {
+        let resp = SendResponse {
+            ok: false,
+            error: Some("1 of 2 targets failed".to_string()),
+            results: Some(vec![
+                TargetResult {
+                    target: "claude".to_string(),
+                    ok: true,
+                    code: None,
+                    error: None,
+                },
+                TargetResult {
+                    target: "codex".to_string(),
+                    ok: false,
+                    code: Some("TARGET_NOT_ALLOWED"),
+                    error: Some("not allowed".to_string()),
+                },
+            ]),
+            ..SendResponse::default()
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ok":false,"error":"1 of 2 targets failed","results":[{"target":"claude","ok":true},{"target":"codex","ok":false,"code":"TARGET_NOT_ALLOWED","error":"not allowed"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_send_response_stale() {
+        let resp = SendResponse {
+            ok: true,
+            stale: Some(true),
+            stale_lines: Some(vec![12, 14]),
+            ..SendResponse::default()
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, r#"{"ok":true,"stale":true,"stale_lines":[12,14]}"#);
+    }
+
+    #[test]
+    fn test_serialize_send_response_error() {
+        let resp = SendResponse {
+            ok: false,
+            error: Some("something went wrong".to_string()),
+            ..SendResponse::default()
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""ok":false"#));
+        assert!(json.contains(r#""error":"something went wrong""#));
+    }
+
+    #[test]
+    fn test_serialize_list_sessions_response_ok() {
+        let resp = ListSessionsResponse {
+            ok: true,
+            sessions: Some(SessionList::for_version(2, vec![session("pigeon")])),
+            error: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""ok":true"#));
+        assert!(json.contains(
+            r#""sessions":[{"name":"pigeon","attached":true,"windows":2,"last_activity":1700000000,"current_command":"claude"}]"#
+        ));
+    }
+
+    fn session(name: &str) -> tmux::Session {
+        tmux::Session {
+            name: name.to_string(),
+            attached: true,
+            windows: 2,
+            last_activity: 1_700_000_000,
+            current_command: "claude".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_v1_request_gets_session_names() {
+        let envelope: Envelope = serde_json::from_str(r#"{"action": "list-sessions"}"#).unwrap();
+        assert!(matches!(envelope.request, Request::ListSessions));
+        let version = envelope.negotiate().unwrap();
+        assert_eq!(version, 1);
+        let resp = ListSessionsResponse {
+            ok: true,
+            sessions: Some(SessionList::for_version(
+                version,
+                vec![session("pigeon"), session("dev")],
+            )),
+            error: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""sessions":["pigeon","dev"]"#), "{json}");
+    }
+
+    #[test]
+    fn test_envelope_keeps_request_fields() {
+        let json = r#"{
+            "action": "send",
+            "protocol_version": 2,
+            "file": "a.rs",
+            "start_line": 3,
+            "tmux_target": "dev",
+            "tags": ["perf"],
+            "profile": "work"
+        }"#;
+        let envelope: Envelope = serde_json::from_str(json).unwrap();
+        assert_eq!(envelope.negotiate(), Ok(2));
+        assert_eq!(envelope.profile.as_deref(), Some("work"));
+        let Request::Send(req) = envelope.request else {
+            panic!("Expected Send variant");
+        };
+        assert_eq!((req.file.as_str(), req.start_line), ("a.rs", Some(3)));
+        assert_eq!(req.tags, ["perf"]);
+    }
+
+    #[test]
+    fn test_negotiate_protocol_version() {
+        let envelope = |json| serde_json::from_str::<Envelope>(json).unwrap();
+        assert_eq!(
+            envelope(r#"{"action": "handshake", "protocol_version": 9}"#).negotiate(),
+            Ok(PROTOCOL_VERSION)
+        );
+        assert_eq!(envelope(r#"{"action": "handshake"}"#).negotiate(), Ok(1));
+        assert!(
+            envelope(r#"{"action": "list-sessions", "protocol_version": 9}"#)
+                .negotiate()
+                .is_err()
+        );
+        assert!(envelope(r#"{"action": "stats", "protocol_version": 0}"#)
+            .negotiate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_serialize_list_sessions_response_error() {
+        let resp = ListSessionsResponse {
+            ok: false,
+            sessions: None,
+            error: Some("tmux not found".to_string()),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""ok":false"#));
+        assert!(json.contains(r#""error":"tmux not found""#));
+        assert!(!json.contains("sessions"));
+    }
+}
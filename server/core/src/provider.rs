@@ -0,0 +1,152 @@
+//! Code review hosts differ in how they name diff sides and refer to a
+//! review. Each has a module here; requests say which one they came from.
+
+mod bitbucket;
+mod gerrit;
+mod github;
+mod gitlab;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where a selection was made. Defaults to GitHub, the extension's home.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Github,
+    Gitlab,
+    Gerrit,
+    Bitbucket,
+}
+
+impl Provider {
+    /// Translate the provider's name for a diff side into "old" or "new".
+    /// Values it does not know are passed through unchanged.
+    pub fn normalize_side(self, side: &str) -> &str {
+        let normalized = match self {
+            Provider::Github => github::side(side),
+            Provider::Gitlab => gitlab::side(side),
+            Provider::Gerrit => gerrit::side(side),
+            Provider::Bitbucket => bitbucket::side(side),
+        };
+        normalized.unwrap_or(side)
+    }
+
+    /// `url` of `file` in the provider's file view, pinned to commit `sha`
+    /// in place of the branch or tag it names. `None` when `url` is not a
+    /// file view URL.
+    pub fn blob_permalink(self, url: &str, file: &str, sha: &str) -> Option<String> {
+        let marker = match self {
+            Provider::Github => github::BLOB_PATH,
+            Provider::Gitlab => gitlab::BLOB_PATH,
+            Provider::Gerrit => gerrit::BLOB_PATH,
+            Provider::Bitbucket => bitbucket::BLOB_PATH,
+        }?;
+        let start = url.find(marker)? + marker.len();
+        let rest = &url[start..];
+        // The ref may contain slashes; it ends where the file path begins
+        let file = format!("/{}", file.trim_start_matches('/'));
+        let end = rest
+            .find(&file)
+            .or_else(|| rest.find(['/', '?', '#']))
+            .unwrap_or(rest.len());
+        Some(format!("{}{sha}{}", &url[..start], &rest[end..]))
+    }
+
+    /// How the provider's users refer to review `number`, e.g. "MR !12"
+    pub fn review_label(self, number: &str, patchset: Option<u32>) -> String {
+        match self {
+            Provider::Github => github::review_label(number),
+            Provider::Gitlab => gitlab::review_label(number),
+            Provider::Gerrit => gerrit::review_label(number, patchset),
+            Provider::Bitbucket => bitbucket::review_label(number),
+        }
+    }
+}
+
+/// `url` of a gist pinned to revision `sha`; gists are GitHub's alone
+pub fn gist_permalink(url: &str, sha: &str) -> Option<String> {
+    github::gist_permalink(url, sha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_side() {
+        assert_eq!(Provider::Github.normalize_side("LEFT"), "old");
+        assert_eq!(Provider::Gerrit.normalize_side("PARENT"), "old");
+        assert_eq!(Provider::Gerrit.normalize_side("REVISION"), "new");
+        assert_eq!(Provider::Bitbucket.normalize_side("FROM"), "old");
+        // The extension's own vocabulary works everywhere
+        assert_eq!(Provider::Gitlab.normalize_side("old"), "old");
+        assert_eq!(Provider::Gerrit.normalize_side("new"), "new");
+        assert_eq!(Provider::Gitlab.normalize_side("sideways"), "sideways");
+    }
+
+    #[test]
+    fn test_blob_permalink() {
+        assert_eq!(
+            Provider::Github
+                .blob_permalink(
+                    "https://github.com/acme/api/blob/feature/x/src/lib.rs#L3-L4",
+                    "src/lib.rs",
+                    "0123abc"
+                )
+                .as_deref(),
+            Some("https://github.com/acme/api/blob/0123abc/src/lib.rs#L3-L4")
+        );
+        assert_eq!(
+            Provider::Gitlab
+                .blob_permalink(
+                    "https://gitlab.com/acme/api/-/blob/main/a.rs",
+                    "a.rs",
+                    "0123abc"
+                )
+                .as_deref(),
+            Some("https://gitlab.com/acme/api/-/blob/0123abc/a.rs")
+        );
+        assert_eq!(
+            Provider::Github.blob_permalink(
+                "https://github.com/acme/api/pull/7",
+                "a.rs",
+                "0123abc"
+            ),
+            None
+        );
+        assert_eq!(
+            Provider::Gerrit.blob_permalink("https://review/c/1/blob/a.rs", "a.rs", "0123abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_gist_permalink() {
+        assert_eq!(
+            gist_permalink("https://gist.github.com/me/0f1e#file-a-rs", "0123abc").as_deref(),
+            Some("https://gist.github.com/me/0f1e/0123abc#file-a-rs")
+        );
+        assert_eq!(
+            gist_permalink("https://gist.github.com/me/0f1e/fedcba9", "0123abc").as_deref(),
+            Some("https://gist.github.com/me/0f1e/0123abc")
+        );
+        assert_eq!(
+            gist_permalink("https://github.com/me/0f1e", "0123abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_review_label() {
+        assert_eq!(Provider::Github.review_label("12", None), "PR #12");
+        assert_eq!(Provider::Gitlab.review_label("12", None), "MR !12");
+        assert_eq!(
+            Provider::Gerrit.review_label("4711", Some(3)),
+            "change 4711, patchset 3"
+        );
+        assert_eq!(Provider::Gerrit.review_label("4711", None), "change 4711");
+        assert_eq!(Provider::Bitbucket.review_label("12", None), "PR #12");
+    }
+}
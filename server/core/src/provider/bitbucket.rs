@@ -0,0 +1,16 @@
+//! Bitbucket: inline comments anchor to the FROM (source) or TO side.
+
+pub fn side(side: &str) -> Option<&'static str> {
+    match side {
+        "old" | "FROM" => Some("old"),
+        "new" | "TO" => Some("new"),
+        _ => None,
+    }
+}
+
+pub fn review_label(number: &str) -> String {
+    format!("PR #{number}")
+}
+
+/// Precedes the ref in file view URLs
+pub const BLOB_PATH: Option<&str> = Some("/src/");
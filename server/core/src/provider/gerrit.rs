@@ -0,0 +1,20 @@
+//! Gerrit: comments sit on the PARENT (base) or REVISION side of a
+//! patchset, and a change evolves through numbered patchsets.
+
+pub fn side(side: &str) -> Option<&'static str> {
+    match side {
+        "old" | "PARENT" => Some("old"),
+        "new" | "REVISION" => Some("new"),
+        _ => None,
+    }
+}
+
+pub fn review_label(change: &str, patchset: Option<u32>) -> String {
+    match patchset {
+        Some(patchset) => format!("change {change}, patchset {patchset}"),
+        None => format!("change {change}"),
+    }
+}
+
+/// File views are served by a separate browser such as Gitiles
+pub const BLOB_PATH: Option<&str> = None;
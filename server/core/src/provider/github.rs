@@ -0,0 +1,35 @@
+//! GitHub: the review API calls diff sides LEFT and RIGHT.
+
+pub fn side(side: &str) -> Option<&'static str> {
+    match side {
+        "old" | "LEFT" => Some("old"),
+        "new" | "RIGHT" => Some("new"),
+        _ => None,
+    }
+}
+
+pub fn review_label(number: &str) -> String {
+    format!("PR #{number}")
+}
+
+/// Precedes the ref in file view URLs
+pub const BLOB_PATH: Option<&str> = Some("/blob/");
+
+/// `url` of a gist, such as `https://gist.github.com/me/0123abcd#file-a-rs`,
+/// pinned to revision `sha`
+pub fn gist_permalink(url: &str, sha: &str) -> Option<String> {
+    let (url, fragment) = url.split_once('#').map_or((url, ""), |(u, f)| (u, f));
+    let (url, _query) = url.split_once('?').unwrap_or((url, ""));
+    let path = url.strip_prefix("https://gist.github.com/")?;
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let (user, id) = match segments[..] {
+        [user, id] | [user, id, _] => (user, id),
+        _ => return None,
+    };
+    let mut permalink = format!("https://gist.github.com/{user}/{id}/{sha}");
+    if !fragment.is_empty() {
+        permalink.push('#');
+        permalink.push_str(fragment);
+    }
+    Some(permalink)
+}
@@ -0,0 +1,16 @@
+//! GitLab: merge requests are referenced by their project-scoped IID with `!`.
+
+pub fn side(side: &str) -> Option<&'static str> {
+    match side {
+        "old" => Some("old"),
+        "new" => Some("new"),
+        _ => None,
+    }
+}
+
+pub fn review_label(iid: &str) -> String {
+    format!("MR !{iid}")
+}
+
+/// Precedes the ref in file view URLs
+pub const BLOB_PATH: Option<&str> = Some("/-/blob/");
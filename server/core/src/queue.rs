@@ -0,0 +1,209 @@
+use crate::lock::FileLock;
+use crate::paths;
+use crate::time;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Answered questions kept for reference; older ones are dropped on push
+const MAX_ANSWERED: usize = 100;
+
+/// A formatted message waiting for an agent to pull it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Question {
+    pub id: u64,
+    pub ts: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub file: String,
+    pub message: String,
+    #[serde(default)]
+    pub answered: bool,
+}
+
+/// Questions held for agents that pull them (see the `queue` backend),
+/// stored as one JSON file that is rewritten atomically on every change.
+pub struct Queue {
+    path: PathBuf,
+}
+
+impl Queue {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Default location: `queue.json` in the platform data directory
+    pub fn default_location() -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("queue.json")))
+    }
+
+    /// Held across each load-modify-store so concurrent hosts cannot
+    /// overwrite each other's changes
+    fn lock(&self) -> io::Result<FileLock> {
+        FileLock::acquire(&self.path.with_extension("json.lock"))
+    }
+
+    fn load(&self) -> io::Result<Vec<Question>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store(&self, questions: &[Question]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(questions)?;
+        let tmp = self
+            .path
+            .with_extension(format!("json.tmp.{}", std::process::id()));
+        fs::write(&tmp, json + "\n")?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Append a question and return it with its assigned id
+    pub fn push(
+        &self,
+        target: Option<&str>,
+        repo: Option<&str>,
+        file: &str,
+        message: &str,
+    ) -> io::Result<Question> {
+        let _lock = self.lock()?;
+        let mut questions = self.load()?;
+        let question = Question {
+            id: questions.iter().map(|q| q.id).max().unwrap_or(0) + 1,
+            ts: time::rfc3339(time::unix_now()),
+            target: target.map(String::from),
+            repo: repo.map(String::from),
+            file: file.to_string(),
+            message: message.to_string(),
+            answered: false,
+        };
+        questions.push(question.clone());
+
+        let answered = questions.iter().filter(|q| q.answered).count();
+        let mut excess = answered.saturating_sub(MAX_ANSWERED);
+        questions.retain(|q| {
+            let drop = q.answered && excess > 0;
+            excess -= usize::from(drop);
+            !drop
+        });
+
+        self.store(&questions)?;
+        Ok(question)
+    }
+
+    /// Unanswered questions, oldest first
+    pub fn pending(&self) -> io::Result<Vec<Question>> {
+        let mut questions = self.load()?;
+        questions.retain(|q| !q.answered);
+        Ok(questions)
+    }
+
+    /// Mark `id` answered. Returns false if no pending question has that id.
+    pub fn mark_answered(&self, id: u64) -> io::Result<bool> {
+        let _lock = self.lock()?;
+        let mut questions = self.load()?;
+        let Some(question) = questions.iter_mut().find(|q| q.id == id && !q.answered) else {
+            return Ok(false);
+        };
+        question.answered = true;
+        self.store(&questions)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_queue(name: &str) -> (PathBuf, Queue) {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-queue-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let queue = Queue::new(dir.join("queue.json"));
+        (dir, queue)
+    }
+
+    #[test]
+    fn test_push_and_pending() {
+        let (dir, queue) = temp_queue("push");
+        assert!(queue.pending().unwrap().is_empty());
+        let first = queue
+            .push(Some("dev"), Some("acme/api"), "a.rs", "one")
+            .unwrap();
+        let second = queue.push(None, None, "b.rs", "two").unwrap();
+        assert_eq!((first.id, second.id), (1, 2));
+        let messages: Vec<String> = queue
+            .pending()
+            .unwrap()
+            .into_iter()
+            .map(|q| q.message)
+            .collect();
+        assert_eq!(messages, ["one", "two"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mark_answered() {
+        let (dir, queue) = temp_queue("answer");
+        queue.push(None, None, "a.rs", "one").unwrap();
+        queue.push(None, None, "b.rs", "two").unwrap();
+        assert!(queue.mark_answered(1).unwrap());
+        assert!(!queue.mark_answered(1).unwrap());
+        assert!(!queue.mark_answered(42).unwrap());
+        let ids: Vec<u64> = queue.pending().unwrap().iter().map(|q| q.id).collect();
+        assert_eq!(ids, [2]);
+
+        // Ids keep increasing past answered questions
+        assert_eq!(queue.push(None, None, "c.rs", "three").unwrap().id, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers_keep_every_question() {
+        let (dir, queue) = temp_queue("concurrent");
+        let path = dir.join("queue.json");
+        let writers: Vec<_> = (0..2)
+            .map(|writer| {
+                // Separate handles, as two host processes would have
+                let queue = Queue::new(path.clone());
+                std::thread::spawn(move || {
+                    for i in 0..20 {
+                        queue
+                            .push(None, None, "a.rs", &format!("{writer}-{i}"))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let mut ids: Vec<u64> = queue.pending().unwrap().iter().map(|q| q.id).collect();
+        ids.sort();
+        assert_eq!(ids, (1..=40).collect::<Vec<u64>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_old_answered_questions_are_dropped() {
+        let (dir, queue) = temp_queue("prune");
+        for i in 1..=MAX_ANSWERED as u64 + 2 {
+            queue.push(None, None, "a.rs", "q").unwrap();
+            queue.mark_answered(i).unwrap();
+        }
+        queue.push(None, None, "a.rs", "pending").unwrap();
+        let all = queue.load().unwrap();
+        assert_eq!(all.len(), MAX_ANSWERED + 1);
+        assert_eq!(all[0].id, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
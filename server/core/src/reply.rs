@@ -0,0 +1,330 @@
+//! Reply capture: with `reply_markers` enabled, each tmux send is wrapped in
+//! marker lines carrying a send id, so the agent's answer can later be cut
+//! out of the pane's scrollback and posted as a review comment.
+//!
+//! What the pane shows is a terminal UI, not the answer alone, so the
+//! extracted text goes through a clean-up pipeline configured by
+//! `ReplySettings`: escape sequences, the agent's chrome (input box borders,
+//! spinners, key hints) and surplus blank lines are removed, and optionally
+//! hard-wrapped paragraphs are joined back into Markdown paragraphs.
+
+use crate::glob;
+use crate::sha256;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Scrollback lines searched for the markers
+pub const CAPTURE_HISTORY_LINES: u32 = 5000;
+
+/// Lines of agent TUIs that are never part of an answer
+pub const DEFAULT_CHROME: &[&str] = &["*esc to interrupt*", "? for shortcuts*", "⏵⏵*", "*⏎ send*"];
+
+/// Characters that start a spinner or status line, such as `✻ Thinking…`
+const SPINNERS: &[char] = &[
+    '⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏', '✻', '✽', '✶', '✳', '✢',
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplySettings {
+    /// Remove terminal escape sequences
+    pub strip_ansi: bool,
+    /// Remove box-drawing borders, spinner lines and lines matching `chrome`
+    pub strip_chrome: bool,
+    /// Glob patterns of further lines to remove, matched against the line
+    /// without surrounding whitespace
+    pub chrome: Vec<String>,
+    /// Keep at most one blank line in a row, and none at the ends
+    pub collapse_blank_lines: bool,
+    /// Join lines the terminal wrapped back into Markdown paragraphs
+    pub reflow: bool,
+}
+
+impl Default for ReplySettings {
+    fn default() -> Self {
+        Self {
+            strip_ansi: true,
+            strip_chrome: true,
+            chrome: DEFAULT_CHROME.iter().map(|p| p.to_string()).collect(),
+            collapse_blank_lines: true,
+            reflow: false,
+        }
+    }
+}
+
+impl ReplySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.chrome.iter().any(|p| p.trim().is_empty()) {
+            return Err("reply.chrome patterns must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Fresh id for a send. Hosts run as separate processes, so mix the clock
+/// with the process id rather than counting.
+pub fn new_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let seed = format!("{nanos}-{}", std::process::id());
+    sha256::hex_digest(seed.as_bytes())[..12].to_string()
+}
+
+fn begin_marker(id: &str) -> String {
+    format!("[pigeon:{id}]")
+}
+
+fn end_marker(id: &str) -> String {
+    format!("[/pigeon:{id}]")
+}
+
+/// Surround `message` with the begin and end markers for `id`
+pub fn wrap(message: &str, id: &str) -> String {
+    format!("{}\n{message}\n{}", begin_marker(id), end_marker(id))
+}
+
+/// The text printed after the prompt for `id`, up to the next pigeon prompt
+/// or the end of `captured`. `None` if the prompt is no longer on screen or
+/// in scrollback.
+pub fn extract(captured: &str, id: &str) -> Option<String> {
+    let end = end_marker(id);
+    let lines: Vec<&str> = captured.lines().collect();
+    // The last occurrence, in case the same prompt was pasted twice
+    let start = lines.iter().rposition(|line| line.contains(&end))? + 1;
+    let reply: Vec<&str> = lines[start..]
+        .iter()
+        .take_while(|line| !line.contains("[pigeon:"))
+        .map(|line| line.trim_end())
+        .collect();
+    Some(reply.join("\n").trim_matches('\n').to_string())
+}
+
+/// `reply` run through the steps `settings` enables
+pub fn clean(reply: &str, settings: &ReplySettings) -> String {
+    let mut text = reply.to_string();
+    if settings.strip_ansi {
+        text = strip_ansi(&text);
+    }
+    if settings.strip_chrome {
+        text = text
+            .lines()
+            .filter(|line| !is_chrome(line, &settings.chrome))
+            .map(strip_border)
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    if settings.reflow {
+        text = reflow(&text);
+    }
+    if settings.collapse_blank_lines {
+        text = collapse_blank_lines(&text);
+    }
+    text
+}
+
+/// `text` without CSI (`ESC [ … final`), OSC (`ESC ] … BEL` or `ESC \`)
+/// and other escape sequences
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // Intermediate bytes, as in `ESC ( B`, then the final one
+            Some(' '..='/') => {
+                while chars.next_if(|c| (' '..='/').contains(c)).is_some() {}
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+fn is_box_drawing(c: char) -> bool {
+    ('\u{2500}'..='\u{257f}').contains(&c)
+}
+
+/// Whether `line` belongs to the TUI rather than the answer: a border, a
+/// spinner or status line, or a configured pattern
+fn is_chrome(line: &str, patterns: &[String]) -> bool {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return false;
+    }
+    trimmed
+        .chars()
+        .all(|c| is_box_drawing(c) || c.is_whitespace())
+        || trimmed.starts_with(SPINNERS)
+        || patterns.iter().any(|p| glob::matches(p, trimmed))
+}
+
+/// `line` without the side borders of a box drawn around it
+fn strip_border(line: &str) -> &str {
+    let trimmed = line.trim();
+    match trimmed
+        .strip_prefix(['│', '┃'])
+        .and_then(|inner| inner.strip_suffix(['│', '┃']))
+    {
+        Some(inner) => inner.strip_prefix(' ').unwrap_or(inner).trim_end(),
+        None => line,
+    }
+}
+
+fn collapse_blank_lines(text: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let blank = line.trim().is_empty();
+        if blank && out.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        out.push(if blank { "" } else { line });
+    }
+    while out.last() == Some(&"") {
+        out.pop();
+    }
+    out.join("\n")
+}
+
+/// Join consecutive lines of a paragraph. Fenced code, lists, headings,
+/// quotes, tables and indented blocks keep their lines.
+fn reflow(text: &str) -> String {
+    let mut out: Vec<String> = Vec::new();
+    let mut fenced = false;
+    let mut joinable = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            out.push(line.to_string());
+            joinable = false;
+            continue;
+        }
+        if fenced || trimmed.is_empty() {
+            out.push(line.to_string());
+            joinable = false;
+            continue;
+        }
+        let structural = trimmed.starts_with(['#', '>', '|', '-', '*', '+', '●', '•'])
+            || trimmed
+                .split_once(". ")
+                .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        let indented = line.starts_with("    ") || line.starts_with('\t');
+        match out.last_mut() {
+            Some(last) if joinable && !structural && !indented => {
+                last.push(' ');
+                last.push_str(trimmed);
+            }
+            _ => out.push(line.trim_end().to_string()),
+        }
+        joinable = !indented;
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_ids_differ() {
+        let id = new_id();
+        assert_eq!(id.len(), 12);
+        assert_ne!(id, new_id());
+    }
+
+    #[test]
+    fn test_extract_reply_after_prompt() {
+        let captured = format!(
+            "$ claude\n> {}\n\n● It parses the header.\n  Then it returns.   \n\n",
+            wrap("src/a.rs:1\nWhy?", "abc")
+        );
+        assert_eq!(
+            extract(&captured, "abc").unwrap(),
+            "● It parses the header.\n  Then it returns."
+        );
+    }
+
+    #[test]
+    fn test_extract_stops_at_next_prompt() {
+        let captured = format!(
+            "{}\nfirst answer\n{}\nsecond answer\n",
+            wrap("one", "a1"),
+            wrap("two", "b2")
+        );
+        assert_eq!(extract(&captured, "a1").unwrap(), "first answer");
+        assert_eq!(extract(&captured, "b2").unwrap(), "second answer");
+    }
+
+    #[test]
+    fn test_extract_missing_prompt() {
+        assert_eq!(extract("nothing here\n", "abc"), None);
+        // The end marker is what counts; a half-visible prompt has no answer yet
+        assert_eq!(extract("[pigeon:abc]\npartial", "abc"), None);
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[1;32mok\x1b[0m \x1b]0;title\x07done\x1b]8;;x\x1b\\ \x1b(Bend"),
+            "ok done end"
+        );
+    }
+
+    #[test]
+    fn test_clean_removes_chrome_and_blank_runs() {
+        let reply = "● It parses the header.\n\n\n  Then it returns.\n\n\
+                     ✻ Thinking… (3s · esc to interrupt)\n\
+                     ╭──────────────╮\n│ > next       │\n╰──────────────╯\n\
+                     ? for shortcuts\n\n";
+        assert_eq!(
+            clean(reply, &ReplySettings::default()),
+            "● It parses the header.\n\n  Then it returns.\n\n> next"
+        );
+        let raw = ReplySettings {
+            strip_ansi: false,
+            strip_chrome: false,
+            chrome: Vec::new(),
+            collapse_blank_lines: false,
+            reflow: false,
+        };
+        assert_eq!(clean(reply, &raw), reply);
+    }
+
+    #[test]
+    fn test_reflow_joins_paragraphs_only() {
+        let reply = "The parser reads the\nheader first, then\nthe body.\n\n\
+                     - one\n- two\n\n```rust\nlet a = 1;\nlet b = 2;\n```\n\
+                     1. first\n2. second";
+        let settings = ReplySettings {
+            reflow: true,
+            ..ReplySettings::default()
+        };
+        assert_eq!(
+            clean(reply, &settings),
+            "The parser reads the header first, then the body.\n\n\
+             - one\n- two\n\n```rust\nlet a = 1;\nlet b = 2;\n```\n\
+             1. first\n2. second"
+        );
+    }
+}
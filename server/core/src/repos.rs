@@ -0,0 +1,88 @@
+use crate::config::Config;
+use std::path::{Component, Path, PathBuf};
+
+/// Local checkout directory for `repo` ("owner/name"). An exact
+/// "owner/name" key wins over a bare "name" key.
+pub fn checkout_dir<'a>(config: &'a Config, repo: &str) -> Option<&'a Path> {
+    let name = repo.rsplit('/').next().unwrap_or(repo);
+    config
+        .repos
+        .get(repo)
+        .or_else(|| config.repos.get(name))
+        .map(Path::new)
+}
+
+/// Absolute local path of `file` inside the mapped checkout of `repo`.
+/// Returns `None` when the repository is not mapped or `file` would
+/// escape the checkout (absolute paths, `..` components).
+pub fn resolve(config: &Config, repo: &str, file: &str) -> Option<PathBuf> {
+    resolve_in(checkout_dir(config, repo)?, file)
+}
+
+/// `file` joined onto `dir`, unless it would escape `dir`
+pub fn resolve_in(dir: &Path, file: &str) -> Option<PathBuf> {
+    let relative = Path::new(file);
+    let safe = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !safe || file.is_empty() {
+        return None;
+    }
+    Some(dir.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(entries: &[(&str, &str)]) -> Config {
+        Config {
+            repos: entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_full_name() {
+        let config = config(&[("acme/backend", "/home/me/src/backend")]);
+        assert_eq!(
+            resolve(&config, "acme/backend", "src/api.rs"),
+            Some(PathBuf::from("/home/me/src/backend/src/api.rs"))
+        );
+    }
+
+    #[test]
+    fn test_resolve_bare_name() {
+        let config = config(&[("backend", "/src/backend")]);
+        assert_eq!(
+            resolve(&config, "acme/backend", "lib.rs"),
+            Some(PathBuf::from("/src/backend/lib.rs"))
+        );
+    }
+
+    #[test]
+    fn test_full_name_wins_over_bare_name() {
+        let config = config(&[("backend", "/bare"), ("acme/backend", "/full")]);
+        assert_eq!(
+            checkout_dir(&config, "acme/backend"),
+            Some(Path::new("/full"))
+        );
+    }
+
+    #[test]
+    fn test_unmapped_repo() {
+        let config = config(&[("acme/backend", "/src/backend")]);
+        assert_eq!(resolve(&config, "acme/frontend", "a.ts"), None);
+    }
+
+    #[test]
+    fn test_rejects_escaping_paths() {
+        let config = config(&[("acme/backend", "/src/backend")]);
+        assert_eq!(resolve(&config, "acme/backend", "../secrets"), None);
+        assert_eq!(resolve(&config, "acme/backend", "/etc/passwd"), None);
+        assert_eq!(resolve(&config, "acme/backend", ""), None);
+    }
+}
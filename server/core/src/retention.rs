@@ -0,0 +1,158 @@
+//! How long the stores on disk keep what they hold. The history, the audit
+//! log, debug reports and attachments each cap their size as they are
+//! written; a sweep also drops what has grown older than its store's age
+//! limit, or beyond a size limit set here. Hosts sweep once on startup, and
+//! the daemon again every `sweep_interval_secs`.
+
+use crate::attachments;
+use crate::audit;
+use crate::config::Config;
+use crate::debug;
+use crate::history;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Shortest interval accepted between sweeps
+pub const MIN_SWEEP_INTERVAL_SECS: u64 = 60;
+
+/// Limits of one store; 0 leaves the limit off
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Drop what is older than this many days
+    pub max_age_days: u32,
+    /// Drop the oldest until the store takes at most this many bytes
+    pub max_bytes: u64,
+}
+
+impl Policy {
+    fn days(max_age_days: u32) -> Self {
+        Self {
+            max_age_days,
+            max_bytes: 0,
+        }
+    }
+
+    /// Anything last written before this is too old
+    pub fn cutoff(&self, now: SystemTime) -> Option<SystemTime> {
+        let age = Duration::from_secs(u64::from(self.max_age_days) * 86_400);
+        (self.max_age_days > 0).then(|| {
+            now.checked_sub(age)
+                .map_or(UNIX_EPOCH, |t| t.max(UNIX_EPOCH))
+        })
+    }
+
+    /// Whether a store taking `bytes` is over its size limit
+    pub fn exceeded_by(&self, bytes: u64) -> bool {
+        self.max_bytes > 0 && bytes > self.max_bytes
+    }
+}
+
+/// Limits of every store, in one place
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionSettings {
+    pub history: Policy,
+    /// Whole rotated files are dropped, once their last line is too old
+    pub audit: Policy,
+    pub debug: Policy,
+    pub attachments: Policy,
+    /// Seconds between sweeps in daemon mode
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            history: Policy::days(90),
+            // Kept for review; rotation already bounds its size
+            audit: Policy::default(),
+            debug: Policy::days(30),
+            attachments: Policy::days(7),
+            sweep_interval_secs: 60 * 60,
+        }
+    }
+}
+
+impl RetentionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sweep_interval_secs < MIN_SWEEP_INTERVAL_SECS {
+            return Err(format!(
+                "retention.sweep_interval_secs must be at least {MIN_SWEEP_INTERVAL_SECS}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What a sweep removed: history entries, audit files, debug reports and
+/// attachments
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Swept {
+    pub history: usize,
+    pub audit: usize,
+    pub debug: usize,
+    pub attachments: usize,
+}
+
+/// Apply the `retention` limits of `config` to every store. A store that
+/// cannot be swept is reported on stderr and left for the next sweep.
+pub fn sweep(config: &Config, now: SystemTime) -> Swept {
+    let settings = &config.retention;
+    let history = history::History::default_location(&config.history)
+        .map(|history| history.sweep(&settings.history, now));
+    let audit =
+        audit::AuditLog::default_location(&config.audit).map(|log| log.sweep(&settings.audit, now));
+    let debug = debug::default_store(&config.debug).map(|store| store.sweep(&settings.debug, now));
+    let attachments =
+        attachments::default_store().map(|store| store.sweep(&settings.attachments, now));
+    Swept {
+        history: removed("history entries", history),
+        audit: removed("audit log files", audit),
+        debug: removed("debug reports", debug),
+        attachments: removed("attachments", attachments),
+    }
+}
+
+fn removed(what: &str, result: Option<io::Result<usize>>) -> usize {
+    match result {
+        Some(Ok(0)) | None => 0,
+        Some(Ok(n)) => {
+            eprintln!("pigeon: removed {n} old {what}");
+            n
+        }
+        Some(Err(e)) => {
+            eprintln!("pigeon: cannot sweep {what}: {e}");
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cutoff() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10 * 86_400);
+        assert_eq!(Policy::default().cutoff(now), None);
+        assert_eq!(
+            Policy::days(3).cutoff(now),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(7 * 86_400))
+        );
+        assert_eq!(Policy::days(30).cutoff(now), Some(SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn test_exceeded_by() {
+        assert!(!Policy::default().exceeded_by(u64::MAX));
+        let capped = Policy {
+            max_age_days: 0,
+            max_bytes: 100,
+        };
+        assert!(!capped.exceeded_by(100));
+        assert!(capped.exceeded_by(101));
+    }
+}
@@ -0,0 +1,1434 @@
+//! The send pipeline: resolve the local checkout, format the message and
+//! deliver it to tmux, then notify and audit.
+
+use crate::config::{Backend, Fallback};
+use crate::protocol::{SendRequest, Timings};
+use crate::{
+    aliases, attachments, audit, base64, budget, classify, clipboard, config, context, diff, emacs,
+    exec, format, fuzzy, git, history, infer, notes, notify, paths, pipe, probes, provider, queue,
+    reply, repos, sha256, thread, tmux, transcript, vscode, watch,
+};
+use std::time::{Duration, Instant};
+
+/// Error with an optional machine-readable code
+#[derive(Debug)]
+pub struct Failure {
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self {
+            code: None,
+            message,
+        }
+    }
+}
+
+impl From<transcript::TranscriptError> for Failure {
+    fn from(e: transcript::TranscriptError) -> Self {
+        Self {
+            code: Some(e.code),
+            message: e.message,
+        }
+    }
+}
+
+impl From<attachments::AttachmentError> for Failure {
+    fn from(e: attachments::AttachmentError) -> Self {
+        Self {
+            code: Some(e.code),
+            message: e.message,
+        }
+    }
+}
+
+impl From<git::GitError> for Failure {
+    fn from(e: git::GitError) -> Self {
+        Self {
+            code: Some(e.code),
+            message: e.message,
+        }
+    }
+}
+
+/// Replace `code`/`question` with their decoded `_b64` counterparts. NUL
+/// characters are dropped because they cannot be passed to tmux.
+pub fn decode_payload(req: &mut SendRequest) -> Result<(), Failure> {
+    for (name, encoded, target) in [
+        ("code_b64", req.code_b64.take(), &mut req.code),
+        ("question_b64", req.question_b64.take(), &mut req.question),
+    ] {
+        let Some(encoded) = encoded else { continue };
+        let invalid = |reason: String| Failure {
+            code: Some("INVALID_BASE64"),
+            message: format!("Invalid {name}: {reason}"),
+        };
+        let bytes = base64::decode(&encoded).map_err(invalid)?;
+        let text = String::from_utf8(bytes).map_err(|e| invalid(e.to_string()))?;
+        *target = text.replace('\0', "");
+    }
+    Ok(())
+}
+
+/// Status-line flash shown after pasting when `display_message` is enabled
+const DISPLAY_MESSAGE_TEXT: &str = "pigeon: new question from browser";
+
+/// Facts about a delivered message reported back to the caller
+#[derive(Default)]
+pub struct SendOutcome {
+    /// Local lines that differ from the selection, when a checkout is mapped
+    pub stale_lines: Option<Vec<u64>>,
+    pub strategy: Option<tmux::Strategy>,
+    pub duration_ms: Option<u64>,
+    pub timings: Option<Timings>,
+    pub send_id: Option<String>,
+    /// What took the message because tmux was unavailable
+    pub fallback: Option<Fallback>,
+    /// How to get tmux working again, when a fallback was used
+    pub hint: Option<String>,
+    /// Target the send went to after resolving an alias, `session_matching`
+    /// corrected it, or `infer_target` found it
+    pub resolved_target: Option<String>,
+    /// Sections cut to fit `max_code_bytes` or the budget
+    pub trimmed: Vec<budget::Trimmed>,
+}
+
+pub fn send(req: &SendRequest, config: &config::Config) -> Result<SendOutcome, Failure> {
+    send_routed(route(req, config), config)
+}
+
+/// A request with its classifiers applied and its target as it will be
+/// delivered to, from `route`
+pub struct Routed {
+    req: SendRequest,
+    resolved_target: Option<String>,
+    /// Why no target could be inferred
+    unroutable: Option<String>,
+    started: Instant,
+}
+
+impl Routed {
+    /// The target the send goes to
+    pub fn target<'a>(&'a self, config: &'a config::Config) -> Option<&'a str> {
+        target(&self.req, config)
+    }
+}
+
+/// Apply the classifiers to `req` and settle its target: an alias or
+/// `default_target` followed, the session corrected by `session_matching`,
+/// or the target inferred from the repository
+pub fn route(req: &SendRequest, config: &config::Config) -> Routed {
+    let started = Instant::now();
+    let mut req = classify::apply(&config.classifiers, req).unwrap_or_else(|| req.clone());
+    let tmux = tmux::Tmux::new(config);
+    let aliased = relocate(&req.tmux_target, config, &tmux);
+    if let Some(target) = &aliased {
+        req.tmux_target = target.clone();
+    }
+    let inferred = inferable(&req, config)
+        .map(|repo| infer::infer_target(config, repo, req.branch.as_deref(), &tmux));
+    let resolved_target = match &inferred {
+        Some(Ok(target)) => Some(target.clone()),
+        _ => resolve_session(&req, config, &tmux).or(aliased),
+    };
+    if let Some(target) = &resolved_target {
+        req.tmux_target = target.clone();
+    }
+    Routed {
+        req,
+        resolved_target,
+        unroutable: inferred.and_then(Result::err),
+        started,
+    }
+}
+
+/// Deliver a request `route` prepared
+pub fn send_routed(routed: Routed, config: &config::Config) -> Result<SendOutcome, Failure> {
+    let Routed {
+        req,
+        resolved_target,
+        unroutable,
+        started,
+    } = routed;
+    let req = &req;
+    let mut result = match unroutable {
+        Some(reason) => Err(Failure::from(format!(
+            "No tmux target given, no default_target configured, and {reason}"
+        ))),
+        None => deliver(req, config),
+    };
+    if let Ok(outcome) = &mut result {
+        outcome.resolved_target = resolved_target;
+        let duration_ms = millis(started.elapsed());
+        if let Some(t) = &outcome.timings {
+            eprintln!(
+                "pigeon: delivered {} in {duration_ms}ms (format {}ms, tmux spawn {}ms, tmux exec {}ms)",
+                req.file, t.format_ms, t.tmux_spawn_ms, t.tmux_exec_ms
+            );
+        }
+        outcome.duration_ms = Some(duration_ms);
+    }
+    let location = format!("{}:{}", req.file, req.start_line.unwrap_or_default());
+    match &result {
+        Ok(_) => notify::notify(
+            &config.notifications,
+            notify::Event::SendSucceeded,
+            "pigeon: delivered",
+            &location,
+        ),
+        Err(e) => notify::notify(
+            &config.notifications,
+            notify::Event::SendFailed,
+            "pigeon: delivery failed",
+            &format!("{location}\n{}", e.message),
+        ),
+    }
+    if config.audit.enabled {
+        audit(req, config, result.as_ref().map(|_| ()));
+    }
+    if let (true, Ok(outcome)) = (config.history.enabled, &result) {
+        record_history(req, config, outcome.send_id.as_deref());
+    }
+    result
+}
+
+/// Remember a delivered send for `list-recent-questions`. Like the audit
+/// log, a failure here is reported on stderr only.
+fn record_history(req: &SendRequest, config: &config::Config, send_id: Option<&str>) {
+    let Some(history) = history::History::default_location(&config.history) else {
+        eprintln!("pigeon: no data directory for the history");
+        return;
+    };
+    let entry = history::HistoryEntry {
+        tags: req.tags.clone(),
+        send_id: send_id.map(String::from),
+        ..history::HistoryEntry::new(
+            target(req, config).unwrap_or_default(),
+            req.repo.as_deref(),
+            &req.file,
+            &req.question,
+        )
+    };
+    if let Err(e) = history.append(entry) {
+        eprintln!("pigeon: failed to write history: {e}");
+    }
+}
+
+/// Record the send in the audit log. A broken log must not block delivery,
+/// so failures are reported on stderr only.
+fn audit(req: &SendRequest, config: &config::Config, result: Result<(), &Failure>) {
+    let entry = audit::AuditEntry {
+        tags: req.tags.clone(),
+        ..audit::AuditEntry::new(
+            target(req, config).unwrap_or_default(),
+            req.repo.as_deref(),
+            &req.file,
+            &req.question,
+            &req.code,
+            result.map_err(|e| e.message.as_str()),
+        )
+    };
+    let Some(log) = audit::AuditLog::default_location(&config.audit) else {
+        eprintln!("pigeon: no data directory for the audit log");
+        return;
+    };
+    if let Err(e) = log.append(&entry) {
+        eprintln!("pigeon: failed to write audit log: {e}");
+    }
+}
+
+/// Send `req` to each of its `tmux_targets` in turn. One target failing does
+/// not stop the others.
+pub fn broadcast(
+    req: &SendRequest,
+    config: &config::Config,
+) -> Vec<(String, Result<SendOutcome, Failure>)> {
+    req.tmux_targets
+        .iter()
+        .map(|target| {
+            let single = SendRequest {
+                tmux_target: target.clone(),
+                tmux_targets: Vec::new(),
+                ..req.clone()
+            };
+            (target.clone(), send(&single, config))
+        })
+        .collect()
+}
+
+/// The request's `repo` when its target is to be inferred from it
+fn inferable<'a>(req: &'a SendRequest, config: &config::Config) -> Option<&'a str> {
+    let wanted = config.infer_target && config.backend == Backend::Tmux;
+    match (wanted, target(req, config)) {
+        (true, None) => req.repo.as_deref(),
+        _ => None,
+    }
+}
+
+/// When `session_matching` is on and the target's session does not exist,
+/// the same target in the closest existing session. Anything unexpected
+/// leaves the target alone for delivery to report.
+fn resolve_session(
+    req: &SendRequest,
+    config: &config::Config,
+    tmux: &tmux::Tmux,
+) -> Option<String> {
+    let settings = &config.session_matching;
+    if !settings.enabled || config.backend != Backend::Tmux {
+        return None;
+    }
+    let target = target(req, config)?;
+    // Pane, window and session ids, and exact-match syntax, are not names
+    if target.starts_with(['%', '@', '$', '=']) {
+        return None;
+    }
+    let split = target.find([':', '.']).unwrap_or(target.len());
+    let (session, rest) = target.split_at(split);
+    let sessions = tmux.list_sessions().ok()?;
+    if sessions.iter().any(|s| s.name == session) {
+        return None;
+    }
+    let name = fuzzy::closest(
+        session,
+        sessions.iter().map(|s| s.name.as_str()),
+        settings.max_distance,
+    )?;
+    eprintln!("pigeon: resolved tmux session {session} to {name}");
+    Some(format!("{name}{rest}"))
+}
+
+/// The request's tmux target, falling back to `default_target`
+pub(crate) fn target<'a>(req: &'a SendRequest, config: &'a config::Config) -> Option<&'a str> {
+    resolve_target(&req.tmux_target, config)
+}
+
+/// Every target `req` goes to, as given, for taking turns at them
+pub fn targets(req: &SendRequest, config: &config::Config) -> Vec<String> {
+    if !req.tmux_targets.is_empty() {
+        return req.tmux_targets.clone();
+    }
+    target(req, config)
+        .map(str::to_string)
+        .into_iter()
+        .collect()
+}
+
+/// Where an alias, or `default_target` when `given` is empty, points now.
+/// Panes are only followed on the tmux backend.
+fn relocate(given: &str, config: &config::Config, tmux: &tmux::Tmux) -> Option<String> {
+    let tmux = Some(tmux).filter(|_| config.backend == Backend::Tmux);
+    aliases::resolve(given, config.default_target.as_deref(), tmux)
+}
+
+/// `given`, or `default_target` when empty, with an alias replaced by the
+/// pane it points at
+fn concrete_target(
+    given: &str,
+    config: &config::Config,
+    tmux: &tmux::Tmux,
+) -> Result<String, Failure> {
+    let target = resolve_target(given, config)
+        .ok_or_else(|| "No tmux target given and no default_target configured".to_string())?;
+    Ok(relocate(given, config, tmux).unwrap_or_else(|| target.to_string()))
+}
+
+/// `concrete_target`, refused unless `allowed_targets` lets it in: reading
+/// a pane is held to the same list as typing into it
+fn readable_target(
+    given: &str,
+    config: &config::Config,
+    tmux: &tmux::Tmux,
+) -> Result<String, Failure> {
+    let target = concrete_target(given, config, tmux)?;
+    if !config.target_allowed(&target) {
+        return Err(Failure {
+            code: Some("TARGET_NOT_ALLOWED"),
+            message: format!("tmux target {target} is not in allowed_targets"),
+        });
+    }
+    Ok(target)
+}
+
+fn resolve_target<'a>(given: &'a str, config: &'a config::Config) -> Option<&'a str> {
+    match (given, &config.default_target) {
+        ("", default) => default.as_deref(),
+        (target, _) => Some(target),
+    }
+}
+
+fn deliver(req: &SendRequest, config: &config::Config) -> Result<SendOutcome, Failure> {
+    let target = target(req, config);
+    if req.stage && config.backend != Backend::Tmux {
+        return Err(Failure::from(
+            "Only the tmux backend can stage a send".to_string(),
+        ));
+    }
+    if config.backend == Backend::Tmux {
+        match target {
+            None => {
+                return Err(Failure::from(
+                    "No tmux target given and no default_target configured".to_string(),
+                ))
+            }
+            // Checked before anything else happens on the request's behalf
+            Some(target) if !config.target_allowed(target) => {
+                return Err(Failure {
+                    code: Some("TARGET_NOT_ALLOWED"),
+                    message: format!("tmux target {target} is not in allowed_targets"),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    check_template(req, config)?;
+    let tmux = tmux::Tmux::new(config);
+    let checked_out = match (&req.repo, &req.commit_sha) {
+        (Some(repo), Some(sha)) if config.checkout_before_send => {
+            Some(checkout_commit(config, repo, sha, config.checkout_mode)?)
+        }
+        _ => None,
+    };
+    let attachment = match &req.image_b64 {
+        Some(encoded) => Some(tmux.host_path(&attachments::save_png(encoded)?)),
+        None => None,
+    };
+    let location = thread_location(req);
+    let turn = target.map(|target| thread::Turn {
+        target,
+        location: &location,
+        conversation_id: req.conversation_id.as_deref(),
+    });
+    let threaded = config.backend == Backend::Tmux
+        && (config.thread_follow_ups || req.conversation_id.is_some());
+    let threads = thread::Threads::default_location().filter(|_| threaded);
+    let follow_up = match (&threads, &turn) {
+        (Some(threads), Some(turn)) => is_follow_up(threads, turn, config),
+        _ => false,
+    };
+    let format_started = Instant::now();
+    let composed = compose(
+        req,
+        config,
+        &tmux,
+        checked_out.as_deref(),
+        attachment.as_deref(),
+        follow_up,
+    );
+    let format_time = format_started.elapsed();
+    let mut send_id = None;
+    let mut fallback = None;
+    let delivery = match config.backend {
+        Backend::Tmux => {
+            let target = target.unwrap_or_default();
+            match deliver_tmux(req, config, &tmux, target, &composed.message) {
+                Ok((delivery, id)) => {
+                    send_id = id;
+                    if let (Some(threads), Some(turn)) = (&threads, &turn) {
+                        if let Err(e) = threads.record_now(turn) {
+                            eprintln!("pigeon: failed to record thread: {e}");
+                        }
+                    }
+                    Some(delivery)
+                }
+                Err(e) => {
+                    // Staged text must land in the pane or nowhere
+                    let Some(reason) =
+                        tmux::Unavailable::from_error(&e.message).filter(|_| !req.stage)
+                    else {
+                        return Err(e);
+                    };
+                    fallback = Some(fall_back(
+                        req,
+                        config,
+                        target,
+                        &composed.message,
+                        reason,
+                        e,
+                    )?);
+                    None
+                }
+            }
+        }
+        Backend::Queue => {
+            queue_message(req, target, &composed.message)?;
+            None
+        }
+        Backend::Notes => {
+            append_note(req, config, &composed.message)?;
+            None
+        }
+        Backend::Pipe => {
+            pipe::deliver(&config.pipe, &exec::System, &composed.message)?;
+            None
+        }
+        Backend::Emacs => {
+            let file = composed.local_path.as_deref().map(|p| (p, req.start_line));
+            emacs::deliver(&config.emacs, &exec::System, &composed.message, file)?;
+            None
+        }
+        Backend::Vscode => {
+            hand_off_to_vscode(
+                req,
+                config,
+                &exec::System,
+                &composed,
+                checked_out.as_deref(),
+            )?;
+            None
+        }
+    };
+    let noted = fallback
+        .as_ref()
+        .is_some_and(|(via, _)| *via == Fallback::Notes);
+    if config.notes.mirror && config.backend != Backend::Notes && !noted {
+        // The message is already delivered; a missing inbox copy is not a failure
+        if let Some(notes) = notes::NotesFile::from_settings(&config.notes) {
+            if let Err(e) = notes.append(&req.file, req.url.as_deref(), &composed.message) {
+                eprintln!("pigeon: failed to append to notes file: {e}");
+            }
+        }
+    }
+    let (fallback, hint) = fallback.unzip();
+    Ok(SendOutcome {
+        stale_lines: composed.comparison.map(|c| c.differing_lines),
+        trimmed: composed.trimmed,
+        strategy: delivery.map(|d| d.strategy),
+        duration_ms: None,
+        timings: Some(Timings {
+            format_ms: millis(format_time),
+            tmux_spawn_ms: delivery.map_or(0, |d| millis(d.spawn_time)),
+            tmux_exec_ms: delivery.map_or(0, |d| millis(d.exec_time)),
+        }),
+        send_id,
+        fallback,
+        hint,
+        resolved_target: None,
+    })
+}
+
+/// Type the message into `target`, with the extras the config asks for.
+/// Returns the marker id when `reply_markers` is enabled.
+fn deliver_tmux(
+    req: &SendRequest,
+    config: &config::Config,
+    tmux: &tmux::Tmux,
+    target: &str,
+    message: &str,
+) -> Result<(tmux::Delivery, Option<String>), Failure> {
+    if config.confirm_before_send && !tmux.confirm(target, &confirmation_summary(req, message))? {
+        return Err(Failure {
+            code: Some("NOT_CONFIRMED"),
+            message: format!("Send to {target} was declined in tmux"),
+        });
+    }
+    let submit = if req.stage {
+        Vec::new()
+    } else {
+        config.submit_keys(target)
+    };
+    let (delivery, send_id) = if config.reply_markers {
+        let id = reply::new_id();
+        (
+            tmux.send(&reply::wrap(message, &id), target, &submit)?,
+            Some(id),
+        )
+    } else {
+        (tmux.send(message, target, &submit)?, None)
+    };
+    if config.display_message {
+        // The prompt has already landed; a missing status flash is not a failure
+        if let Err(e) = tmux.display_message(target, DISPLAY_MESSAGE_TEXT) {
+            eprintln!("pigeon: {e}");
+        }
+    }
+    Ok((delivery, send_id))
+}
+
+/// Try `fallbacks` in order once tmux turned out to be unreachable. Returns
+/// the one that took the message and a hint for fixing tmux; with none left,
+/// the send fails with the hint attached.
+fn fall_back(
+    req: &SendRequest,
+    config: &config::Config,
+    target: &str,
+    message: &str,
+    reason: tmux::Unavailable,
+    error: Failure,
+) -> Result<(Fallback, String), Failure> {
+    let os = std::env::consts::OS;
+    let hint = reason.hint(os, target);
+    for &fallback in &config.fallbacks {
+        let result = match fallback {
+            Fallback::Clipboard => clipboard::copy(&exec::System, os, message).map(|_| ()),
+            Fallback::Notes => append_note(req, config, message),
+            Fallback::Queue => queue_message(req, Some(target), message),
+        };
+        match result {
+            Ok(()) => {
+                eprintln!("pigeon: {hint}; delivered through the {fallback:?} fallback");
+                return Ok((fallback, hint));
+            }
+            Err(e) => eprintln!("pigeon: {fallback:?} fallback failed: {e}"),
+        }
+    }
+    Err(Failure {
+        code: Some("TMUX_UNAVAILABLE"),
+        message: format!("{} ({hint})", error.message),
+    })
+}
+
+fn queue_message(req: &SendRequest, target: Option<&str>, message: &str) -> Result<(), String> {
+    let queue = queue::Queue::default_location()
+        .ok_or_else(|| "Cannot locate data directory for the queue".to_string())?;
+    queue
+        .push(target, req.repo.as_deref(), &req.file, message)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to queue question: {e}"))
+}
+
+fn append_note(req: &SendRequest, config: &config::Config, message: &str) -> Result<(), String> {
+    let notes = notes::NotesFile::from_settings(&config.notes)
+        .ok_or_else(|| "Writing to notes requires notes.path".to_string())?;
+    notes
+        .append(&req.file, req.url.as_deref(), message)
+        .map_err(|e| format!("Failed to append to notes file: {e}"))
+}
+
+/// Open the selection in VS Code, when it maps to a local file, and leave the
+/// message where `vscode.handoff` says
+fn hand_off_to_vscode(
+    req: &SendRequest,
+    config: &config::Config,
+    exec: &dyn exec::Exec,
+    composed: &Composed,
+    checked_out: Option<&std::path::Path>,
+) -> Result<(), String> {
+    let settings = &config.vscode;
+    if let Some(path) = &composed.local_path {
+        vscode::open(settings, exec, path, req.start_line)?;
+    }
+    match settings.handoff {
+        vscode::Handoff::Clipboard => {
+            clipboard::copy(exec, std::env::consts::OS, &composed.message).map(|_| ())
+        }
+        vscode::Handoff::Inbox => {
+            let workspace = checked_out
+                .or_else(|| repos::checkout_dir(config, req.repo.as_deref()?))
+                .ok_or_else(|| {
+                    "The vscode inbox needs the repository mapped in repos".to_string()
+                })?;
+            vscode::inbox(workspace)
+                .append(&req.file, req.url.as_deref(), &composed.message)
+                .map_err(|e| format!("Failed to write to the VS Code inbox: {e}"))
+        }
+    }
+}
+
+/// Popup text for `confirm_before_send`: where the message points and how
+/// big it is, then the start of the question. Control characters from the
+/// browser are dropped, so escape sequences cannot redraw the popup into a
+/// different message.
+fn confirmation_summary(req: &SendRequest, message: &str) -> String {
+    const QUESTION_PREVIEW_CHARS: usize = 200;
+    let mut location: String = req.file.chars().filter(|c| !c.is_control()).collect();
+    if let Some(start) = req.start_line {
+        location.push_str(&format!(":{start}"));
+        if let Some(end) = req.end_line.filter(|&end| end != start) {
+            location.push_str(&format!("-{end}"));
+        }
+    }
+    let mut question: String = req
+        .question
+        .chars()
+        .take(QUESTION_PREVIEW_CHARS)
+        .filter(|&c| c == '\n' || !c.is_control())
+        .collect();
+    if req.question.chars().nth(QUESTION_PREVIEW_CHARS).is_some() {
+        question.push('…');
+    }
+    format!(
+        "pigeon: incoming message from the browser\n\n{location}\n{} lines, {} bytes\n\n{question}",
+        message.lines().count(),
+        message.len()
+    )
+}
+
+/// Identifies the selection for follow-up detection. The code is included
+/// so a selection that changed under the same lines is shown again.
+fn thread_location(req: &SendRequest) -> String {
+    let key = format!(
+        "{}\n{}\n{:?}\n{:?}\n{:?}\n{}",
+        req.repo.as_deref().unwrap_or_default(),
+        req.file,
+        req.start_line,
+        req.end_line,
+        req.side.as_deref().map(|s| req.provider.normalize_side(s)),
+        req.code
+    );
+    sha256::hex_digest(key.as_bytes())
+}
+
+/// Thread state is a convenience; if it cannot be read, send in full
+fn is_follow_up(threads: &thread::Threads, turn: &thread::Turn, config: &config::Config) -> bool {
+    threads
+        .is_follow_up(turn, config.thread_follow_ups, crate::time::unix_now())
+        .unwrap_or_else(|e| {
+            eprintln!("pigeon: failed to read threads: {e}");
+            false
+        })
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// The answer printed after the send `send_id`, read from the pane's scrollback
+pub fn get_reply(config: &config::Config, target: &str, send_id: &str) -> Result<String, Failure> {
+    let tmux = tmux::Tmux::new(config);
+    let target = &readable_target(target, config, &tmux)?;
+    let captured = tmux.capture_pane(target, reply::CAPTURE_HISTORY_LINES)?;
+    reply::extract(&captured, send_id)
+        .map(|reply| reply::clean(&reply, &config.reply))
+        .ok_or_else(|| Failure {
+            code: Some("REPLY_NOT_FOUND"),
+            message: format!("Send {send_id} is no longer visible in {target}"),
+        })
+}
+
+/// Where `export_transcript` put the transcript
+pub enum Transcript {
+    Written(std::path::PathBuf),
+    Inline(String),
+}
+
+/// The transcript of sends to `target`, written to `path` or, when small
+/// enough, returned. A pane that cannot be captured leaves out the replies.
+pub fn export_transcript(
+    config: &config::Config,
+    target: &str,
+    path: Option<&std::path::Path>,
+) -> Result<Transcript, Failure> {
+    let tmux = tmux::Tmux::new(config);
+    let target = &readable_target(target, config, &tmux)?;
+    let entries = history::for_target(config, target)?;
+    let captured = match tmux.capture_pane(target, reply::CAPTURE_HISTORY_LINES) {
+        Ok(captured) => Some(captured),
+        Err(e) => {
+            eprintln!("pigeon: exporting {target} without pane output: {e}");
+            None
+        }
+    };
+    let doc = transcript::render(target, &entries, captured.as_deref(), &config.reply);
+    match path {
+        Some(path) => {
+            let dir = transcript::dir(config)
+                .ok_or_else(|| "Cannot locate data directory for transcripts".to_string())?;
+            transcript::write(&dir, path, &doc)?;
+            Ok(Transcript::Written(path.to_path_buf()))
+        }
+        None if doc.len() > transcript::MAX_INLINE_BYTES => Err(Failure {
+            code: Some("TRANSCRIPT_TOO_LARGE"),
+            message: format!(
+                "The transcript is {} bytes; give a path to write it to",
+                doc.len()
+            ),
+        }),
+        None => Ok(Transcript::Inline(doc)),
+    }
+}
+
+/// Wait until `target` has answered: its output changed and then stayed
+/// the same for the idle time in `options`
+pub fn watch_target(
+    config: &config::Config,
+    target: &str,
+    options: &watch::WatchOptions,
+) -> Result<watch::Idle, Failure> {
+    let tmux = tmux::Tmux::new(config);
+    let target = &readable_target(target, config, &tmux)?;
+    watch::watch(options, || {
+        tmux.capture_pane(target, reply::CAPTURE_HISTORY_LINES)
+    })
+    .map_err(|e| match e {
+        watch::WatchError::Capture(message) => Failure::from(message),
+        watch::WatchError::TimedOut => Failure {
+            code: Some("WATCH_TIMEOUT"),
+            message: format!(
+                "{target} did not go idle within {}s",
+                options.timeout.as_secs()
+            ),
+        },
+    })
+}
+
+/// What `kill-session` or `respawn-agent` acted on
+pub struct Lifecycle {
+    pub session: String,
+    pub panes: Vec<tmux::Pane>,
+}
+
+/// End the session containing `target`. Every pane in it must be in
+/// `allowed_targets`, since every one of them is killed.
+pub fn kill_session(config: &config::Config, target: &str) -> Result<Lifecycle, Failure> {
+    kill_session_with(config, target, &tmux::Tmux::new(config))
+}
+
+fn kill_session_with(
+    config: &config::Config,
+    target: &str,
+    tmux: &tmux::Tmux,
+) -> Result<Lifecycle, Failure> {
+    let pane = lifecycle_pane(config, target, tmux)?;
+    let session = pane.session().to_string();
+    let panes: Vec<tmux::Pane> = tmux
+        .list_panes()?
+        .into_iter()
+        .filter(|pane| pane.session() == session)
+        .collect();
+    for pane in &panes {
+        check_lifecycle_target(config, pane)?;
+    }
+    tmux.kill_session(&session)?;
+    eprintln!("pigeon: killed tmux session {session}");
+    Ok(Lifecycle { session, panes })
+}
+
+/// Restart the program in `target`'s pane, running `command` if given
+pub fn respawn_agent(
+    config: &config::Config,
+    target: &str,
+    command: Option<&str>,
+) -> Result<Lifecycle, Failure> {
+    respawn_agent_with(config, target, command, &tmux::Tmux::new(config))
+}
+
+fn respawn_agent_with(
+    config: &config::Config,
+    target: &str,
+    command: Option<&str>,
+    tmux: &tmux::Tmux,
+) -> Result<Lifecycle, Failure> {
+    if command.is_some_and(|command| command.trim().is_empty()) {
+        return Err("command must not be empty".to_string().into());
+    }
+    let pane = lifecycle_pane(config, target, tmux)?;
+    check_lifecycle_target(config, &pane)?;
+    tmux.respawn_pane(&pane.id, command)?;
+    eprintln!("pigeon: respawned pane {} in {}", pane.id, pane.target);
+    Ok(Lifecycle {
+        session: pane.session().to_string(),
+        panes: vec![pane],
+    })
+}
+
+/// The pane a lifecycle action is about, once `lifecycle_actions` allows
+/// them at all
+fn lifecycle_pane(
+    config: &config::Config,
+    target: &str,
+    tmux: &tmux::Tmux,
+) -> Result<tmux::Pane, Failure> {
+    if !config.lifecycle_actions {
+        return Err(Failure {
+            code: Some("LIFECYCLE_DISABLED"),
+            message: "kill-session and respawn-agent need lifecycle_actions in config".to_string(),
+        });
+    }
+    if config.backend != Backend::Tmux {
+        return Err("Only the tmux backend has sessions to manage"
+            .to_string()
+            .into());
+    }
+    let target = concrete_target(target, config, tmux)?;
+    Ok(tmux.pane(&target)?)
+}
+
+fn check_lifecycle_target(config: &config::Config, pane: &tmux::Pane) -> Result<(), Failure> {
+    if config.target_allowed(&pane.target) {
+        return Ok(());
+    }
+    Err(Failure {
+        code: Some("TARGET_NOT_ALLOWED"),
+        message: format!("tmux target {} is not in allowed_targets", pane.target),
+    })
+}
+
+/// A template asked for by name must exist; silently using another layout
+/// would hide a typo
+fn check_template(req: &SendRequest, config: &config::Config) -> Result<(), Failure> {
+    match &req.template {
+        Some(name) if !config.templates.contains_key(name) => Err(Failure {
+            code: Some("UNKNOWN_TEMPLATE"),
+            message: format!("No template named {name} in config"),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Format a send without delivering it, checking anything out, or saving
+/// its attachment. An attachment is still validated and shown as a placeholder.
+pub fn preview(req: &SendRequest, config: &config::Config) -> Result<Composed, Failure> {
+    let classified = classify::apply(&config.classifiers, req);
+    let req = classified.as_ref().unwrap_or(req);
+    check_template(req, config)?;
+    let attachment = match &req.image_b64 {
+        Some(encoded) => {
+            attachments::decode_png(encoded)?;
+            Some(attachments::PREVIEW_PATH)
+        }
+        None => None,
+    };
+    let tmux = tmux::Tmux::new(config);
+    Ok(compose(req, config, &tmux, None, attachment, false))
+}
+
+/// Message text plus how the selection compares to the local checkout
+pub struct Composed {
+    pub message: String,
+    /// Sections cut to fit `max_code_bytes` or the budget
+    pub trimmed: Vec<budget::Trimmed>,
+    pub comparison: Option<diff::Comparison>,
+    /// The selected file in the local checkout, when one is mapped
+    pub local_path: Option<std::path::PathBuf>,
+}
+
+/// Run the formatting pipeline without side effects. `checked_out` is the
+/// directory holding the PR's commit, when one was checked out for this send;
+/// `attachment` is the path of the saved `image_b64`; `follow_up` selects
+/// the short form for a selection the agent was just sent.
+pub fn compose(
+    req: &SendRequest,
+    config: &config::Config,
+    tmux: &tmux::Tmux,
+    checked_out: Option<&std::path::Path>,
+    attachment: Option<&str>,
+    follow_up: bool,
+) -> Composed {
+    let resolved = match (checked_out, &req.repo) {
+        (Some(dir), _) => repos::resolve_in(dir, &req.file),
+        (None, Some(repo)) => repos::resolve(config, repo, &req.file),
+        (None, None) => None,
+    };
+    let local_path = resolved.as_deref().map(|path| tmux.host_path(path));
+
+    let side = match req.source_kind {
+        format::SourceKind::Diff => req.side.as_deref().map(|s| req.provider.normalize_side(s)),
+        _ => None,
+    };
+    let permalink = permalink(req);
+    let review = req
+        .review
+        .as_deref()
+        .map(|number| req.provider.review_label(number, req.patchset));
+
+    // Deleted lines have no counterpart in the local file
+    let local_content = match (&resolved, side) {
+        (Some(path), side) if side != Some("old") => read_local_file(path),
+        _ => None,
+    };
+    let surrounding = local_content.as_deref().and_then(|content| {
+        let lines = req.expand_context.unwrap_or(config.expand_context);
+        let start = req.start_line?;
+        let end = req.end_line.unwrap_or(start);
+        (lines > 0).then(|| context::surrounding(content, start, end, lines))
+    });
+    let comparison = local_content
+        .as_deref()
+        .zip(req.start_line)
+        .map(|(content, start)| diff::compare(&req.code, content, start));
+    let stale = comparison.as_ref().is_some_and(|c| !c.matches());
+    let environment = if config.stamp.any() {
+        let checkout = checked_out.or_else(|| {
+            req.repo
+                .as_deref()
+                .and_then(|repo| repos::checkout_dir(config, repo))
+        });
+        probes::gather(&config.stamp, checkout, crate::time::unix_now()).describe(&config.locale)
+    } else {
+        None
+    };
+
+    let selection = format::Selection {
+        file: &req.file,
+        local_path: local_path.as_deref(),
+        start_line: req.start_line,
+        end_line: req.end_line,
+        side,
+        review: review.as_deref(),
+        code: &req.code,
+        question: &req.question,
+        surrounding: surrounding.as_ref(),
+        stale: stale && config.note_stale,
+        attachment,
+        follow_up,
+        url: req.url.as_deref(),
+        branch: req.branch.as_deref(),
+        commit_sha: req.commit_sha.as_deref(),
+        kind: req.source_kind,
+        permalink: permalink.as_deref(),
+        environment: environment.as_deref(),
+    };
+    let options = format::FormatOptions {
+        number_lines: req.number_lines.unwrap_or(config.number_lines),
+        wrap_width: wrap_width(req, config, tmux),
+        ..config.format_options(target(req, config), req.template.as_deref())
+    };
+    let rendered = format::render_trimmed(&selection, &options);
+    Composed {
+        message: rendered.message,
+        trimmed: rendered.trimmed,
+        comparison,
+        local_path: resolved,
+    }
+}
+
+/// The URL of a blob or gist selection pinned to its `commit_sha`, or as
+/// given when it cannot be pinned
+fn permalink(req: &SendRequest) -> Option<String> {
+    let url = req.url.as_deref()?;
+    let pinned = match (req.source_kind, req.commit_sha.as_deref()) {
+        (format::SourceKind::Blob, Some(sha)) => req.provider.blob_permalink(url, &req.file, sha),
+        (format::SourceKind::Gist, Some(sha)) => provider::gist_permalink(url, sha),
+        (format::SourceKind::Blob | format::SourceKind::Gist, None) => None,
+        _ => return None,
+    };
+    Some(pinned.unwrap_or_else(|| url.to_string()))
+}
+
+pub fn checkout_commit(
+    config: &config::Config,
+    repo: &str,
+    sha: &str,
+    mode: git::CheckoutMode,
+) -> Result<std::path::PathBuf, git::GitError> {
+    let dir = repos::checkout_dir(config, repo).ok_or_else(|| git::GitError {
+        code: "REPO_NOT_MAPPED",
+        message: format!("No local checkout mapped for {repo}"),
+    })?;
+    let worktrees = paths::cache_dir()
+        .ok_or_else(|| git::GitError {
+            code: "GIT_FAILED",
+            message: "Cannot locate cache directory for worktrees".to_string(),
+        })?
+        .join("worktrees")
+        .join(repo.replace('/', "-"));
+    git::checkout(dir, sha, mode, &worktrees)
+}
+
+/// Columns code may take in the target pane when `wrap` is enabled. A pane
+/// that cannot be measured leaves wrapping to the terminal.
+fn wrap_width(req: &SendRequest, config: &config::Config, tmux: &tmux::Tmux) -> Option<usize> {
+    if !config.wrap.enabled || config.backend != Backend::Tmux {
+        return None;
+    }
+    match tmux.pane_width(target(req, config)?) {
+        Ok(width) => Some(width.saturating_sub(config.wrap.margin)),
+        Err(e) => {
+            eprintln!("pigeon: not wrapping code: {e}");
+            None
+        }
+    }
+}
+
+/// Local checkout content is best-effort; an unreadable file should not
+/// block delivery.
+fn read_local_file(path: &std::path::Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| eprintln!("pigeon: cannot read {}: {e}", path.display()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Request;
+
+    fn send_request(extra: &str) -> SendRequest {
+        let json = format!(r#"{{"action": "send", "file": "a.rs", "tmux_target": "dev"{extra}}}"#);
+        match serde_json::from_str(&json).unwrap() {
+            Request::Send(req) => req,
+            _ => panic!("Expected Send variant"),
+        }
+    }
+
+    #[test]
+    fn test_decode_payload() {
+        // "tab\there\u{0}" and "Why?"
+        let mut req = send_request(
+            r#", "code": "ignored", "code_b64": "dGFiCWhlcmUA", "question_b64": "V2h5Pw""#,
+        );
+        decode_payload(&mut req).unwrap();
+        assert_eq!(req.code, "tab\there");
+        assert_eq!(req.question, "Why?");
+        assert!(req.code_b64.is_none());
+    }
+
+    #[test]
+    fn test_decode_payload_without_b64_fields() {
+        let mut req = send_request(r#", "code": "x", "question": "q""#);
+        decode_payload(&mut req).unwrap();
+        assert_eq!((req.code.as_str(), req.question.as_str()), ("x", "q"));
+    }
+
+    #[test]
+    fn test_decode_payload_errors() {
+        let mut req = send_request(r#", "code_b64": "not base64!""#);
+        let err = decode_payload(&mut req).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_BASE64"));
+        assert!(
+            err.message.starts_with("Invalid code_b64:"),
+            "{}",
+            err.message
+        );
+
+        // 0xFF is not valid UTF-8
+        let mut req = send_request(r#", "question_b64": "/w==""#);
+        let err = decode_payload(&mut req).unwrap_err();
+        assert_eq!(err.code, Some("INVALID_BASE64"));
+        assert!(err.message.starts_with("Invalid question_b64:"));
+    }
+
+    #[test]
+    fn test_preview_formats_without_tmux() {
+        let json = r#"{
+            "action": "preview",
+            "file": "src/lib.rs",
+            "start_line": 3,
+            "code": "let x = 1;",
+            "question": "Why?",
+            "tmux_target": ""
+        }"#;
+        let Request::Preview(req) = serde_json::from_str(json).unwrap() else {
+            panic!("Expected Preview variant");
+        };
+        let config = config::Config::default();
+        let composed = compose(&req, &config, &tmux::Tmux::new(&config), None, None, false);
+        assert_eq!(composed.message, "src/lib.rs:3\n```\nlet x = 1;\n```\nWhy?");
+        assert!(composed.comparison.is_none());
+    }
+
+    #[test]
+    fn test_preview_uses_named_template() {
+        let config = config::Config {
+            templates: std::collections::BTreeMap::from([(
+                "short".to_string(),
+                "{{question}} ({{location}})".to_string(),
+            )]),
+            ..config::Config::default()
+        };
+        let req = send_request(r#", "start_line": 3, "question": "Why?", "template": "short""#);
+        assert_eq!(preview(&req, &config).unwrap().message, "Why? (a.rs:3)");
+
+        let req = send_request(r#", "template": "long""#);
+        let err = preview(&req, &config).err().unwrap();
+        assert_eq!(err.code, Some("UNKNOWN_TEMPLATE"));
+    }
+
+    #[test]
+    fn test_preview_applies_classifiers() {
+        let config = config::Config {
+            templates: std::collections::BTreeMap::from([(
+                "write-test".to_string(),
+                "Write a test: {{question}}".to_string(),
+            )]),
+            classifiers: serde_json::from_str(
+                r#"[{"keywords": ["test"], "template": "write-test"}]"#,
+            )
+            .unwrap(),
+            ..config::Config::default()
+        };
+        let req = send_request(r#", "question": "Does this need a TEST?""#);
+        assert_eq!(
+            preview(&req, &config).unwrap().message,
+            "Write a test: Does this need a TEST?"
+        );
+    }
+
+    #[test]
+    fn test_resolve_session_corrects_missing_session() {
+        let config = config::Config {
+            session_matching: crate::fuzzy::MatchSettings {
+                enabled: true,
+                max_distance: 2,
+            },
+            ..config::Config::default()
+        };
+        let sessions = "1\t2\t100\tclaude\tclaude-api\n0\t1\t90\tzsh\tscratch\n";
+        let resolve = |target: &str| {
+            let fake = crate::exec::fake::Fake::new();
+            fake.succeed(sessions);
+            let tmux = tmux::Tmux::new(&config).with_exec(fake);
+            let req = SendRequest {
+                tmux_target: target.to_string(),
+                ..send_request("")
+            };
+            resolve_session(&req, &config, &tmux)
+        };
+        assert_eq!(resolve("Claude-API:1.0").as_deref(), Some("claude-api:1.0"));
+        assert_eq!(resolve("claud-api").as_deref(), Some("claude-api"));
+        assert_eq!(resolve("claude-api:2"), None);
+        assert_eq!(resolve("something-else"), None);
+        assert_eq!(resolve("%3"), None);
+    }
+
+    #[test]
+    fn test_request_number_lines_overrides_config() {
+        let config = config::Config {
+            number_lines: true,
+            ..config::Config::default()
+        };
+        let req = send_request(r#", "start_line": 7, "code": "x();""#);
+        assert!(preview(&req, &config).unwrap().message.contains("7 | x();"));
+        let req = send_request(r#", "start_line": 7, "code": "x();", "number_lines": false"#);
+        assert!(preview(&req, &config)
+            .unwrap()
+            .message
+            .contains("```\nx();"));
+    }
+
+    #[test]
+    fn test_broadcast_reports_each_target() {
+        let config = config::Config {
+            allowed_targets: vec!["agents:*".to_string()],
+            ..config::Config::default()
+        };
+        let req = send_request(r#", "tmux_targets": ["other:1", "other:2"]"#);
+        let results = broadcast(&req, &config);
+        let targets: Vec<&str> = results.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(targets, ["other:1", "other:2"]);
+        for (_, result) in &results {
+            let err = result.as_ref().err().unwrap();
+            assert_eq!(err.code, Some("TARGET_NOT_ALLOWED"));
+        }
+    }
+
+    #[test]
+    fn test_deliver_rejects_target_outside_allowlist() {
+        let config = config::Config {
+            allowed_targets: vec!["agents:*".to_string()],
+            ..config::Config::default()
+        };
+        let err = deliver(&send_request(""), &config).err().unwrap();
+        assert_eq!(err.code, Some("TARGET_NOT_ALLOWED"));
+    }
+
+    fn reading_allowlist() -> config::Config {
+        config::Config {
+            allowed_targets: vec!["agents:*".to_string()],
+            ..config::Config::default()
+        }
+    }
+
+    #[test]
+    fn test_get_reply_respects_allowlist() {
+        let err = get_reply(&reading_allowlist(), "shell:1", "abc")
+            .err()
+            .unwrap();
+        assert_eq!(err.code, Some("TARGET_NOT_ALLOWED"));
+    }
+
+    #[test]
+    fn test_watch_target_respects_allowlist() {
+        let options = watch::WatchOptions::from_request(None, None).unwrap();
+        let err = watch_target(&reading_allowlist(), "shell:1", &options)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, Some("TARGET_NOT_ALLOWED"));
+    }
+
+    #[test]
+    fn test_export_transcript_respects_allowlist() {
+        let err = export_transcript(&reading_allowlist(), "shell:1", None)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, Some("TARGET_NOT_ALLOWED"));
+    }
+
+    #[test]
+    fn test_inferred_target_needs_mapped_repo() {
+        let mut req = send_request("");
+        req.tmux_target.clear();
+        req.repo = Some("acme/api".to_string());
+        assert_eq!(
+            inferable(&req, &config::Config::default()),
+            Some("acme/api")
+        );
+        let err = send(&req, &config::Config::default()).err().unwrap();
+        assert!(
+            err.message.contains("acme/api is not mapped"),
+            "{}",
+            err.message
+        );
+
+        let config = config::Config {
+            default_target: Some("dev".to_string()),
+            ..config::Config::default()
+        };
+        assert_eq!(inferable(&req, &config), None);
+    }
+
+    #[test]
+    fn test_fall_back_without_fallbacks_adds_hint() {
+        let error = Failure::from("Failed to run tmux: not found".to_string());
+        let err = fall_back(
+            &send_request(""),
+            &config::Config::default(),
+            "dev",
+            "msg",
+            tmux::Unavailable::NoServer,
+            error,
+        )
+        .unwrap_err();
+        assert_eq!(err.code, Some("TMUX_UNAVAILABLE"));
+        assert!(
+            err.message.contains("tmux new-session -s dev"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_fall_back_to_notes() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-send-test-fallback-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("inbox.md");
+        let config = config::Config {
+            fallbacks: vec![Fallback::Notes],
+            notes: notes::NotesSettings {
+                path: Some(path.display().to_string()),
+                mirror: false,
+            },
+            ..config::Config::default()
+        };
+        let error = Failure::from("Failed to run tmux: not found".to_string());
+        let (via, hint) = fall_back(
+            &send_request(""),
+            &config,
+            "dev",
+            "Why?",
+            tmux::Unavailable::NotInstalled,
+            error,
+        )
+        .unwrap();
+        assert_eq!(via, Fallback::Notes);
+        assert!(hint.starts_with("tmux not found"), "{hint}");
+        assert!(std::fs::read_to_string(&path).unwrap().contains("Why?"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_vscode_clipboard_handoff_does_not_wait_on_clipboard_tool() {
+        let fake = crate::exec::fake::Fake::new();
+        let composed = Composed {
+            message: "Why?".to_string(),
+            trimmed: Vec::new(),
+            comparison: None,
+            local_path: None,
+        };
+        let config = config::Config::default();
+        assert_eq!(config.vscode.handoff, vscode::Handoff::Clipboard);
+        hand_off_to_vscode(&send_request(""), &config, &*fake, &composed, None).unwrap();
+        assert_eq!(fake.inputs(), [Some(b"Why?".to_vec())]);
+        assert_eq!(fake.captured(), [false]);
+    }
+
+    #[test]
+    fn test_confirmation_summary() {
+        let req = send_request(r#", "start_line": 3, "end_line": 5, "question": "Why?""#);
+        assert_eq!(
+            confirmation_summary(&req, "a.rs:3-5\nWhy?"),
+            "pigeon: incoming message from the browser\n\na.rs:3-5\n2 lines, 13 bytes\n\nWhy?"
+        );
+        let long = send_request(&format!(r#", "question": "{}""#, "é".repeat(300)));
+        let summary = confirmation_summary(&long, "");
+        assert!(summary.ends_with(&format!("{}…", "é".repeat(200))));
+        let mut forged = send_request(r#", "question": "\u001b[H\u009b2JOK?\nSure\r""#);
+        forged.file = "a.rs\u{1b}[2J\r\nb.rs".to_string();
+        assert_eq!(
+            confirmation_summary(&forged, "x"),
+            "pigeon: incoming message from the browser\n\na.rs[2Jb.rs\n1 lines, 1 bytes\n\n[H2JOK?\nSure"
+        );
+    }
+
+    #[test]
+    fn test_compose_applies_provider_conventions() {
+        let req = send_request(
+            r#", "provider": "gerrit", "side": "PARENT", "review": "4711", "patchset": 2, "start_line": 5, "code": "x""#,
+        );
+        let config = config::Config::default();
+        let composed = compose(&req, &config, &tmux::Tmux::new(&config), None, None, false);
+        assert!(composed
+            .message
+            .starts_with("a.rs:5 (change 4711, patchset 2) (deleted lines)\n"));
+    }
+
+    #[test]
+    fn test_lifecycle_actions_are_gated() {
+        let fake = crate::exec::fake::Fake::new();
+        let config = config::Config::default();
+        let tmux = tmux::Tmux::new(&config).with_exec(fake.clone());
+        let err = kill_session_with(&config, "work:1.0", &tmux).err().unwrap();
+        assert_eq!(err.code, Some("LIFECYCLE_DISABLED"));
+        let err = respawn_agent_with(&config, "work:1.0", None, &tmux)
+            .err()
+            .unwrap();
+        assert_eq!(err.code, Some("LIFECYCLE_DISABLED"));
+        assert!(fake.calls().is_empty());
+    }
+
+    #[test]
+    fn test_kill_session_needs_every_pane_allowed() {
+        let config = config::Config {
+            lifecycle_actions: true,
+            allowed_targets: vec!["work:1.*".to_string()],
+            ..config::Config::default()
+        };
+        let panes = "%3\twork:1.0\tclaude\t/src\n%4\twork:2.0\tzsh\t/src\n%5\tother:0.0\tzsh\t/\n";
+        let fake = crate::exec::fake::Fake::new();
+        let tmux = tmux::Tmux::new(&config).with_exec(fake.clone());
+        for _ in 0..2 {
+            fake.succeed("%3\n");
+            fake.succeed(panes);
+            fake.succeed(panes);
+        }
+        let err = kill_session_with(&config, "work:1.0", &tmux).err().unwrap();
+        assert_eq!(err.code, Some("TARGET_NOT_ALLOWED"));
+        assert!(err.message.contains("work:2.0"), "{}", err.message);
+        let config = config::Config {
+            allowed_targets: vec!["work:*".to_string()],
+            ..config
+        };
+        fake.succeed("");
+        let killed = kill_session_with(&config, "work:1.0", &tmux).unwrap();
+        assert_eq!(killed.session, "work");
+        let ids: Vec<&str> = killed.panes.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["%3", "%4"]);
+        assert_eq!(
+            fake.calls().last().unwrap()[1..],
+            ["kill-session", "-t", "=work"]
+        );
+    }
+
+    #[test]
+    fn test_respawn_agent_targets_the_pane_id() {
+        let config = config::Config {
+            lifecycle_actions: true,
+            ..config::Config::default()
+        };
+        let fake = crate::exec::fake::Fake::new();
+        let tmux = tmux::Tmux::new(&config).with_exec(fake.clone());
+        fake.succeed("%3\n");
+        fake.succeed("%3\twork:1.0\tclaude\t/src\n");
+        fake.succeed("");
+        let respawned = respawn_agent_with(&config, "work:1.0", Some("claude"), &tmux).unwrap();
+        assert_eq!(respawned.panes[0].target, "work:1.0");
+        assert_eq!(
+            fake.calls().last().unwrap()[1..],
+            ["respawn-pane", "-k", "-t", "%3", "claude"]
+        );
+        assert!(respawn_agent_with(&config, "work:1.0", Some(" "), &tmux).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Two-phase sends: `stage-send` types the message into the pane without
+//! submitting it, so it can be checked in the terminal, and `commit-send`
+//! or `abort-send` later presses the submit keys or clears the input line.
+//! `undo-last` clears whichever staged message came last.
+
+use crate::config::Config;
+use crate::history;
+use crate::protocol::SendRequest;
+use crate::reply;
+use crate::send::{self, Failure, SendOutcome};
+use crate::tmux::Tmux;
+use std::collections::HashMap;
+
+/// A message typed into a pane and not submitted yet
+struct Staged {
+    /// Pane it was typed into, which the history records
+    target: String,
+    /// Target as the send gave it, such as an alias, for `undo-last`
+    given: String,
+    /// Order of staging, for `undo-last`
+    seq: u64,
+}
+
+/// Staged messages still waiting in their pane, by stage id
+#[derive(Default)]
+pub struct Stages {
+    pending: HashMap<String, Staged>,
+    staged: u64,
+}
+
+impl Stages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Type `req` into its target and remember the target under a new
+    /// stage id. A target holds one staged message at a time, since a second
+    /// would be typed onto the end of the first.
+    pub fn stage(
+        &mut self,
+        req: &SendRequest,
+        config: &Config,
+    ) -> Result<(SendOutcome, String), Failure> {
+        let given = send::target(req, config).unwrap_or_default().to_string();
+        self.check_free(&given)?;
+        let staged = SendRequest {
+            stage: true,
+            tmux_targets: Vec::new(),
+            ..req.clone()
+        };
+        // Checked before typing: the target the send resolves to may already
+        // hold a stage under another name
+        let routed = send::route(&staged, config);
+        let target = routed.target(config).unwrap_or(&given).to_string();
+        self.check_free(&target)?;
+        let outcome = send::send_routed(routed, config)?;
+        let id = std::iter::repeat_with(reply::new_id)
+            .find(|id| !self.pending.contains_key(id))
+            .expect("unbounded iterator always yields a free id");
+        self.staged += 1;
+        let staged = Staged {
+            target,
+            given,
+            seq: self.staged,
+        };
+        self.pending.insert(id.clone(), staged);
+        Ok((outcome, id))
+    }
+
+    fn check_free(&self, target: &str) -> Result<(), Failure> {
+        match self.pending.iter().find(|(_, s)| s.target == target) {
+            Some((id, _)) => Err(Failure {
+                code: Some("STAGE_PENDING"),
+                message: format!(
+                    "{target} already holds staged send {id}; commit or abort it first"
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Press the target's submit keys. The stage is kept when tmux fails,
+    /// so the commit can be retried.
+    pub fn commit(&mut self, id: &str, config: &Config, tmux: &Tmux) -> Result<(), Failure> {
+        let target = &self.staged(id)?.target;
+        let outputs = tmux.submit(target, &config.submit_keys(target))?;
+        if let Some(failed) = outputs.iter().find(|o| !o.success) {
+            return Err(Failure::from(format!(
+                "tmux send-keys failed: {}",
+                failed.stderr.trim()
+            )));
+        }
+        self.pending.remove(id);
+        Ok(())
+    }
+
+    /// Clear the target's input line, discarding the staged message, and
+    /// mark its history entry aborted
+    pub fn abort(&mut self, id: &str, config: &Config, tmux: &Tmux) -> Result<(), Failure> {
+        self.abort_marking(id, config, tmux, |target| {
+            history::mark_aborted(config, target)
+        })
+    }
+
+    /// `abort`, marking the history entry of the pane through `mark`
+    fn abort_marking(
+        &mut self,
+        id: &str,
+        config: &Config,
+        tmux: &Tmux,
+        mark: impl FnOnce(&str),
+    ) -> Result<(), Failure> {
+        let staged = self.staged(id)?;
+        tmux.clear_input(&staged.target, &config.clear_keys(&staged.target))?;
+        mark(&staged.target);
+        self.pending.remove(id);
+        Ok(())
+    }
+
+    /// Abort the message staged last, into `target` when not empty (as the
+    /// send gave it or as the pane it went to). Its stage id and pane.
+    pub fn undo_last(
+        &mut self,
+        target: &str,
+        config: &Config,
+        tmux: &Tmux,
+    ) -> Result<(String, String), Failure> {
+        let (id, staged) = self
+            .pending
+            .iter()
+            .filter(|(_, s)| target.is_empty() || s.target == target || s.given == target)
+            .max_by_key(|(_, s)| s.seq)
+            .ok_or_else(|| Failure {
+                code: Some("NOTHING_TO_UNDO"),
+                message: match target {
+                    "" => "No staged send is waiting to be submitted".to_string(),
+                    target => format!("No staged send is waiting in {target}"),
+                },
+            })?;
+        let (id, pane) = (id.clone(), staged.target.clone());
+        self.abort(&id, config, tmux)?;
+        Ok((id, pane))
+    }
+
+    fn staged(&self, id: &str) -> Result<&Staged, Failure> {
+        self.pending.get(id).ok_or_else(|| Failure {
+            code: Some("UNKNOWN_STAGE"),
+            message: format!("Unknown staged send: {id}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+
+    fn staged(target: &str) -> (Stages, String) {
+        let mut stages = Stages::new();
+        let id = "s1".to_string();
+        stages.stage_as(&id, target, target);
+        (stages, id)
+    }
+
+    impl Stages {
+        fn stage_as(&mut self, id: &str, target: &str, given: &str) {
+            self.staged += 1;
+            let staged = Staged {
+                target: target.to_string(),
+                given: given.to_string(),
+                seq: self.staged,
+            };
+            self.pending.insert(id.to_string(), staged);
+        }
+    }
+
+    #[test]
+    fn test_commit_presses_submit_keys() {
+        let config: Config =
+            serde_json::from_str(r#"{"submit_keys": {"dev": ["Escape", "Enter"]}}"#).unwrap();
+        let fake = Fake::new();
+        let tmux = Tmux::new(&config).with_exec(fake.clone());
+        let (mut stages, id) = staged("dev");
+        stages.commit(&id, &config, &tmux).unwrap();
+        let keys: Vec<String> = fake.calls().into_iter().map(|c| c[3..].join(" ")).collect();
+        assert_eq!(keys, ["dev Escape", "dev Enter"]);
+        let err = stages.commit(&id, &config, &tmux).unwrap_err();
+        assert_eq!(err.code, Some("UNKNOWN_STAGE"));
+    }
+
+    #[test]
+    fn test_failed_commit_keeps_stage() {
+        let config = Config::default();
+        let fake = Fake::new();
+        fake.fail("can't find pane: dev");
+        let tmux = Tmux::new(&config).with_exec(fake.clone());
+        let (mut stages, id) = staged("dev");
+        let err = stages.commit(&id, &config, &tmux).unwrap_err();
+        assert!(err.message.contains("can't find pane"), "{}", err.message);
+        assert!(stages.pending.contains_key(&id));
+    }
+
+    #[test]
+    fn test_abort_clears_input_line() {
+        let fake = Fake::new();
+        let tmux = Tmux::new(&Config::default()).with_exec(fake.clone());
+        let (mut stages, id) = staged("dev");
+        stages.abort(&id, &Config::default(), &tmux).unwrap();
+        assert_eq!(fake.calls()[0][1..], ["send-keys", "-t", "dev", "C-u"]);
+        assert!(stages.pending.is_empty());
+    }
+
+    #[test]
+    fn test_abort_marks_history_of_resolved_pane() {
+        let path = std::env::temp_dir().join(format!(
+            "pigeon-stage-test-alias-{}/history.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+        let history = history::History::new(path.clone(), &Default::default());
+        // An older send to a session named like the alias, then the staged
+        // send, which the history records under the pane the alias pointed at
+        let entry = |target| history::HistoryEntry::new(target, None, "a.rs", "Why?");
+        history.append(entry("claude")).unwrap();
+        history.append(entry("claude:0.0")).unwrap();
+
+        let fake = Fake::new();
+        let tmux = Tmux::new(&Config::default()).with_exec(fake.clone());
+        let mut stages = Stages::new();
+        stages.stage_as("s1", "claude:0.0", "claude");
+        stages
+            .abort_marking("s1", &Config::default(), &tmux, |target| {
+                assert!(history.mark_aborted(target).unwrap());
+            })
+            .unwrap();
+        let aborted: Vec<(String, bool)> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.target, e.aborted))
+            .collect();
+        assert_eq!(
+            aborted,
+            [
+                ("claude".to_string(), false),
+                ("claude:0.0".to_string(), true)
+            ]
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_abort_presses_clear_keys() {
+        let config: Config =
+            serde_json::from_str(r#"{"clear_keys": {"codex:*": ["Escape", "C-u"]}}"#).unwrap();
+        let fake = Fake::new();
+        let tmux = Tmux::new(&config).with_exec(fake.clone());
+        let (mut stages, id) = staged("codex:1");
+        stages.abort(&id, &config, &tmux).unwrap();
+        let keys: Vec<String> = fake.calls().into_iter().map(|c| c[3..].join(" ")).collect();
+        assert_eq!(keys, ["codex:1 Escape", "codex:1 C-u"]);
+    }
+
+    #[test]
+    fn test_undo_last_clears_latest_stage() {
+        let config = Config::default();
+        let fake = Fake::new();
+        let tmux = Tmux::new(&config).with_exec(fake.clone());
+        let mut stages = Stages::new();
+        stages.stage_as("s1", "%1", "dev");
+        stages.stage_as("s2", "%2", "ops");
+        stages.stage_as("s3", "%3", "docs");
+
+        let undone = stages.undo_last("", &config, &tmux).unwrap();
+        assert_eq!(undone, ("s3".to_string(), "%3".to_string()));
+        // Narrowed by the target as given or by the pane
+        assert_eq!(stages.undo_last("dev", &config, &tmux).unwrap().0, "s1");
+        assert_eq!(stages.undo_last("%2", &config, &tmux).unwrap().0, "s2");
+        let err = stages.undo_last("", &config, &tmux).unwrap_err();
+        assert_eq!(err.code, Some("NOTHING_TO_UNDO"));
+        assert_eq!(fake.calls().len(), 3);
+    }
+
+    #[test]
+    fn test_one_stage_per_target() {
+        let (mut stages, _) = staged("dev");
+        let config: Config = serde_json::from_str(r#"{"default_target": "dev"}"#).unwrap();
+        let req: SendRequest = serde_json::from_str(r#"{"file": "a.rs"}"#).unwrap();
+        let Err(err) = stages.stage(&req, &config) else {
+            panic!("staged twice onto dev");
+        };
+        assert_eq!(err.code, Some("STAGE_PENDING"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::audit::{AuditEntry, AuditLog};
+use crate::config::Config;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Usage aggregates over the audit log
+#[derive(Serialize, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Stats {
+    pub total: u64,
+    pub failed: u64,
+    pub by_target: BTreeMap<String, u64>,
+    /// Sends without a repository are not counted here
+    pub by_repo: BTreeMap<String, u64>,
+    /// Keyed by UTC date (`YYYY-MM-DD`)
+    pub by_day: BTreeMap<String, u64>,
+    /// A send with several tags counts toward each
+    pub by_tag: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub fn from_entries<'a>(entries: impl IntoIterator<Item = &'a AuditEntry>) -> Self {
+        let mut stats = Self::default();
+        for entry in entries {
+            stats.total += 1;
+            if !entry.ok {
+                stats.failed += 1;
+            }
+            *stats.by_target.entry(entry.target.clone()).or_default() += 1;
+            if let Some(repo) = &entry.repo {
+                *stats.by_repo.entry(repo.clone()).or_default() += 1;
+            }
+            // Timestamps are RFC 3339 in UTC, so the date is the first 10 chars
+            if let Some(day) = entry.ts.get(..10) {
+                *stats.by_day.entry(day.to_string()).or_default() += 1;
+            }
+            for tag in &entry.tags {
+                *stats.by_tag.entry(tag.clone()).or_default() += 1;
+            }
+        }
+        stats
+    }
+}
+
+/// Aggregate the audit log selected by `config`, optionally only the sends
+/// labeled `tag`
+pub fn collect(config: &Config, tag: Option<&str>) -> Result<Stats, String> {
+    if !config.audit.enabled {
+        return Err("Usage stats need the audit log; set audit.enabled in config".to_string());
+    }
+    let log = AuditLog::default_location(&config.audit)
+        .ok_or_else(|| "Cannot locate data directory".to_string())?;
+    let entries = log
+        .entries()
+        .map_err(|e| format!("Failed to read audit log: {e}"))?;
+    Ok(Stats::from_entries(entries.iter().filter(|entry| {
+        tag.is_none_or(|tag| entry.tags.iter().any(|t| t == tag))
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ts: &str, target: &str, repo: Option<&str>, ok: bool) -> AuditEntry {
+        AuditEntry {
+            ts: ts.to_string(),
+            target: target.to_string(),
+            repo: repo.map(String::from),
+            file: "a.rs".to_string(),
+            question_sha256: String::new(),
+            code_bytes: 0,
+            ok,
+            error: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let entries = [
+            entry("2026-10-14T09:30:45Z", "dev", Some("acme/api"), true),
+            entry("2026-10-14T23:59:59Z", "dev:1", Some("acme/api"), false),
+            entry("2026-10-15T00:00:00Z", "dev", None, true),
+        ];
+        let stats = Stats::from_entries(&entries);
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(
+            stats.by_target,
+            BTreeMap::from([("dev".to_string(), 2), ("dev:1".to_string(), 1)])
+        );
+        assert_eq!(stats.by_repo, BTreeMap::from([("acme/api".to_string(), 2)]));
+        assert_eq!(
+            stats.by_day,
+            BTreeMap::from([("2026-10-14".to_string(), 2), ("2026-10-15".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn test_counts_tags() {
+        let entries = [
+            AuditEntry {
+                tags: vec!["perf".to_string(), "nit".to_string()],
+                ..entry("2026-10-14T09:30:45Z", "dev", None, true)
+            },
+            AuditEntry {
+                tags: vec!["perf".to_string()],
+                ..entry("2026-10-14T09:31:00Z", "dev", None, true)
+            },
+        ];
+        assert_eq!(
+            Stats::from_entries(&entries).by_tag,
+            BTreeMap::from([("nit".to_string(), 1), ("perf".to_string(), 2)])
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(Stats::from_entries(&[]), Stats::default());
+    }
+}
@@ -0,0 +1,178 @@
+//! Fixed phrases that end up in messages, per locale. A locale such as
+//! `ja-JP` falls back to `ja` and then to English, phrase by phrase, so a
+//! partial translation is still usable.
+
+/// Locale used when none is configured, and the last fallback
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// A phrase in a formatted message. Some take `{{placeholders}}`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Text {
+    /// Appended to the location for a selection on the old side
+    DeletedLines,
+    /// Appended to the location when the local checkout differs
+    LocalDiffers,
+    /// Used when the request has no question
+    DefaultQuestion,
+    /// Appended to truncated code
+    Truncated,
+    /// `{{lines}}`: line range of the context block
+    ContextBefore,
+    ContextAfter,
+    /// `{{path}}`: saved attachment
+    AttachedImage,
+    /// `{{location}}`: the selection the agent was just sent
+    FollowUp,
+    /// `{{url}}`: page the selection was made on
+    Source,
+    /// `{{facts}}`: environment facts stamped onto a send
+    Environment,
+    /// `{{branch}}`: branch of the mapped checkout
+    Branch,
+    /// The mapped checkout has uncommitted changes, or has none
+    Uncommitted,
+    Clean,
+    /// `{{url}}`: blob or gist URL pinned to a commit
+    Permalink,
+    /// `{{source}}`: page quoted text was selected on
+    QuotedFrom,
+}
+
+const EN: &[(Text, &str)] = &[
+    (Text::DeletedLines, "(deleted lines)"),
+    (Text::LocalDiffers, "(local checkout differs from the PR)"),
+    (Text::DefaultQuestion, "Explain this code"),
+    (Text::Truncated, "...(truncated)"),
+    (
+        Text::ContextBefore,
+        "Context before (lines {{lines}}, not selected):",
+    ),
+    (
+        Text::ContextAfter,
+        "Context after (lines {{lines}}, not selected):",
+    ),
+    (Text::AttachedImage, "Attached image: {{path}}"),
+    (
+        Text::FollowUp,
+        "Follow-up on {{location}} (same code as above):",
+    ),
+    (Text::Source, "Source: {{url}}"),
+    (Text::Environment, "Environment: {{facts}}"),
+    (Text::Branch, "branch {{branch}}"),
+    (Text::Uncommitted, "uncommitted changes"),
+    (Text::Clean, "no uncommitted changes"),
+    (Text::Permalink, "Permalink: {{url}}"),
+    (Text::QuotedFrom, "From {{source}}:"),
+];
+
+const JA: &[(Text, &str)] = &[
+    (Text::DeletedLines, "(削除された行)"),
+    (
+        Text::LocalDiffers,
+        "(ローカルのチェックアウトは PR と異なります)",
+    ),
+    (Text::DefaultQuestion, "このコードを説明してください"),
+    (Text::Truncated, "...(省略)"),
+    (
+        Text::ContextBefore,
+        "前の文脈 ({{lines}} 行目、選択範囲外):",
+    ),
+    (Text::ContextAfter, "後の文脈 ({{lines}} 行目、選択範囲外):"),
+    (Text::AttachedImage, "添付画像: {{path}}"),
+    (
+        Text::FollowUp,
+        "{{location}} について追加の質問 (コードは上と同じ):",
+    ),
+    (Text::Source, "出典: {{url}}"),
+    (Text::Environment, "環境: {{facts}}"),
+    (Text::Branch, "ブランチ {{branch}}"),
+    (Text::Uncommitted, "未コミットの変更あり"),
+    (Text::Clean, "未コミットの変更なし"),
+    (Text::Permalink, "パーマリンク: {{url}}"),
+    (Text::QuotedFrom, "{{source}} より:"),
+];
+
+/// Locales with a table, by lowercase tag
+const TABLES: &[(&str, &[(Text, &str)])] = &[("en", EN), ("ja", JA)];
+
+/// Tags tried for `locale`, most specific first: `pt_BR` → `pt-br`, `pt`
+fn chain(locale: &str) -> Vec<String> {
+    let tag = locale.trim().replace('_', "-").to_lowercase();
+    let mut chain = Vec::new();
+    let mut current = tag.as_str();
+    while !current.is_empty() {
+        chain.push(current.to_string());
+        current = current.rsplit_once('-').map_or("", |(parent, _)| parent);
+    }
+    chain
+}
+
+/// `text` in `locale`, falling back along its chain to English
+pub fn text(locale: &str, text: Text) -> &'static str {
+    chain(locale)
+        .iter()
+        .map(String::as_str)
+        .chain([DEFAULT_LOCALE])
+        .filter_map(|tag| TABLES.iter().find(|(t, _)| *t == tag))
+        .find_map(|(_, table)| table.iter().find(|(k, _)| *k == text))
+        .map(|(_, phrase)| *phrase)
+        .expect("the English table has every phrase")
+}
+
+/// Locale tags are letters, digits, `-` and `_`
+pub fn validate_locale(locale: &str) -> Result<(), String> {
+    let valid = !locale.is_empty()
+        && locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid locale '{locale}'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[Text] = &[
+        Text::DeletedLines,
+        Text::LocalDiffers,
+        Text::DefaultQuestion,
+        Text::Truncated,
+        Text::ContextBefore,
+        Text::ContextAfter,
+        Text::AttachedImage,
+        Text::FollowUp,
+        Text::Source,
+        Text::Environment,
+        Text::Branch,
+        Text::Uncommitted,
+        Text::Clean,
+        Text::Permalink,
+        Text::QuotedFrom,
+    ];
+
+    #[test]
+    fn test_english_is_complete() {
+        for &key in ALL {
+            assert!(EN.iter().any(|(k, _)| *k == key), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        assert_eq!(chain("ja_JP"), ["ja-jp", "ja"]);
+        assert_eq!(text("ja-JP", Text::Truncated), "...(省略)");
+        assert_eq!(text("fr", Text::Truncated), "...(truncated)");
+        assert_eq!(text("", Text::DefaultQuestion), "Explain this code");
+    }
+
+    #[test]
+    fn test_validate_locale() {
+        assert!(validate_locale("pt_BR").is_ok());
+        assert!(validate_locale("").is_err());
+        assert!(validate_locale("en us").is_err());
+    }
+}
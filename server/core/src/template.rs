@@ -0,0 +1,73 @@
+/// Minimal `{{name}}` placeholder substitution for message templates.
+/// Values are inserted verbatim and never re-scanned, so code containing
+/// `{{` cannot inject further placeholders.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let name = after[..end].trim();
+        match vars.iter().find(|(k, _)| *k == name) {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Check that every placeholder in `template` is one of `known`
+pub fn validate(template: &str, known: &[&str]) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            return Err("Unclosed placeholder: missing '}}'".to_string());
+        };
+        let name = after[..end].trim();
+        if !known.contains(&name) {
+            return Err(format!(
+                "Unknown placeholder '{{{{{name}}}}}' (available: {})",
+                known.join(", ")
+            ));
+        }
+        rest = &after[end + 2..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_placeholders() {
+        let out = render("{{a}} and {{ b }}", &[("a", "1"), ("b", "2")]);
+        assert_eq!(out, "1 and 2");
+    }
+
+    #[test]
+    fn test_render_does_not_rescan_values() {
+        let out = render("{{code}}", &[("code", "{{question}}"), ("question", "x")]);
+        assert_eq!(out, "{{question}}");
+    }
+
+    #[test]
+    fn test_render_keeps_unknown_and_unclosed() {
+        assert_eq!(render("{{nope}} {{", &[]), "{{nope}} {{");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("{{file}}\n{{code}}", &["file", "code"]).is_ok());
+        let err = validate("{{fiel}}", &["file"]).unwrap_err();
+        assert!(err.contains("{{fiel}}"));
+        assert!(validate("{{file", &["file"]).is_err());
+    }
+}
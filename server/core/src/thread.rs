@@ -0,0 +1,173 @@
+//! Conversation threads: the last location sent to each target, so a
+//! follow-up about the same code can skip repeating the header and code the
+//! agent has just seen.
+
+use crate::lock::FileLock;
+use crate::paths;
+use crate::time;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// After this long without a send, a target's thread is considered over
+/// (the agent may have been restarted or moved on)
+const THREAD_IDLE_SECS: u64 = 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct LastSend {
+    location: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversation_id: Option<String>,
+    ts: u64,
+}
+
+/// What a send refers to, for comparing it with the previous one
+pub struct Turn<'a> {
+    pub target: &'a str,
+    /// Key identifying the selection; only compared for equality
+    pub location: &'a str,
+    /// Explicit grouping from the extension
+    pub conversation_id: Option<&'a str>,
+}
+
+/// Last send per target, kept in one JSON file
+pub struct Threads {
+    path: PathBuf,
+}
+
+impl Threads {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Default location: `threads.json` in the platform data directory
+    pub fn default_location() -> Option<Self> {
+        Some(Self::new(paths::data_dir()?.join("threads.json")))
+    }
+
+    fn load(&self) -> io::Result<BTreeMap<String, LastSend>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether `turn` continues the target's thread at the same location.
+    /// With a `conversation_id`, only sends in that conversation count;
+    /// without one, consecutive sends count only when `implicit` is set.
+    pub fn is_follow_up(&self, turn: &Turn, implicit: bool, now: u64) -> io::Result<bool> {
+        let threads = self.load()?;
+        let Some(last) = threads.get(turn.target) else {
+            return Ok(false);
+        };
+        let grouped = match turn.conversation_id {
+            Some(id) => last.conversation_id.as_deref() == Some(id),
+            None => implicit,
+        };
+        Ok(grouped
+            && last.location == turn.location
+            && now.saturating_sub(last.ts) < THREAD_IDLE_SECS)
+    }
+
+    /// Remember `turn` as the target's latest send, dropping idle threads
+    pub fn record(&self, turn: &Turn, now: u64) -> io::Result<()> {
+        let _lock = FileLock::acquire(&self.path.with_extension("json.lock"))?;
+        let mut threads = self.load()?;
+        threads.retain(|_, last| now.saturating_sub(last.ts) < THREAD_IDLE_SECS);
+        threads.insert(
+            turn.target.to_string(),
+            LastSend {
+                location: turn.location.to_string(),
+                conversation_id: turn.conversation_id.map(String::from),
+                ts: now,
+            },
+        );
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self
+            .path
+            .with_extension(format!("json.tmp.{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_string_pretty(&threads)? + "\n")?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// `record` at the current time
+    pub fn record_now(&self, turn: &Turn) -> io::Result<()> {
+        self.record(turn, time::unix_now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_threads(name: &str) -> (PathBuf, Threads) {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-thread-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let threads = Threads::new(dir.join("threads.json"));
+        (dir, threads)
+    }
+
+    fn turn<'a>(location: &'a str, conversation_id: Option<&'a str>) -> Turn<'a> {
+        Turn {
+            target: "dev",
+            location,
+            conversation_id,
+        }
+    }
+
+    #[test]
+    fn test_implicit_follow_up() {
+        let (dir, threads) = temp_threads("implicit");
+        assert!(!threads
+            .is_follow_up(&turn("a.rs:1", None), true, 100)
+            .unwrap());
+        threads.record(&turn("a.rs:1", None), 100).unwrap();
+        assert!(threads
+            .is_follow_up(&turn("a.rs:1", None), true, 160)
+            .unwrap());
+        assert!(!threads
+            .is_follow_up(&turn("a.rs:1", None), false, 160)
+            .unwrap());
+        assert!(!threads
+            .is_follow_up(&turn("a.rs:2", None), true, 160)
+            .unwrap());
+        assert!(!threads
+            .is_follow_up(&turn("a.rs:1", None), true, 100 + THREAD_IDLE_SECS)
+            .unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_conversation_id_groups_explicitly() {
+        let (dir, threads) = temp_threads("explicit");
+        threads.record(&turn("a.rs:1", Some("c1")), 100).unwrap();
+        assert!(threads
+            .is_follow_up(&turn("a.rs:1", Some("c1")), false, 110)
+            .unwrap());
+        assert!(!threads
+            .is_follow_up(&turn("a.rs:1", Some("c2")), true, 110)
+            .unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_drops_idle_threads() {
+        let (dir, threads) = temp_threads("prune");
+        threads.record(&turn("a.rs:1", None), 100).unwrap();
+        let other = Turn {
+            target: "other",
+            location: "b.rs:1",
+            conversation_id: None,
+        };
+        threads.record(&other, 100 + THREAD_IDLE_SECS).unwrap();
+        assert_eq!(threads.load().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
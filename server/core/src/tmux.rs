@@ -0,0 +1,1277 @@
+use crate::config::Config;
+use crate::exec::{self, Exec};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How tmux is invoked: directly, or through `wsl.exe` when the host runs
+/// natively on Windows and tmux lives inside a WSL distribution.
+pub struct Tmux {
+    program: String,
+    prefix: Vec<String>,
+    discovery: Discovery,
+    /// Paste every message through a buffer so bracketed paste applies
+    bracketed_paste: bool,
+    exec: Box<dyn Exec>,
+}
+
+impl Tmux {
+    pub fn new(config: &Config) -> Self {
+        Self::for_platform(std::env::consts::OS, config)
+    }
+
+    fn for_platform(os: &str, config: &Config) -> Self {
+        if os == "windows" {
+            let mut prefix = Vec::new();
+            if let Some(distro) = &config.wsl_distro {
+                prefix.extend(["-d".to_string(), distro.clone()]);
+            }
+            prefix.extend(["--".to_string(), "tmux".to_string()]);
+            return Self {
+                program: "wsl.exe".to_string(),
+                prefix,
+                discovery: Discovery {
+                    path: "wsl.exe".to_string(),
+                    source: Source::Wsl,
+                },
+                bracketed_paste: config.bracketed_paste,
+                exec: Box::new(exec::System),
+            };
+        }
+        let discovery = find_tmux(config);
+        Self {
+            program: discovery.path.clone(),
+            prefix: Vec::new(),
+            discovery,
+            bracketed_paste: config.bracketed_paste,
+            exec: Box::new(exec::System),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_exec(self, exec: impl Exec + 'static) -> Self {
+        Self {
+            exec: Box::new(exec),
+            ..self
+        }
+    }
+
+    pub fn discovery(&self) -> &Discovery {
+        &self.discovery
+    }
+
+    /// Render a host path the way tmux's side of the bridge sees it
+    pub fn host_path(&self, path: &Path) -> String {
+        let path = path.display().to_string();
+        match self.discovery.source {
+            Source::Wsl => to_wsl_path(&path),
+            _ => path,
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<exec::Output, String> {
+        self.run_with_input(args, None)
+    }
+
+    fn run_with_input(&self, args: &[&str], input: Option<&[u8]>) -> Result<exec::Output, String> {
+        let argv: Vec<String> = self
+            .prefix
+            .iter()
+            .cloned()
+            .chain(args.iter().map(|a| a.to_string()))
+            .collect();
+        self.exec
+            .run_with_input(&self.program, &argv, input)
+            .map_err(|e| format!("Failed to run tmux: {e}"))
+    }
+
+    /// Paste `message` into `target` and press the `submit` keys. Messages
+    /// too large to pass as a `send-keys` argument go through a tmux buffer
+    /// instead.
+    pub fn send(
+        &self,
+        message: &str,
+        target: &str,
+        submit: &[SubmitKey],
+    ) -> Result<Delivery, String> {
+        let strategy = Strategy::for_message(message, self.bracketed_paste);
+        let outputs = match strategy {
+            Strategy::SendKeys => {
+                let keys = self.run(&["send-keys", "-t", target, message])?;
+                require_success(&keys)?;
+                vec![keys]
+            }
+            Strategy::PasteBuffer => self.paste_buffer(message, target)?,
+        };
+        let mut outputs = outputs;
+        outputs.extend(self.submit(target, submit)?);
+        Ok(outputs.iter().fold(
+            Delivery {
+                strategy,
+                spawn_time: Duration::ZERO,
+                exec_time: Duration::ZERO,
+            },
+            |mut delivery, output| {
+                delivery.spawn_time += output.spawn_time;
+                delivery.exec_time += output.exec_time;
+                delivery
+            },
+        ))
+    }
+
+    /// Press the `submit` keys in `target`, pausing before those with a delay
+    pub fn submit(&self, target: &str, submit: &[SubmitKey]) -> Result<Vec<exec::Output>, String> {
+        let mut outputs = Vec::new();
+        for step in submit {
+            let (key, delay_ms) = step.parts();
+            if delay_ms > 0 {
+                std::thread::sleep(Duration::from_millis(delay_ms));
+            }
+            outputs.push(self.run(&["send-keys", "-t", target, key])?);
+        }
+        Ok(outputs)
+    }
+
+    /// Empty the input line of `target` by pressing `keys`
+    pub fn clear_input(&self, target: &str, keys: &[SubmitKey]) -> Result<(), String> {
+        let outputs = self.submit(target, keys)?;
+        if let Some(failed) = outputs.iter().find(|o| !o.success) {
+            return Err(format!("tmux send-keys failed: {}", failed.stderr.trim()));
+        }
+        Ok(())
+    }
+
+    /// Load `message` from stdin into a buffer of its own and paste it,
+    /// deleting the buffer afterwards. Like a failed `send-keys`, a failed
+    /// load or paste is reported; nothing is pasted after a failed load.
+    fn paste_buffer(&self, message: &str, target: &str) -> Result<Vec<exec::Output>, String> {
+        let buffer = paste_buffer_name();
+        let load = self.run_with_input(
+            &["load-buffer", "-b", &buffer, "-"],
+            Some(message.as_bytes()),
+        )?;
+        if !load.success {
+            return Err(format!("tmux load-buffer failed: {}", load.stderr.trim()));
+        }
+        // -p: bracketed paste when the application asks for it
+        // -r: keep LF line endings, as send-keys does, instead of turning them into CR
+        let paste = self.run(&[
+            "paste-buffer",
+            "-d",
+            "-p",
+            "-r",
+            "-b",
+            &buffer,
+            "-t",
+            target,
+        ])?;
+        if !paste.success {
+            // -d only deletes the buffer after a successful paste
+            let _ = self.run(&["delete-buffer", "-b", &buffer]);
+            return Err(format!("tmux paste-buffer failed: {}", paste.stderr.trim()));
+        }
+        Ok(vec![load, paste])
+    }
+
+    /// Flash `text` in the status line of the client showing `target`
+    pub fn display_message(&self, target: &str, text: &str) -> Result<(), String> {
+        let output = self.run(&["display-message", "-t", target, text])?;
+        if !output.success {
+            return Err(format!(
+                "tmux display-message failed: {}",
+                output.stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Show `summary` in a popup on the client viewing `target` and ask
+    /// whether to deliver. Returns whether the user answered y; closing the
+    /// popup counts as no. Blocks until the popup is dismissed.
+    pub fn confirm(&self, target: &str, summary: &str) -> Result<bool, String> {
+        let clients = self.run(&["list-clients", "-t", target, "-F", "#{client_name}"])?;
+        if !clients.success {
+            return Err(format!(
+                "tmux list-clients failed: {}",
+                clients.stderr.trim()
+            ));
+        }
+        // A popup needs a client to draw on; the first one attached will do
+        let Some(client) = clients
+            .stdout
+            .lines()
+            .map(|c| c.trim_end_matches('\r'))
+            .find(|c| !c.is_empty())
+        else {
+            return Err(format!(
+                "No tmux client is attached to {target} to confirm the send"
+            ));
+        };
+        let script = format!(
+            "printf '%s\\n\\n' {}; printf 'Deliver? [y/N] '; read -r answer; \
+             [ \"$answer\" = y ] || [ \"$answer\" = Y ]",
+            shell_quote(summary)
+        );
+        let output = self.run(&[
+            "display-popup",
+            "-c",
+            client,
+            "-t",
+            target,
+            "-T",
+            " pigeon ",
+            "-E",
+            &script,
+        ])?;
+        // The popup's exit status comes back as tmux's own; only a tmux
+        // failure writes to stderr
+        match (output.success, output.stderr.trim()) {
+            (true, _) => Ok(true),
+            (false, "") => Ok(false),
+            (false, error) => Err(format!("tmux display-popup failed: {error}")),
+        }
+    }
+
+    /// Text of `target` including up to `history` lines of scrollback, with
+    /// wrapped lines joined back together
+    pub fn capture_pane(&self, target: &str, history: u32) -> Result<String, String> {
+        let start = format!("-{history}");
+        let output = self.run(&["capture-pane", "-p", "-J", "-S", &start, "-t", target])?;
+        if !output.success {
+            return Err(format!(
+                "tmux capture-pane failed: {}",
+                output.stderr.trim()
+            ));
+        }
+        Ok(output.stdout.replace("\r\n", "\n"))
+    }
+
+    /// Version, server and feature support of the tmux that would be used
+    pub fn probe(&self) -> Environment {
+        let mut environment = Environment {
+            tmux: self.discovery.clone(),
+            version: None,
+            server_running: false,
+            socket_path: None,
+            features: Features::default(),
+            error: None,
+        };
+        let version = match self.run(&["-V"]) {
+            Ok(output) if output.success => output.stdout.trim().to_string(),
+            Ok(output) => {
+                environment.error = Some(format!("tmux -V failed: {}", output.stderr.trim()));
+                return environment;
+            }
+            Err(e) => {
+                environment.error = Some(e);
+                return environment;
+            }
+        };
+        let version = version
+            .strip_prefix("tmux ")
+            .unwrap_or(&version)
+            .to_string();
+        environment.features = Features::for_version(&version);
+        environment.version = Some(version);
+        match self.run(&["display-message", "-p", "#{socket_path}"]) {
+            Ok(output) if output.success => {
+                environment.server_running = true;
+                environment.socket_path = Some(output.stdout.trim().to_string());
+            }
+            Ok(output) => environment.socket_path = socket_from_error(&output.stderr),
+            Err(e) => environment.error = Some(e),
+        }
+        environment
+    }
+
+    /// Width of `target` in columns
+    pub fn pane_width(&self, target: &str) -> Result<usize, String> {
+        let output = self.run(&["display-message", "-p", "-t", target, "#{pane_width}"])?;
+        if !output.success {
+            return Err(format!(
+                "tmux display-message failed: {}",
+                output.stderr.trim()
+            ));
+        }
+        output
+            .stdout
+            .trim()
+            .parse()
+            .map_err(|_| format!("Unexpected pane width from tmux: {:?}", output.stdout))
+    }
+
+    /// Id of the pane `target` names, such as `%12`
+    pub fn pane_id(&self, target: &str) -> Result<String, String> {
+        let output = self.run(&["display-message", "-p", "-t", target, "#{pane_id}"])?;
+        if !output.success {
+            return Err(format!(
+                "tmux display-message failed: {}",
+                output.stderr.trim()
+            ));
+        }
+        match output.stdout.trim() {
+            id if id.starts_with('%') => Ok(id.to_string()),
+            other => Err(format!("Unexpected pane id from tmux: {other:?}")),
+        }
+    }
+
+    /// The pane `target` names, as `list_panes` would show it
+    pub fn pane(&self, target: &str) -> Result<Pane, String> {
+        let id = self.pane_id(target)?;
+        self.list_panes()?
+            .into_iter()
+            .find(|pane| pane.id == id)
+            .ok_or_else(|| format!("pane {id} is not listed"))
+    }
+
+    /// End the session named exactly `session`
+    pub fn kill_session(&self, session: &str) -> Result<(), String> {
+        let output = self.run(&["kill-session", "-t", &format!("={session}")])?;
+        if !output.success {
+            return Err(format!(
+                "tmux kill-session failed: {}",
+                output.stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Kill the program in `target` and start `command` there, or the
+    /// command the pane was created with
+    pub fn respawn_pane(&self, target: &str, command: Option<&str>) -> Result<(), String> {
+        let mut args = vec!["respawn-pane", "-k", "-t", target];
+        args.extend(command);
+        let output = self.run(&args)?;
+        if !output.success {
+            return Err(format!(
+                "tmux respawn-pane failed: {}",
+                output.stderr.trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every pane on the server with its working directory
+    pub fn list_panes(&self) -> Result<Vec<Pane>, String> {
+        let output = self.run(&["list-panes", "-a", "-F", PANE_FORMAT])?;
+        if !output.success {
+            return Err(format!("tmux list-panes failed: {}", output.stderr.trim()));
+        }
+        Ok(output
+            .stdout
+            .lines()
+            .filter_map(|line| Pane::parse(line.trim_end_matches('\r')))
+            .collect())
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<Session>, String> {
+        let output = self.run(&["list-sessions", "-F", SESSION_FORMAT])?;
+
+        if !output.success {
+            return Err(format!("tmux list-sessions failed: {}", output.stderr));
+        }
+
+        // wsl.exe relays Linux output unchanged, but strip a stray CR just in case
+        Ok(output
+            .stdout
+            .lines()
+            .filter_map(|line| Session::parse(line.trim_end_matches('\r')))
+            .collect())
+    }
+}
+
+/// What the `probe-environment` action reports about tmux
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Environment {
+    pub tmux: Discovery,
+    /// `tmux -V` without the program name, such as `3.3a`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub server_running: bool,
+    /// Where the server listens (or would), even when it is not running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<String>,
+    pub features: Features,
+    /// Why tmux could not be run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Optional tmux features pigeon relies on, by version
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Features {
+    /// `display-popup`, needed by `confirm_before_send` (3.2+)
+    pub display_popup: bool,
+    /// `paste-buffer -p`, needed for bracketed paste (1.7+)
+    pub bracketed_paste: bool,
+}
+
+impl Features {
+    /// Builds such as `next-3.5` or `openbsd-7.4` are assumed to be recent
+    fn for_version(version: &str) -> Self {
+        let at_least = |wanted: (u32, u32)| parse_version(version).is_none_or(|v| v >= wanted);
+        Self {
+            display_popup: at_least((3, 2)),
+            bracketed_paste: at_least((1, 7)),
+        }
+    }
+}
+
+/// Major and minor number of a release version such as `3.3a`
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let (major, rest) = version.split_once('.')?;
+    let minor: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Socket path named in "no server running on …" or "error connecting to …"
+fn socket_from_error(stderr: &str) -> Option<String> {
+    let stderr = stderr.trim();
+    let path = stderr
+        .strip_prefix("no server running on ")
+        .or_else(|| stderr.strip_prefix("error connecting to "))?;
+    let path = path.split(" (").next().unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// Fields of `Pane`, tab-separated. The path goes last since it is the
+/// field most likely to contain a tab.
+const PANE_FORMAT: &str =
+    "#{pane_id}\t#{session_name}:#{window_index}.#{pane_index}\t#{pane_current_command}\t#{pane_current_path}";
+
+/// A pane as reported by `list-panes`
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Pane {
+    /// Such as `%3`, stable for the life of the pane
+    pub id: String,
+    /// Such as `work:2.1`, for showing to people
+    pub target: String,
+    /// Program running in the pane, such as `claude` or `zsh`
+    pub current_command: String,
+    /// Working directory of the pane's foreground process, as tmux sees it
+    pub current_path: String,
+}
+
+impl Pane {
+    /// Name of the session the pane is in
+    pub fn session(&self) -> &str {
+        self.target
+            .rsplit_once(':')
+            .map_or(self.target.as_str(), |(session, _)| session)
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        Some(Self {
+            id: fields.next()?.to_string(),
+            target: fields.next()?.to_string(),
+            current_command: fields.next()?.to_string(),
+            current_path: fields.next()?.to_string(),
+        })
+    }
+}
+
+/// Fields of `Session`, tab-separated. The name goes last since it is the
+/// only one that may itself contain a tab.
+const SESSION_FORMAT: &str = "#{session_attached}\t#{session_windows}\t#{session_activity}\t#{pane_current_command}\t#{session_name}";
+
+/// A tmux session as reported by `list-sessions`
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Session {
+    pub name: String,
+    /// Whether any client is showing the session
+    pub attached: bool,
+    pub windows: u32,
+    /// Unix time of the session's last activity
+    pub last_activity: u64,
+    /// Program running in the active pane, such as `claude` or `zsh`
+    pub current_command: String,
+}
+
+impl Session {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, '\t');
+        let attached = fields.next()?.parse::<u32>().ok()?;
+        let windows = fields.next()?.parse().ok()?;
+        let last_activity = fields.next()?.parse().ok()?;
+        let current_command = fields.next()?.to_string();
+        Some(Self {
+            name: fields.next()?.to_string(),
+            attached: attached > 0,
+            windows,
+            last_activity,
+            current_command,
+        })
+    }
+}
+
+/// A `send-keys` that failed typed nothing, whether the server or the pane
+/// was missing
+fn require_success(output: &exec::Output) -> Result<(), String> {
+    if !output.success {
+        return Err(format!("tmux send-keys failed: {}", output.stderr.trim()));
+    }
+    Ok(())
+}
+
+/// Quote `text` as a single POSIX shell word
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Longest pause accepted before a submit key
+pub const MAX_SUBMIT_DELAY_MS: u64 = 5000;
+
+/// A key pressed after the paste to submit it: a tmux key name such as
+/// `"Enter"`, or `{"key": "Enter", "delay_ms": 100}` to pause first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum SubmitKey {
+    Key(String),
+    Delayed { key: String, delay_ms: u64 },
+}
+
+impl SubmitKey {
+    /// What most TUIs need: a single Enter
+    pub fn default_sequence() -> Vec<Self> {
+        vec![Self::Key("Enter".to_string())]
+    }
+
+    /// C-u, which shells and most TUIs bind to deleting back to the start
+    /// of the line
+    pub fn clear_sequence() -> Vec<Self> {
+        vec![Self::Key("C-u".to_string())]
+    }
+
+    /// Key name and the pause before it
+    pub fn parts(&self) -> (&str, u64) {
+        match self {
+            Self::Key(key) => (key, 0),
+            Self::Delayed { key, delay_ms } => (key, *delay_ms),
+        }
+    }
+}
+
+/// Sends pasted so far by this process, numbering their buffers
+static PASTE_BUFFERS: AtomicU64 = AtomicU64::new(0);
+
+/// A tmux buffer of its own for each pasted message, such as `pigeon-812-3`.
+/// Sends to other targets, from this host or the host of another browser
+/// profile, paste at the same time on the same tmux server.
+fn paste_buffer_name() -> String {
+    let n = PASTE_BUFFERS.fetch_add(1, Ordering::Relaxed);
+    format!("pigeon-{}-{n}", std::process::id())
+}
+
+/// Largest message passed directly to `send-keys`. tmux clients send each
+/// command to the server in one message capped at 16KiB, and Windows limits
+/// the whole `wsl.exe` command line to 32K characters, so leave ample room.
+const SEND_KEYS_MAX_BYTES: usize = 8 * 1024;
+
+/// How a message was typed into the target pane
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The message as a `send-keys` argument
+    SendKeys,
+    /// `load-buffer` from stdin followed by `paste-buffer`
+    PasteBuffer,
+}
+
+impl Strategy {
+    /// `send-keys` types newlines as Enter presses, so with `bracketed`
+    /// multi-line messages are pasted for the receiving program to see as
+    /// one input
+    fn for_message(message: &str, bracketed: bool) -> Self {
+        if message.len() > SEND_KEYS_MAX_BYTES || (bracketed && message.contains('\n')) {
+            Self::PasteBuffer
+        } else {
+            Self::SendKeys
+        }
+    }
+}
+
+/// Result of `Tmux::send`: the strategy used and time spent starting and
+/// running tmux, summed over all invocations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub strategy: Strategy,
+    pub spawn_time: Duration,
+    pub exec_time: Duration,
+}
+
+/// Why tmux could not take a message at all, as opposed to a bad target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unavailable {
+    /// The binary (or `wsl.exe`) could not be run
+    NotInstalled,
+    /// tmux ran but found no server to talk to
+    NoServer,
+}
+
+impl Unavailable {
+    /// Classify an error returned by `Tmux`
+    pub fn from_error(error: &str) -> Option<Self> {
+        if error.starts_with("Failed to run tmux") || error.contains("tmux: command not found") {
+            Some(Self::NotInstalled)
+        } else if error.contains("no server running") || error.contains("error connecting to") {
+            Some(Self::NoServer)
+        } else {
+            None
+        }
+    }
+
+    /// What the user can do about it on `os`
+    pub fn hint(self, os: &str, target: &str) -> String {
+        match (self, os) {
+            (Self::NotInstalled, "macos") => {
+                "tmux not found; install it with `brew install tmux` or set tmux_path".to_string()
+            }
+            (Self::NotInstalled, "windows") => {
+                "tmux not found in WSL; install it there with `sudo apt install tmux` or set wsl_distro"
+                    .to_string()
+            }
+            (Self::NotInstalled, _) => {
+                "tmux not found; install it with your package manager (e.g. `sudo apt install tmux`) or set tmux_path"
+                    .to_string()
+            }
+            (Self::NoServer, _) => {
+                format!("tmux server not running; start one with `tmux new-session -s {target}`")
+            }
+        }
+    }
+}
+
+/// Where the tmux binary was found, reported by the `doctor` action
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Discovery {
+    pub path: String,
+    pub source: Source,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// `PIGEON_TMUX` environment variable
+    Env,
+    /// `tmux_path` in the config file
+    Config,
+    /// Found on `PATH`
+    Path,
+    /// One of the well-known install locations
+    KnownLocation,
+    /// Nothing found; relying on the OS to resolve `tmux`
+    Fallback,
+    /// Invoked inside WSL through `wsl.exe`
+    Wsl,
+}
+
+/// Discovery result cached per configured `tmux_path`, since probing the
+/// filesystem on every request is wasteful and the answer rarely changes.
+static DISCOVERED: Mutex<Option<(Option<String>, Discovery)>> = Mutex::new(None);
+
+/// Find tmux binary path. Chrome Native Messaging launches with a minimal PATH,
+/// so besides honoring overrides and PATH we check common locations where
+/// package managers install tmux.
+pub fn find_tmux(config: &Config) -> Discovery {
+    let mut cache = DISCOVERED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((key, found)) = cache.as_ref() {
+        if *key == config.tmux_path {
+            return found.clone();
+        }
+    }
+    let found = discover(
+        std::env::var("PIGEON_TMUX").ok(),
+        config.tmux_path.as_deref(),
+        std::env::var_os("PATH"),
+        std::env::var("HOME").ok(),
+        |p| p.is_file(),
+    );
+    *cache = Some((config.tmux_path.clone(), found.clone()));
+    found
+}
+
+fn discover(
+    env_override: Option<String>,
+    config_path: Option<&str>,
+    path_var: Option<OsString>,
+    home: Option<String>,
+    exists: impl Fn(&Path) -> bool,
+) -> Discovery {
+    let found = |path: PathBuf, source| Discovery {
+        path: path.display().to_string(),
+        source,
+    };
+
+    // Explicit overrides are trusted even if they do not exist yet, so a
+    // typo surfaces as a clear "failed to run" error rather than silently
+    // picking another binary.
+    if let Some(path) = env_override.filter(|p| !p.is_empty()) {
+        return found(PathBuf::from(path), Source::Env);
+    }
+    if let Some(path) = config_path {
+        return found(PathBuf::from(path), Source::Config);
+    }
+
+    if let Some(path_var) = path_var {
+        for dir in std::env::split_paths(&path_var) {
+            let candidate = dir.join("tmux");
+            if exists(&candidate) {
+                return found(candidate, Source::Path);
+            }
+        }
+    }
+
+    let mut candidates = vec![
+        PathBuf::from("/opt/homebrew/bin/tmux"), // Homebrew on Apple Silicon
+        PathBuf::from("/usr/local/bin/tmux"),    // Homebrew on Intel Mac / Linux manual install
+        PathBuf::from("/usr/bin/tmux"),          // System package manager
+        PathBuf::from("/home/linuxbrew/.linuxbrew/bin/tmux"), // Linuxbrew (shared)
+        PathBuf::from("/run/current-system/sw/bin/tmux"), // NixOS system profile
+    ];
+    if let Some(home) = home {
+        let home = PathBuf::from(home);
+        candidates.extend([
+            home.join(".nix-profile/bin/tmux"),        // Nix user profile
+            home.join(".linuxbrew/bin/tmux"),          // Linuxbrew (per-user)
+            home.join(".local/share/mise/shims/tmux"), // mise
+            home.join(".asdf/shims/tmux"),             // asdf
+            home.join(".local/bin/tmux"),              // Manual per-user install
+        ]);
+    }
+    for candidate in candidates {
+        if exists(&candidate) {
+            return found(candidate, Source::KnownLocation);
+        }
+    }
+
+    found(PathBuf::from("tmux"), Source::Fallback)
+}
+
+/// Translate a Windows path into its WSL mount (`C:\src\x` → `/mnt/c/src/x`).
+/// Paths that are not drive-absolute only get their separators normalized.
+fn to_wsl_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        let drive = (bytes[0] as char).to_ascii_lowercase();
+        let rest = path[2..].replace('\\', "/");
+        let rest = rest.trim_start_matches('/');
+        if rest.is_empty() {
+            return format!("/mnt/{drive}");
+        }
+        return format!("/mnt/{drive}/{rest}");
+    }
+    path.replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+
+    fn argv(tmux: Tmux, args: &[&str]) -> Vec<String> {
+        let fake = Fake::new();
+        tmux.with_exec(fake.clone()).run(args).unwrap();
+        fake.calls().remove(0)
+    }
+
+    fn fake_tmux() -> (Tmux, std::rc::Rc<Fake>) {
+        let fake = Fake::new();
+        let tmux = Tmux::for_platform("linux", &Config::default()).with_exec(fake.clone());
+        (tmux, fake)
+    }
+
+    #[test]
+    fn test_send_pastes_then_presses_enter() {
+        let (tmux, fake) = fake_tmux();
+        let delivery = tmux
+            .send("hello", "dev:1", &SubmitKey::default_sequence())
+            .unwrap();
+        assert_eq!(delivery.strategy, Strategy::SendKeys);
+        let calls: Vec<Vec<String>> = fake.calls().into_iter().map(|c| c[1..].to_vec()).collect();
+        assert_eq!(
+            calls,
+            [
+                ["send-keys", "-t", "dev:1", "hello"],
+                ["send-keys", "-t", "dev:1", "Enter"]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_send_presses_submit_sequence() {
+        let (tmux, fake) = fake_tmux();
+        let submit: Vec<SubmitKey> =
+            serde_json::from_str(r#"["Escape", {"key": "Enter", "delay_ms": 1}]"#).unwrap();
+        tmux.send("hello", "dev", &submit).unwrap();
+        let keys: Vec<String> = fake.calls().into_iter().map(|c| c[4].clone()).collect();
+        assert_eq!(keys, ["hello", "Escape", "Enter"]);
+    }
+
+    #[test]
+    fn test_bracketed_paste_pastes_multi_line_messages() {
+        let fake = Fake::new();
+        let config = Config {
+            bracketed_paste: true,
+            ..Config::default()
+        };
+        let tmux = Tmux::for_platform("linux", &config).with_exec(fake.clone());
+        let submit = SubmitKey::default_sequence();
+        assert_eq!(
+            tmux.send("one line", "dev", &submit).unwrap().strategy,
+            Strategy::SendKeys
+        );
+        let delivery = tmux.send("fn a() {\n}\nWhy?", "dev", &submit).unwrap();
+        assert_eq!(delivery.strategy, Strategy::PasteBuffer);
+        assert!(fake.calls()[3].contains(&"-p".to_string()));
+    }
+
+    #[test]
+    fn test_large_message_uses_paste_buffer() {
+        let (tmux, fake) = fake_tmux();
+        let message = "x".repeat(SEND_KEYS_MAX_BYTES + 1);
+        let delivery = tmux
+            .send(&message, "dev", &SubmitKey::default_sequence())
+            .unwrap();
+        assert_eq!(delivery.strategy, Strategy::PasteBuffer);
+        let calls: Vec<Vec<String>> = fake.calls().into_iter().map(|c| c[1..].to_vec()).collect();
+        let buffer = &calls[0][2];
+        assert!(buffer.starts_with(&format!("pigeon-{}-", std::process::id())));
+        assert_eq!(
+            calls,
+            [
+                vec!["load-buffer", "-b", buffer, "-"],
+                vec!["paste-buffer", "-d", "-p", "-r", "-b", buffer, "-t", "dev"],
+                vec!["send-keys", "-t", "dev", "Enter"],
+            ]
+        );
+        assert_eq!(fake.inputs()[0].as_deref(), Some(message.as_bytes()));
+    }
+
+    #[test]
+    fn test_each_paste_has_its_own_buffer() {
+        let (tmux, fake) = fake_tmux();
+        let message = "x".repeat(SEND_KEYS_MAX_BYTES + 1);
+        tmux.send(&message, "dev", &[]).unwrap();
+        fake.succeed("");
+        fake.fail("can't find pane: gone");
+        assert!(tmux.send(&message, "gone", &[]).is_err());
+        let calls = fake.calls();
+        let (first, second) = (&calls[0][3], &calls[2][3]);
+        assert_ne!(first, second);
+        // A buffer left behind by a failed paste is deleted
+        assert_eq!(calls[4][1..], ["delete-buffer", "-b", second.as_str()]);
+    }
+
+    #[test]
+    fn test_failed_buffer_load_stops_before_paste() {
+        let (tmux, fake) = fake_tmux();
+        fake.fail("no server running");
+        let err = tmux
+            .send(
+                &"x".repeat(SEND_KEYS_MAX_BYTES + 1),
+                "dev",
+                &SubmitKey::default_sequence(),
+            )
+            .unwrap_err();
+        assert_eq!(err, "tmux load-buffer failed: no server running");
+        assert_eq!(fake.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_send_reports_missing_binary() {
+        let (tmux, fake) = fake_tmux();
+        fake.missing();
+        let err = tmux
+            .send("hello", "dev", &SubmitKey::default_sequence())
+            .unwrap_err();
+        assert!(err.starts_with("Failed to run tmux"), "{err}");
+        assert_eq!(fake.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_send_keys_reports_missing_server() {
+        let (tmux, fake) = fake_tmux();
+        fake.fail("no server running on /tmp/tmux-1000/default\n");
+        let err = tmux
+            .send("hello", "dev", &SubmitKey::default_sequence())
+            .unwrap_err();
+        assert_eq!(Unavailable::from_error(&err), Some(Unavailable::NoServer));
+        assert_eq!(fake.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_send_keys_reports_missing_pane() {
+        let (tmux, fake) = fake_tmux();
+        fake.fail("can't find pane: gone\n");
+        let err = tmux
+            .send("hello", "gone", &SubmitKey::default_sequence())
+            .unwrap_err();
+        assert_eq!(err, "tmux send-keys failed: can't find pane: gone");
+        assert_eq!(Unavailable::from_error(&err), None);
+        assert_eq!(fake.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_unavailable_classification_and_hints() {
+        assert_eq!(
+            Unavailable::from_error("Failed to run tmux: No such file or directory"),
+            Some(Unavailable::NotInstalled)
+        );
+        assert_eq!(
+            Unavailable::from_error(
+                "tmux load-buffer failed: error connecting to /tmp/tmux-0/default"
+            ),
+            Some(Unavailable::NoServer)
+        );
+        assert_eq!(
+            Unavailable::from_error("tmux paste-buffer failed: can't find pane: nope"),
+            None
+        );
+        assert!(Unavailable::NotInstalled
+            .hint("macos", "dev")
+            .contains("brew install tmux"));
+        assert!(Unavailable::NoServer
+            .hint("linux", "dev")
+            .contains("tmux new-session -s dev"));
+    }
+
+    #[test]
+    fn test_probe_reports_version_and_server() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("tmux 3.1c\n");
+        fake.succeed("/tmp/tmux-1000/default\n");
+        let environment = tmux.probe();
+        assert_eq!(environment.version.as_deref(), Some("3.1c"));
+        assert!(environment.server_running);
+        assert_eq!(
+            environment.socket_path.as_deref(),
+            Some("/tmp/tmux-1000/default")
+        );
+        assert!(!environment.features.display_popup);
+        assert!(environment.features.bracketed_paste);
+        assert_eq!(
+            fake.calls()[1][1..],
+            ["display-message", "-p", "#{socket_path}"]
+        );
+    }
+
+    #[test]
+    fn test_probe_without_server() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("tmux next-3.5\n");
+        fake.fail("error connecting to /tmp/tmux-1000/default (No such file or directory)\n");
+        let environment = tmux.probe();
+        assert!(!environment.server_running);
+        assert_eq!(
+            environment.socket_path.as_deref(),
+            Some("/tmp/tmux-1000/default")
+        );
+        assert!(environment.features.display_popup);
+        assert!(environment.error.is_none());
+    }
+
+    #[test]
+    fn test_probe_without_tmux() {
+        let (tmux, fake) = fake_tmux();
+        fake.missing();
+        let environment = tmux.probe();
+        assert!(environment.version.is_none());
+        assert!(environment.error.unwrap().starts_with("Failed to run tmux"));
+        assert_eq!(fake.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("3.3a"), Some((3, 3)));
+        assert_eq!(parse_version("2.9"), Some((2, 9)));
+        assert_eq!(parse_version("next-3.5"), None);
+        assert_eq!(parse_version("master"), None);
+    }
+
+    #[test]
+    fn test_list_sessions_parses_output() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("1\t2\t1700000000\tclaude\tmain\r\n0\t1\t1700000100\tzsh\tmy\twork\n");
+        let sessions = tmux.list_sessions().unwrap();
+        assert_eq!(
+            sessions[0],
+            Session {
+                name: "main".to_string(),
+                attached: true,
+                windows: 2,
+                last_activity: 1_700_000_000,
+                current_command: "claude".to_string(),
+            }
+        );
+        assert_eq!(sessions[1].name, "my\twork");
+        assert!(!sessions[1].attached);
+        assert_eq!(
+            fake.calls()[0][1..],
+            ["list-sessions", "-F", SESSION_FORMAT]
+        );
+    }
+
+    #[test]
+    fn test_pane_width() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("120\r\n");
+        assert_eq!(tmux.pane_width("dev"), Ok(120));
+        assert_eq!(
+            fake.calls()[0][1..],
+            ["display-message", "-p", "-t", "dev", "#{pane_width}"]
+        );
+        fake.fail("can't find pane: dev");
+        assert!(tmux.pane_width("dev").is_err());
+    }
+
+    #[test]
+    fn test_pane_id() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("%12\n");
+        assert_eq!(tmux.pane_id("work:2.1").as_deref(), Ok("%12"));
+        assert_eq!(
+            fake.calls()[0][1..],
+            ["display-message", "-p", "-t", "work:2.1", "#{pane_id}"]
+        );
+        fake.succeed("\n");
+        assert!(tmux.pane_id("work:2.1").is_err());
+    }
+
+    #[test]
+    fn test_kill_session_matches_exactly() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("");
+        tmux.kill_session("work").unwrap();
+        assert_eq!(fake.calls()[0][1..], ["kill-session", "-t", "=work"]);
+        fake.fail("can't find session: work");
+        assert!(tmux.kill_session("work").is_err());
+    }
+
+    #[test]
+    fn test_respawn_pane() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("");
+        fake.succeed("");
+        tmux.respawn_pane("%3", None).unwrap();
+        tmux.respawn_pane("%3", Some("claude --continue")).unwrap();
+        let calls = fake.calls();
+        assert_eq!(calls[0][1..], ["respawn-pane", "-k", "-t", "%3"]);
+        assert_eq!(
+            calls[1][1..],
+            ["respawn-pane", "-k", "-t", "%3", "claude --continue"]
+        );
+    }
+
+    #[test]
+    fn test_list_panes_parses_output() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed(
+            "%0\twork:2.1\tclaude\t/home/me/src/api\r\n%4\tmain:0.0\tzsh\t/home/me/my\tdir\n",
+        );
+        let panes = tmux.list_panes().unwrap();
+        assert_eq!(
+            panes[0],
+            Pane {
+                id: "%0".to_string(),
+                target: "work:2.1".to_string(),
+                current_command: "claude".to_string(),
+                current_path: "/home/me/src/api".to_string(),
+            }
+        );
+        assert_eq!(panes[1].current_path, "/home/me/my\tdir");
+        assert_eq!(panes[0].session(), "work");
+        assert_eq!(
+            fake.calls()[0][1..],
+            ["list-panes", "-a", "-F", PANE_FORMAT]
+        );
+    }
+
+    #[test]
+    fn test_list_sessions_without_server() {
+        let (tmux, fake) = fake_tmux();
+        fake.fail("no server running on /tmp/tmux-1000/default");
+        let err = tmux.list_sessions().unwrap_err();
+        assert!(err.contains("no server running"), "{err}");
+    }
+
+    #[test]
+    fn test_display_message_failure() {
+        let (tmux, fake) = fake_tmux();
+        fake.fail("can't find pane: nope\n");
+        assert_eq!(
+            tmux.display_message("nope", "hi").unwrap_err(),
+            "tmux display-message failed: can't find pane: nope"
+        );
+    }
+
+    #[test]
+    fn test_confirm_opens_popup_on_attached_client() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("/dev/pts/3\n");
+        assert!(tmux.confirm("dev:1", "it's here").unwrap());
+        let calls = fake.calls();
+        assert_eq!(
+            calls[0][1..],
+            ["list-clients", "-t", "dev:1", "-F", "#{client_name}"]
+        );
+        assert_eq!(
+            calls[1][1..8],
+            [
+                "display-popup",
+                "-c",
+                "/dev/pts/3",
+                "-t",
+                "dev:1",
+                "-T",
+                " pigeon "
+            ]
+        );
+        assert!(calls[1][9].starts_with(r"printf '%s\n\n' 'it'\''s here';"));
+    }
+
+    #[test]
+    fn test_confirm_declined_or_unavailable() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("/dev/pts/3\n");
+        fake.fail("");
+        assert!(!tmux.confirm("dev", "summary").unwrap());
+
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("");
+        let err = tmux.confirm("dev", "summary").unwrap_err();
+        assert!(err.starts_with("No tmux client is attached"), "{err}");
+    }
+
+    #[test]
+    fn test_capture_pane_includes_scrollback() {
+        let (tmux, fake) = fake_tmux();
+        fake.succeed("line\r\n");
+        assert_eq!(tmux.capture_pane("dev", 100).unwrap(), "line\n");
+        assert_eq!(
+            fake.calls()[0][1..],
+            ["capture-pane", "-p", "-J", "-S", "-100", "-t", "dev"]
+        );
+    }
+
+    #[test]
+    fn test_windows_uses_wsl_bridge() {
+        let tmux = Tmux::for_platform("windows", &Config::default());
+        assert_eq!(
+            argv(tmux, &["list-sessions"]),
+            ["wsl.exe", "--", "tmux", "list-sessions"]
+        );
+    }
+
+    #[test]
+    fn test_windows_with_distro() {
+        let config = Config {
+            wsl_distro: Some("Ubuntu-24.04".to_string()),
+            ..Config::default()
+        };
+        let tmux = Tmux::for_platform("windows", &config);
+        assert_eq!(
+            argv(tmux, &["send-keys", "-t", "dev", "Enter"]),
+            [
+                "wsl.exe",
+                "-d",
+                "Ubuntu-24.04",
+                "--",
+                "tmux",
+                "send-keys",
+                "-t",
+                "dev",
+                "Enter"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unix_invokes_tmux_directly() {
+        let tmux = Tmux::for_platform("linux", &Config::default());
+        let argv = argv(tmux, &["list-sessions"]);
+        assert!(argv[0].ends_with("tmux"));
+        assert_eq!(argv[1..], ["list-sessions"]);
+    }
+
+    fn discover_with(
+        env: Option<&str>,
+        config: Option<&str>,
+        path_var: Option<&str>,
+        existing: &[&str],
+    ) -> Discovery {
+        discover(
+            env.map(String::from),
+            config,
+            path_var.map(OsString::from),
+            Some("/home/me".to_string()),
+            |p| existing.iter().any(|e| Path::new(e) == p),
+        )
+    }
+
+    #[test]
+    fn test_env_override_wins() {
+        let found = discover_with(
+            Some("/opt/tmux"),
+            Some("/cfg/tmux"),
+            None,
+            &["/usr/bin/tmux"],
+        );
+        assert_eq!(found.path, "/opt/tmux");
+        assert_eq!(found.source, Source::Env);
+    }
+
+    #[test]
+    fn test_config_path_beats_search() {
+        let found = discover_with(
+            None,
+            Some("/cfg/tmux"),
+            Some("/usr/bin"),
+            &["/usr/bin/tmux"],
+        );
+        assert_eq!(found.path, "/cfg/tmux");
+        assert_eq!(found.source, Source::Config);
+    }
+
+    #[test]
+    fn test_path_search() {
+        let found = discover_with(None, None, Some("/nope:/custom/bin"), &["/custom/bin/tmux"]);
+        assert_eq!(found.path, "/custom/bin/tmux");
+        assert_eq!(found.source, Source::Path);
+    }
+
+    #[test]
+    fn test_nix_profile_location() {
+        let found = discover_with(None, None, None, &["/home/me/.nix-profile/bin/tmux"]);
+        assert_eq!(found.path, "/home/me/.nix-profile/bin/tmux");
+        assert_eq!(found.source, Source::KnownLocation);
+    }
+
+    #[test]
+    fn test_fallback_when_nothing_found() {
+        let found = discover_with(Some(""), None, Some("/empty"), &[]);
+        assert_eq!(found.path, "tmux");
+        assert_eq!(found.source, Source::Fallback);
+    }
+
+    #[test]
+    fn test_to_wsl_path() {
+        assert_eq!(to_wsl_path(r"C:\Users\me\src"), "/mnt/c/Users/me/src");
+        assert_eq!(to_wsl_path("D:/work/repo"), "/mnt/d/work/repo");
+        assert_eq!(to_wsl_path(r"E:\"), "/mnt/e");
+        assert_eq!(to_wsl_path("/home/me/src"), "/home/me/src");
+    }
+
+    #[test]
+    fn test_host_path_translated_for_wsl() {
+        let tmux = Tmux::for_platform("windows", &Config::default());
+        assert_eq!(
+            tmux.host_path(Path::new(r"C:\src\app\lib.rs")),
+            "/mnt/c/src/app/lib.rs"
+        );
+    }
+}
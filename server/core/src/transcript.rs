@@ -0,0 +1,259 @@
+//! Transcripts: the sends to one target from the history, each with the
+//! agent's reply when it can still be found in the pane, as a markdown
+//! document for archiving next to the PR.
+
+use crate::config::Config;
+use crate::history::HistoryEntry;
+use crate::{paths, reply, time};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Transcripts larger than this must be written to a file rather than
+/// returned in the response, which Chrome caps at 1 MiB
+pub const MAX_INLINE_BYTES: usize = 256 * 1024;
+
+/// Render the transcript of `entries`, the sends to `target` oldest first.
+/// Replies are cut out of `captured` by send id; when some are missing the
+/// whole capture is appended instead, so nothing the pane shows is lost.
+/// Replies are cleaned up as `settings` asks, like `get-reply` answers.
+pub fn render(
+    target: &str,
+    entries: &[HistoryEntry],
+    captured: Option<&str>,
+    settings: &reply::ReplySettings,
+) -> String {
+    let mut doc = format!(
+        "# Transcript: {target}\n\nExported {}, {} sends.\n",
+        time::rfc3339(time::unix_now()),
+        entries.len()
+    );
+    let mut missing_replies = false;
+    for entry in entries {
+        let location = match &entry.repo {
+            Some(repo) => format!("{repo}/{}", entry.file),
+            None => entry.file.clone(),
+        };
+        doc.push_str(&format!("\n## {} {location}\n\n", entry.ts));
+        if !entry.tags.is_empty() {
+            doc.push_str(&format!("Tags: {}\n\n", entry.tags.join(", ")));
+        }
+        for line in entry.question.lines() {
+            doc.push_str(if line.is_empty() { ">" } else { "> " });
+            doc.push_str(line);
+            doc.push('\n');
+        }
+        let found = match (&entry.send_id, captured) {
+            (Some(id), Some(captured)) => {
+                reply::extract(captured, id).map(|text| reply::clean(&text, settings))
+            }
+            _ => None,
+        };
+        match found {
+            Some(text) => doc.push_str(&format!("\n### Reply\n\n{}", fenced(&text))),
+            None => missing_replies = true,
+        }
+    }
+    if let Some(captured) = captured.filter(|_| missing_replies) {
+        doc.push_str(&format!(
+            "\n## Pane output\n\n{}",
+            fenced(captured.trim_end())
+        ));
+    }
+    doc
+}
+
+/// `text` in a code fence longer than any backtick run inside it
+fn fenced(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}text\n{text}\n{fence}\n")
+}
+
+/// Transcript failure with a machine-readable code for the extension
+#[derive(Debug, PartialEq, Eq)]
+pub struct TranscriptError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl TranscriptError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Directory transcripts are written into: `transcripts_dir`, or
+/// `transcripts/` in the platform data directory
+pub fn dir(config: &Config) -> Option<PathBuf> {
+    match &config.transcripts_dir {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => paths::data_dir().map(|dir| dir.join("transcripts")),
+    }
+}
+
+/// Write `doc` to a new file at `path`, which must be absolute and inside
+/// `dir`. Existing files are never replaced.
+pub fn write(dir: &Path, path: &Path, doc: &str) -> Result<(), TranscriptError> {
+    let outside = || {
+        TranscriptError::new(
+            "PATH_NOT_ALLOWED",
+            format!(
+                "{} is not an absolute path inside {}",
+                path.display(),
+                dir.display()
+            ),
+        )
+    };
+    let parent = path
+        .parent()
+        .filter(|_| path.is_absolute() && path.starts_with(dir));
+    let Some(parent) = parent else {
+        return Err(outside());
+    };
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(outside());
+    }
+    let failed = |e: io::Error| {
+        TranscriptError::new(
+            "SAVE_FAILED",
+            format!("Cannot write {}: {e}", path.display()),
+        )
+    };
+    fs::create_dir_all(parent).map_err(failed)?;
+    // A symlinked directory inside `dir` must not lead out of it
+    if !parent
+        .canonicalize()
+        .map_err(failed)?
+        .starts_with(dir.canonicalize().map_err(failed)?)
+    {
+        return Err(outside());
+    }
+    let mut file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => TranscriptError::new(
+                "TRANSCRIPT_EXISTS",
+                format!("{} already exists; give a new path", path.display()),
+            ),
+            _ => failed(e),
+        })?;
+    io::Write::write_all(&mut file, doc.as_bytes()).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, question: &str, send_id: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            ts: "2026-10-15T08:30:00Z".to_string(),
+            send_id: send_id.map(String::from),
+            ..HistoryEntry::new("dev", Some("acme/api"), file, question)
+        }
+    }
+
+    #[test]
+    fn test_replies_follow_their_questions() {
+        let captured = format!(
+            "{}\nThe parser owns it.\n$ \n",
+            reply::wrap("src/a.rs:3\nWho frees this?", "id1")
+        );
+        let entries = [entry(
+            "src/a.rs",
+            "Who frees this?\n\nSee line 3",
+            Some("id1"),
+        )];
+        let doc = render(
+            "dev",
+            &entries,
+            Some(&captured),
+            &reply::ReplySettings::default(),
+        );
+        assert!(doc.starts_with("# Transcript: dev\n\nExported "), "{doc}");
+        assert!(
+            doc.contains(
+                "## 2026-10-15T08:30:00Z acme/api/src/a.rs\n\n> Who frees this?\n>\n> See line 3\n\n### Reply\n\n```text\nThe parser owns it."
+            ),
+            "{doc}"
+        );
+        assert!(!doc.contains("## Pane output"), "{doc}");
+    }
+
+    #[test]
+    fn test_pane_appended_when_replies_are_missing() {
+        let entries = [entry("src/a.rs", "Why?", None)];
+        let doc = render(
+            "dev",
+            &entries,
+            Some("$ claude\nBecause.\n\n"),
+            &reply::ReplySettings::default(),
+        );
+        assert!(
+            doc.ends_with("## Pane output\n\n```text\n$ claude\nBecause.\n```\n"),
+            "{doc}"
+        );
+        let doc = render("dev", &entries, None, &reply::ReplySettings::default());
+        assert!(doc.ends_with("> Why?\n"), "{doc}");
+    }
+
+    #[test]
+    fn test_fence_outlasts_backticks() {
+        assert_eq!(
+            fenced("a\n```rust\nb\n```"),
+            "````text\na\n```rust\nb\n```\n````\n"
+        );
+        assert_eq!(fenced("plain"), "```text\nplain\n```\n");
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "pigeon-transcript-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_write_creates_directory() {
+        let dir = test_dir("write");
+        let path = dir.join("reviews").join("pr-7.md");
+        write(&dir, &path, "# Transcript\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# Transcript\n");
+        let err = write(&dir, &path, "# Again\n").unwrap_err();
+        assert_eq!(err.code, "TRANSCRIPT_EXISTS");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# Transcript\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_stays_inside_directory() {
+        let dir = test_dir("confined");
+        let outside = test_dir("outside");
+        for path in [
+            PathBuf::from("pr-7.md"),
+            outside.join("pr-7.md"),
+            dir.join("..").join("pr-7.md"),
+        ] {
+            let err = write(&dir, &path, "x").unwrap_err();
+            assert_eq!(err.code, "PATH_NOT_ALLOWED", "{}", path.display());
+        }
+        #[cfg(unix)]
+        {
+            fs::create_dir_all(&dir).unwrap();
+            fs::create_dir_all(&outside).unwrap();
+            std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+            let err = write(&dir, &dir.join("link").join("pr-7.md"), "x").unwrap_err();
+            assert_eq!(err.code, "PATH_NOT_ALLOWED");
+        }
+        assert!(!outside.join("pr-7.md").exists());
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&outside);
+    }
+}
@@ -0,0 +1,390 @@
+//! Whether a newer host has been released, and installing it. The latest
+//! release of `update.repo` is read from the GitHub API with `curl`.
+//! Installing downloads the release's binary for this platform next to the
+//! running one, checks it against the release's `.sha256` file when there
+//! is one, and renames it over the old binary, so a failed download never
+//! leaves a broken host behind.
+
+use crate::exec::Exec;
+use crate::sha256;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Repository whose releases are checked unless `update.repo` says otherwise
+pub const DEFAULT_REPO: &str = "x7c1/pigeon";
+
+/// Seconds allowed for asking GitHub about the latest release
+const CHECK_TIMEOUT_SECS: &str = "10";
+
+/// Seconds allowed for downloading a binary
+const DOWNLOAD_TIMEOUT_SECS: &str = "300";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpdateSettings {
+    /// GitHub repository whose releases are checked, as `owner/name`
+    pub repo: String,
+    /// Let `check-update` replace the binary when a request asks it to
+    pub allow_install: bool,
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        Self {
+            repo: DEFAULT_REPO.to_string(),
+            allow_install: false,
+        }
+    }
+}
+
+impl UpdateSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        let valid = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        };
+        match self.repo.split_once('/') {
+            Some((owner, name)) if valid(owner) && valid(name) => Ok(()),
+            _ => Err(format!("update.repo must be owner/name, not {}", self.repo)),
+        }
+    }
+}
+
+/// The latest release, as far as installing it is concerned
+#[derive(Debug, PartialEq, Eq)]
+pub struct Release {
+    /// Its tag without a leading `v`, such as `0.2.0`
+    pub version: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+#[derive(Deserialize)]
+struct ApiRelease {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+/// Ask GitHub for the latest release of `repo`, drafts and pre-releases
+/// left out
+pub fn latest(exec: &dyn Exec, repo: &str) -> Result<Release, String> {
+    let url = format!("https://api.github.com/repos/{repo}/releases/latest");
+    let body = curl(exec, &["--max-time", CHECK_TIMEOUT_SECS], &url)?;
+    let release: ApiRelease =
+        serde_json::from_str(&body).map_err(|e| format!("Unexpected answer from {url}: {e}"))?;
+    let version = release.tag_name.trim_start_matches('v').to_string();
+    if parse_version(&version).is_none() {
+        return Err(format!(
+            "Latest release {} is not a version",
+            release.tag_name
+        ));
+    }
+    Ok(Release {
+        version,
+        assets: release.assets,
+    })
+}
+
+/// Whether `latest` is a later version than `current`
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// Release numbers, then whether it is a full release rather than a
+/// pre-release such as `0.2.0-rc.1`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Version(Vec<u64>, bool);
+
+fn parse_version(text: &str) -> Option<Version> {
+    let text = text.trim_start_matches('v');
+    let (numbers, pre) = match text.split_once('-') {
+        Some((numbers, pre)) => (numbers, Some(pre)),
+        None => (text, None),
+    };
+    let mut numbers: Vec<u64> = numbers
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    while numbers.len() > 1 && numbers.last() == Some(&0) {
+        numbers.pop();
+    }
+    Some(Version(numbers, pre.is_none()))
+}
+
+/// Name of the release asset holding the binary for `os` and `arch`, such
+/// as `pigeon-host-x86_64-linux`
+pub fn asset_name(os: &str, arch: &str) -> String {
+    format!("pigeon-host-{arch}-{os}")
+}
+
+/// Replace the binary at `exe` with the one `release` has for `os` and
+/// `arch`. The new binary runs the next time the host starts.
+pub fn install(
+    exec: &dyn Exec,
+    release: &Release,
+    os: &str,
+    arch: &str,
+    exe: &Path,
+) -> Result<(), String> {
+    if os == "windows" {
+        return Err("Self-update cannot replace a running binary on Windows".to_string());
+    }
+    let name = asset_name(os, arch);
+    let asset = release.asset(&name).ok_or_else(|| {
+        format!(
+            "Release {} has no {name}; download it by hand",
+            release.version
+        )
+    })?;
+    let download = staging_path(exe);
+    let result = download_to(exec, release, asset, &download)
+        .and_then(|()| prepare(exec, os, exe, &download))
+        .and_then(|()| {
+            std::fs::rename(&download, exe)
+                .map_err(|e| format!("Cannot replace {}: {e}", exe.display()))
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&download);
+    }
+    result
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+/// Where the new binary is downloaded: beside `exe`, so the rename over it
+/// stays on one file system
+fn staging_path(exe: &Path) -> PathBuf {
+    let name = exe.file_name().map_or_else(
+        || "pigeon-host".into(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    exe.with_file_name(format!(".{name}.update"))
+}
+
+/// Download `asset` to `path`, checked against its `.sha256` file. Chrome
+/// runs what is installed, so a release without one is refused.
+fn download_to(
+    exec: &dyn Exec,
+    release: &Release,
+    asset: &Asset,
+    path: &Path,
+) -> Result<(), String> {
+    let checksum = release
+        .asset(&format!("{}.sha256", asset.name))
+        .ok_or_else(|| {
+            format!(
+                "Release {} has no checksum published for {}; not installing it",
+                release.version, asset.name
+            )
+        })?;
+    let path_arg = path.display().to_string();
+    curl(
+        exec,
+        &["--max-time", DOWNLOAD_TIMEOUT_SECS, "-o", &path_arg],
+        &asset.browser_download_url,
+    )?;
+    let expected = curl(
+        exec,
+        &["--max-time", CHECK_TIMEOUT_SECS],
+        &checksum.browser_download_url,
+    )?;
+    let expected = expected.split_whitespace().next().unwrap_or_default();
+    let data = std::fs::read(path).map_err(|e| format!("Cannot read {path_arg}: {e}"))?;
+    let actual = sha256::hex_digest(&data);
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "Checksum of {} does not match {}: got {actual}",
+            asset.name, checksum.name
+        ));
+    }
+    Ok(())
+}
+
+/// Give the download the permissions of `exe` and, on macOS, the ad-hoc
+/// signature Chrome needs to launch it
+fn prepare(exec: &dyn Exec, os: &str, exe: &Path, download: &Path) -> Result<(), String> {
+    let permissions = std::fs::metadata(exe)
+        .map_err(|e| format!("Cannot read {}: {e}", exe.display()))?
+        .permissions();
+    std::fs::set_permissions(download, permissions)
+        .map_err(|e| format!("Cannot set permissions of {}: {e}", download.display()))?;
+    if os == "macos" {
+        let args = ["-s", "-", "-f"]
+            .map(String::from)
+            .into_iter()
+            .chain([download.display().to_string()])
+            .collect::<Vec<_>>();
+        // As install.sh does; an unsigned binary still runs from a terminal
+        let _ = exec.run("codesign", &args);
+    }
+    Ok(())
+}
+
+fn curl(exec: &dyn Exec, options: &[&str], url: &str) -> Result<String, String> {
+    let mut args: Vec<String> = ["-fsSL", "-H", "Accept: application/vnd.github+json"]
+        .iter()
+        .chain(options)
+        .map(|arg| arg.to_string())
+        .collect();
+    args.push(url.to_string());
+    match exec.run("curl", &args) {
+        Ok(output) if output.success => Ok(output.stdout),
+        Ok(output) => Err(format!("curl {url} failed: {}", output.stderr.trim())),
+        Err(e) => Err(format!("Failed to run curl: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+
+    const LATEST: &str = r#"{
+        "tag_name": "v0.2.0",
+        "assets": [
+            {"name": "pigeon-host-x86_64-linux", "browser_download_url": "https://example.com/bin"},
+            {"name": "pigeon-host-x86_64-linux.sha256", "browser_download_url": "https://example.com/sum"}
+        ]
+    }"#;
+
+    #[test]
+    fn test_latest_reads_tag_and_assets() {
+        let fake = Fake::new();
+        fake.succeed(LATEST);
+        let release = latest(&*fake, "x7c1/pigeon").unwrap();
+        assert_eq!(release.version, "0.2.0");
+        assert!(release.asset("pigeon-host-x86_64-linux").is_some());
+        assert_eq!(
+            fake.calls()[0].last().unwrap(),
+            "https://api.github.com/repos/x7c1/pigeon/releases/latest"
+        );
+    }
+
+    #[test]
+    fn test_latest_reports_failures() {
+        let fake = Fake::new();
+        fake.fail("curl: (22) The requested URL returned error: 404");
+        let err = latest(&*fake, "x7c1/pigeon").unwrap_err();
+        assert!(err.contains("404"), "{err}");
+        fake.succeed(r#"{"tag_name": "nightly"}"#);
+        assert!(latest(&*fake, "x7c1/pigeon").is_err());
+        fake.missing();
+        assert!(latest(&*fake, "x7c1/pigeon").is_err());
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.0"));
+        assert!(is_newer("v0.10.0", "0.9.3"));
+        assert!(is_newer("1.0.0", "1.0.0-rc.1"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1", "0.1.0"));
+        assert!(!is_newer("0.1.0", "0.2.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+    }
+
+    #[test]
+    fn test_validate_repo() {
+        assert!(UpdateSettings::default().validate().is_ok());
+        for repo in ["pigeon", "x7c1/", "a/b/c", "x7c1/pi geon"] {
+            let settings = UpdateSettings {
+                repo: repo.to_string(),
+                ..UpdateSettings::default()
+            };
+            assert!(settings.validate().is_err(), "{repo}");
+        }
+    }
+
+    fn release() -> Release {
+        let fake = Fake::new();
+        fake.succeed(LATEST);
+        latest(&*fake, "x7c1/pigeon").unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-update-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_install_checks_sum_and_replaces_binary() {
+        let dir = test_dir("install");
+        let exe = dir.join("pigeon-host");
+        std::fs::write(&exe, "old").unwrap();
+        // Stands in for curl writing the download
+        std::fs::write(staging_path(&exe), "new").unwrap();
+        let fake = Fake::new();
+        fake.succeed("");
+        fake.succeed(&format!("{}  pigeon-host\n", sha256::hex_digest(b"new")));
+        install(&*fake, &release(), "linux", "x86_64", &exe).unwrap();
+        assert_eq!(std::fs::read_to_string(&exe).unwrap(), "new");
+        assert!(!staging_path(&exe).exists());
+        let calls = fake.calls();
+        assert!(calls[0].contains(&staging_path(&exe).display().to_string()));
+        assert_eq!(calls[1].last().unwrap(), "https://example.com/sum");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_keeps_binary_on_bad_sum() {
+        let dir = test_dir("bad-sum");
+        let exe = dir.join("pigeon-host");
+        std::fs::write(&exe, "old").unwrap();
+        std::fs::write(staging_path(&exe), "tampered").unwrap();
+        let fake = Fake::new();
+        fake.succeed("");
+        fake.succeed(&sha256::hex_digest(b"new"));
+        let err = install(&*fake, &release(), "linux", "x86_64", &exe).unwrap_err();
+        assert!(err.contains("Checksum"), "{err}");
+        assert_eq!(std::fs::read_to_string(&exe).unwrap(), "old");
+        assert!(!staging_path(&exe).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_needs_published_sum() {
+        let dir = test_dir("no-sum");
+        let exe = dir.join("pigeon-host");
+        std::fs::write(&exe, "old").unwrap();
+        let mut release = release();
+        release
+            .assets
+            .retain(|asset| !asset.name.ends_with(".sha256"));
+        let fake = Fake::new();
+        let err = install(&*fake, &release, "linux", "x86_64", &exe).unwrap_err();
+        assert!(err.contains("no checksum published"), "{err}");
+        assert!(fake.calls().is_empty());
+        assert_eq!(std::fs::read_to_string(&exe).unwrap(), "old");
+        assert!(!staging_path(&exe).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_needs_asset_for_platform() {
+        let fake = Fake::new();
+        let exe = Path::new("/nonexistent/pigeon-host");
+        let err = install(&*fake, &release(), "macos", "aarch64", exe).unwrap_err();
+        assert!(err.contains("pigeon-host-aarch64-macos"), "{err}");
+        assert!(install(&*fake, &release(), "windows", "x86_64", exe).is_err());
+        assert!(fake.calls().is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Uploads with no activity for this long are discarded
+pub const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Cap on the accumulated size of a single upload
+pub const MAX_UPLOAD_BYTES: usize = 16 * 1024 * 1024;
+
+/// Selections streamed in pieces by `begin-send` / `append-chunk` / `commit-send`.
+/// Chrome limits extension → host messages, so large code is sent as chunks
+/// that are concatenated here until the commit arrives.
+pub struct Uploads<T> {
+    pending: HashMap<String, Upload<T>>,
+    timeout: Duration,
+}
+
+struct Upload<T> {
+    meta: T,
+    data: String,
+    last_activity: Instant,
+}
+
+impl<T> Uploads<T> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            pending: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Start an upload. `data` is the initial content, possibly empty.
+    pub fn begin(&mut self, id: String, meta: T, data: String) -> Result<(), String> {
+        self.discard_expired(Instant::now());
+        if self.pending.contains_key(&id) {
+            return Err(format!("Upload already in progress: {id}"));
+        }
+        check_size(data.len())?;
+        self.pending.insert(
+            id,
+            Upload {
+                meta,
+                data,
+                last_activity: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn append(&mut self, id: &str, chunk: &str) -> Result<(), String> {
+        self.discard_expired(Instant::now());
+        let upload = self
+            .pending
+            .get_mut(id)
+            .ok_or_else(|| format!("Unknown or expired upload: {id}"))?;
+        if let Err(e) = check_size(upload.data.len() + chunk.len()) {
+            self.pending.remove(id);
+            return Err(e);
+        }
+        upload.data.push_str(chunk);
+        upload.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Finish an upload, returning its metadata and accumulated content
+    pub fn commit(&mut self, id: &str) -> Result<(T, String), String> {
+        self.discard_expired(Instant::now());
+        self.pending
+            .remove(id)
+            .map(|u| (u.meta, u.data))
+            .ok_or_else(|| format!("Unknown or expired upload: {id}"))
+    }
+
+    fn discard_expired(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.pending
+            .retain(|_, u| now.duration_since(u.last_activity) < timeout);
+    }
+}
+
+fn check_size(len: usize) -> Result<(), String> {
+    if len > MAX_UPLOAD_BYTES {
+        return Err(format!(
+            "Upload too large: exceeds the {MAX_UPLOAD_BYTES} byte limit"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_are_concatenated() {
+        let mut uploads = Uploads::new(UPLOAD_TIMEOUT);
+        uploads.begin("u1".into(), "meta", "ab".into()).unwrap();
+        uploads.append("u1", "cd").unwrap();
+        uploads.append("u1", "ef").unwrap();
+        let (meta, data) = uploads.commit("u1").unwrap();
+        assert_eq!(meta, "meta");
+        assert_eq!(data, "abcdef");
+    }
+
+    #[test]
+    fn test_commit_removes_upload() {
+        let mut uploads = Uploads::new(UPLOAD_TIMEOUT);
+        uploads.begin("u1".into(), (), String::new()).unwrap();
+        uploads.commit("u1").unwrap();
+        assert!(uploads.commit("u1").is_err());
+    }
+
+    #[test]
+    fn test_unknown_upload_fails() {
+        let mut uploads: Uploads<()> = Uploads::new(UPLOAD_TIMEOUT);
+        let err = uploads.append("missing", "x").unwrap_err();
+        assert!(err.contains("missing"));
+    }
+
+    #[test]
+    fn test_duplicate_begin_fails() {
+        let mut uploads = Uploads::new(UPLOAD_TIMEOUT);
+        uploads.begin("u1".into(), (), String::new()).unwrap();
+        assert!(uploads.begin("u1".into(), (), String::new()).is_err());
+    }
+
+    #[test]
+    fn test_abandoned_upload_is_discarded() {
+        let mut uploads = Uploads::new(Duration::ZERO);
+        uploads.begin("u1".into(), (), String::new()).unwrap();
+        assert!(uploads.append("u1", "x").is_err());
+    }
+
+    #[test]
+    fn test_oversized_upload_is_dropped() {
+        let mut uploads = Uploads::new(UPLOAD_TIMEOUT);
+        uploads.begin("u1".into(), (), String::new()).unwrap();
+        let big = "x".repeat(MAX_UPLOAD_BYTES + 1);
+        assert!(uploads.append("u1", &big).is_err());
+        assert!(uploads.commit("u1").is_err());
+    }
+}
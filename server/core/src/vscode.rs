@@ -0,0 +1,105 @@
+use crate::exec::Exec;
+use crate::notes::NotesFile;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Where the `vscode` backend leaves the message for the person in the editor
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Handoff {
+    /// Copy it to the system clipboard
+    #[default]
+    Clipboard,
+    /// Append it to `.pigeon/inbox.md` in the local checkout
+    Inbox,
+}
+
+/// Settings for the `vscode` backend
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct VscodeSettings {
+    /// VS Code command line launcher (`code`, `code-insiders`, `codium`)
+    pub code: String,
+    pub handoff: Handoff,
+}
+
+impl Default for VscodeSettings {
+    fn default() -> Self {
+        Self {
+            code: "code".to_string(),
+            handoff: Handoff::default(),
+        }
+    }
+}
+
+impl VscodeSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.code.trim().is_empty() {
+            return Err("vscode.code must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Open `path` at `line` in the running VS Code window
+pub fn open(
+    settings: &VscodeSettings,
+    exec: &dyn Exec,
+    path: &Path,
+    line: Option<u64>,
+) -> Result<(), String> {
+    let mut location = path.display().to_string();
+    if let Some(line) = line {
+        location.push_str(&format!(":{line}"));
+    }
+    let program = &settings.code;
+    let output = exec
+        .run(program, &["-g".to_string(), location])
+        .map_err(|e| format!("Failed to run {program}: {e}"))?;
+    if !output.success {
+        return Err(format!("{program} failed: {}", output.stderr.trim()));
+    }
+    Ok(())
+}
+
+/// `.pigeon/inbox.md` in `workspace`
+pub fn inbox(workspace: &Path) -> NotesFile {
+    NotesFile::new(workspace.join(".pigeon").join("inbox.md"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::fake::Fake;
+    use std::fs;
+
+    #[test]
+    fn test_open_goes_to_line() {
+        let fake = Fake::new();
+        let settings = VscodeSettings::default();
+        open(&settings, &*fake, Path::new("/src/api/lib.rs"), Some(42)).unwrap();
+        open(&settings, &*fake, Path::new("/src/api/lib.rs"), None).unwrap();
+        assert_eq!(
+            fake.calls(),
+            [
+                vec!["code", "-g", "/src/api/lib.rs:42"],
+                vec!["code", "-g", "/src/api/lib.rs"]
+            ]
+        );
+
+        fake.missing();
+        let err = open(&settings, &*fake, Path::new("/a"), None).unwrap_err();
+        assert!(err.starts_with("Failed to run code"), "{err}");
+    }
+
+    #[test]
+    fn test_inbox_in_workspace() {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-vscode-test-inbox-{}", std::process::id()));
+        inbox(&dir).append("src/a.rs", None, "Why?").unwrap();
+        let written = fs::read_to_string(dir.join(".pigeon/inbox.md")).unwrap();
+        assert!(written.ends_with("src/a.rs\n\nWhy?\n"), "{written}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
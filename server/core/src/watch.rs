@@ -0,0 +1,192 @@
+//! Idle detection: after a send, `watch-target` samples the pane until its
+//! output has changed and then stopped changing, so the extension can tell
+//! that the agent has finished answering.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Seconds without new output that count as finished, unless asked otherwise
+pub const DEFAULT_IDLE_SECS: u64 = 5;
+
+/// Seconds to wait for the agent before giving up, unless asked otherwise
+pub const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// Longest watch accepted
+pub const MAX_TIMEOUT_SECS: u64 = 2 * 60 * 60;
+
+/// Time between captures of the pane
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct WatchOptions {
+    pub idle: Duration,
+    pub timeout: Duration,
+    pub interval: Duration,
+}
+
+impl WatchOptions {
+    /// Options from a request's `idle_secs` and `timeout_secs`
+    pub fn from_request(idle_secs: Option<u64>, timeout_secs: Option<u64>) -> Result<Self, String> {
+        let idle_secs = idle_secs.unwrap_or(DEFAULT_IDLE_SECS);
+        let timeout_secs = timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+        if idle_secs == 0 || timeout_secs == 0 || timeout_secs > MAX_TIMEOUT_SECS {
+            return Err(format!(
+                "idle_secs must be positive and timeout_secs between 1 and {MAX_TIMEOUT_SECS}"
+            ));
+        }
+        if idle_secs > timeout_secs {
+            return Err("idle_secs must not exceed timeout_secs".to_string());
+        }
+        Ok(Self {
+            idle: Duration::from_secs(idle_secs),
+            timeout: Duration::from_secs(timeout_secs),
+            interval: SAMPLE_INTERVAL,
+        })
+    }
+}
+
+/// The pane went quiet after producing output
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Idle {
+    /// Lines printed since the watch started
+    pub new_lines: usize,
+    /// Bytes in those lines, newlines included
+    pub new_bytes: usize,
+    /// Time from the start of the watch until the pane was found idle
+    pub waited_ms: u64,
+}
+
+pub enum WatchError {
+    /// Capturing the pane failed, or the job was cancelled
+    Capture(String),
+    /// The pane never changed, or never stopped changing
+    TimedOut,
+}
+
+/// Sample the pane through `capture` until it has changed and then stayed
+/// the same for `options.idle`
+pub fn watch(
+    options: &WatchOptions,
+    mut capture: impl FnMut() -> Result<String, String>,
+) -> Result<Idle, WatchError> {
+    let started = Instant::now();
+    let baseline = capture().map_err(WatchError::Capture)?;
+    let mut last = baseline.clone();
+    let mut changed_at = None;
+    while started.elapsed() < options.timeout {
+        thread::sleep(options.interval);
+        let current = capture().map_err(WatchError::Capture)?;
+        if current != last {
+            changed_at = Some(Instant::now());
+            last = current;
+        } else if changed_at.is_some_and(|at| at.elapsed() >= options.idle) {
+            let (new_lines, new_bytes) = new_output(&baseline, &last);
+            return Ok(Idle {
+                new_lines,
+                new_bytes,
+                waited_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+    Err(WatchError::TimedOut)
+}
+
+/// Lines and bytes in `after` that follow what `before` showed. Scrolling
+/// drops lines off the top, so `before` is matched by its longest tail that
+/// `after` starts with; with no overlap everything in `after` is new.
+fn new_output(before: &str, after: &str) -> (usize, usize) {
+    let before = content_lines(before);
+    let after = content_lines(after);
+    let kept = (0..=before.len())
+        .map(|skip| &before[skip..])
+        .find(|tail| after.starts_with(tail))
+        .map_or(0, <[&str]>::len);
+    let new = &after[kept..];
+    (new.len(), new.iter().map(|line| line.len() + 1).sum())
+}
+
+/// Lines of a capture without the blank rows below the cursor
+fn content_lines(captured: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = captured.lines().collect();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(idle_ms: u64, timeout_ms: u64) -> WatchOptions {
+        WatchOptions {
+            idle: Duration::from_millis(idle_ms),
+            timeout: Duration::from_millis(timeout_ms),
+            interval: Duration::from_millis(1),
+        }
+    }
+
+    /// Returns `captures` in turn, then repeats the last one
+    fn scripted(captures: &[&str]) -> impl FnMut() -> Result<String, String> {
+        let mut captures: Vec<String> = captures.iter().rev().map(|c| c.to_string()).collect();
+        move || {
+            Ok(if captures.len() > 1 {
+                captures.pop().unwrap()
+            } else {
+                captures[0].clone()
+            })
+        }
+    }
+
+    #[test]
+    fn test_new_output() {
+        assert_eq!(
+            new_output("$ ask\n\n\n", "$ ask\nthinking\nanswer\n\n"),
+            (2, 16)
+        );
+        // Scrolled by one line
+        assert_eq!(new_output("a\nb\nc", "b\nc\nd"), (1, 2));
+        assert_eq!(new_output("a\nb", "x\ny"), (2, 4));
+        assert_eq!(new_output("a\nb", "a\nb\n  \n"), (0, 0));
+    }
+
+    #[test]
+    fn test_idle_after_output() {
+        let capture = scripted(&["$ ask", "$ ask\nthinking", "$ ask\nthinking\nanswer"]);
+        let Ok(idle) = watch(&options(20, 2000), capture) else {
+            panic!("pane should have gone idle");
+        };
+        assert_eq!((idle.new_lines, idle.new_bytes), (2, 16));
+        assert!(idle.waited_ms >= 20);
+    }
+
+    #[test]
+    fn test_unchanged_pane_times_out() {
+        let result = watch(&options(5, 50), scripted(&["$ ask"]));
+        assert!(matches!(result, Err(WatchError::TimedOut)));
+    }
+
+    #[test]
+    fn test_capture_failure_ends_watch() {
+        let mut calls = 0;
+        let result = watch(&options(5, 1000), || {
+            calls += 1;
+            if calls > 2 {
+                Err("can't find pane: dev".to_string())
+            } else {
+                Ok(format!("line {calls}"))
+            }
+        });
+        assert!(matches!(result, Err(WatchError::Capture(e)) if e.contains("can't find pane")));
+    }
+
+    #[test]
+    fn test_options_from_request() {
+        let defaults = WatchOptions::from_request(None, None).unwrap();
+        assert_eq!(defaults.idle, Duration::from_secs(DEFAULT_IDLE_SECS));
+        assert!(WatchOptions::from_request(Some(0), None).is_err());
+        assert!(WatchOptions::from_request(Some(30), Some(10)).is_err());
+        assert!(WatchOptions::from_request(None, Some(MAX_TIMEOUT_SECS + 1)).is_err());
+    }
+}
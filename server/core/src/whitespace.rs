@@ -0,0 +1,156 @@
+//! Whitespace clean-up for selected code, so deeply nested snippets do not
+//! spend the message budget on indentation.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Widest tab stop accepted for `tab_width`
+pub const MAX_TAB_WIDTH: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct WhitespaceSettings {
+    /// Expand tabs to stops this many columns apart; 0 keeps them
+    pub tab_width: usize,
+    /// Remove spaces and tabs at the end of lines
+    pub strip_trailing: bool,
+    /// Remove the indentation all non-blank lines share
+    pub dedent: bool,
+}
+
+impl WhitespaceSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tab_width > MAX_TAB_WIDTH {
+            return Err(format!(
+                "whitespace.tab_width must be at most {MAX_TAB_WIDTH}"
+            ));
+        }
+        Ok(())
+    }
+
+    fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Apply `settings` to `code`. Tabs are expanded first, so dedenting sees
+/// mixed tab and space indentation as columns.
+pub fn normalize(code: &str, settings: &WhitespaceSettings) -> String {
+    if settings.is_noop() {
+        return code.to_string();
+    }
+    let mut lines: Vec<String> = code
+        .split('\n')
+        .map(|line| {
+            let line = if settings.tab_width > 0 {
+                expand_tabs(line, settings.tab_width)
+            } else {
+                line.to_string()
+            };
+            if settings.strip_trailing {
+                line.trim_end_matches([' ', '\t', '\r']).to_string()
+            } else {
+                line
+            }
+        })
+        .collect();
+    if settings.dedent {
+        dedent(&mut lines);
+    }
+    lines.join("\n")
+}
+
+fn expand_tabs(line: &str, width: usize) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = width - column % width;
+            out.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            out.push(c);
+            column += 1;
+        }
+    }
+    out
+}
+
+/// Strip the longest leading whitespace shared by every non-blank line
+fn dedent(lines: &mut [String]) {
+    let indent = |line: &str| -> usize { line.len() - line.trim_start_matches([' ', '\t']).len() };
+    let mut common: Option<&str> = None;
+    for line in lines.iter().filter(|l| !l.trim().is_empty()) {
+        let prefix = &line[..indent(line)];
+        common = Some(match common {
+            None => prefix,
+            Some(c) => {
+                let shared = c
+                    .bytes()
+                    .zip(prefix.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &c[..shared]
+            }
+        });
+    }
+    let width = common.map_or(0, str::len);
+    if width == 0 {
+        return;
+    }
+    for line in lines.iter_mut() {
+        // Blank lines may be shorter than the shared indentation
+        let strip = width.min(indent(line));
+        line.drain(..strip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(tab_width: usize, strip_trailing: bool, dedent: bool) -> WhitespaceSettings {
+        WhitespaceSettings {
+            tab_width,
+            strip_trailing,
+            dedent,
+        }
+    }
+
+    #[test]
+    fn test_default_keeps_code() {
+        let code = "\tif x {  \n\t\ty();\n\t}";
+        assert_eq!(normalize(code, &WhitespaceSettings::default()), code);
+    }
+
+    #[test]
+    fn test_expand_tabs_to_stops() {
+        let s = settings(4, false, false);
+        assert_eq!(normalize("\tx", &s), "    x");
+        assert_eq!(normalize("ab\tc", &s), "ab  c");
+        assert_eq!(normalize("abcd\te", &s), "abcd    e");
+    }
+
+    #[test]
+    fn test_strip_trailing() {
+        let s = settings(0, true, false);
+        assert_eq!(normalize("a  \nb\t\r\n", &s), "a\nb\n");
+    }
+
+    #[test]
+    fn test_dedent() {
+        let s = settings(0, false, true);
+        let code = "        if x {\n            y();\n\n        }";
+        assert_eq!(normalize(code, &s), "if x {\n    y();\n\n}");
+        // A blank line with some indentation does not limit the rest
+        assert_eq!(normalize("    a\n  \n    b", &s), "a\n\nb");
+        // Mismatched tabs and spaces only share what is identical
+        assert_eq!(normalize("\t a\n\t  b", &s), "a\n b");
+    }
+
+    #[test]
+    fn test_dedent_after_expanding_tabs() {
+        let s = settings(4, true, true);
+        assert_eq!(normalize("\t\tx();  \n        y();", &s), "x();\ny();");
+    }
+}
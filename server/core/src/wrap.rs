@@ -0,0 +1,196 @@
+//! Soft wrapping of long code lines to the width of the tmux pane they are
+//! pasted into. The terminal would otherwise break them at its last column,
+//! mid-token and back at column 0, which makes nested code hard to follow.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Marker after the indentation of continuation rows, unless configured
+pub const DEFAULT_MARKER: &str = "↪ ";
+
+/// Widest `marker` accepted, in columns
+pub const MAX_MARKER_COLUMNS: usize = 8;
+
+/// Fewest columns left for code on a continuation row. Without room for
+/// this many, the indentation is dropped, and then wrapping is given up.
+pub const MIN_TEXT_COLUMNS: usize = 10;
+
+/// Columns between tab stops, as terminals default to
+const TAB_STOP: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct WrapSettings {
+    /// Wrap code lines wider than the target pane
+    pub enabled: bool,
+    /// Put after the indentation of continuation rows
+    pub marker: String,
+    /// Columns to keep free on the right, for agent TUIs whose input box is
+    /// narrower than the pane
+    pub margin: usize,
+}
+
+impl Default for WrapSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            marker: DEFAULT_MARKER.to_string(),
+            margin: 0,
+        }
+    }
+}
+
+impl WrapSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.marker.chars().any(char::is_control) || self.marker.width() > MAX_MARKER_COLUMNS {
+            return Err(format!(
+                "wrap.marker must be at most {MAX_MARKER_COLUMNS} columns without control characters"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Rows showing `line` within `width` columns. Continuation rows repeat the
+/// line's indentation followed by `marker`. Rows break after the last space
+/// that fits, or between grapheme clusters inside a token too long for one.
+pub fn wrap_line(line: &str, width: usize, marker: &str) -> Vec<String> {
+    if fit(line, 0, width).0 == line.len() {
+        return vec![line.to_string()];
+    }
+    let text = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - text.len()];
+    let prefix = [format!("{indent}{marker}"), marker.to_string()]
+        .into_iter()
+        .find(|prefix| columns(prefix) + MIN_TEXT_COLUMNS <= width);
+    let Some(prefix) = prefix else {
+        return vec![line.to_string()];
+    };
+    let mut rows = Vec::new();
+    let mut lead = String::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let (end, space) = fit(rest, columns(&lead), width);
+        let end = match (end, space) {
+            (end, _) if end == rest.len() => end,
+            (_, Some(space)) => space,
+            // Not even one grapheme fits; take it anyway to make progress
+            (0, None) => rest.graphemes(true).next().map_or(rest.len(), str::len),
+            (end, None) => end,
+        };
+        rows.push(format!("{lead}{}", &rest[..end]));
+        rest = &rest[end..];
+        lead.clone_from(&prefix);
+    }
+    rows
+}
+
+/// Byte length of the longest run of whole graphemes at the start of `text`
+/// that fits between column `start` and `width`, and the end of the last
+/// space in it that follows other text
+fn fit(text: &str, start: usize, width: usize) -> (usize, Option<usize>) {
+    let mut column = start;
+    let mut end = 0;
+    let mut space = None;
+    let mut after_text = false;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        column = advance(column, grapheme);
+        if column > width {
+            break;
+        }
+        end = i + grapheme.len();
+        if grapheme == " " || grapheme == "\t" {
+            if after_text {
+                space = Some(end);
+            }
+        } else {
+            after_text = true;
+        }
+    }
+    (end, space)
+}
+
+fn columns(text: &str) -> usize {
+    text.graphemes(true).fold(0, advance)
+}
+
+fn advance(column: usize, grapheme: &str) -> usize {
+    match grapheme {
+        "\t" => column + TAB_STOP - column % TAB_STOP,
+        _ => column + grapheme.width(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_lines_are_kept() {
+        assert_eq!(wrap_line("let x = 1;", 40, "> "), ["let x = 1;"]);
+        assert_eq!(wrap_line("", 40, "> "), [""]);
+    }
+
+    #[test]
+    fn test_wraps_at_spaces_keeping_indentation() {
+        let line = "    call(first_argument, second_argument, third_argument);";
+        let rows = wrap_line(line, 30, "> ");
+        assert_eq!(
+            rows,
+            [
+                "    call(first_argument, ",
+                "    > second_argument, ",
+                "    > third_argument);",
+            ]
+        );
+        assert!(rows.iter().all(|row| columns(row) <= 30));
+        let joined: String = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| if i == 0 { row.as_str() } else { &row[6..] })
+            .collect();
+        assert_eq!(joined, line);
+    }
+
+    #[test]
+    fn test_long_tokens_break_between_graphemes() {
+        let rows = wrap_line(&"x".repeat(25), 12, "> ");
+        assert_eq!(rows, ["xxxxxxxxxxxx", "> xxxxxxxxxx", "> xxx"]);
+        // Wide characters take two columns and are never split
+        let rows = wrap_line(&"漢".repeat(8), 12, "> ");
+        assert_eq!(rows, ["漢漢漢漢漢漢", "> 漢漢"]);
+        let rows = wrap_line("👩‍👩‍👧‍👦 family emoji stays whole here", 14, "> ");
+        assert!(rows[0].starts_with("👩‍👩‍👧‍👦"), "{rows:?}");
+    }
+
+    #[test]
+    fn test_deep_indentation_gives_way() {
+        let line = format!("{}{}", " ".repeat(16), "word ".repeat(6));
+        let rows = wrap_line(&line, 24, "> ");
+        assert!(rows[1].starts_with("> word"), "{rows:?}");
+        // Too narrow for any continuation row: left to the terminal
+        assert_eq!(wrap_line(&line, 8, "> "), [line]);
+    }
+
+    #[test]
+    fn test_tabs_count_to_the_next_stop() {
+        assert_eq!(columns("\tx"), 9);
+        assert_eq!(wrap_line("\tabcdef ghi", 16, "> "), ["\tabcdef ", "> ghi"]);
+        assert_eq!(
+            wrap_line("\tabcdef ghijkl mnopqr", 24, "> "),
+            ["\tabcdef ghijkl ", "\t> mnopqr"]
+        );
+    }
+
+    #[test]
+    fn test_validate_marker() {
+        assert!(WrapSettings::default().validate().is_ok());
+        let settings = WrapSettings {
+            marker: "\n".to_string(),
+            ..WrapSettings::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
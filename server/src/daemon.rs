@@ -0,0 +1,207 @@
+//! Daemon mode: one long-running host shared by every browser profile,
+//! reached over a Unix socket. The process Chrome starts then only relays
+//! frames, so jobs and upload state survive Chrome restarting it.
+
+use crate::framing;
+use pigeon_core::paths;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// First file descriptor systemd passes to a socket-activated service
+const LISTEN_FDS_START: i32 = 3;
+
+/// How long the relay waits for a freshly started service to listen
+const START_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `pigeon.sock` in the data directory
+pub fn socket_path() -> Option<PathBuf> {
+    paths::data_dir().map(|dir| dir.join("pigeon.sock"))
+}
+
+/// Accept connections until the listener fails, handling each on its own
+/// thread
+pub fn run(handle: impl Fn(UnixStream) + Clone + Send + 'static) -> io::Result<()> {
+    let listener = listen()?;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("pigeon: failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let handle = handle.clone();
+        thread::spawn(move || handle(stream));
+    }
+    Ok(())
+}
+
+/// The socket systemd activated us with, or our own bound at `socket_path`
+fn listen() -> io::Result<UnixListener> {
+    if let Some(listener) = activated_listener() {
+        return Ok(listener);
+    }
+    let path = socket_path()
+        .ok_or_else(|| io::Error::other("cannot locate data directory for the socket"))?;
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another daemon is listening on {}", path.display()),
+            ));
+        }
+        // Left behind by a daemon that did not exit cleanly
+        fs::remove_file(&path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    eprintln!("pigeon: listening on {}", path.display());
+    Ok(listener)
+}
+
+/// The listening socket passed by systemd socket activation, if any
+fn activated_listener() -> Option<UnixListener> {
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    use std::os::fd::FromRawFd;
+    // SAFETY: systemd hands this process ownership of the listening socket
+    // at LISTEN_FDS_START, checked above via LISTEN_PID and LISTEN_FDS, and
+    // nothing else in the process uses that descriptor.
+    Some(unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// Connect to the daemon, starting the service first when nothing listens.
+/// `None` means the caller should handle messages itself.
+pub fn connect() -> Option<UnixStream> {
+    let path = socket_path()?;
+    if let Ok(stream) = UnixStream::connect(&path) {
+        return Some(stream);
+    }
+    if let Err(e) = crate::service::start() {
+        eprintln!("pigeon: cannot start the daemon service: {e}");
+        return None;
+    }
+    let step = Duration::from_millis(100);
+    for _ in 0..START_TIMEOUT.as_millis() / step.as_millis() {
+        thread::sleep(step);
+        if let Ok(stream) = UnixStream::connect(&path) {
+            return Some(stream);
+        }
+    }
+    eprintln!(
+        "pigeon: daemon did not start listening on {}",
+        path.display()
+    );
+    None
+}
+
+/// Longest origin frame accepted at the start of a connection
+const MAX_ORIGIN_BYTES: usize = 4096;
+
+/// Pass Chrome's frames to the daemon and its responses back until either
+/// side closes. The relay's own origin goes first, since only this process
+/// was told which extension it serves.
+pub fn relay(stream: UnixStream, origin: Option<&str>) -> io::Result<()> {
+    let mut upstream = stream.try_clone()?;
+    announce(&mut upstream, origin)?;
+    thread::spawn(move || {
+        let _ = pump(&mut io::stdin().lock(), &mut upstream);
+        // Lets the daemon see end of input, as it would from Chrome
+        let _ = upstream.shutdown(Shutdown::Write);
+    });
+    let mut downstream = stream;
+    pump(&mut downstream, &mut io::stdout().lock())
+}
+
+/// Write `origin` as a frame holding `{"origin": ...}`
+fn announce(to: &mut impl Write, origin: Option<&str>) -> io::Result<()> {
+    let frame = serde_json::json!({ "origin": origin }).to_string();
+    to.write_all(&(frame.len() as u32).to_le_bytes())?;
+    to.write_all(frame.as_bytes())?;
+    to.flush()
+}
+
+/// The origin a relay announced at the start of its connection
+pub fn read_origin(from: &mut impl Read) -> Result<Option<String>, String> {
+    let frame = framing::read_message(from, MAX_ORIGIN_BYTES)
+        .map_err(|e| e.to_string())?
+        .ok_or("connection closed before the relay announced its origin")?;
+    let hello: serde_json::Value =
+        serde_json::from_str(&frame).map_err(|e| format!("Invalid origin frame: {e}"))?;
+    Ok(hello["origin"].as_str().map(String::from))
+}
+
+/// Copy bytes as they arrive. Unlike `io::copy` into stdout, every chunk is
+/// flushed at once, since frames carry no newline to trigger a flush.
+fn pump(from: &mut impl Read, to: &mut impl Write) -> io::Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&buf[..n])?;
+        to.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pump_copies_until_eof() {
+        let mut out = Vec::new();
+        pump(&mut &b"\x05\x00\x00\x00hello"[..], &mut out).unwrap();
+        assert_eq!(out, b"\x05\x00\x00\x00hello");
+    }
+
+    #[test]
+    fn test_origin_announced_first() {
+        let mut frame = Vec::new();
+        announce(&mut frame, Some("chrome-extension://abc/")).unwrap();
+        frame.extend_from_slice(b"\x02\x00\x00\x00{}");
+        let mut input = &frame[..];
+        assert_eq!(
+            read_origin(&mut input).unwrap().as_deref(),
+            Some("chrome-extension://abc/")
+        );
+        assert_eq!(input, b"\x02\x00\x00\x00{}");
+
+        let mut frame = Vec::new();
+        announce(&mut frame, None).unwrap();
+        assert_eq!(read_origin(&mut &frame[..]).unwrap(), None);
+        assert!(read_origin(&mut &b""[..]).is_err());
+    }
+
+    #[test]
+    fn test_relayed_stream_round_trip() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let echo = thread::spawn(move || {
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).unwrap();
+            server.write_all(&received).unwrap();
+        });
+        let mut upstream = client.try_clone().unwrap();
+        pump(&mut &b"frame"[..], &mut upstream).unwrap();
+        upstream.shutdown(Shutdown::Write).unwrap();
+        let mut back = Vec::new();
+        pump(&mut client, &mut back).unwrap();
+        echo.join().unwrap();
+        assert_eq!(back, b"frame");
+    }
+}
@@ -0,0 +1,265 @@
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Chrome drops the port when a single host → extension message exceeds 1MB
+pub const CHROME_MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// Bytes of the original payload carried by each continuation frame.
+/// Re-escaping the payload as a JSON string at most doubles it (`"` and `\`),
+/// so frames stay well below Chrome's limit.
+const FRAME_DATA_BYTES: usize = 256 * 1024;
+
+/// Responses larger than this are refused instead of being split
+pub const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Continuation frame of a response too large for a single message.
+/// The extension concatenates `data` of parts `0..total` and parses the result.
+#[derive(Serialize)]
+struct Frame<'a> {
+    id: u64,
+    part: usize,
+    total: usize,
+    data: &'a str,
+}
+
+/// Split a serialized response into messages Chrome will accept.
+/// Small responses pass through unchanged as a single message.
+pub fn split(json: &str) -> Result<Vec<String>, String> {
+    if json.len() <= CHROME_MAX_MESSAGE_BYTES {
+        return Ok(vec![json.to_string()]);
+    }
+    if json.len() > MAX_RESPONSE_BYTES {
+        return Err(format!(
+            "Response too large: {} bytes exceeds the {} byte limit",
+            json.len(),
+            MAX_RESPONSE_BYTES
+        ));
+    }
+
+    let chunks = chunk_at_char_boundaries(json, FRAME_DATA_BYTES);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let total = chunks.len();
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(part, data)| {
+            serde_json::to_string(&Frame {
+                id,
+                part,
+                total,
+                data,
+            })
+            .expect("Failed to serialize frame")
+        })
+        .collect())
+}
+
+/// A message from the extension that could not be read
+#[derive(Debug)]
+pub enum ReadError {
+    /// Longer than allowed. The body is skipped unread, so the stream stays
+    /// in step when the length itself was genuine.
+    TooLarge { len: usize, max: usize },
+    /// The body is not UTF-8; it has been consumed, so reading can go on
+    InvalidUtf8,
+    /// The stream ended or failed inside a frame. Nothing after this point
+    /// can be trusted.
+    Truncated(io::Error),
+}
+
+impl ReadError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TooLarge { .. } => "MESSAGE_TOO_LARGE",
+            Self::InvalidUtf8 | Self::Truncated(_) => "INVALID_FRAME",
+        }
+    }
+
+    /// Whether the host has to stop reading
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Truncated(_))
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { len, max } => {
+                write!(
+                    f,
+                    "Message too large: {len} bytes exceeds the {max} byte limit"
+                )
+            }
+            Self::InvalidUtf8 => write!(f, "Message is not valid UTF-8"),
+            Self::Truncated(e) => write!(f, "Message frame is incomplete: {e}"),
+        }
+    }
+}
+
+/// Read one Native Messaging message: a 4-byte little-endian length, then
+/// that many bytes of UTF-8 JSON. Returns `None` when the stream ends
+/// cleanly between messages.
+pub fn read_message(reader: &mut impl Read, max: usize) -> Result<Option<String>, ReadError> {
+    let mut len_bytes = [0u8; 4];
+    let mut filled = 0;
+    while filled < len_bytes.len() {
+        match reader.read(&mut len_bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(ReadError::Truncated(io::ErrorKind::UnexpectedEof.into())),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(ReadError::Truncated(e)),
+        }
+    }
+    let len = u32::from_le_bytes(len_bytes) as usize;
+
+    if len > max {
+        // Skip without allocating; a bogus length ends in `Truncated` instead
+        let skipped = io::copy(&mut reader.take(len as u64), &mut io::sink())
+            .map_err(ReadError::Truncated)?;
+        if skipped < len as u64 {
+            return Err(ReadError::Truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+        return Err(ReadError::TooLarge { len, max });
+    }
+
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).map_err(ReadError::Truncated)?;
+    String::from_utf8(buf)
+        .map(Some)
+        .map_err(|_| ReadError::InvalidUtf8)
+}
+
+/// Split `s` into pieces of at most `max` bytes without breaking UTF-8 sequences
+fn chunk_at_char_boundaries(s: &str, max: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let mut end = max.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_response_is_not_framed() {
+        let frames = split(r#"{"ok":true}"#).unwrap();
+        assert_eq!(frames, vec![r#"{"ok":true}"#.to_string()]);
+    }
+
+    #[test]
+    fn test_large_response_reassembles() {
+        let payload = serde_json::json!({
+            "ok": true,
+            "text": "\"quoted\" \\ 日本語 ".repeat(200_000),
+        })
+        .to_string();
+        let frames = split(&payload).unwrap();
+        assert!(frames.len() > 1);
+
+        let mut joined = String::new();
+        for (i, frame) in frames.iter().enumerate() {
+            assert!(frame.len() <= CHROME_MAX_MESSAGE_BYTES);
+            let value: serde_json::Value = serde_json::from_str(frame).unwrap();
+            assert_eq!(value["part"], i);
+            assert_eq!(value["total"], frames.len());
+            joined.push_str(value["data"].as_str().unwrap());
+        }
+        assert_eq!(joined, payload);
+    }
+
+    #[test]
+    fn test_frames_share_id() {
+        let payload = "x".repeat(CHROME_MAX_MESSAGE_BYTES + 1);
+        let frames = split(&payload).unwrap();
+        let ids: Vec<serde_json::Value> = frames
+            .iter()
+            .map(|f| serde_json::from_str::<serde_json::Value>(f).unwrap()["id"].clone())
+            .collect();
+        assert!(ids.iter().all(|id| *id == ids[0]));
+    }
+
+    #[test]
+    fn test_oversized_response_is_rejected() {
+        let payload = "x".repeat(MAX_RESPONSE_BYTES + 1);
+        let err = split(&payload).unwrap_err();
+        assert!(err.contains("Response too large"));
+    }
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut bytes = (body.len() as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn test_read_messages_until_clean_end() {
+        let mut stream = frame(br#"{"action":"handshake"}"#);
+        stream.extend(frame(b"{}"));
+        let mut reader = stream.as_slice();
+        assert_eq!(
+            read_message(&mut reader, 1024).unwrap().unwrap(),
+            r#"{"action":"handshake"}"#
+        );
+        assert_eq!(read_message(&mut reader, 1024).unwrap().unwrap(), "{}");
+        assert!(read_message(&mut reader, 1024).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_oversized_message_is_skipped() {
+        let mut stream = frame(&[b'x'; 100]);
+        stream.extend(frame(b"{}"));
+        let mut reader = stream.as_slice();
+        let err = read_message(&mut reader, 50).unwrap_err();
+        assert_eq!(err.code(), "MESSAGE_TOO_LARGE");
+        assert!(!err.is_fatal());
+        assert_eq!(read_message(&mut reader, 50).unwrap().unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_bogus_length_does_not_allocate() {
+        let mut stream = u32::MAX.to_le_bytes().to_vec();
+        stream.extend(b"garbage");
+        let err = read_message(&mut stream.as_slice(), 1024).unwrap_err();
+        assert!(err.is_fatal(), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_skipped() {
+        let mut stream = frame(&[0xff, 0xfe]);
+        stream.extend(frame(b"{}"));
+        let mut reader = stream.as_slice();
+        let err = read_message(&mut reader, 1024).unwrap_err();
+        assert_eq!(err.code(), "INVALID_FRAME");
+        assert!(!err.is_fatal());
+        assert_eq!(read_message(&mut reader, 1024).unwrap().unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_truncated_frames_are_fatal() {
+        for stream in [&[5u8, 0][..], &[5, 0, 0, 0, b'{']] {
+            let err = read_message(&mut &stream[..], 1024).unwrap_err();
+            assert!(err.is_fatal());
+            assert_eq!(err.code(), "INVALID_FRAME");
+        }
+    }
+
+    #[test]
+    fn test_chunk_respects_char_boundaries() {
+        let chunks = chunk_at_char_boundaries("aあいう", 4);
+        assert_eq!(chunks, vec!["aあ", "い", "う"]);
+    }
+}
@@ -1,382 +1,1337 @@
-use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+mod daemon;
+mod framing;
+mod mcp;
+#[cfg(unix)]
+mod service;
+#[cfg(unix)]
+mod shutdown;
+
+use pigeon_core::protocol::*;
+use pigeon_core::send::{
+    broadcast, checkout_commit, decode_payload, export_transcript, get_reply, kill_session,
+    preview, respawn_agent, send, targets, watch_target, Failure, Lifecycle, SendOutcome,
+    Transcript,
+};
+use pigeon_core::{
+    aliases, config, debug, exec, git, history, infer, jobs, lanes, metrics, queue, repos,
+    retention, stage, stats, tmux, update, upload, watch,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::io::{self, Read, Write};
-use std::process::Command;
-
-#[derive(Deserialize)]
-#[serde(tag = "action")]
-enum Request {
-    #[serde(rename = "send")]
-    Send {
-        file: String,
-        start_line: Option<u64>,
-        end_line: Option<u64>,
-        /// "old" for deleted lines, "new" (or absent) for current/added lines
-        side: Option<String>,
-        code: String,
-        question: String,
-        tmux_target: String,
-        debug_html: Option<String>,
-    },
-    #[serde(rename = "list-sessions")]
-    ListSessions,
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long running jobs, such as sends being typed, may take to finish once
+/// the host is asked to exit
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Write a response using Native Messaging protocol
+fn write_message(out: &mut dyn Write, msg: &str) {
+    let bytes = msg.as_bytes();
+    let len = (bytes.len() as u32).to_le_bytes();
+    let _ = out.write_all(&len);
+    let _ = out.write_all(bytes);
+    let _ = out.flush();
 }
 
-#[derive(Serialize)]
-struct SendResponse {
-    ok: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+/// Write a response, splitting it into continuation frames when it exceeds
+/// Chrome's per-message limit. Responses over the hard cap become an error.
+fn write_json(out: &mut dyn Write, value: &impl Serialize) {
+    let json = serde_json::to_string(value).expect("Failed to serialize response");
+    match framing::split(&json) {
+        Ok(frames) => frames.iter().for_each(|frame| write_message(out, frame)),
+        Err(e) => write_json(
+            out,
+            &SendResponse {
+                ok: false,
+                error: Some(e),
+                ..SendResponse::default()
+            },
+        ),
+    }
 }
 
-#[derive(Serialize)]
-struct ListSessionsResponse {
-    ok: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    sessions: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+/// State kept across messages for as long as Chrome keeps the host running
+struct HostState {
+    config: config::LiveConfig,
+    uploads: upload::Uploads<SendRequest>,
+    jobs: jobs::Jobs,
+    stages: stage::Stages,
+    metrics: metrics::Metrics,
+    lanes: lanes::Lanes,
+    /// Requests of scheduled sends by job id, to set aside at shutdown
+    scheduled: Arc<Mutex<BTreeMap<String, SendRequest>>>,
+    /// Config with the profile the request being handled selected
+    profile: Option<config::Config>,
 }
 
-/// Read a message using Native Messaging protocol (4-byte little-endian length prefix)
-fn read_message() -> io::Result<String> {
-    let mut len_bytes = [0u8; 4];
-    io::stdin().read_exact(&mut len_bytes)?;
-    let len = u32::from_le_bytes(len_bytes) as usize;
+impl HostState {
+    /// Config for the request being handled: the live config, with the
+    /// request's profile applied when it selected one
+    fn config(&self) -> &config::Config {
+        self.profile.as_ref().unwrap_or_else(|| self.config.get())
+    }
+
+    fn new() -> Self {
+        Self {
+            config: config::LiveConfig::new(config::ConfigFile::default_location()),
+            uploads: upload::Uploads::new(upload::UPLOAD_TIMEOUT),
+            jobs: jobs::Jobs::new(),
+            stages: stage::Stages::new(),
+            metrics: metrics::Metrics::new(),
+            lanes: lanes::Lanes::default_location(),
+            scheduled: Arc::default(),
+            profile: None,
+        }
+    }
+
+    /// Jobs and scheduled sends, for shutdown to reach without the state lock
+    fn pending(&self) -> Pending {
+        Pending {
+            jobs: self.jobs.clone(),
+            scheduled: self.scheduled.clone(),
+        }
+    }
 
-    let mut buf = vec![0u8; len];
-    io::stdin().read_exact(&mut buf)?;
-    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    fn config_response(&self, result: Result<(), config::SetError>) -> ConfigResponse {
+        let path = self.config.path().map(|p| p.display().to_string());
+        match result {
+            Ok(()) => ConfigResponse {
+                ok: true,
+                config: Some(self.config.get().clone()),
+                generation: Some(self.config.generation()),
+                path,
+                code: None,
+                error: None,
+            },
+            Err(e) => ConfigResponse {
+                ok: false,
+                config: None,
+                generation: None,
+                path,
+                code: e.code,
+                error: Some(e.message),
+            },
+        }
+    }
 }
 
-/// Write a response using Native Messaging protocol
-fn write_message(msg: &str) {
-    let bytes = msg.as_bytes();
-    let len = (bytes.len() as u32).to_le_bytes();
-    let _ = io::stdout().write_all(&len);
-    let _ = io::stdout().write_all(bytes);
-    let _ = io::stdout().flush();
+/// What shutdown waits for or sets aside, shared with `HostState`
+struct Pending {
+    jobs: jobs::Jobs,
+    scheduled: Arc<Mutex<BTreeMap<String, SendRequest>>>,
 }
 
-fn write_json(value: &impl Serialize) {
-    let json = serde_json::to_string(value).expect("Failed to serialize response");
-    write_message(&json);
+fn aliases() -> Result<aliases::Aliases, Failure> {
+    aliases::Aliases::default_location()
+        .ok_or_else(|| Failure::from("Cannot locate data directory for aliases".to_string()))
 }
 
-fn format_message(
-    file: &str,
-    start_line: Option<u64>,
-    end_line: Option<u64>,
-    side: Option<&str>,
-    code: &str,
-    question: &str,
-) -> String {
-    let mut msg = String::new();
+fn write_lifecycle_result(out: &mut dyn Write, result: Result<Lifecycle, Failure>) {
+    write_json(
+        out,
+        &match result {
+            Ok(done) => LifecycleResponse {
+                ok: true,
+                session: Some(done.session),
+                panes: Some(done.panes),
+                code: None,
+                error: None,
+            },
+            Err(e) => LifecycleResponse {
+                ok: false,
+                session: None,
+                panes: None,
+                code: e.code,
+                error: Some(e.message),
+            },
+        },
+    );
+}
 
-    // File location
-    msg.push_str(file);
-    match (start_line, end_line) {
-        (Some(s), Some(e)) if s != e => msg.push_str(&format!(":{s}-{e}")),
-        (Some(s), _) => msg.push_str(&format!(":{s}")),
-        _ => {}
+fn write_aliases_result(
+    out: &mut dyn Write,
+    result: Result<BTreeMap<String, aliases::Alias>, Failure>,
+) {
+    write_json(
+        out,
+        &match result {
+            Ok(aliases) => AliasesResponse {
+                ok: true,
+                aliases: Some(aliases),
+                code: None,
+                error: None,
+            },
+            Err(e) => AliasesResponse {
+                ok: false,
+                aliases: None,
+                code: e.code,
+                error: Some(e.message),
+            },
+        },
+    )
+}
+
+fn write_panes_result(out: &mut dyn Write, result: Result<Vec<tmux::Pane>, String>) {
+    write_json(
+        out,
+        &match result {
+            Ok(panes) => ListPanesResponse {
+                ok: true,
+                panes: Some(panes),
+                error: None,
+            },
+            Err(e) => ListPanesResponse {
+                ok: false,
+                panes: None,
+                error: Some(e),
+            },
+        },
+    )
+}
+
+fn write_send_result(out: &mut dyn Write, result: Result<SendOutcome, Failure>) {
+    write_json(out, &send_response(result))
+}
+
+fn send_response(result: Result<SendOutcome, Failure>) -> SendResponse {
+    match result {
+        Ok(outcome) => SendResponse {
+            ok: true,
+            code: None,
+            error: None,
+            stale: outcome.stale_lines.as_ref().map(|l| !l.is_empty()),
+            stale_lines: outcome.stale_lines.filter(|l| !l.is_empty()),
+            trimmed: outcome.trimmed,
+            strategy: outcome.strategy,
+            duration_ms: outcome.duration_ms,
+            timings: outcome.timings,
+            send_id: outcome.send_id,
+            fallback: outcome.fallback,
+            hint: outcome.hint,
+            resolved_target: outcome.resolved_target,
+            results: None,
+            stage_id: None,
+            queue_position: None,
+        },
+        Err(e) => SendResponse {
+            ok: false,
+            code: e.code,
+            error: Some(e.message),
+            ..SendResponse::default()
+        },
     }
-    if side == Some("old") {
-        msg.push_str(" (deleted lines)");
+}
+
+/// Combine the outcomes of a `tmux_targets` send
+fn broadcast_response(results: Vec<(String, Result<SendOutcome, Failure>)>) -> SendResponse {
+    let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+    let results: Vec<TargetResult> = results
+        .into_iter()
+        .map(|(target, result)| match result {
+            Ok(_) => TargetResult {
+                target,
+                ok: true,
+                code: None,
+                error: None,
+            },
+            Err(e) => TargetResult {
+                target,
+                ok: false,
+                code: e.code,
+                error: Some(e.message),
+            },
+        })
+        .collect();
+    SendResponse {
+        ok: failed == 0,
+        error: (failed > 0).then(|| format!("{failed} of {} targets failed", results.len())),
+        results: Some(results),
+        ..SendResponse::default()
     }
-    msg.push('\n');
-
-    // Code (truncate at char boundary to avoid panic on multibyte strings)
-    let truncated_code = if code.len() > 2000 {
-        let end = code
-            .char_indices()
-            .map(|(i, _)| i)
-            .take_while(|&i| i <= 2000)
-            .last()
-            .unwrap_or(0);
-        format!("{}...(truncated)", &code[..end])
+}
+
+/// Decode and deliver `req` to its target, or each of its `tmux_targets`
+fn deliver_send(
+    mut req: SendRequest,
+    config: &config::Config,
+    metrics: &metrics::Metrics,
+) -> SendResponse {
+    let resp = if let Err(e) = decode_payload(&mut req) {
+        send_response(Err(e))
+    } else if req.tmux_targets.is_empty() {
+        send_response(send(&req, config))
     } else {
-        code.to_string()
+        broadcast_response(broadcast(&req, config))
     };
-    msg.push_str("```\n");
-    msg.push_str(&truncated_code);
-    msg.push_str("\n```\n");
+    record_send(metrics, &resp);
+    resp
+}
 
-    // Question
-    if !question.is_empty() {
-        msg.push_str(question);
-    } else {
-        msg.push_str("Explain this code");
+/// Count the sends in `resp`, failures by code, and how long they took
+fn record_send(metrics: &metrics::Metrics, resp: &SendResponse) {
+    let count = |ok: bool, code: Option<&str>| {
+        metrics.increment("sends_total", &[]);
+        if !ok {
+            metrics.increment("send_failures_total", &[("code", code.unwrap_or("OTHER"))]);
+        }
+    };
+    match &resp.results {
+        Some(results) => results.iter().for_each(|r| count(r.ok, r.code)),
+        None => count(resp.ok, resp.code),
+    }
+    if let Some(ms) = resp.duration_ms {
+        metrics.observe("send_duration_ms", ms);
+    }
+    if let Some(timings) = &resp.timings {
+        metrics.observe("tmux_exec_ms", timings.tmux_exec_ms);
     }
-
-    msg
 }
 
-/// Find tmux binary path. Chrome Native Messaging launches with a minimal PATH,
-/// so we check common locations where package managers install tmux.
-fn find_tmux() -> String {
-    let candidates = [
-        "/opt/homebrew/bin/tmux", // Homebrew on Apple Silicon
-        "/usr/local/bin/tmux",    // Homebrew on Intel Mac / Linux manual install
-        "/usr/bin/tmux",          // System package manager
-    ];
-    for path in candidates {
-        if std::path::Path::new(path).exists() {
-            return path.to_string();
+/// What is left of a request once the state can be unlocked: a send waiting
+/// its turn, or for a confirmation, must not hold up other connections
+type Deferred = Box<dyn FnOnce(&mut dyn Write)>;
+
+/// Deliver `req`, in the background when it asks for `async`, or later
+/// when it asks for a delivery time. Sends to one target take turns in the
+/// order they arrive, scheduled ones from when they come due. A synchronous
+/// send is returned, to deliver and answer after unlocking the state.
+fn dispatch_send(req: SendRequest, state: &HostState, out: &mut dyn Write) -> Option<Deferred> {
+    let due = match jobs::due_time(req.deliver_after_ms, req.deliver_at) {
+        Ok(due) => due,
+        Err(e) => {
+            write_send_result(out, Err(e.into()));
+            return None;
         }
+    };
+    let config = state.config().clone();
+    let metrics = state.metrics.clone();
+    if due.is_some() {
+        let lanes = state.lanes.clone();
+        let saved = req.clone();
+        let job_id = spawn_job(state, "send", due, move || {
+            match enter_lane(&lanes, &req, &config) {
+                Ok(ticket) => deliver_in_turn(ticket, req, &config, &metrics),
+                Err(e) => send_response(Err(e)),
+            }
+        });
+        let mut scheduled = state.scheduled.lock().unwrap_or_else(|e| e.into_inner());
+        scheduled.retain(|id, _| {
+            state
+                .jobs
+                .status(id)
+                .is_some_and(|s| s.state == jobs::JobState::Scheduled)
+        });
+        scheduled.insert(job_id.clone(), saved);
+        write_json(
+            out,
+            &JobResponse {
+                ok: true,
+                job_id,
+                queue_position: None,
+            },
+        );
+        return None;
+    }
+    let ticket = match enter_lane(&state.lanes, &req, &config) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            write_send_result(out, Err(e));
+            return None;
+        }
+    };
+    if req.asynchronous {
+        let queue_position = Some(ticket.position);
+        let job_id = spawn_job(state, "send", None, move || {
+            deliver_in_turn(ticket, req, &config, &metrics)
+        });
+        write_json(
+            out,
+            &JobResponse {
+                ok: true,
+                job_id,
+                queue_position,
+            },
+        );
+        None
+    } else {
+        Some(Box::new(move |out| {
+            write_json(out, &deliver_in_turn(ticket, req, &config, &metrics))
+        }))
     }
-    "tmux".to_string()
 }
 
-fn send_to_tmux(message: &str, target: &str) -> Result<(), String> {
-    let tmux = find_tmux();
+/// Finish up before exiting: set scheduled sends aside for the next host
+/// when `save_scheduled`, and give running jobs `DRAIN_TIMEOUT` to finish.
+/// The state stays unlocked, since a request holding it may be waiting on a
+/// person to confirm a send.
+fn drain(pending: &Pending, save_scheduled: bool) {
+    if save_scheduled {
+        set_aside_scheduled(pending);
+    }
+    let left = pending.jobs.drain(DRAIN_TIMEOUT);
+    if left > 0 {
+        eprintln!("pigeon: exiting with {left} jobs still running");
+    }
+}
 
-    Command::new(&tmux)
-        .args(["send-keys", "-t", target, message])
-        .status()
-        .map_err(|e| format!("Failed to run tmux: {e}"))?;
+/// Withdraw the scheduled sends and save them for the next host to schedule
+fn set_aside_scheduled(pending: &Pending) {
+    let taken = pending.jobs.take_scheduled();
+    if taken.is_empty() {
+        return;
+    }
+    let mut requests = pending.scheduled.lock().unwrap_or_else(|e| e.into_inner());
+    let saved: Vec<jobs::SavedJob> = taken
+        .into_iter()
+        .filter_map(|(id, due_at)| {
+            Some(jobs::SavedJob {
+                action: "send".to_string(),
+                due_at,
+                request: serde_json::to_value(requests.remove(&id)?).ok()?,
+            })
+        })
+        .collect();
+    let count = saved.len();
+    let result = jobs::SavedJobs::default_location()
+        .ok_or_else(|| io::Error::other("cannot locate data directory"))
+        .and_then(|store| store.put(saved));
+    match result {
+        Ok(()) => eprintln!("pigeon: saved {count} scheduled sends for the next start"),
+        Err(e) => eprintln!("pigeon: lost {count} scheduled sends: {e}"),
+    }
+}
 
-    Command::new(&tmux)
-        .args(["send-keys", "-t", target, "Enter"])
-        .status()
-        .map_err(|e| format!("Failed to run tmux: {e}"))?;
+/// Schedule again the sends a previous host set aside; those already due
+/// are delivered at once
+fn restore_scheduled(state: &HostState) {
+    let Some(store) = jobs::SavedJobs::default_location() else {
+        return;
+    };
+    let saved = match store.take() {
+        Ok(saved) => saved,
+        Err(e) => return eprintln!("pigeon: cannot read saved scheduled sends: {e}"),
+    };
+    for job in saved {
+        match serde_json::from_value::<SendRequest>(job.request) {
+            Ok(req) => {
+                let req = SendRequest {
+                    deliver_after_ms: None,
+                    deliver_at: Some(job.due_at),
+                    ..req
+                };
+                // Due now or later, so always a job
+                let _ = dispatch_send(req, state, &mut io::sink());
+            }
+            Err(e) => eprintln!("pigeon: dropping a saved scheduled send: {e}"),
+        }
+    }
+}
 
-    Ok(())
+/// A place in line at every target of `req`
+fn enter_lane(
+    lanes: &lanes::Lanes,
+    req: &SendRequest,
+    config: &config::Config,
+) -> Result<lanes::Ticket, Failure> {
+    lanes
+        .enter(&targets(req, config), config.max_queued_per_target)
+        .map_err(|busy| Failure {
+            code: Some("TARGET_BUSY"),
+            message: busy.message(),
+        })
 }
 
-fn list_sessions() -> Result<Vec<String>, String> {
-    let tmux = find_tmux();
+/// Deliver `req` once the sends ahead of `ticket` are done
+fn deliver_in_turn(
+    ticket: lanes::Ticket,
+    req: SendRequest,
+    config: &config::Config,
+    metrics: &metrics::Metrics,
+) -> SendResponse {
+    let position = ticket.position;
+    let _turn = ticket.wait();
+    let mut resp = deliver_send(req, config, metrics);
+    resp.queue_position = Some(position).filter(|&p| p > 0);
+    resp
+}
 
-    let output = Command::new(&tmux)
-        .args(["list-sessions", "-F", "#{session_name}"])
-        .output()
-        .map_err(|e| format!("Failed to run tmux: {e}"))?;
+/// Run `work` as a background job, at once or from `due`, and acknowledge
+/// with its id
+fn start_job<R: Serialize>(
+    out: &mut dyn Write,
+    state: &HostState,
+    action: &'static str,
+    due: Option<SystemTime>,
+    work: impl FnOnce() -> R + Send + 'static,
+) {
+    let job_id = spawn_job(state, action, due, work);
+    write_json(
+        out,
+        &JobResponse {
+            ok: true,
+            job_id,
+            queue_position: None,
+        },
+    );
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tmux list-sessions failed: {stderr}"));
+/// Run `work` as a background job, at once or from `due`; its id
+fn spawn_job<R: Serialize>(
+    state: &HostState,
+    action: &'static str,
+    due: Option<SystemTime>,
+    work: impl FnOnce() -> R + Send + 'static,
+) -> String {
+    let work = move || serde_json::to_value(work()).expect("Failed to serialize response");
+    match due {
+        Some(due) => state.jobs.schedule(action, due, work),
+        None => state.jobs.spawn(action, work),
     }
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let sessions: Vec<String> = stdout.lines().map(|s| s.to_string()).collect();
-    Ok(sessions)
+fn checkout_response(
+    config: &config::Config,
+    repo: &str,
+    commit_sha: &str,
+    mode: Option<git::CheckoutMode>,
+) -> CheckoutResponse {
+    let mode = mode.unwrap_or(config.checkout_mode);
+    match checkout_commit(config, repo, commit_sha, mode) {
+        Ok(path) => CheckoutResponse {
+            ok: true,
+            path: Some(path.display().to_string()),
+            code: None,
+            error: None,
+        },
+        Err(e) => CheckoutResponse {
+            ok: false,
+            path: None,
+            code: Some(e.code),
+            error: Some(e.message),
+        },
+    }
+}
+
+/// Compare this binary with the latest release of `repo`, and with
+/// `install` replace it when the release is newer
+fn check_update(repo: &str, install: bool) -> CheckUpdateResponse {
+    let mut resp = CheckUpdateResponse {
+        ok: false,
+        current: env!("CARGO_PKG_VERSION"),
+        latest: None,
+        update_available: None,
+        installed: None,
+        code: None,
+        error: None,
+    };
+    let release = match update::latest(&exec::System, repo) {
+        Ok(release) => release,
+        Err(e) => {
+            resp.code = Some("UPDATE_CHECK_FAILED");
+            resp.error = Some(e);
+            return resp;
+        }
+    };
+    let newer = update::is_newer(&release.version, resp.current);
+    resp.update_available = Some(newer);
+    if install && newer {
+        let installed = std::env::current_exe()
+            .map_err(|e| format!("Cannot locate the running binary: {e}"))
+            .and_then(|exe| {
+                let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+                update::install(&exec::System, &release, os, arch, &exe)?;
+                Ok(exe)
+            });
+        match installed {
+            Ok(exe) => resp.installed = Some(exe.display().to_string()),
+            Err(e) => {
+                resp.latest = Some(release.version);
+                resp.code = Some("UPDATE_INSTALL_FAILED");
+                resp.error = Some(e);
+                return resp;
+            }
+        }
+    }
+    resp.ok = true;
+    resp.latest = Some(release.version);
+    resp
 }
 
-fn handle_request(req: Request) {
+/// Answer `req` in the shapes of protocol `version`, with the config of
+/// `profile` when given. A send is returned rather than delivered, for the
+/// caller to run once it has unlocked `state`.
+fn handle_request(
+    req: Request,
+    version: u32,
+    profile: Option<&str>,
+    state: &mut HostState,
+    out: &mut dyn Write,
+) -> Option<Deferred> {
+    state.config.reload_if_changed();
+    let action = req.action();
+    if !state.config.get().action_enabled(action) {
+        write_json(
+            out,
+            &SendResponse {
+                ok: false,
+                code: Some("ACTION_DISABLED"),
+                error: Some(format!("The {action} action is disabled in config")),
+                ..SendResponse::default()
+            },
+        );
+        return None;
+    }
+    state
+        .metrics
+        .increment("requests_total", &[("action", action)]);
+    state.profile = match profile.map(|name| state.config.get().profile(name)) {
+        None => None,
+        Some(Ok(config)) => Some(config),
+        Some(Err(e)) => {
+            write_json(
+                out,
+                &SendResponse {
+                    ok: false,
+                    code: Some("UNKNOWN_PROFILE"),
+                    error: Some(e),
+                    ..SendResponse::default()
+                },
+            );
+            return None;
+        }
+    };
     match req {
-        Request::Send {
+        Request::Handshake => write_json(
+            out,
+            &HandshakeResponse {
+                ok: true,
+                version: env!("CARGO_PKG_VERSION"),
+                config_generation: state.config.generation(),
+                protocol_version: version,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+            },
+        ),
+        Request::Send(req) => return dispatch_send(req, state, out),
+        Request::Preview(mut req) => {
+            let result = decode_payload(&mut req).and_then(|()| preview(&req, state.config()));
+            match result {
+                Ok(composed) => {
+                    let stale_lines = composed.comparison.map(|c| c.differing_lines);
+                    write_json(
+                        out,
+                        &PreviewResponse {
+                            ok: true,
+                            message: composed.message,
+                            stale: stale_lines.as_ref().map(|l| !l.is_empty()),
+                            stale_lines: stale_lines.filter(|l| !l.is_empty()),
+                            trimmed: composed.trimmed,
+                        },
+                    )
+                }
+                Err(e) => write_send_result(out, Err(e)),
+            }
+        }
+        Request::BeginSend {
+            upload_id,
+            mut request,
+        } => {
+            let code = std::mem::take(&mut request.code);
+            let result = state.uploads.begin(upload_id, request, code);
+            write_send_result(
+                out,
+                result
+                    .map(|()| SendOutcome::default())
+                    .map_err(Failure::from),
+            )
+        }
+        Request::AppendChunk { upload_id, data } => {
+            let result = state.uploads.append(&upload_id, &data);
+            write_send_result(
+                out,
+                result
+                    .map(|()| SendOutcome::default())
+                    .map_err(Failure::from),
+            )
+        }
+        Request::StageSend(mut req) => {
+            let config = state.config().clone();
+            let result = decode_payload(&mut req).and_then(|()| state.stages.stage(&req, &config));
+            let resp = match result {
+                Ok((outcome, stage_id)) => SendResponse {
+                    stage_id: Some(stage_id),
+                    ..send_response(Ok(outcome))
+                },
+                Err(e) => send_response(Err(e)),
+            };
+            record_send(&state.metrics, &resp);
+            write_json(out, &resp)
+        }
+        Request::CommitSend {
+            upload_id: Some(upload_id),
+            stage_id: None,
+        } => match state.uploads.commit(&upload_id) {
+            Ok((mut req, code)) => {
+                req.code = code;
+                return dispatch_send(req, state, out);
+            }
+            Err(e) => write_send_result(out, Err(Failure::from(e))),
+        },
+        Request::CommitSend {
+            upload_id: None,
+            stage_id: Some(stage_id),
+        } => {
+            let config = state.config().clone();
+            let result = state
+                .stages
+                .commit(&stage_id, &config, &tmux::Tmux::new(&config));
+            write_send_result(out, result.map(|()| SendOutcome::default()))
+        }
+        Request::CommitSend { .. } => write_send_result(
+            out,
+            Err(Failure::from(
+                "commit-send needs either upload_id or stage_id".to_string(),
+            )),
+        ),
+        Request::AbortSend { stage_id } => {
+            let config = state.config().clone();
+            let tmux = tmux::Tmux::new(&config);
+            let result = state.stages.abort(&stage_id, &config, &tmux);
+            write_send_result(out, result.map(|()| SendOutcome::default()))
+        }
+        Request::UndoLast { tmux_target } => {
+            let config = state.config().clone();
+            let tmux = tmux::Tmux::new(&config);
+            let resp = match state.stages.undo_last(&tmux_target, &config, &tmux) {
+                Ok((stage_id, pane)) => UndoResponse {
+                    ok: true,
+                    stage_id: Some(stage_id),
+                    tmux_target: Some(pane),
+                    code: None,
+                    error: None,
+                },
+                Err(e) => UndoResponse {
+                    ok: false,
+                    stage_id: None,
+                    tmux_target: None,
+                    code: e.code,
+                    error: Some(e.message),
+                },
+            };
+            write_json(out, &resp);
+        }
+        Request::Doctor => write_json(
+            out,
+            &DoctorResponse {
+                ok: true,
+                version: env!("CARGO_PKG_VERSION"),
+                os: std::env::consts::OS,
+                tmux: tmux::Tmux::new(state.config()).discovery().clone(),
+                config_path: state.config.path().map(|p| p.display().to_string()),
+            },
+        ),
+        Request::ProbeEnvironment => write_json(
+            out,
+            &ProbeEnvironmentResponse {
+                ok: true,
+                environment: tmux::Tmux::new(state.config()).probe(),
+            },
+        ),
+        Request::ResolvePath { repo, file } => {
+            let resp = match repos::resolve(state.config(), &repo, &file) {
+                Some(path) => ResolvePathResponse {
+                    ok: true,
+                    exists: Some(path.is_file()),
+                    path: Some(path.display().to_string()),
+                    error: None,
+                },
+                None => ResolvePathResponse {
+                    ok: false,
+                    path: None,
+                    exists: None,
+                    error: Some(format!("No local checkout mapped for {repo}/{file}")),
+                },
+            };
+            write_json(out, &resp)
+        }
+        Request::Checkout {
+            repo,
+            commit_sha,
+            mode,
+            asynchronous,
+        } => {
+            if asynchronous {
+                let config = state.config().clone();
+                start_job(out, state, "checkout", None, move || {
+                    checkout_response(&config, &repo, &commit_sha, mode)
+                });
+            } else {
+                write_json(
+                    out,
+                    &checkout_response(state.config(), &repo, &commit_sha, mode),
+                );
+            }
+        }
+        Request::CheckUpdate {
+            install,
+            asynchronous,
+        } => {
+            let settings = state.config().update.clone();
+            if install && !settings.allow_install {
+                write_json(
+                    out,
+                    &CheckUpdateResponse {
+                        ok: false,
+                        current: env!("CARGO_PKG_VERSION"),
+                        latest: None,
+                        update_available: None,
+                        installed: None,
+                        code: Some("UPDATE_INSTALL_DISABLED"),
+                        error: Some(
+                            "installing updates needs update.allow_install in config".to_string(),
+                        ),
+                    },
+                );
+            } else if asynchronous {
+                start_job(out, state, "check-update", None, move || {
+                    check_update(&settings.repo, install)
+                });
+            } else {
+                write_json(out, &check_update(&settings.repo, install));
+            }
+        }
+        Request::JobStatus { job_id } => match state.jobs.status(&job_id) {
+            Some(status) => write_json(
+                out,
+                &JobStatusResponse {
+                    ok: true,
+                    status: Some(status),
+                    error: None,
+                },
+            ),
+            None => write_json(
+                out,
+                &JobStatusResponse {
+                    ok: false,
+                    status: None,
+                    error: Some(format!("Unknown job: {job_id}")),
+                },
+            ),
+        },
+        Request::CancelJob { job_id } => write_json(
+            out,
+            &match state.jobs.cancel(&job_id) {
+                Ok(status) => JobStatusResponse {
+                    ok: true,
+                    status: Some(status),
+                    error: None,
+                },
+                Err(e) => JobStatusResponse {
+                    ok: false,
+                    status: None,
+                    error: Some(e),
+                },
+            },
+        ),
+        Request::ListJobs { state: job_state } => write_json(
+            out,
+            &JobListResponse {
+                ok: true,
+                jobs: state
+                    .jobs
+                    .list(job_state)
+                    .into_iter()
+                    .map(|(job_id, status)| JobEntry { job_id, status })
+                    .collect(),
+            },
+        ),
+        Request::Metrics { format } => {
+            let snapshot = state.metrics.snapshot();
+            write_json(
+                out,
+                &match format {
+                    MetricsFormat::Json => MetricsResponse {
+                        ok: true,
+                        metrics: Some(snapshot),
+                        text: None,
+                    },
+                    MetricsFormat::Prometheus => MetricsResponse {
+                        ok: true,
+                        metrics: None,
+                        text: Some(snapshot.prometheus()),
+                    },
+                },
+            )
+        }
+        Request::Schema => write_json(
+            out,
+            &SchemaResponse {
+                ok: true,
+                schema: schema(),
+            },
+        ),
+        Request::ListProfiles => write_json(
+            out,
+            &ProfilesResponse {
+                ok: true,
+                profiles: state.config.get().profiles.keys().cloned().collect(),
+            },
+        ),
+        Request::GetConfig => write_json(out, &state.config_response(Ok(()))),
+        Request::SetConfig { config } => {
+            let result = state.config.set(&config);
+            write_json(out, &state.config_response(result))
+        }
+        Request::Stats { tag } => match stats::collect(state.config(), tag.as_deref()) {
+            Ok(stats) => write_json(
+                out,
+                &StatsResponse {
+                    ok: true,
+                    stats: Some(stats),
+                    error: None,
+                },
+            ),
+            Err(e) => write_json(
+                out,
+                &StatsResponse {
+                    ok: false,
+                    stats: None,
+                    error: Some(e),
+                },
+            ),
+        },
+        Request::ListRecentQuestions {
+            repo,
             file,
-            start_line,
-            end_line,
-            side,
-            code,
-            question,
-            tmux_target,
-            debug_html,
+            tag,
+            limit,
         } => {
-            // Write debug HTML to file when file path extraction failed
-            if let Some(ref html) = debug_html {
-                if let Ok(home) = std::env::var("HOME") {
-                    let debug_path = format!("{home}/.config/pigeon/debug.json");
-                    let _ = std::fs::write(&debug_path, html);
-                }
+            let filter = history::Filter {
+                repo: repo.as_deref(),
+                file: file.as_deref(),
+                tag: tag.as_deref(),
+            };
+            match history::recent_questions(state.config(), &filter, limit) {
+                Ok(questions) => write_json(
+                    out,
+                    &RecentQuestionsResponse {
+                        ok: true,
+                        questions: Some(questions),
+                        error: None,
+                    },
+                ),
+                Err(e) => write_json(
+                    out,
+                    &RecentQuestionsResponse {
+                        ok: false,
+                        questions: None,
+                        error: Some(e),
+                    },
+                ),
             }
-
-            let message = format_message(
-                &file,
-                start_line,
-                end_line,
-                side.as_deref(),
-                &code,
-                &question,
-            );
-
-            match send_to_tmux(&message, &tmux_target) {
-                Ok(()) => write_json(&SendResponse {
+        }
+        Request::GetReply {
+            tmux_target,
+            send_id,
+        } => match get_reply(state.config(), &tmux_target, &send_id) {
+            Ok(reply) => write_json(
+                out,
+                &GetReplyResponse {
                     ok: true,
+                    reply: Some(reply),
+                    code: None,
                     error: None,
-                }),
-                Err(e) => write_json(&SendResponse {
+                },
+            ),
+            Err(e) => write_json(
+                out,
+                &GetReplyResponse {
+                    ok: false,
+                    reply: None,
+                    code: e.code,
+                    error: Some(e.message),
+                },
+            ),
+        },
+        Request::WatchTarget {
+            tmux_target,
+            idle_secs,
+            timeout_secs,
+        } => match watch::WatchOptions::from_request(idle_secs, timeout_secs) {
+            Ok(options) => {
+                let config = state.config().clone();
+                start_job(
+                    out,
+                    state,
+                    "watch-target",
+                    None,
+                    move || match watch_target(&config, &tmux_target, &options) {
+                        Ok(idle) => WatchTargetResponse {
+                            ok: true,
+                            idle: Some(idle),
+                            code: None,
+                            error: None,
+                        },
+                        Err(e) => WatchTargetResponse {
+                            ok: false,
+                            idle: None,
+                            code: e.code,
+                            error: Some(e.message),
+                        },
+                    },
+                )
+            }
+            Err(e) => write_json(
+                out,
+                &WatchTargetResponse {
                     ok: false,
+                    idle: None,
+                    code: None,
                     error: Some(e),
-                }),
+                },
+            ),
+        },
+        Request::ExportTranscript { tmux_target, path } => {
+            let path = path.map(PathBuf::from);
+            let resp = match export_transcript(state.config(), &tmux_target, path.as_deref()) {
+                Ok(Transcript::Written(path)) => TranscriptResponse {
+                    ok: true,
+                    path: Some(path.display().to_string()),
+                    markdown: None,
+                    code: None,
+                    error: None,
+                },
+                Ok(Transcript::Inline(markdown)) => TranscriptResponse {
+                    ok: true,
+                    path: None,
+                    markdown: Some(markdown),
+                    code: None,
+                    error: None,
+                },
+                Err(e) => TranscriptResponse {
+                    ok: false,
+                    path: None,
+                    markdown: None,
+                    code: e.code,
+                    error: Some(e.message),
+                },
+            };
+            write_json(out, &resp)
+        }
+        Request::ReportDebug { html, context } => {
+            match debug::save_report(html, context, &state.config().debug) {
+                Ok(path) => write_json(
+                    out,
+                    &ReportDebugResponse {
+                        ok: true,
+                        path: Some(path),
+                        error: None,
+                    },
+                ),
+                Err(e) => write_json(
+                    out,
+                    &ReportDebugResponse {
+                        ok: false,
+                        path: None,
+                        error: Some(e),
+                    },
+                ),
             }
         }
-        Request::ListSessions => match list_sessions() {
-            Ok(sessions) => write_json(&ListSessionsResponse {
-                ok: true,
-                sessions: Some(sessions),
-                error: None,
+        Request::AddAlias { name, tmux_target } => write_aliases_result(
+            out,
+            aliases().and_then(|aliases| {
+                let tmux = tmux::Tmux::new(state.config());
+                let alias = aliases::Alias::bookmark(&tmux_target, &tmux);
+                aliases.add(&name, alias).map_err(Failure::from)
             }),
-            Err(e) => write_json(&ListSessionsResponse {
-                ok: false,
-                sessions: None,
-                error: Some(e),
+        ),
+        Request::RemoveAlias { name } => write_aliases_result(
+            out,
+            aliases().and_then(|aliases| match aliases.remove(&name) {
+                Ok(true) => aliases.list().map_err(|e| Failure::from(e.to_string())),
+                Ok(false) => Err(Failure {
+                    code: Some("UNKNOWN_ALIAS"),
+                    message: format!("No alias named {name}"),
+                }),
+                Err(e) => Err(Failure::from(e.to_string())),
             }),
+        ),
+        Request::ListAliases => write_aliases_result(
+            out,
+            aliases().and_then(|aliases| aliases.list().map_err(|e| Failure::from(e.to_string()))),
+        ),
+        Request::KillSession { tmux_target } => {
+            write_lifecycle_result(out, kill_session(state.config(), &tmux_target))
+        }
+        Request::RespawnAgent {
+            tmux_target,
+            command,
+        } => write_lifecycle_result(
+            out,
+            respawn_agent(state.config(), &tmux_target, command.as_deref()),
+        ),
+        Request::ListPanes => write_panes_result(out, tmux::Tmux::new(state.config()).list_panes()),
+        Request::FindTargetByPath { path } => write_panes_result(
+            out,
+            infer::panes_in(Path::new(&path), &tmux::Tmux::new(state.config())),
+        ),
+        Request::ListSessions => match tmux::Tmux::new(state.config()).list_sessions() {
+            Ok(sessions) => write_json(
+                out,
+                &ListSessionsResponse {
+                    ok: true,
+                    sessions: Some(SessionList::for_version(version, sessions)),
+                    error: None,
+                },
+            ),
+            Err(e) => write_json(
+                out,
+                &ListSessionsResponse {
+                    ok: false,
+                    sessions: None,
+                    error: Some(e),
+                },
+            ),
         },
     }
+    None
 }
 
 fn main() {
-    // Native Messaging Host receives messages one at a time.
-    // Chrome starts and stops the process as needed.
-    while let Ok(raw) = read_message() {
-        let req: Request = match serde_json::from_str(&raw) {
-            Ok(r) => r,
-            Err(e) => {
-                write_json(&SendResponse {
-                    ok: false,
-                    error: Some(format!("Invalid JSON: {e}")),
-                });
-                continue;
-            }
+    // Chrome passes the extension origin as the first argument, so a flag
+    // here can only come from a user or agent configuration
+    if std::env::args().nth(1).as_deref() == Some("--mcp") {
+        let Some(queue) = queue::Queue::default_location() else {
+            eprintln!("pigeon: cannot locate data directory for the queue");
+            std::process::exit(1);
         };
-
-        handle_request(req);
+        if let Err(e) = mcp::serve(io::stdin().lock(), io::stdout(), &queue) {
+            eprintln!("pigeon: {e}");
+            std::process::exit(1);
+        }
+        return;
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_deserialize_send_request() {
-        let json = r#"{
-            "action": "send",
-            "file": "src/main.rs",
-            "start_line": 10,
-            "end_line": 20,
-            "side": "new",
-            "code": "fn main() {}",
-            "question": "What does this do?",
-            "tmux_target": "my-session"
-        }"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        match req {
-            Request::Send {
-                file,
-                tmux_target,
-                question,
-                ..
-            } => {
-                assert_eq!(file, "src/main.rs");
-                assert_eq!(tmux_target, "my-session");
-                assert_eq!(question, "What does this do?");
+    #[cfg(unix)]
+    match std::env::args().nth(1).as_deref() {
+        Some("--daemon") => {
+            let state = HostState::new();
+            restore_scheduled(&state);
+            let pending = state.pending();
+            shutdown::on_signal(move || drain(&pending, true));
+            let state = Arc::new(Mutex::new(state));
+            sweep_periodically(state.clone());
+            let result = daemon::run(move |stream| {
+                let mut input = match stream.try_clone() {
+                    Ok(input) => input,
+                    Err(e) => return eprintln!("pigeon: {e}"),
+                };
+                match daemon::read_origin(&mut input) {
+                    Ok(origin) => serve(&mut input, &mut &stream, &state, origin.as_deref()),
+                    Err(e) => eprintln!("pigeon: {e}"),
+                }
+            });
+            if let Err(e) = result {
+                eprintln!("pigeon: {e}");
+                std::process::exit(1);
             }
-            _ => panic!("Expected Send variant"),
+            return;
         }
-    }
-
-    #[test]
-    fn test_deserialize_list_sessions_request() {
-        let json = r#"{"action": "list-sessions"}"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        assert!(matches!(req, Request::ListSessions));
-    }
-
-    #[test]
-    fn test_deserialize_send_with_optional_fields() {
-        let json = r#"{
-            "action": "send",
-            "file": "lib.rs",
-            "code": "let x = 1;",
-            "question": "",
-            "tmux_target": "dev"
-        }"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        match req {
-            Request::Send {
-                start_line,
-                end_line,
-                side,
-                debug_html,
-                ..
-            } => {
-                assert!(start_line.is_none());
-                assert!(end_line.is_none());
-                assert!(side.is_none());
-                assert!(debug_html.is_none());
+        Some("self-update") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            match self_update(&args) {
+                Ok(done) => println!("{done}"),
+                Err(e) => {
+                    eprintln!("pigeon: {e}");
+                    std::process::exit(1);
+                }
             }
-            _ => panic!("Expected Send variant"),
+            return;
         }
+        Some("service") => {
+            let args: Vec<String> = std::env::args().skip(2).collect();
+            match service::main(&args) {
+                Ok(done) => println!("{done}"),
+                Err(e) => {
+                    eprintln!("pigeon: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        _ => {}
     }
 
-    #[test]
-    fn test_deserialize_unknown_action_fails() {
-        let json = r#"{"action": "unknown"}"#;
-        let result: Result<Request, _> = serde_json::from_str(json);
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn test_format_message_basic() {
-        let msg = format_message(
-            "src/main.rs",
-            Some(10),
-            Some(20),
-            None,
-            "fn main() {}",
-            "Explain",
-        );
-        assert!(msg.contains("src/main.rs:10-20"));
-        assert!(msg.contains("fn main() {}"));
-        assert!(msg.contains("Explain"));
+    // Native Messaging Host receives messages one at a time.
+    // Chrome starts and stops the process as needed.
+    let origin = extension_origin();
+    let state = HostState::new();
+    #[cfg(unix)]
+    if state.config.get().daemon {
+        if let Some(stream) = daemon::connect() {
+            if let Err(e) = daemon::relay(stream, origin.as_deref()) {
+                eprintln!("pigeon: lost connection to the daemon: {e}");
+            }
+            return;
+        }
+        eprintln!("pigeon: daemon unavailable, handling messages in this process");
     }
-
-    #[test]
-    fn test_format_message_deleted_lines() {
-        let msg = format_message("old.rs", Some(5), None, Some("old"), "deleted code", "Why?");
-        assert!(msg.contains("old.rs:5 (deleted lines)"));
+    // Off the request path, so a large store cannot delay the first answer
+    let config = state.config.get().clone();
+    std::thread::spawn(move || retention::sweep(&config, SystemTime::now()));
+    let pending = state.pending();
+    #[cfg(unix)]
+    {
+        let pending = state.pending();
+        shutdown::on_signal(move || drain(&pending, false));
     }
+    serve(
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+        &Mutex::new(state),
+        origin.as_deref(),
+    );
+    // Chrome closed stdin; let sends already under way reach their panes
+    drain(&pending, false);
+}
 
-    #[test]
-    fn test_format_message_empty_question() {
-        let msg = format_message("file.rs", None, None, None, "code", "");
-        assert!(msg.contains("Explain this code"));
+/// `pigeon-host self-update [--check]`: install the latest release over this
+/// binary, or with `--check` only say whether there is a newer one. Run by
+/// hand, so it does not need `update.allow_install`.
+#[cfg(unix)]
+fn self_update(args: &[String]) -> Result<String, String> {
+    let install = match args {
+        [] => true,
+        [flag] if flag == "--check" => false,
+        _ => return Err("usage: pigeon-host self-update [--check]".to_string()),
+    };
+    let live = config::LiveConfig::new(config::ConfigFile::default_location());
+    let resp = check_update(&live.get().update.repo, install);
+    if let Some(error) = resp.error {
+        return Err(error);
     }
+    let latest = resp.latest.unwrap_or_default();
+    Ok(match (resp.installed, resp.update_available) {
+        (Some(path), _) => {
+            format!("Installed {latest} at {path}; it runs from the next start of the host")
+        }
+        (None, Some(true)) => format!("{latest} is available (running {})", resp.current),
+        _ => format!("{} is the latest release", resp.current),
+    })
+}
 
-    #[test]
-    fn test_serialize_send_response_ok() {
-        let resp = SendResponse {
-            ok: true,
-            error: None,
-        };
-        let json = serde_json::to_string(&resp).unwrap();
-        assert_eq!(json, r#"{"ok":true}"#);
-    }
+/// Apply the `retention` limits now and then every `sweep_interval_secs`,
+/// with the config current at each sweep
+#[cfg(unix)]
+fn sweep_periodically(state: Arc<Mutex<HostState>>) {
+    std::thread::spawn(move || loop {
+        let config = state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .config
+            .get()
+            .clone();
+        retention::sweep(&config, SystemTime::now());
+        let interval = config.retention.sweep_interval_secs;
+        std::thread::sleep(Duration::from_secs(interval));
+    });
+}
 
-    #[test]
-    fn test_serialize_send_response_error() {
-        let resp = SendResponse {
-            ok: false,
-            error: Some("something went wrong".to_string()),
-        };
-        let json = serde_json::to_string(&resp).unwrap();
-        assert!(json.contains(r#""ok":false"#));
-        assert!(json.contains(r#""error":"something went wrong""#));
-    }
+/// The extension Chrome started the host for, which it passes as the first
+/// argument, such as `chrome-extension://<id>/`
+fn extension_origin() -> Option<String> {
+    std::env::args()
+        .nth(1)
+        .filter(|arg| arg.starts_with("chrome-extension://"))
+}
 
-    #[test]
-    fn test_serialize_list_sessions_response_ok() {
-        let resp = ListSessionsResponse {
-            ok: true,
-            sessions: Some(vec!["pigeon".to_string(), "dev".to_string()]),
-            error: None,
+/// Answer framed requests from `input` until it closes. The state is locked
+/// per request, and not while a send is delivered, so several connections
+/// can share it. `allowed_origins` is
+/// checked on every request, so editing it applies to open connections.
+fn serve(
+    input: &mut impl Read,
+    out: &mut dyn Write,
+    state: &Mutex<HostState>,
+    origin: Option<&str>,
+) {
+    let lock = || state.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        let max = lock().config.get().max_message_bytes;
+        let raw = match framing::read_message(input, max) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("pigeon: {e}");
+                write_json(
+                    out,
+                    &SendResponse {
+                        ok: false,
+                        code: Some(e.code()),
+                        error: Some(e.to_string()),
+                        ..SendResponse::default()
+                    },
+                );
+                if e.is_fatal() {
+                    break;
+                }
+                continue;
+            }
         };
-        let json = serde_json::to_string(&resp).unwrap();
-        assert!(json.contains(r#""ok":true"#));
-        assert!(json.contains(r#""sessions":["pigeon","dev"]"#));
-    }
-
-    #[test]
-    fn test_serialize_list_sessions_response_error() {
-        let resp = ListSessionsResponse {
-            ok: false,
-            sessions: None,
-            error: Some("tmux not found".to_string()),
+        let envelope: Envelope = match serde_json::from_str(&raw) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                write_json(
+                    out,
+                    &SendResponse {
+                        ok: false,
+                        error: Some(format!("Invalid JSON: {e}")),
+                        ..SendResponse::default()
+                    },
+                );
+                continue;
+            }
         };
-        let json = serde_json::to_string(&resp).unwrap();
-        assert!(json.contains(r#""ok":false"#));
-        assert!(json.contains(r#""error":"tmux not found""#));
-        assert!(!json.contains("sessions"));
+        if !lock().config.get().origin_allowed(origin) {
+            write_json(
+                out,
+                &SendResponse {
+                    ok: false,
+                    code: Some("ORIGIN_NOT_ALLOWED"),
+                    error: Some(format!(
+                        "Extension {} is not in allowed_origins",
+                        origin.unwrap_or("(unknown)")
+                    )),
+                    ..SendResponse::default()
+                },
+            );
+            continue;
+        }
+        match envelope.negotiate() {
+            Ok(version) => {
+                let deferred = handle_request(
+                    envelope.request,
+                    version,
+                    envelope.profile.as_deref(),
+                    &mut lock(),
+                    out,
+                );
+                if let Some(deliver) = deferred {
+                    deliver(out);
+                }
+            }
+            Err(e) => write_json(
+                out,
+                &SendResponse {
+                    ok: false,
+                    code: Some("UNSUPPORTED_PROTOCOL_VERSION"),
+                    error: Some(e),
+                    ..SendResponse::default()
+                },
+            ),
+        }
     }
 }
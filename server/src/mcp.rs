@@ -0,0 +1,185 @@
+//! Model Context Protocol server over stdio (`pigeon-host --mcp`), letting
+//! agents pull questions held by the `queue` backend instead of receiving
+//! pasted text. Messages are newline-delimited JSON-RPC 2.0.
+
+use pigeon_core::queue::Queue;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+/// Revision answered when the client does not ask for one
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Serve requests from `input` until it closes
+pub fn serve(input: impl BufRead, mut output: impl Write, queue: &Queue) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_line(&line, queue) {
+            writeln!(output, "{response}")?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `None` for notifications, which get no response
+fn handle_line(line: &str, queue: &Queue) -> Option<Value> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => return Some(error(Value::Null, -32700, &format!("Parse error: {e}"))),
+    };
+    let id = message.get("id").cloned()?;
+    let method = message["method"].as_str().unwrap_or_default();
+    let params = &message["params"];
+    let result = match method {
+        "initialize" => json!({
+            "protocolVersion": params["protocolVersion"].as_str().unwrap_or(PROTOCOL_VERSION),
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "pigeon", "version": env!("CARGO_PKG_VERSION") },
+        }),
+        "ping" => json!({}),
+        "tools/list" => json!({ "tools": tools() }),
+        "tools/call" => call_tool(params, queue),
+        _ => return Some(error(id, -32601, &format!("Method not found: {method}"))),
+    };
+    Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn tools() -> Value {
+    json!([
+        {
+            "name": "get_pending_questions",
+            "description": "List questions sent from the browser that have not been answered yet, oldest first. Each has an id, the source file, and the formatted message.",
+            "inputSchema": { "type": "object", "properties": {} },
+        },
+        {
+            "name": "mark_answered",
+            "description": "Mark a pending question as answered so it is no longer returned.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "id": { "type": "integer", "description": "Question id" } },
+                "required": ["id"],
+            },
+        },
+    ])
+}
+
+/// Tool failures are reported in the result with `isError`, as MCP expects,
+/// so the agent can see and react to them
+fn call_tool(params: &Value, queue: &Queue) -> Value {
+    let result = match params["name"].as_str().unwrap_or_default() {
+        "get_pending_questions" => queue
+            .pending()
+            .map_err(|e| format!("Failed to read queue: {e}"))
+            .and_then(|pending| serde_json::to_string_pretty(&pending).map_err(|e| e.to_string())),
+        "mark_answered" => match params["arguments"]["id"].as_u64() {
+            Some(id) => match queue.mark_answered(id) {
+                Ok(true) => Ok(format!("Marked question {id} as answered")),
+                Ok(false) => Err(format!("No pending question with id {id}")),
+                Err(e) => Err(format!("Failed to update queue: {e}")),
+            },
+            None => Err("mark_answered needs an integer id".to_string()),
+        },
+        name => Err(format!("Unknown tool: {name}")),
+    };
+    match result {
+        Ok(text) => json!({ "content": [{ "type": "text", "text": text }] }),
+        Err(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": true }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_queue(name: &str) -> (PathBuf, Queue) {
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-mcp-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let queue = Queue::new(dir.join("queue.json"));
+        (dir, queue)
+    }
+
+    fn run(input: &str, queue: &Queue) -> Vec<Value> {
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, queue).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_initialize_and_list_tools() {
+        let (dir, queue) = temp_queue("init");
+        let responses = run(
+            concat!(
+                r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26"}}"#,
+                "\n",
+                r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+                "\n",
+                r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+                "\n",
+            ),
+            &queue,
+        );
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(responses[0]["result"]["serverInfo"]["name"], "pigeon");
+        let names: Vec<&str> = responses[1]["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["get_pending_questions", "mark_answered"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_pending_questions_and_mark_answered() {
+        let (dir, queue) = temp_queue("tools");
+        queue.push(Some("dev"), None, "a.rs", "Why?").unwrap();
+        let responses = run(
+            concat!(
+                r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"get_pending_questions"}}"#,
+                "\n",
+                r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"mark_answered","arguments":{"id":1}}}"#,
+                "\n",
+                r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"mark_answered","arguments":{"id":1}}}"#,
+                "\n",
+            ),
+            &queue,
+        );
+        let text = responses[0]["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap();
+        let pending: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(pending[0]["message"], "Why?");
+        assert!(responses[1]["result"].get("isError").is_none());
+        assert_eq!(responses[2]["result"]["isError"], true);
+        assert!(queue.pending().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let (_, queue) = temp_queue("errors");
+        let responses = run(
+            "not json\n{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"resources/list\"}\n",
+            &queue,
+        );
+        assert_eq!(responses[0]["error"]["code"], -32700);
+        assert_eq!(responses[1]["id"], 7);
+        assert_eq!(responses[1]["error"]["code"], -32601);
+    }
+}
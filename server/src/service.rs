@@ -0,0 +1,261 @@
+//! `pigeon-host service install|start|stop`: keep the daemon running at
+//! login as a systemd user service (socket-activated) or a launchd agent.
+
+use crate::daemon;
+use pigeon_core::exec::{self, Exec};
+use pigeon_core::paths;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SYSTEMD_UNIT: &str = "pigeon";
+const LAUNCHD_LABEL: &str = "io.github.x7c1.pigeon";
+
+/// Run the `service` subcommand and describe what was done
+pub fn main(args: &[String]) -> Result<String, String> {
+    match args.first().map(String::as_str) {
+        Some("install") => install(),
+        Some("start") => start().map(|()| "Started the pigeon service".to_string()),
+        Some("stop") => stop().map(|()| "Stopped the pigeon service".to_string()),
+        _ => Err("Usage: pigeon-host service install|start|stop".to_string()),
+    }
+}
+
+enum Manager {
+    Systemd,
+    Launchd,
+}
+
+fn manager() -> Result<Manager, String> {
+    match std::env::consts::OS {
+        "linux" => Ok(Manager::Systemd),
+        "macos" => Ok(Manager::Launchd),
+        os => Err(format!(
+            "Services are supported on Linux (systemd) and macOS (launchd), not {os}"
+        )),
+    }
+}
+
+fn install() -> Result<String, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Cannot locate pigeon-host: {e}"))?;
+    let socket = daemon::socket_path().ok_or("Cannot locate data directory for the socket")?;
+    match manager()? {
+        Manager::Systemd => {
+            let dir = systemd_dir()?;
+            for (name, contents) in systemd_units(&exe, &socket) {
+                write(&dir.join(name), &contents)?;
+            }
+            systemctl(&["daemon-reload"])?;
+            systemctl(&[
+                "enable",
+                "--now",
+                &format!("{SYSTEMD_UNIT}.socket"),
+                &format!("{SYSTEMD_UNIT}.service"),
+            ])?;
+            Ok(format!("Installed systemd user units in {}", dir.display()))
+        }
+        Manager::Launchd => {
+            let plist = launchd_plist_path()?;
+            let log = paths::cache_dir()
+                .ok_or("Cannot locate cache directory for the daemon log")?
+                .join("daemon.log");
+            write(&plist, &launchd_plist(&exe, &log))?;
+            // Reload a previous version; failing because none was loaded is fine
+            let _ = launchctl(&["unload", &plist.display().to_string()]);
+            launchctl(&["load", "-w", &plist.display().to_string()])?;
+            Ok(format!("Installed launchd agent {}", plist.display()))
+        }
+    }
+}
+
+/// Start the installed service. Used by the relay when no daemon listens.
+pub fn start() -> Result<(), String> {
+    match manager()? {
+        Manager::Systemd => {
+            require_installed(&systemd_dir()?.join(format!("{SYSTEMD_UNIT}.service")))?;
+            systemctl(&[
+                "start",
+                &format!("{SYSTEMD_UNIT}.socket"),
+                &format!("{SYSTEMD_UNIT}.service"),
+            ])
+        }
+        Manager::Launchd => {
+            let plist = launchd_plist_path()?;
+            require_installed(&plist)?;
+            // Loading starts the agent; when it is already loaded, kick it
+            launchctl(&["load", "-w", &plist.display().to_string()])
+                .or_else(|_| launchctl(&["start", LAUNCHD_LABEL]))
+        }
+    }
+}
+
+fn stop() -> Result<(), String> {
+    match manager()? {
+        Manager::Systemd => systemctl(&[
+            "stop",
+            &format!("{SYSTEMD_UNIT}.service"),
+            &format!("{SYSTEMD_UNIT}.socket"),
+        ]),
+        // KeepAlive would restart a merely stopped agent
+        Manager::Launchd => launchctl(&["unload", &launchd_plist_path()?.display().to_string()]),
+    }
+}
+
+fn require_installed(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} not found; run `pigeon-host service install` first",
+            path.display()
+        ))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user`, next to pigeon's own config directory
+fn systemd_dir() -> Result<PathBuf, String> {
+    paths::config_dir()
+        .and_then(|dir| Some(dir.parent()?.join("systemd").join("user")))
+        .ok_or_else(|| "Cannot locate the systemd user unit directory".to_string())
+}
+
+fn launchd_plist_path() -> Result<PathBuf, String> {
+    let home = std::env::var_os("HOME").ok_or("HOME is not set")?;
+    Ok(PathBuf::from(home)
+        .join("Library/LaunchAgents")
+        .join(format!("{LAUNCHD_LABEL}.plist")))
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {e}", dir.display()))?;
+    }
+    fs::write(path, contents).map_err(|e| format!("Cannot write {}: {e}", path.display()))
+}
+
+fn systemctl(args: &[&str]) -> Result<(), String> {
+    let mut argv = vec!["--user"];
+    argv.extend(args);
+    run("systemctl", &argv)
+}
+
+fn launchctl(args: &[&str]) -> Result<(), String> {
+    run("launchctl", args)
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let output = exec::System
+        .run(program, &args)
+        .map_err(|e| format!("Failed to run {program}: {e}"))?;
+    if !output.success {
+        return Err(format!(
+            "{program} {} failed: {}",
+            args.join(" "),
+            output.stderr.trim()
+        ));
+    }
+    Ok(())
+}
+
+/// The socket unit systemd listens on, and the service it activates
+fn systemd_units(exe: &Path, socket: &Path) -> [(String, String); 2] {
+    let socket_unit = format!(
+        "[Unit]\n\
+         Description=pigeon native messaging host socket\n\
+         \n\
+         [Socket]\n\
+         ListenStream={}\n\
+         SocketMode=0600\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n",
+        socket.display()
+    );
+    let service_unit = format!(
+        "[Unit]\n\
+         Description=pigeon native messaging host\n\
+         Requires={SYSTEMD_UNIT}.socket\n\
+         \n\
+         [Service]\n\
+         ExecStart=\"{}\" --daemon\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe.display()
+    );
+    [
+        (format!("{SYSTEMD_UNIT}.socket"), socket_unit),
+        (format!("{SYSTEMD_UNIT}.service"), service_unit),
+    ]
+}
+
+/// A launchd agent started at login and restarted if it exits. launchd
+/// socket activation needs its C API, so the daemon binds the socket itself.
+fn launchd_plist(exe: &Path, log: &Path) -> String {
+    let exe = xml_escape(&exe.display().to_string());
+    let log = xml_escape(&log.display().to_string());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{LAUNCHD_LABEL}</string>
+  <key>ProgramArguments</key>
+  <array>
+    <string>{exe}</string>
+    <string>--daemon</string>
+  </array>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>KeepAlive</key>
+  <true/>
+  <key>StandardErrorPath</key>
+  <string>{log}</string>
+</dict>
+</plist>
+"#
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_units() {
+        let [(socket_name, socket), (service_name, service)] = systemd_units(
+            Path::new("/opt/pigeon/pigeon-host"),
+            Path::new("/home/me/.local/share/pigeon/pigeon.sock"),
+        );
+        assert_eq!(socket_name, "pigeon.socket");
+        assert!(socket.contains("ListenStream=/home/me/.local/share/pigeon/pigeon.sock\n"));
+        assert_eq!(service_name, "pigeon.service");
+        assert!(service.contains("ExecStart=\"/opt/pigeon/pigeon-host\" --daemon\n"));
+        assert!(service.contains("Requires=pigeon.socket\n"));
+    }
+
+    #[test]
+    fn test_launchd_plist_escapes_paths() {
+        let plist = launchd_plist(
+            Path::new("/Users/me/R&D/pigeon-host"),
+            Path::new("/Users/me/Library/Caches/pigeon/daemon.log"),
+        );
+        assert!(plist.contains("<string>/Users/me/R&amp;D/pigeon-host</string>"));
+        assert!(plist.contains("<string>io.github.x7c1.pigeon</string>"));
+        assert!(plist.contains("<string>--daemon</string>"));
+    }
+
+    #[test]
+    fn test_usage_without_subcommand() {
+        assert!(main(&[]).unwrap_err().starts_with("Usage:"));
+        assert!(main(&["restart".to_string()]).is_err());
+    }
+}
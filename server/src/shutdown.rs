@@ -0,0 +1,41 @@
+//! Orderly exit on SIGTERM or SIGINT, which service managers and Chrome
+//! send to a host they are done with. The handler only raises a flag; a
+//! watcher thread then runs the host's clean-up, outside signal context,
+//! and exits.
+
+use std::ffi::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+
+/// How often the watcher looks at the flag
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signum: c_int, handler: usize) -> usize;
+}
+
+extern "C" fn request(_: c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Run `finish` and exit once the process is asked to terminate
+pub fn on_signal(finish: impl FnOnce() + Send + 'static) {
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: `request` only stores to an atomic, which is
+        // async-signal-safe, and stays valid for the life of the process.
+        unsafe { signal(signum, request as extern "C" fn(c_int) as usize) };
+    }
+    thread::spawn(move || {
+        while !REQUESTED.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+        }
+        finish();
+        std::process::exit(0);
+    });
+}
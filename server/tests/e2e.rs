@@ -0,0 +1,272 @@
+//! End-to-end tests: the host binary, driven over native-messaging stdio
+//! the way Chrome drives it, sending into a real tmux server. Every test
+//! starts its own tmux server on a private socket, with its own config and
+//! data directories, and kills it when done. Skipped when tmux is not
+//! installed.
+
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Session every test sends to
+const SESSION: &str = "e2e";
+
+/// How long a pane gets to show what was sent
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stands in for an agent: prints each line it reads, without the tty echo,
+/// and answers every pigeon prompt once its end marker arrives
+const FAKE_AGENT: &str = r#"stty -echo; while IFS= read -r line; do printf '%s\n' "$line"; case "$line" in *'[/pigeon:'*) echo 'answer: 42';; esac; done"#;
+
+/// A disposable tmux server and the directories the host runs with
+struct Sandbox {
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    /// `None` when tmux is not installed
+    fn new(name: &str, config: Value) -> Option<Self> {
+        if Command::new("tmux").arg("-V").output().is_err() {
+            eprintln!("tmux not installed; skipping {name}");
+            return None;
+        }
+        let dir =
+            std::env::temp_dir().join(format!("pigeon-e2e-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for sub in ["config/pigeon", "data", "cache", "tmux"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        std::fs::write(
+            dir.join("config/pigeon/config.json"),
+            serde_json::to_vec(&config).unwrap(),
+        )
+        .unwrap();
+        let sandbox = Self { dir };
+        let status = sandbox
+            .tmux(&["new-session", "-d", "-s", SESSION, "-x", "200", "-y", "50"])
+            .arg(FAKE_AGENT)
+            .status()
+            .unwrap();
+        assert!(status.success(), "cannot start tmux");
+        Some(sandbox)
+    }
+
+    /// `command` with the sandbox's tmux socket and directories
+    fn command(&self, program: impl AsRef<std::ffi::OsStr>) -> Command {
+        let mut command = Command::new(program);
+        command
+            .env_remove("TMUX")
+            .env_remove("PIGEON_TMUX")
+            .env("TMUX_TMPDIR", self.dir.join("tmux"))
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env("XDG_DATA_HOME", self.dir.join("data"))
+            .env("XDG_CACHE_HOME", self.dir.join("cache"));
+        command
+    }
+
+    fn tmux(&self, args: &[&str]) -> Command {
+        let mut command = self.command("tmux");
+        command.args(args);
+        command
+    }
+
+    fn host(&self) -> Host {
+        let mut child = self
+            .command(env!("CARGO_BIN_EXE_pigeon-host"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Host {
+            stdin: Some(child.stdin.take().unwrap()),
+            stdout: child.stdout.take().unwrap(),
+            child,
+        }
+    }
+
+    fn pane(&self) -> String {
+        let output = self
+            .tmux(&["capture-pane", "-p", "-J", "-t", SESSION])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// The pane once it shows `expected`; panics with what it shows instead
+    /// after `SETTLE_TIMEOUT`
+    fn wait_for(&self, expected: &str) -> String {
+        let deadline = Instant::now() + SETTLE_TIMEOUT;
+        loop {
+            let pane = self.pane();
+            if pane.contains(expected) {
+                return pane;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "pane never showed {expected:?}:\n{pane}"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = self.tmux(&["kill-server"]).status();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The host binary on the other end of a native-messaging pipe
+struct Host {
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    child: Child,
+}
+
+impl Host {
+    /// Send one framed request and read the framed response
+    fn call(&mut self, request: Value) -> Value {
+        let body = serde_json::to_vec(&request).unwrap();
+        let stdin = self.stdin.as_mut().unwrap();
+        stdin.write_all(&(body.len() as u32).to_le_bytes()).unwrap();
+        stdin.write_all(&body).unwrap();
+        stdin.flush().unwrap();
+        let mut len = [0; 4];
+        self.stdout.read_exact(&mut len).unwrap();
+        let mut body = vec![0; u32::from_le_bytes(len) as usize];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        // Closing stdin is how Chrome tells the host to exit
+        drop(self.stdin.take());
+        if self.child.wait().is_err() {
+            let _ = self.child.kill();
+        }
+    }
+}
+
+fn send(question: &str) -> Value {
+    json!({"action": "send", "file": "src/lib.rs", "question": question, "tmux_target": SESSION})
+}
+
+#[test]
+fn test_send_reaches_pane() {
+    let Some(sandbox) = Sandbox::new("send", json!({})) else {
+        return;
+    };
+    let response = sandbox.host().call(send("what does this do?"));
+    assert_eq!(response["ok"], true, "{response}");
+    assert_eq!(response["strategy"], "send-keys");
+    let pane = sandbox.wait_for("what does this do?");
+    assert!(pane.contains("src/lib.rs"), "{pane}");
+}
+
+#[test]
+fn test_long_message_is_pasted() {
+    let Some(sandbox) = Sandbox::new("paste", json!({})) else {
+        return;
+    };
+    // Over the send-keys limit, in lines short enough for the tty
+    let question: String = (0..400)
+        .map(|i| format!("line {i:03} of a long question\n"))
+        .collect();
+    let response = sandbox.host().call(send(&question));
+    assert_eq!(response["ok"], true, "{response}");
+    assert_eq!(response["strategy"], "paste-buffer");
+    sandbox.wait_for("line 399 of a long question");
+}
+
+#[test]
+fn test_bracketed_paste() {
+    let Some(sandbox) = Sandbox::new("bracketed", json!({"bracketed_paste": true})) else {
+        return;
+    };
+    let response = sandbox.host().call(send("first line\nsecond line"));
+    assert_eq!(response["ok"], true, "{response}");
+    assert_eq!(response["strategy"], "paste-buffer");
+    let pane = sandbox.wait_for("second line");
+    assert!(pane.contains("first line"), "{pane}");
+}
+
+#[test]
+fn test_target_validation() {
+    let config = json!({"allowed_targets": ["e2e*", "other:*"]});
+    let Some(sandbox) = Sandbox::new("targets", config) else {
+        return;
+    };
+    let mut host = sandbox.host();
+    let mut refused = send("not for this pane");
+    refused["tmux_target"] = json!("elsewhere");
+    let response = host.call(refused);
+    assert_eq!(response["ok"], false, "{response}");
+    assert_eq!(response["code"], "TARGET_NOT_ALLOWED");
+
+    // Allowed, but no such session on this server
+    let mut missing = send("nobody is listening");
+    missing["tmux_target"] = json!("other:0");
+    let response = host.call(missing);
+    assert_eq!(response["ok"], false, "{response}");
+
+    assert_eq!(host.call(send("this one is fine"))["ok"], true);
+    let pane = sandbox.wait_for("this one is fine");
+    assert!(!pane.contains("not for this pane"), "{pane}");
+    assert!(!pane.contains("nobody is listening"), "{pane}");
+}
+
+#[test]
+fn test_reply_is_captured_from_pane() {
+    let Some(sandbox) = Sandbox::new("reply", json!({"reply_markers": true})) else {
+        return;
+    };
+    let mut host = sandbox.host();
+    let response = host.call(send("what is the answer?"));
+    assert_eq!(response["ok"], true, "{response}");
+    let send_id = response["send_id"].as_str().unwrap().to_string();
+    sandbox.wait_for("answer: 42");
+    let response = host.call(json!({
+        "action": "get-reply",
+        "tmux_target": SESSION,
+        "send_id": send_id,
+    }));
+    assert_eq!(response["ok"], true, "{response}");
+    assert_eq!(response["reply"], "answer: 42");
+}
+
+#[test]
+fn test_stage_checks_resolved_target_before_typing() {
+    let Some(sandbox) = Sandbox::new("stage", json!({})) else {
+        return;
+    };
+    let mut host = sandbox.host();
+    let response =
+        host.call(json!({"action": "add-alias", "name": "agent", "tmux_target": SESSION}));
+    assert_eq!(response["ok"], true, "{response}");
+    let stage = |question: &str| {
+        let mut req = send(question);
+        req["action"] = json!("stage-send");
+        req["tmux_target"] = json!("agent");
+        req
+    };
+    let response = host.call(stage("first staged"));
+    assert_eq!(response["ok"], true, "{response}");
+    let stage_id = response["stage_id"].clone();
+
+    // The alias names no staged target, but the pane it points at holds one
+    let response = host.call(stage("second staged"));
+    assert_eq!(response["ok"], false, "{response}");
+    assert_eq!(response["code"], "STAGE_PENDING");
+
+    let response = host.call(json!({"action": "commit-send", "stage_id": stage_id}));
+    assert_eq!(response["ok"], true, "{response}");
+    let pane = sandbox.wait_for("first staged");
+    assert!(!pane.contains("second staged"), "{pane}");
+}